@@ -0,0 +1,19 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use mc_server::chunk_service::Dimension;
+use mc_server::datapack::DataPack;
+use mc_server::resource_manager::ResourceManager;
+
+fn chunk_encoding(c: &mut Criterion) {
+    let vanilla = DataPack::vanilla("resources/generated");
+    let resource_manager = ResourceManager::new("resources", &[&vanilla]).unwrap();
+    let block_registry = resource_manager.block_registry();
+    let biomes = resource_manager.registry_ids("worldgen/biome");
+    let overworld = Dimension::load("world", -64, 384).unwrap();
+    // One of the fully generated chunks of the fixture world
+    let (chunk, _) = overworld.read_chunk(0, -33, &block_registry, &biomes).unwrap();
+
+    c.bench_function("encode spawn chunk", |b| b.iter(|| chunk.network_data(&block_registry)));
+}
+
+criterion_group!(benches, chunk_encoding);
+criterion_main!(benches);
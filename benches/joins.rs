@@ -0,0 +1,79 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use mc_server::block_storage::ChunkBlocks;
+use mc_server::chunk_service::Dimension;
+use mc_server::datapack::DataPack;
+use mc_server::light::ChunkLight;
+use mc_server::packet::{ConfigurationPacketResponse, PlayPacketClientBound};
+use mc_server::resource_manager::ResourceManager;
+
+/// Tracks the bytes that are alive and the most that were alive at once so the memory used per join can be reported
+struct CountingAllocator;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(live, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const JOINS: usize = 50;
+/// One of the fully generated chunks of the fixture world, there are none around 0 0
+const SPAWN_CHUNK: (i32, i32) = (0, -33);
+
+/// Does what the server does for a joining client: hand out the shared registries and encode the
+/// configuration packets and the spawn chunk from them
+fn simulate_joins(resource_manager: &ResourceManager, spawn_chunk: &(ChunkBlocks, ChunkLight)) -> usize {
+    let mut bytes_sent = 0;
+    for _ in 0..JOINS {
+        let block_registry = resource_manager.block_registry();
+        let registries = resource_manager.registries();
+        let tags = resource_manager.tags();
+
+        bytes_sent += ConfigurationPacketResponse::update_tags(&tags).len();
+        for (registry_id, entries) in registries.iter() {
+            bytes_sent += ConfigurationPacketResponse::registry_data(registry_id, entries, &[]).len();
+        }
+        let (blocks, light) = spawn_chunk;
+        bytes_sent += PlayPacketClientBound::chunk_data(SPAWN_CHUNK.0, SPAWN_CHUNK.1, blocks, &[], light, &block_registry).len();
+    }
+    bytes_sent
+}
+
+fn joins(c: &mut Criterion) {
+    let vanilla = DataPack::vanilla("resources/generated");
+    let resource_manager = ResourceManager::new("resources", &[&vanilla]).unwrap();
+    let overworld = Dimension::load("world", -64, 384).unwrap();
+    let biomes = resource_manager.registry_ids("worldgen/biome");
+    let (blocks, light) = overworld.read_chunk(SPAWN_CHUNK.0, SPAWN_CHUNK.1, &resource_manager.block_registry(), &biomes).unwrap();
+    let spawn_chunk = (blocks, light.unwrap_or(ChunkLight::new(overworld.min_section(), overworld.section_count())));
+
+    let live_before = LIVE.load(Ordering::Relaxed);
+    PEAK.store(live_before, Ordering::Relaxed);
+    let bytes_sent = simulate_joins(&resource_manager, &spawn_chunk);
+    let peak = PEAK.load(Ordering::Relaxed) - live_before;
+    let retained = LIVE.load(Ordering::Relaxed).saturating_sub(live_before);
+    println!("{JOINS} joins sent {} KiB, needed at most {} KiB more memory and kept {} KiB ({} KiB per join)",
+        bytes_sent / 1024, peak / 1024, retained / 1024, retained / 1024 / JOINS);
+
+    let mut group = c.benchmark_group("joins");
+    group.throughput(Throughput::Elements(JOINS as u64));
+    group.bench_function("50 simulated joins", |b| b.iter(|| simulate_joins(&resource_manager, &spawn_chunk)));
+    group.finish();
+}
+
+criterion_group!(benches, joins);
+criterion_main!(benches);
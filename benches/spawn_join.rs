@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::mpsc::channel;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use mc_server::chunk_service::{ChunkKey, ChunkService, Dimension};
+use mc_server::datapack::DataPack;
+use mc_server::resource_manager::ResourceManager;
+use mc_server::server_util::ServerConnectionThreadBound;
+use mc_world_parser::Position;
+
+const PLAYERS: usize = 10;
+/// The fixture world has no chunks around 0 0, this is the middle of the largest square of saved chunks
+const SPAWN_CHUNK: (i32, i32) = (0, -39);
+const VIEW_DISTANCE: i32 = 7;
+
+/// Every player asks for the chunks around spawn and waits until it has the packets for all of them
+fn join_spawn(chunk_service: &ChunkService) -> usize {
+    let mut bytes = 0;
+    let receivers = (0..PLAYERS).map(|_| {
+        let (sender, receiver) = channel();
+        for x in -VIEW_DISTANCE..VIEW_DISTANCE {
+            for z in -VIEW_DISTANCE..VIEW_DISTANCE {
+                let pos = Position::new(SPAWN_CHUNK.0 + x, 0, SPAWN_CHUNK.1 + z);
+                chunk_service.request(ChunkKey::new("minecraft:overworld", pos), x.abs().max(z.abs()), sender.clone());
+            }
+        }
+        receiver
+    }).collect::<Vec<_>>();
+
+    for receiver in receivers {
+        for _ in 0..(VIEW_DISTANCE * 2).pow(2) {
+            if let Ok(ServerConnectionThreadBound::ChunkData(Some(chunk))) = receiver.recv() {
+                // What the connection does with a cached packet
+                bytes += chunk.packet.to_vec().len();
+            }
+        }
+    }
+    bytes
+}
+
+fn spawn_join(c: &mut Criterion) {
+    let vanilla = DataPack::vanilla("resources/generated");
+    let resource_manager = ResourceManager::new("resources", &[&vanilla]).unwrap();
+    let overworld = Dimension::load("world", -64, 384).unwrap();
+    let biomes = Arc::new(resource_manager.registry_ids("worldgen/biome"));
+    let new_service = || ChunkService::new(HashMap::from([("minecraft:overworld".to_string(), overworld.clone())]), resource_manager.block_registry(), resource_manager.builtin_registry("block_entity_type"), biomes.clone());
+
+    let mut group = c.benchmark_group("10 player spawn join");
+    group.sample_size(10);
+    group.bench_function("cold cache", |b| {
+        // Returned so stopping the worker threads isn't measured
+        b.iter_batched(new_service, |chunk_service| { join_spawn(&chunk_service); chunk_service }, BatchSize::PerIteration)
+    });
+    let warm_service = new_service();
+    assert!(join_spawn(&warm_service) > 0, "No chunks around spawn were loaded");
+    group.bench_function("warm cache", |b| b.iter(|| join_spawn(&warm_service)));
+    group.finish();
+}
+
+criterion_group!(benches, spawn_join);
+criterion_main!(benches);
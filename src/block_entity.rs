@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+use inbt::NbtTag;
+use log::warn;
+use crate::nbt_util::NbtTagExt;
+
+/// A block entity like a chest or sign, with the data the client needs to render it
+#[derive(Debug, Clone)]
+pub struct BlockEntity {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub id: String,
+    /// Protocol id from the block_entity_type registry
+    pub type_id: i32,
+    /// Everything but the id and position, as a nameless compound
+    pub data: NbtTag,
+}
+
+impl BlockEntity {
+    /// Reads a block entity from the `block_entities` list of a chunk. Returns `None` for unknown types.
+    pub fn from_nbt(tag: &NbtTag, block_entity_types: &BTreeMap<String, i32>) -> Option<Self> {
+        let id = tag.child("id")?.as_str()?.to_string();
+        let Some(type_id) = block_entity_types.get(&id).copied() else {
+            warn!("Skipping block entity of unknown type {}", id);
+            return None;
+        };
+        let data = tag.as_list()?.iter()
+            .filter(|child| {
+                let name: &str = &child.name();
+                !matches!(name, "id" | "x" | "y" | "z" | "keepPacked")
+            })
+            .cloned()
+            .collect();
+        Some(Self {
+            x: tag.child("x")?.as_i32()?,
+            y: tag.child("y")?.as_i32()?,
+            z: tag.child("z")?.as_i32()?,
+            id,
+            type_id,
+            data: NbtTag::Compound("".to_string(), data),
+        })
+    }
+
+    /// The block entity as saved in the `block_entities` list of a chunk
+    pub fn to_nbt(&self) -> NbtTag {
+        let mut nbt = self.data.clone();
+        for tag in [
+            NbtTag::String("id".to_string(), self.id.clone()),
+            NbtTag::Int("x".to_string(), self.x),
+            NbtTag::Int("y".to_string(), self.y),
+            NbtTag::Int("z".to_string(), self.z),
+        ] {
+            nbt.set_child(tag);
+        }
+        nbt
+    }
+
+    /// Reads every block entity of a chunk. The ones that can't be read, like those of unknown types, are
+    /// returned as they were so they can be written back.
+    pub fn from_chunk_nbt(chunk: &NbtTag, block_entity_types: &BTreeMap<String, i32>) -> (Vec<Self>, Vec<NbtTag>) {
+        let mut block_entities = vec![];
+        let mut unknown = vec![];
+        for tag in chunk.child("block_entities").and_then(|b| b.as_list()).into_iter().flatten() {
+            match Self::from_nbt(tag, block_entity_types) {
+                Some(block_entity) => block_entities.push(block_entity),
+                None => unknown.push(tag.clone()),
+            }
+        }
+        (block_entities, unknown)
+    }
+}
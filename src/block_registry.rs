@@ -1,10 +1,13 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::{fs, io};
 use std::path::Path;
+use std::sync::Arc;
 use mc_world_parser::Block;
 use mc_world_parser::section::BlockIDGetter;
 use serde::Deserialize;
 use serde_json::Value;
+use crate::collision::{self, Aabb};
+use crate::light;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct BlockStateDefinition {
@@ -29,9 +32,90 @@ pub struct BlockStates {
     states: Vec<BlockState>,
 }
 
+/// Finds the state id of a block from its properties without going through every state
+#[derive(Debug, Clone)]
+enum StateLookup {
+    /// States are numbered the way vanilla does it: every combination of property values in order, with the
+    /// last property changing fastest. The id is then the property value indices read as a mixed-radix number.
+    MixedRadix { base_id: i32, properties: Vec<(String, Vec<String>)> },
+    /// Fallback for blocks whose states don't follow that layout
+    Map(HashMap<BTreeMap<String, String>, i32>),
+}
+
+impl StateLookup {
+    fn new(states: &BlockStates) -> Self {
+        let mixed_radix = Self::MixedRadix {
+            base_id: states.states.iter().map(|s| s.id).min().unwrap_or(0),
+            properties: states.properties.iter().map(|(name, values)| (name.clone(), values.clone())).collect(),
+        };
+        if states.states.iter().all(|state| mixed_radix.state_id(&state.properties) == Some(state.id)) {
+            mixed_radix
+        } else {
+            Self::Map(states.states.iter().map(|state| (state.properties.clone(), state.id)).collect())
+        }
+    }
+
+    fn state_id(&self, block_properties: &BTreeMap<String, String>) -> Option<i32> {
+        match self {
+            StateLookup::MixedRadix { base_id, properties } => {
+                if block_properties.len() != properties.len() {
+                    return None;
+                }
+                let mut index = 0;
+                for (name, values) in properties {
+                    let value = block_properties.get(name)?;
+                    index = index * values.len() + values.iter().position(|v| v == value)?;
+                }
+                Some(base_id + index as i32)
+            }
+            StateLookup::Map(map) => map.get(block_properties).copied(),
+        }
+    }
+}
+
+/// What the light engine, chunk encoding and movement checks need to know about a state, looked up by state id
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StateInfo {
+    pub luminance: u8,
+    pub opacity: u8,
+    pub is_air: bool,
+    /// Counts for the MOTION_BLOCKING heightmap: the block has collision or contains a fluid
+    pub blocks_motion: bool,
+    /// What entities can't move through, `None` for blocks without collision
+    pub collision: Option<Aabb>,
+}
+
+/// Block types that don't stop movement
+const NON_COLLIDING_BLOCK_TYPES: &[&str] = &[
+    "minecraft:air", "minecraft:flower", "minecraft:tall_flower", "minecraft:double_plant", "minecraft:tall_grass",
+    "minecraft:sapling", "minecraft:torch", "minecraft:wall_torch", "minecraft:redstone_torch", "minecraft:redstone_wall_torch",
+    "minecraft:redstone_wire", "minecraft:rail", "minecraft:powered_rail", "minecraft:detector_rail", "minecraft:standing_sign",
+    "minecraft:wall_sign", "minecraft:ceiling_hanging_sign", "minecraft:wall_hanging_sign", "minecraft:button",
+    "minecraft:pressure_plate", "minecraft:weighted_pressure_plate", "minecraft:vine", "minecraft:glow_lichen",
+    "minecraft:mushroom", "minecraft:fungus", "minecraft:roots", "minecraft:crop", "minecraft:stem", "minecraft:attached_stem",
+    "minecraft:sugar_cane", "minecraft:sweet_berry_bush", "minecraft:nether_wart", "minecraft:fire", "minecraft:soul_fire",
+    "minecraft:banner", "minecraft:wall_banner", "minecraft:lever", "minecraft:trip_wire", "minecraft:trip_wire_hook",
+    "minecraft:structure_void", "minecraft:light", "minecraft:web", "minecraft:coral_plant", "minecraft:coral_fan",
+];
+
+fn blocks_motion(block_type: &str, name: &str, properties: &BTreeMap<String, String>) -> bool {
+    let has_fluid = properties.get("waterlogged").is_some_and(|w| w == "true")
+        || matches!(name, "minecraft:water" | "minecraft:lava" | "minecraft:bubble_column" | "minecraft:kelp" | "minecraft:kelp_plant" | "minecraft:seagrass" | "minecraft:tall_seagrass");
+    has_fluid || !NON_COLLIDING_BLOCK_TYPES.contains(&block_type)
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct BlockRegistry {
-    blocks: BTreeMap<String, BlockStates>
+    blocks: BTreeMap<String, BlockStates>,
+    #[serde(skip)]
+    block_ids: BTreeMap<String, i32>,
+    /// (first state id, block name) sorted by state id, for going from a state back to its block
+    #[serde(skip)]
+    blocks_by_first_state: Vec<(i32, String)>,
+    #[serde(skip)]
+    state_lookup: HashMap<String, StateLookup>,
+    #[serde(skip)]
+    state_info: Vec<StateInfo>,
 }
 
 impl BlockIDGetter for BlockRegistry {
@@ -40,24 +124,126 @@ impl BlockIDGetter for BlockRegistry {
     }
 }
 
+/// Handle to a shared registry for APIs that want to own their `BlockIDGetter`. Cloning it doesn't copy the registry.
+#[derive(Debug, Clone)]
+pub struct SharedBlockRegistry(pub Arc<BlockRegistry>);
+
+impl BlockIDGetter for SharedBlockRegistry {
+    fn id_of(&self, block: &Block) -> i32 {
+        self.0.id_of(block)
+    }
+}
+
 impl BlockRegistry {
     pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let json = fs::read_to_string(path)?;
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// Builds the registry from the contents of a blocks report
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        let blocks: BTreeMap<String, BlockStates> = serde_json::from_str(json)?;
 
-        let blocks: BTreeMap<String, BlockStates> = serde_json::from_str(&*json).unwrap();
-        Ok(Self {blocks})
+        // State ids are handed out in block registry order, so sorting by the first state gives the block ids
+        let mut names_by_first_state = blocks.iter()
+            .map(|(name, states)| (states.states.iter().map(|s| s.id).min().unwrap_or(i32::MAX), name.clone()))
+            .collect::<Vec<_>>();
+        names_by_first_state.sort();
+        let block_ids = names_by_first_state.iter().enumerate().map(|(id, (_, name))| (name.clone(), id as i32)).collect();
+
+        let state_lookup = blocks.iter().map(|(name, states)| (name.clone(), StateLookup::new(states))).collect();
+
+        let state_count = blocks.values().flat_map(|states| states.states.iter()).map(|state| state.id + 1).max().unwrap_or(0);
+        let mut state_info = vec![StateInfo::default(); state_count as usize];
+        for (name, states) in &blocks {
+            for state in &states.states {
+                state_info[state.id as usize] = StateInfo {
+                    luminance: light::luminance_of(name, &state.properties),
+                    opacity: light::opacity_of(&states.definition.r#type, name, &state.properties),
+                    is_air: matches!(&**name, "minecraft:air" | "minecraft:cave_air" | "minecraft:void_air"),
+                    blocks_motion: blocks_motion(&states.definition.r#type, name, &state.properties),
+                    collision: collision::collision_box_of(&states.definition.r#type, name, &state.properties),
+                };
+            }
+        }
+
+        Ok(Self {blocks, block_ids, blocks_by_first_state: names_by_first_state, state_lookup, state_info})
+    }
+
+    /// Unknown states are treated like air
+    pub fn state_info(&self, id: i32) -> StateInfo {
+        self.state_info.get(id as usize).copied().unwrap_or(StateInfo { luminance: 0, opacity: 0, is_air: true, blocks_motion: false, collision: None })
+    }
+
+    /// Bits per entry the client expects for block states sent without a palette
+    pub fn state_bits(&self) -> u8 {
+        (usize::BITS - self.state_info.len().saturating_sub(1).leading_zeros()) as u8
+    }
+
+    /// Returns the block registry id (not a state id) of the block
+    pub fn block_id(&self, name: &str) -> Option<i32> {
+        self.block_ids.get(name).copied()
     }
 
     pub fn get_blockstate_of_block(&self, block: &Block) -> Option<i32> {
-        for (name, states) in &self.blocks {
-            if block.identifier().eq(name) {
-                for state in &states.states {
-                    if state.properties.eq(block.properties()) {
-                        return Some(state.id);
-                    }
-                }
-            }
+        self.state_id(block.identifier(), block.properties())
+    }
+
+    pub fn state_id(&self, name: &str, properties: &BTreeMap<String, String>) -> Option<i32> {
+        self.state_lookup.get(name)?.state_id(properties)
+    }
+
+    /// Returns the block name and properties of a state id
+    pub fn state(&self, id: i32) -> Option<(&str, &BTreeMap<String, String>)> {
+        let index = match self.blocks_by_first_state.binary_search_by_key(&id, |(first_state, _)| *first_state) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        let name = &self.blocks_by_first_state[index].1;
+        let state = self.blocks.get(name)?.states.iter().find(|state| state.id == id)?;
+        Some((name, &state.properties))
+    }
+
+    pub fn default_state(&self, name: &str) -> Option<i32> {
+        let states = self.blocks.get(name)?;
+        states.states.iter().find(|state| state.default).or(states.states.first()).map(|state| state.id)
+    }
+
+    /// Returns every property of the block with its possible values
+    pub fn properties_of(&self, name: &str) -> Option<&BTreeMap<String, Vec<String>>> {
+        self.blocks.get(name).map(|states| &states.properties)
+    }
+
+    /// Returns the block type from the definition, e.g. `minecraft:stair`
+    pub fn block_type_of(&self, name: &str) -> Option<&str> {
+        self.blocks.get(name).map(|states| &*states.definition.r#type)
+    }
+
+    /// Returns the state id of the same block with one property changed
+    pub fn with_property(&self, id: i32, key: &str, value: &str) -> Option<i32> {
+        let (name, properties) = self.state(id)?;
+        if !self.properties_of(name)?.get(key)?.iter().any(|v| v == value) {
+            return None;
+        }
+        let mut properties = properties.clone();
+        properties.insert(key.to_string(), value.to_string());
+        self.state_id(name, &properties)
+    }
+
+    /// Parses block states the way commands write them, like `stone_stairs[facing=east]`.
+    /// Properties that aren't given keep their default value.
+    pub fn parse_state(&self, state: &str) -> Option<i32> {
+        let (name, properties) = match state.split_once('[') {
+            Some((name, properties)) => (name, Some(properties.strip_suffix(']')?)),
+            None => (state, None),
+        };
+        let name = if name.contains(':') { name.to_string() } else { "minecraft:".to_string() + name };
+
+        let mut id = self.default_state(&name)?;
+        for property in properties.into_iter().flat_map(|p| p.split(',')).filter(|p| !p.trim().is_empty()) {
+            let (key, value) = property.split_once('=')?;
+            id = self.with_property(id, key.trim(), value.trim())?;
         }
-        None
+        Some(id)
     }
 }
\ No newline at end of file
@@ -0,0 +1,341 @@
+use std::collections::{BTreeMap, HashMap};
+use inbt::NbtTag;
+use mc_datatypes::VarInt;
+use crate::block_registry::{BlockRegistry, StateInfo};
+use crate::nbt_util::NbtTagExt;
+
+const SECTION_VOLUME: usize = 16 * 16 * 16;
+/// Properties of states the block registry doesn't know, which are saved as air
+static NO_PROPERTIES: BTreeMap<String, String> = BTreeMap::new();
+
+#[derive(Debug, Clone)]
+struct SectionBlocks {
+    states: Box<[u16; SECTION_VOLUME]>,
+    /// The biome paletted container as it was sent, since nothing changes biomes
+    biomes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeightmapType {
+    /// Highest block that blocks movement or contains a fluid
+    MotionBlocking,
+    /// Highest block that isn't air
+    WorldSurface,
+}
+
+impl HeightmapType {
+    const ALL: [HeightmapType; 2] = [HeightmapType::MotionBlocking, HeightmapType::WorldSurface];
+
+    fn name(self) -> &'static str {
+        match self {
+            HeightmapType::MotionBlocking => "MOTION_BLOCKING",
+            HeightmapType::WorldSurface => "WORLD_SURFACE",
+        }
+    }
+
+    fn counts(self, info: StateInfo) -> bool {
+        match self {
+            HeightmapType::MotionBlocking => info.blocks_motion && !info.is_air,
+            HeightmapType::WorldSurface => !info.is_air,
+        }
+    }
+}
+
+/// Block states of a chunk that can be changed, which `mc_world_parser` chunks can't.
+/// Read straight from the chunk's NBT, so a chunk is only encoded once, when it's sent.
+#[derive(Debug, Clone)]
+pub struct ChunkBlocks {
+    min_section: i32,
+    sections: Vec<SectionBlocks>,
+    /// For every column (x + z * 16), one above the highest block counting for the heightmap, relative to the bottom of the world
+    motion_blocking: Box<[u16; 256]>,
+    world_surface: Box<[u16; 256]>,
+}
+
+impl ChunkBlocks {
+    /// A chunk of air, in the first biome
+    pub fn empty(min_section: i32, section_count: usize) -> Self {
+        Self {
+            min_section,
+            sections: (0..section_count).map(|_| SectionBlocks {
+                states: Box::new([0; SECTION_VOLUME]),
+                // A single valued container of biome 0 without any data
+                biomes: vec![0, 0, 0],
+            }).collect(),
+            motion_blocking: Box::new([0; 256]),
+            world_surface: Box::new([0; 256]),
+        }
+    }
+
+    /// Reads the block states and biomes from the `sections` of a chunk's NBT, with heightmaps worked out from the
+    /// blocks. States the block registry doesn't have become air, unknown biomes the first biome.
+    pub fn from_nbt(chunk: &NbtTag, min_section: i32, section_count: usize, block_registry: &BlockRegistry, biomes: &BTreeMap<String, i32>) -> Self {
+        let mut blocks = Self::empty(min_section, section_count);
+        let biome_bits = bits_for(biomes.len());
+        for section in chunk.child("sections").and_then(|s| s.as_list()).into_iter().flatten() {
+            let Some(index) = section.child("Y").and_then(|y| y.as_i32()).map(|y| y - min_section).filter(|i| (0..section_count as i32).contains(i)) else {
+                continue;
+            };
+            let target = &mut blocks.sections[index as usize];
+            if let Some(states) = section.child("block_states") {
+                let states = read_saved_container(states, SECTION_VOLUME, 4, |entry| {
+                    let properties = entry.child("Properties").and_then(|p| p.as_list()).into_iter().flatten()
+                        .filter_map(|property| Some((property.name().to_string(), property.as_str()?.to_string())))
+                        .collect();
+                    block_registry.state_id(entry.child("Name")?.as_str()?, &properties)
+                });
+                target.states.copy_from_slice(&states);
+            }
+            if let Some(section_biomes) = section.child("biomes") {
+                let values = read_saved_container(section_biomes, 64, 1, |entry| biomes.get(entry.as_str()?).copied());
+                target.biomes.clear();
+                write_paletted_container(&mut target.biomes, &values, 1, 3, biome_bits);
+            }
+        }
+        for x in 0..16 {
+            for z in 0..16 {
+                for heightmap in HeightmapType::ALL {
+                    blocks.update_height(heightmap, x, z, blocks.max_y(), block_registry);
+                }
+            }
+        }
+        blocks
+    }
+
+    /// Puts the block states into the `sections` of a chunk's NBT, keeping everything else in them
+    pub fn write_sections(&self, sections: &mut Vec<NbtTag>, block_registry: &BlockRegistry) {
+        for (i, section) in self.sections.iter().enumerate() {
+            let (palette, indices) = palette_of(&*section.states);
+            let palette_nbt = palette.iter().map(|state| {
+                let mut entry = NbtTag::Compound("".to_string(), vec![]);
+                let (name, properties) = block_registry.state(*state as i32).unwrap_or(("minecraft:air", &NO_PROPERTIES));
+                entry.set_child(NbtTag::String("Name".to_string(), name.to_string()));
+                if !properties.is_empty() {
+                    entry.set_child(NbtTag::Compound("Properties".to_string(), properties.iter()
+                        .map(|(key, value)| NbtTag::String(key.clone(), value.clone()))
+                        .collect()));
+                }
+                entry
+            }).collect();
+            let mut states = vec![NbtTag::List("palette".to_string(), palette_nbt)];
+            // A single state needs no data
+            if palette.len() > 1 {
+                let bits = bits_for(palette.len()).max(4);
+                states.push(NbtTag::LongArray("data".to_string(), pack(&indices, bits).into_iter().map(|long| long as i64).collect()));
+            }
+            section_nbt_mut(sections, self.min_section + i as i32).set_child(NbtTag::Compound("block_states".to_string(), states));
+        }
+    }
+
+    /// Replaces the computed heightmaps with the ones vanilla saved in the chunk's `Heightmaps`
+    pub fn read_heightmaps(&mut self, chunk: &NbtTag) {
+        let bits = self.height_bits();
+        let per_long = 64 / bits as usize;
+        for heightmap in HeightmapType::ALL {
+            let Some(NbtTag::LongArray(_, longs)) = chunk.child("Heightmaps").and_then(|h| h.child(heightmap.name())) else {
+                continue;
+            };
+            if longs.len() != 256usize.div_ceil(per_long) {
+                continue;
+            }
+            let heights = self.heights_mut(heightmap);
+            for (i, height) in heights.iter_mut().enumerate() {
+                *height = ((longs[i / per_long] as u64 >> ((i % per_long) * bits as usize)) & ((1 << bits) - 1)) as u16;
+            }
+        }
+    }
+
+    /// The heightmaps as sent in Chunk Data and saved in the region files
+    pub fn heightmaps_nbt(&self) -> NbtTag {
+        let bits = self.height_bits();
+        let per_long = 64 / bits as usize;
+        let tags = HeightmapType::ALL.iter().map(|heightmap| {
+            let mut longs = vec![0i64; 256usize.div_ceil(per_long)];
+            for (i, height) in self.heights(*heightmap).iter().enumerate() {
+                longs[i / per_long] |= (*height as i64) << ((i % per_long) * bits as usize);
+            }
+            NbtTag::LongArray(heightmap.name().to_string(), longs)
+        }).collect();
+        NbtTag::Compound("".to_string(), tags)
+    }
+
+    /// Height of a column in a heightmap as an absolute y, one above the highest block
+    pub fn height(&self, heightmap: HeightmapType, x: i32, z: i32) -> i32 {
+        self.heights(heightmap)[(x + z * 16) as usize] as i32 + self.min_section * 16
+    }
+
+    fn heights(&self, heightmap: HeightmapType) -> &[u16; 256] {
+        match heightmap {
+            HeightmapType::MotionBlocking => &self.motion_blocking,
+            HeightmapType::WorldSurface => &self.world_surface,
+        }
+    }
+
+    fn heights_mut(&mut self, heightmap: HeightmapType) -> &mut [u16; 256] {
+        match heightmap {
+            HeightmapType::MotionBlocking => &mut self.motion_blocking,
+            HeightmapType::WorldSurface => &mut self.world_surface,
+        }
+    }
+
+    /// Bits per height, enough for every height from 0 to the world height
+    fn height_bits(&self) -> u8 {
+        (u32::BITS - (self.sections.len() as u32 * 16).leading_zeros()) as u8
+    }
+
+    fn max_y(&self) -> i32 {
+        (self.min_section + self.sections.len() as i32) * 16 - 1
+    }
+
+    /// Searches down from `start_y` for the highest block that counts for the heightmap
+    fn update_height(&mut self, heightmap: HeightmapType, x: i32, z: i32, start_y: i32, block_registry: &BlockRegistry) {
+        let min_y = self.min_section * 16;
+        let height = (min_y..=start_y).rev()
+            .find(|y| heightmap.counts(block_registry.state_info(self.get(x, *y, z))))
+            .map(|y| y + 1 - min_y)
+            .unwrap_or(0);
+        self.heights_mut(heightmap)[(x + z * 16) as usize] = height as u16;
+    }
+
+    pub fn min_section(&self) -> i32 {
+        self.min_section
+    }
+
+    pub fn section_count(&self) -> usize {
+        self.sections.len()
+    }
+
+    fn index_of(&self, x: i32, y: i32, z: i32) -> Option<(usize, usize)> {
+        let section = y.div_euclid(16) - self.min_section;
+        if !(0..self.sections.len() as i32).contains(&section) {
+            return None;
+        }
+        Some((section as usize, (y.rem_euclid(16) * 256 + z * 16 + x) as usize))
+    }
+
+    /// State id at a block. `x` and `z` are inside the chunk, `y` is absolute. Outside the world it's air.
+    pub fn get(&self, x: i32, y: i32, z: i32) -> i32 {
+        self.index_of(x, y, z).map(|(section, index)| self.sections[section].states[index] as i32).unwrap_or(0)
+    }
+
+    /// Changes a block and returns the state it replaced, or `None` if `y` is outside the world
+    pub fn set(&mut self, x: i32, y: i32, z: i32, state: i32, block_registry: &BlockRegistry) -> Option<i32> {
+        let (section, index) = self.index_of(x, y, z)?;
+        let old = std::mem::replace(&mut self.sections[section].states[index], state as u16);
+
+        let info = block_registry.state_info(state);
+        for heightmap in HeightmapType::ALL {
+            let height = self.height(heightmap, x, z);
+            if heightmap.counts(info) && y >= height {
+                self.heights_mut(heightmap)[(x + z * 16) as usize] = (y + 1 - self.min_section * 16) as u16;
+            } else if !heightmap.counts(info) && y == height - 1 {
+                // The top block went away, so look for the next one below it
+                self.update_height(heightmap, x, z, y, block_registry);
+            }
+        }
+        Some(old as i32)
+    }
+
+    /// Encodes the sections again for Chunk Data
+    pub fn network_data(&self, block_registry: &BlockRegistry) -> Vec<u8> {
+        let mut data = vec![];
+        for section in &self.sections {
+            let block_count = section.states.iter().filter(|state| !block_registry.state_info(**state as i32).is_air).count();
+            data.extend_from_slice(&(block_count as i16).to_be_bytes());
+            write_paletted_container(&mut data, &*section.states, 4, 8, block_registry.state_bits());
+            data.extend_from_slice(&section.biomes);
+        }
+        data
+    }
+}
+
+/// The section in the `sections` of a chunk's NBT at a section y, added if the chunk doesn't have it
+pub(crate) fn section_nbt_mut(sections: &mut Vec<NbtTag>, section_y: i32) -> &mut NbtTag {
+    let index = match sections.iter().position(|s| s.child("Y").and_then(|y| y.as_i32()) == Some(section_y)) {
+        Some(index) => index,
+        None => {
+            sections.push(NbtTag::Compound("".to_string(), vec![NbtTag::Byte("Y".to_string(), section_y as i8)]));
+            sections.len() - 1
+        }
+    };
+    &mut sections[index]
+}
+
+/// Bits needed to tell `count` values apart
+fn bits_for(count: usize) -> u8 {
+    (usize::BITS - count.saturating_sub(1).leading_zeros()) as u8
+}
+
+/// The distinct values in order of appearance, and the palette index of each value
+fn palette_of(values: &[u16]) -> (Vec<u16>, Vec<u16>) {
+    let mut palette = vec![];
+    let mut palette_index = HashMap::new();
+    let indices = values.iter()
+        .map(|value| *palette_index.entry(*value).or_insert_with(|| {
+            palette.push(*value);
+            palette.len() as u16 - 1
+        }))
+        .collect::<Vec<_>>();
+    (palette, indices)
+}
+
+/// Packs values into longs, lowest bits first. Entries don't span longs.
+fn pack(values: &[u16], bits: u8) -> Vec<u64> {
+    let per_long = 64 / bits as usize;
+    let mut longs = vec![0u64; values.len().div_ceil(per_long)];
+    for (i, value) in values.iter().enumerate() {
+        longs[i / per_long] |= (*value as u64) << ((i % per_long) * bits as usize);
+    }
+    longs
+}
+
+/// Reads a paletted container as saved in a chunk section, `{palette: [...], data: [L; ...]}`, into one value per
+/// entry. `palette_value` looks up the id of a palette entry, entries without one become 0.
+fn read_saved_container(container: &NbtTag, size: usize, min_bits: u8, palette_value: impl Fn(&NbtTag) -> Option<i32>) -> Vec<u16> {
+    let palette = container.child("palette").and_then(|p| p.as_list()).into_iter().flatten()
+        .map(|entry| palette_value(entry).unwrap_or(0) as u16)
+        .collect::<Vec<_>>();
+    // Containers with a single value have no data
+    let Some(NbtTag::LongArray(_, longs)) = container.child("data") else {
+        return vec![palette.first().copied().unwrap_or(0); size];
+    };
+    let bits = bits_for(palette.len()).max(min_bits);
+    let per_long = 64 / bits as usize;
+    let mask = (1u64 << bits) - 1;
+    (0..size)
+        .map(|i| {
+            let long = longs.get(i / per_long).copied().unwrap_or(0) as u64;
+            let index = (long >> ((i % per_long) * bits as usize)) & mask;
+            palette.get(index as usize).copied().unwrap_or(0)
+        })
+        .collect()
+}
+
+/// Writes a paletted container in the network format. Bits per entry below `min_bits` are raised to it like the
+/// client expects, and above `max_indirect_bits` the values are written without a palette.
+fn write_paletted_container(data: &mut Vec<u8>, values: &[u16], min_bits: u8, max_indirect_bits: u8, direct_bits: u8) {
+    let (palette, indices) = palette_of(values);
+    if palette.len() == 1 {
+        data.push(0);
+        data.append(&mut VarInt::new(palette[0] as i32).bytes);
+        data.append(&mut VarInt::new(0).bytes);
+        return;
+    }
+
+    let indirect_bits = bits_for(palette.len()).max(min_bits);
+    let longs = if indirect_bits <= max_indirect_bits {
+        data.push(indirect_bits);
+        data.append(&mut VarInt::new(palette.len() as i32).bytes);
+        for value in &palette {
+            data.append(&mut VarInt::new(*value as i32).bytes);
+        }
+        pack(&indices, indirect_bits)
+    } else {
+        data.push(direct_bits);
+        pack(values, direct_bits)
+    };
+    data.append(&mut VarInt::new(longs.len() as i32).bytes);
+    for long in longs {
+        data.extend_from_slice(&long.to_be_bytes());
+    }
+}
@@ -0,0 +1,589 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::thread::JoinHandle;
+use inbt::NbtTag;
+use log::{debug, trace, warn};
+use lru::LruCache;
+use mc_world_parser::Position;
+use crate::block_entity::BlockEntity;
+use crate::block_registry::BlockRegistry;
+use crate::block_storage::ChunkBlocks;
+use crate::error::ServerError;
+use crate::light::ChunkLight;
+use crate::light_engine::LightEngine;
+use crate::nbt_util::NbtTagExt;
+use crate::packet::PlayPacketClientBound;
+use crate::region::RegionStorage;
+use crate::server_util::ServerConnectionThreadBound;
+
+/// Amount of parsed chunks kept in memory
+const CHUNK_CACHE_SIZE: usize = 1024;
+/// Amount of encoded chunk packets kept in memory
+const PACKET_CACHE_SIZE: usize = 1024;
+
+/// A chunk together with its encoded Chunk Data and Update Light packet, which is the same for every player
+#[derive(Debug, Clone)]
+pub struct LoadedChunk {
+    pub dimension: String,
+    pub x: i32,
+    pub z: i32,
+    pub blocks: Arc<ChunkBlocks>,
+    pub block_entities: Arc<Vec<BlockEntity>>,
+    pub light: Arc<ChunkLight>,
+    pub packet: Arc<Vec<u8>>,
+}
+
+/// The parsed parts of a chunk that are kept in the cache
+#[derive(Debug, Clone)]
+struct ChunkContents {
+    blocks: Arc<ChunkBlocks>,
+    block_entities: Arc<Vec<BlockEntity>>,
+    /// Block entities the server couldn't read, written back as they were
+    unknown_block_entities: Arc<Vec<NbtTag>>,
+    light: Arc<ChunkLight>,
+}
+
+/// Where the chunks of a dimension are stored and how tall it is
+#[derive(Clone)]
+pub struct Dimension {
+    pub regions: RegionStorage,
+    /// Entities are stored in their own region files since 1.17
+    pub entities: RegionStorage,
+    pub min_y: i32,
+    pub height: i32,
+}
+
+impl Dimension {
+    /// `path` is the dimension's folder, the one containing `region` and `entities`
+    pub fn load(path: &str, min_y: i32, height: i32) -> Result<Self, ServerError> {
+        // Fails for dimensions that were never generated
+        fs::metadata(path)?;
+        Ok(Self {
+            regions: RegionStorage::new(Path::new(path).join("region")),
+            entities: RegionStorage::new(Path::new(path).join("entities")),
+            min_y,
+            height,
+        })
+    }
+
+    pub fn min_section(&self) -> i32 {
+        self.min_y.div_euclid(16)
+    }
+
+    pub fn section_count(&self) -> usize {
+        (self.height / 16) as usize
+    }
+
+    /// The chunk's NBT from its region file, `None` if it was never saved or can't be read
+    fn read_nbt(&self, x: i32, z: i32) -> Option<NbtTag> {
+        match self.regions.read_chunk(x, z) {
+            Ok(nbt) => nbt,
+            Err(err) => {
+                warn!("Could not read chunk {} {} from its region file: {}", x, z, err);
+                None
+            }
+        }
+    }
+
+    /// The chunk's blocks and the light stored with them, if the chunk was lit before it was saved
+    pub fn read_chunk(&self, x: i32, z: i32, block_registry: &BlockRegistry, biomes: &BTreeMap<String, i32>) -> Option<(ChunkBlocks, Option<ChunkLight>)> {
+        let nbt = self.read_nbt(x, z)?;
+        let blocks = ChunkBlocks::from_nbt(&nbt, self.min_section(), self.section_count(), block_registry, biomes);
+        Some((blocks, self.light_of(&nbt)))
+    }
+
+    fn light_of(&self, nbt: &NbtTag) -> Option<ChunkLight> {
+        (nbt.child("isLightOn").and_then(|l| l.as_i8()) == Some(1))
+            .then(|| ChunkLight::from_nbt(nbt, self.min_section(), self.section_count()))
+    }
+
+    /// Reads everything the server keeps of a chunk from one read of its NBT
+    fn load_chunk(&self, x: i32, z: i32, block_registry: &Arc<BlockRegistry>, block_entity_types: &BTreeMap<String, i32>, biomes: &BTreeMap<String, i32>) -> Option<ChunkContents> {
+        let nbt = self.read_nbt(x, z)?;
+        let mut blocks = ChunkBlocks::from_nbt(&nbt, self.min_section(), self.section_count(), block_registry, biomes);
+        blocks.read_heightmaps(&nbt);
+        let blocks = Arc::new(blocks);
+        let light = self.light_of(&nbt).unwrap_or_else(|| {
+            trace!("Lighting chunk {} {}", x, z);
+            let mut engine = LightEngine::new(block_registry);
+            engine.add_chunk(x, z, blocks.clone(), ChunkLight::new(self.min_section(), self.section_count()));
+            engine.light_chunk(x, z);
+            engine.remove_chunk(x, z).unwrap()
+        });
+        let (block_entities, unknown_block_entities) = BlockEntity::from_chunk_nbt(&nbt, block_entity_types);
+        Some(ChunkContents {
+            blocks,
+            block_entities: Arc::new(block_entities),
+            unknown_block_entities: Arc::new(unknown_block_entities),
+            light: Arc::new(light),
+        })
+    }
+
+    /// Writes a changed chunk back into its region file, over the NBT it was loaded from
+    fn save_chunk(&self, x: i32, z: i32, contents: &ChunkContents, block_registry: &BlockRegistry) -> Result<(), ServerError> {
+        let Some(mut nbt) = self.regions.read_chunk(x, z)? else {
+            return Err(ServerError::ChunkMissing(x, z));
+        };
+        let mut sections = nbt.child("sections").and_then(|s| s.as_list()).cloned().unwrap_or_default();
+        contents.blocks.write_sections(&mut sections, block_registry);
+        contents.light.write_sections(&mut sections);
+        nbt.set_child(NbtTag::List("sections".to_string(), sections));
+        if let NbtTag::Compound(_, heightmaps) = contents.blocks.heightmaps_nbt() {
+            nbt.set_child(NbtTag::Compound("Heightmaps".to_string(), heightmaps));
+        }
+        let block_entities = contents.block_entities.iter().map(|b| b.to_nbt()).chain(contents.unknown_block_entities.iter().cloned());
+        nbt.set_child(NbtTag::List("block_entities".to_string(), block_entities.collect()));
+        nbt.set_child(NbtTag::Byte("isLightOn".to_string(), 1));
+        self.regions.write_chunk(x, z, &nbt)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChunkKey {
+    pub dimension: String,
+    pub x: i32,
+    pub z: i32,
+}
+
+impl ChunkKey {
+    pub fn new<S: Into<String>>(dimension: S, pos: Position) -> Self {
+        Self {
+            dimension: dimension.into(),
+            x: pos.x,
+            z: pos.z,
+        }
+    }
+
+    /// The chunk containing a block
+    pub fn of_block<S: Into<String>>(dimension: S, block_x: i32, block_z: i32) -> Self {
+        Self {
+            dimension: dimension.into(),
+            x: block_x.div_euclid(16),
+            z: block_z.div_euclid(16),
+        }
+    }
+}
+
+/// A queued chunk load. Closer chunks are loaded first.
+#[derive(Debug, PartialEq, Eq)]
+struct ChunkRequest {
+    distance: Reverse<i32>,
+    /// Tie breaker so requests with the same distance are handled in order
+    sequence: Reverse<u64>,
+    key: ChunkKey,
+}
+
+impl PartialOrd for ChunkRequest {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ChunkRequest {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.distance, self.sequence).cmp(&(other.distance, other.sequence))
+    }
+}
+
+/// A block change for the workers to apply
+struct BlockEdit {
+    dimension: String,
+    x: i32,
+    y: i32,
+    z: i32,
+    block_state: i32,
+    /// The players in the dimension, who get the packets about the change for the chunks they have
+    recipients: Vec<Sender<ServerConnectionThreadBound>>,
+}
+
+/// What a worker does next
+enum Job {
+    Load { key: ChunkKey, cached_chunk: Option<ChunkContents>, version: u64 },
+    Edit(BlockEdit),
+}
+
+struct ChunkServiceState {
+    queue: BinaryHeap<ChunkRequest>,
+    next_sequence: u64,
+    /// Connections waiting for a chunk. A chunk only gets loaded once no matter how many players want it.
+    waiting: HashMap<ChunkKey, Vec<Sender<ServerConnectionThreadBound>>>,
+    loading: HashSet<ChunkKey>,
+    /// Parsed chunks, `None` for chunks that don't exist in the region files
+    cache: LruCache<ChunkKey, Option<ChunkContents>>,
+    /// Chunks that were changed, which can't be dropped from memory like the ones in `cache` until they are saved
+    edited: HashMap<ChunkKey, ChunkContents>,
+    packets: LruCache<ChunkKey, Arc<Vec<u8>>>,
+    /// Bumped when a chunk changes, so packets encoded from the old contents aren't cached. Dropped once the
+    /// chunk leaves memory, since nothing encoded from it can be around anymore.
+    versions: HashMap<ChunkKey, u64>,
+    /// Block changes waiting for a worker, in the order they were made
+    edits: VecDeque<BlockEdit>,
+    /// Whether a worker is applying a block change. They're applied one at a time so they land in order.
+    editing: bool,
+    shutdown: bool,
+}
+
+/// Loads chunks from the region files on a pool of worker threads, so players don't wait on each other.
+/// The chunk packets are encoded on the workers too and cached, so sending a chunk to more players is just a copy.
+pub struct ChunkService {
+    state: Arc<(Mutex<ChunkServiceState>, Condvar)>,
+    workers: Vec<JoinHandle<()>>,
+    dimensions: Arc<HashMap<String, Dimension>>,
+    block_registry: Arc<BlockRegistry>,
+}
+
+impl ChunkService {
+    /// `block_entity_types` is the `block_entity_type` registry, used to send the block entities of a chunk, and
+    /// `biomes` the ids of the `worldgen/biome` registry the client was sent
+    pub fn new(dimensions: HashMap<String, Dimension>, block_registry: Arc<BlockRegistry>, block_entity_types: Arc<BTreeMap<String, i32>>, biomes: Arc<BTreeMap<String, i32>>) -> Self {
+        let state = Arc::new((Mutex::new(ChunkServiceState {
+            queue: BinaryHeap::new(),
+            next_sequence: 0,
+            waiting: HashMap::new(),
+            loading: HashSet::new(),
+            cache: LruCache::new(NonZeroUsize::new(CHUNK_CACHE_SIZE).unwrap()),
+            packets: LruCache::new(NonZeroUsize::new(PACKET_CACHE_SIZE).unwrap()),
+            edited: HashMap::new(),
+            versions: HashMap::new(),
+            edits: VecDeque::new(),
+            editing: false,
+            shutdown: false,
+        }), Condvar::new()));
+        let dimensions = Arc::new(dimensions);
+
+        let worker_count = thread::available_parallelism().map(|n| n.get()).unwrap_or(2).min(8);
+        debug!("Starting {} chunk loading threads", worker_count);
+        let workers = (0..worker_count).map(|_| {
+            let state = state.clone();
+            let dimensions = dimensions.clone();
+            let block_registry = block_registry.clone();
+            let block_entity_types = block_entity_types.clone();
+            let biomes = biomes.clone();
+            thread::spawn(move || Self::worker(state, dimensions, block_registry, block_entity_types, biomes))
+        }).collect();
+
+        Self {
+            state,
+            workers,
+            dimensions,
+            block_registry,
+        }
+    }
+
+    /// Queues a chunk for the connection behind `sender`, which gets it as `ChunkData` once it's loaded.
+    /// `distance` is how far the chunk is from the player, in chunks.
+    pub fn request(&self, key: ChunkKey, distance: i32, sender: Sender<ServerConnectionThreadBound>) {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        match state.cached(&key) {
+            Some(None) => {
+                let _ = sender.send(ServerConnectionThreadBound::ChunkData(None));
+                return;
+            }
+            Some(Some(contents)) => {
+                if let Some(packet) = state.packets.get(&key) {
+                    let _ = sender.send(ServerConnectionThreadBound::ChunkData(Some(LoadedChunk { dimension: key.dimension.clone(), x: key.x, z: key.z, blocks: contents.blocks, block_entities: contents.block_entities, light: contents.light, packet: packet.clone() })));
+                    return;
+                }
+            }
+            None => {}
+        }
+
+        let already_queued = state.waiting.contains_key(&key);
+        state.waiting.entry(key.clone()).or_default().push(sender);
+        if already_queued && state.loading.contains(&key) {
+            return;
+        }
+        // Queued again even if it's already in the queue, in case this player is closer. The other entry gets skipped.
+        state.queue_request(key, distance);
+        condvar.notify_one();
+    }
+
+    pub fn dimension(&self, name: &str) -> Option<&Dimension> {
+        self.dimensions.get(name)
+    }
+
+    /// State id at a block, if its chunk is in memory. Doesn't load anything, so it's cheap enough for every player move.
+    pub fn block_at(&self, dimension: &str, x: i32, y: i32, z: i32) -> Option<i32> {
+        let (lock, _) = &*self.state;
+        let contents = lock.lock().unwrap().cached(&ChunkKey::of_block(dimension, x, z))??;
+        Some(contents.blocks.get(x.rem_euclid(16), y, z.rem_euclid(16)))
+    }
+
+    /// Queues a block change for the workers, which relight around it and send the Update Light packets for the
+    /// chunks whose light changed, and Block Entity Data if the block keeps its block entity, to `recipients`.
+    /// Changes are applied in the order they were queued.
+    pub fn set_block(&self, dimension: &str, x: i32, y: i32, z: i32, block_state: i32, recipients: Vec<Sender<ServerConnectionThreadBound>>) {
+        let (lock, condvar) = &*self.state;
+        lock.lock().unwrap().edits.push_back(BlockEdit { dimension: dimension.to_string(), x, y, z, block_state, recipients });
+        condvar.notify_one();
+    }
+
+    /// Applies a block change on a worker. Only one worker applies changes at a time, the one that set `editing`.
+    fn apply_edit(lock: &Mutex<ChunkServiceState>, condvar: &Condvar, dimensions: &HashMap<String, Dimension>, block_registry: &Arc<BlockRegistry>, block_entity_types: &BTreeMap<String, i32>, biomes: &BTreeMap<String, i32>, edit: BlockEdit) {
+        let center = ChunkKey::of_block(edit.dimension.clone(), edit.x, edit.z);
+        // Light can spread into the neighbouring chunks
+        let keys = (-1..=1).flat_map(|dx| (-1..=1).map(move |dz| (dx, dz)))
+            .map(|(dx, dz)| ChunkKey { dimension: center.dimension.clone(), x: center.x + dx, z: center.z + dz })
+            .collect::<Vec<_>>();
+
+        // Chunks that aren't cached are loaded without holding the lock, so the other workers can keep going
+        let missing = {
+            let mut state = lock.lock().unwrap();
+            keys.iter().filter(|key| state.cached(key).is_none()).cloned().collect::<Vec<_>>()
+        };
+        let loaded = missing.into_iter()
+            .map(|key| {
+                let contents = dimensions.get(&key.dimension).and_then(|dimension| dimension.load_chunk(key.x, key.z, block_registry, block_entity_types, biomes));
+                (key, contents)
+            })
+            .collect::<Vec<_>>();
+        let mut area = {
+            let mut state = lock.lock().unwrap();
+            for (key, contents) in loaded {
+                if state.cached(&key).is_none() {
+                    state.cache_put(key, contents);
+                }
+            }
+            keys.iter()
+                .filter_map(|key| Some(((key.x, key.z), state.cached(key).flatten()?)))
+                .collect::<BTreeMap<_, _>>()
+        };
+
+        // The block is changed and relit on copies, so the lock isn't held for it. Nothing else changes chunks
+        // in the meantime, since changes are applied one at a time.
+        let packets = Self::change_block(block_registry, &center, &mut area, edit.x, edit.y, edit.z, edit.block_state).unwrap_or_default();
+
+        let mut state = lock.lock().unwrap();
+        for key in &keys {
+            let light_changed = packets.iter().any(|(changed, _)| changed == key);
+            if *key != center && !light_changed {
+                continue;
+            }
+            if let Some(contents) = area.remove(&(key.x, key.z)) {
+                state.invalidate(key.clone(), contents);
+            }
+        }
+        // Sent before the next change can start, so players get the packets of the changes in order
+        for (key, packet) in packets {
+            let packet = Arc::new(packet);
+            for recipient in &edit.recipients {
+                let _ = recipient.send(ServerConnectionThreadBound::ChunkPacket { chunk_x: key.x, chunk_z: key.z, packet: packet.clone() });
+            }
+        }
+        state.editing = false;
+        if state.edits.is_empty() {
+            // For `save`, which waits for all changes
+            condvar.notify_all();
+        }
+    }
+
+    /// Changes a block in `area`, the chunks around it by position, and relights them. `None` if the block's chunk
+    /// doesn't exist or `y` is outside the world.
+    fn change_block(block_registry: &BlockRegistry, center: &ChunkKey, area: &mut BTreeMap<(i32, i32), ChunkContents>, x: i32, y: i32, z: i32, block_state: i32) -> Option<Vec<(ChunkKey, Vec<u8>)>> {
+        let center_contents = area.get_mut(&(center.x, center.z))?;
+        let old_state = Arc::make_mut(&mut center_contents.blocks).set(x.rem_euclid(16), y, z.rem_euclid(16), block_state, block_registry)?;
+
+        // A block entity stays when only the state of its block changes, like a chest turning around
+        let mut packets = vec![];
+        let block_name = |state| block_registry.state(state).map(|(name, _)| name.to_string());
+        let same_block = block_name(old_state) == block_name(block_state);
+        if let Some(index) = center_contents.block_entities.iter().position(|b| (b.x, b.y, b.z) == (x, y, z)) {
+            if same_block {
+                packets.push((center.clone(), PlayPacketClientBound::block_entity_data(&center_contents.block_entities[index])));
+            } else {
+                Arc::make_mut(&mut center_contents.block_entities).remove(index);
+            }
+        }
+        let position_of = |tag: &NbtTag| ["x", "y", "z"].map(|name| tag.child(name).and_then(|v| v.as_i32()));
+        if !same_block && center_contents.unknown_block_entities.iter().any(|tag| position_of(tag) == [Some(x), Some(y), Some(z)]) {
+            Arc::make_mut(&mut center_contents.unknown_block_entities).retain(|tag| position_of(tag) != [Some(x), Some(y), Some(z)]);
+        }
+
+        let mut engine = LightEngine::new(block_registry);
+        for ((chunk_x, chunk_z), contents) in area.iter() {
+            engine.add_chunk(*chunk_x, *chunk_z, contents.blocks.clone(), (*contents.light).clone());
+        }
+        engine.block_changed(x, y, z);
+        for (chunk_x, chunk_z, light, sections) in engine.into_changed() {
+            packets.push((ChunkKey { dimension: center.dimension.clone(), x: chunk_x, z: chunk_z }, PlayPacketClientBound::update_light(chunk_x, chunk_z, &light.only_sections(&sections))));
+            if let Some(contents) = area.get_mut(&(chunk_x, chunk_z)) {
+                contents.light = Arc::new(light);
+            }
+        }
+        Some(packets)
+    }
+
+    /// Writes the changed chunks back to their region files, after which they can leave memory like any other
+    /// chunk. Waits for the queued block changes first. The files are written without holding the lock.
+    /// Returns how many chunks couldn't be saved.
+    pub fn save(&self) -> usize {
+        let (lock, condvar) = &*self.state;
+        let edited = {
+            let mut state = lock.lock().unwrap();
+            while !state.edits.is_empty() || state.editing {
+                state = condvar.wait(state).unwrap();
+            }
+            // Waiting may have taken a wake up meant for an idle worker
+            condvar.notify_all();
+            state.edited.iter().map(|(key, contents)| (key.clone(), contents.clone(), state.version(key))).collect::<Vec<_>>()
+        };
+        let mut failed = 0;
+        let mut saved = vec![];
+        for (key, contents, version) in edited {
+            let Some(dimension) = self.dimensions.get(&key.dimension) else {
+                continue;
+            };
+            match dimension.save_chunk(key.x, key.z, &contents, &self.block_registry) {
+                Ok(()) => saved.push((key, version)),
+                Err(err) => {
+                    warn!("Could not save chunk {} {} in {}: {}", key.x, key.z, key.dimension, err);
+                    failed += 1;
+                }
+            }
+        }
+        let mut state = lock.lock().unwrap();
+        for (key, version) in saved {
+            // Chunks that changed again while they were written stay until the next save
+            if state.version(&key) != version {
+                continue;
+            }
+            if let Some(contents) = state.edited.remove(&key) {
+                state.cache_put(key, Some(contents));
+            }
+        }
+        failed
+    }
+
+    fn worker(state: Arc<(Mutex<ChunkServiceState>, Condvar)>, dimensions: Arc<HashMap<String, Dimension>>, block_registry: Arc<BlockRegistry>, block_entity_types: Arc<BTreeMap<String, i32>>, biomes: Arc<BTreeMap<String, i32>>) {
+        let (lock, condvar) = &*state;
+        loop {
+            let job = {
+                let mut state = lock.lock().unwrap();
+                loop {
+                    if state.shutdown {
+                        return;
+                    }
+                    // Block changes go first, players see them right away
+                    if !state.editing {
+                        if let Some(edit) = state.edits.pop_front() {
+                            state.editing = true;
+                            break Job::Edit(edit);
+                        }
+                    }
+                    match state.queue.pop() {
+                        // Skip requests that were already handled or are being handled by another thread
+                        Some(request) if !state.waiting.contains_key(&request.key) || state.loading.contains(&request.key) => continue,
+                        Some(request) => {
+                            state.loading.insert(request.key.clone());
+                            let cached_chunk = state.cached(&request.key).flatten();
+                            let version = state.version(&request.key);
+                            break Job::Load { key: request.key, cached_chunk, version };
+                        }
+                        None => state = condvar.wait(state).unwrap(),
+                    }
+                }
+            };
+            let (key, cached_chunk, version) = match job {
+                Job::Load { key, cached_chunk, version } => (key, cached_chunk, version),
+                Job::Edit(edit) => {
+                    Self::apply_edit(lock, condvar, &dimensions, &block_registry, &block_entity_types, &biomes, edit);
+                    continue;
+                }
+            };
+
+            let contents = cached_chunk.or_else(|| {
+                trace!("Loading chunk {:?}", key);
+                dimensions.get(&key.dimension).and_then(|dimension| dimension.load_chunk(key.x, key.z, &block_registry, &block_entity_types, &biomes))
+            });
+            let loaded = contents.clone().map(|contents| LoadedChunk {
+                dimension: key.dimension.clone(),
+                x: key.x,
+                z: key.z,
+                packet: Arc::new(PlayPacketClientBound::chunk_data(key.x, key.z, &contents.blocks, &contents.block_entities, &contents.light, &block_registry)),
+                blocks: contents.blocks,
+                block_entities: contents.block_entities,
+                light: contents.light,
+            });
+
+            let mut state = lock.lock().unwrap();
+            state.loading.remove(&key);
+            if state.version(&key) != version {
+                // The chunk changed while it was encoded, so do it again from the changed contents
+                state.queue_request(key, 0);
+                condvar.notify_one();
+                continue;
+            }
+            if !state.edited.contains_key(&key) {
+                state.cache_put(key.clone(), contents);
+            }
+            if let Some(loaded) = &loaded {
+                state.packets.put(key.clone(), loaded.packet.clone());
+            }
+            for sender in state.waiting.remove(&key).unwrap_or_default() {
+                let _ = sender.send(ServerConnectionThreadBound::ChunkData(loaded.clone()));
+            }
+        }
+    }
+}
+
+impl ChunkServiceState {
+    /// Returns `None` if the chunk isn't in memory, and `Some(None)` if it doesn't exist
+    fn cached(&mut self, key: &ChunkKey) -> Option<Option<ChunkContents>> {
+        match self.edited.get(key) {
+            Some(contents) => Some(Some(contents.clone())),
+            None => self.cache.get(key).cloned(),
+        }
+    }
+
+    /// Caches a chunk, dropping the version of the chunk that leaves memory to make room
+    fn cache_put(&mut self, key: ChunkKey, contents: Option<ChunkContents>) {
+        let Some((evicted, _)) = self.cache.push(key, contents) else {
+            return;
+        };
+        // A worker still encoding the chunk compares the version when it's done
+        if !self.cache.contains(&evicted) && !self.edited.contains_key(&evicted) && !self.loading.contains(&evicted) {
+            self.versions.remove(&evicted);
+        }
+    }
+
+    /// Counts the changes to a chunk
+    fn version(&self, key: &ChunkKey) -> u64 {
+        self.versions.get(key).copied().unwrap_or(0)
+    }
+
+    /// Replaces the contents of a chunk after it changed. They're kept until saved, and the encoded packet is dropped.
+    fn invalidate(&mut self, key: ChunkKey, contents: ChunkContents) {
+        self.cache.pop(&key);
+        self.packets.pop(&key);
+        *self.versions.entry(key.clone()).or_default() += 1;
+        self.edited.insert(key, contents);
+    }
+
+    fn queue_request(&mut self, key: ChunkKey, distance: i32) {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.queue.push(ChunkRequest {
+            distance: Reverse(distance),
+            sequence: Reverse(sequence),
+            key,
+        });
+    }
+}
+
+impl Drop for ChunkService {
+    fn drop(&mut self) {
+        let (lock, condvar) = &*self.state;
+        lock.lock().unwrap().shutdown = true;
+        condvar.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+use crate::light::OPAQUE_BLOCK_TYPES;
+
+/// An axis aligned box, in blocks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub min_z: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+    pub max_z: f64,
+}
+
+impl Aabb {
+    pub const FULL_BLOCK: Aabb = Aabb::new(0.0, 0.0, 0.0, 1.0, 1.0, 1.0);
+
+    pub const fn new(min_x: f64, min_y: f64, min_z: f64, max_x: f64, max_y: f64, max_z: f64) -> Self {
+        Self { min_x, min_y, min_z, max_x, max_y, max_z }
+    }
+
+    /// The box of an entity with its feet centered at `x`, `y`, `z`
+    pub fn of_entity(x: f64, y: f64, z: f64, width: f64, height: f64) -> Self {
+        Self::new(x - width / 2.0, y, z - width / 2.0, x + width / 2.0, y + height, z + width / 2.0)
+    }
+
+    /// A box in block pixels, like vanilla's `Block.box`
+    const fn pixels(min_x: f64, min_y: f64, min_z: f64, max_x: f64, max_y: f64, max_z: f64) -> Self {
+        Self::new(min_x / 16.0, min_y / 16.0, min_z / 16.0, max_x / 16.0, max_y / 16.0, max_z / 16.0)
+    }
+
+    pub fn offset(self, x: f64, y: f64, z: f64) -> Self {
+        Self::new(self.min_x + x, self.min_y + y, self.min_z + z, self.max_x + x, self.max_y + y, self.max_z + z)
+    }
+
+    /// Makes the box smaller on every side
+    pub fn deflate(self, amount: f64) -> Self {
+        Self::new(self.min_x + amount, self.min_y + amount, self.min_z + amount, self.max_x - amount, self.max_y - amount, self.max_z - amount)
+    }
+
+    /// Boxes that only touch don't intersect
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min_x < other.max_x && self.max_x > other.min_x
+            && self.min_y < other.max_y && self.max_y > other.min_y
+            && self.min_z < other.max_z && self.max_z > other.min_z
+    }
+
+    /// Every block position the box reaches into. Starts one block lower, since fences and walls stick out of
+    /// the top of their block.
+    pub fn blocks(&self) -> impl Iterator<Item = (i32, i32, i32)> {
+        let (min_x, min_y, min_z) = (self.min_x.floor() as i32, self.min_y.floor() as i32 - 1, self.min_z.floor() as i32);
+        let (max_x, max_y, max_z) = (self.max_x.floor() as i32, self.max_y.floor() as i32, self.max_z.floor() as i32);
+        (min_x..=max_x).flat_map(move |x| (min_y..=max_y).flat_map(move |y| (min_z..=max_z).map(move |z| (x, y, z))))
+    }
+}
+
+/// The part of a block state that entities can't move through, relative to the block. Vanilla has exact shapes,
+/// which the data reports don't include, so this goes by block type. Blocks with a shape that isn't known here
+/// have none, so movement checks only reject moves that are clearly wrong.
+pub fn collision_box_of(block_type: &str, name: &str, properties: &BTreeMap<String, String>) -> Option<Aabb> {
+    let property = |key: &str| properties.get(key).map(|v| &**v);
+    let half = |top: bool| if top { Aabb::pixels(0.0, 8.0, 0.0, 16.0, 16.0, 16.0) } else { Aabb::pixels(0.0, 0.0, 0.0, 16.0, 8.0, 16.0) };
+    match name {
+        "minecraft:farmland" | "minecraft:dirt_path" => return Some(Aabb::pixels(0.0, 0.0, 0.0, 16.0, 15.0, 16.0)),
+        "minecraft:soul_sand" | "minecraft:mud" => return Some(Aabb::pixels(0.0, 0.0, 0.0, 16.0, 14.0, 16.0)),
+        "minecraft:honey_block" => return Some(Aabb::pixels(1.0, 0.0, 1.0, 15.0, 15.0, 15.0)),
+        "minecraft:cactus" => return Some(Aabb::pixels(1.0, 0.0, 1.0, 15.0, 15.0, 15.0)),
+        "minecraft:glass" | "minecraft:tinted_glass" | "minecraft:ice" | "minecraft:packed_ice" | "minecraft:blue_ice"
+        | "minecraft:slime_block" | "minecraft:glowstone" | "minecraft:sea_lantern" | "minecraft:beacon" | "minecraft:spawner"
+        | "minecraft:trial_spawner" | "minecraft:vault" => return Some(Aabb::FULL_BLOCK),
+        _ => {}
+    }
+    match block_type {
+        "minecraft:slab" => match property("type") {
+            Some("double") => Some(Aabb::FULL_BLOCK),
+            Some(half_type) => Some(half(half_type == "top")),
+            None => None,
+        },
+        // Only the half that is always there, not the step
+        "minecraft:stair" => Some(half(property("half") == Some("top"))),
+        "minecraft:carpet" | "minecraft:wool_carpet" => Some(Aabb::pixels(0.0, 0.0, 0.0, 16.0, 1.0, 16.0)),
+        "minecraft:snow_layer" => {
+            let layers = property("layers").and_then(|l| l.parse::<f64>().ok()).unwrap_or(1.0);
+            (layers > 1.0).then(|| Aabb::pixels(0.0, 0.0, 0.0, 16.0, (layers - 1.0) * 2.0, 16.0))
+        }
+        // Only the post, not the sides that connect to other blocks
+        "minecraft:fence" | "minecraft:wall" => Some(Aabb::pixels(6.0, 0.0, 6.0, 10.0, 24.0, 10.0)),
+        "minecraft:fence_gate" if property("open") == Some("false") => Some(Aabb::pixels(6.0, 0.0, 6.0, 10.0, 24.0, 10.0)),
+        "minecraft:iron_bars" | "minecraft:stained_glass_pane" => Some(Aabb::pixels(7.0, 0.0, 7.0, 9.0, 16.0, 9.0)),
+        "minecraft:bed" => Some(Aabb::pixels(0.0, 0.0, 0.0, 16.0, 9.0, 16.0)),
+        "minecraft:chest" | "minecraft:trapped_chest" | "minecraft:ender_chest" => Some(Aabb::pixels(1.0, 0.0, 1.0, 15.0, 14.0, 15.0)),
+        "minecraft:stained_glass" | "minecraft:transparent" => Some(Aabb::FULL_BLOCK),
+        _ if block_type.ends_with("leaves") => Some(Aabb::FULL_BLOCK),
+        _ if OPAQUE_BLOCK_TYPES.contains(&block_type) => Some(Aabb::FULL_BLOCK),
+        _ => None,
+    }
+}
+
+/// Whether `to` overlaps a block that `from` doesn't, so entities that are stuck in a block can still get out.
+/// `collision_at` gives the collision box of the block at a position, relative to the block.
+pub fn moves_into_block(from: &Aabb, to: &Aabb, collision_at: impl Fn(i32, i32, i32) -> Option<Aabb>) -> bool {
+    to.blocks().any(|(x, y, z)| {
+        collision_at(x, y, z)
+            .map(|shape| shape.offset(x as f64, y as f64, z as f64))
+            .is_some_and(|shape| shape.intersects(to) && !shape.intersects(from))
+    })
+}
@@ -1,3 +1,6 @@
+/// Arguments of /gamemode, the index is the game mode's id
+pub const GAME_MODES: [&str; 4] = ["survival", "creative", "adventure", "spectator"];
+
 #[derive(Debug)]
 #[repr(u8)]
 pub enum CommandNodeType {
@@ -12,6 +15,17 @@ pub enum CommandParsers {
     Float { min: Option<f32>, max: Option<f32> },
     Double { min: Option<f64>, max: Option<f64> },
     Integer { min: Option<i32>, max: Option<i32> },
+    String(StringParserType),
+    BlockPos,
+    BlockState,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(i32)]
+pub enum StringParserType {
+    SingleWord = 0,
+    QuotablePhrase = 1,
+    GreedyPhrase = 2,
 }
 
 impl CommandParsers {
@@ -21,12 +35,15 @@ impl CommandParsers {
             CommandParsers::Float { .. } => 1,
             CommandParsers::Double { .. } => 2,
             CommandParsers::Integer { .. } => 3,
+            CommandParsers::String(_) => 5,
+            CommandParsers::BlockPos => 8,
+            CommandParsers::BlockState => 12,
         }
     }
 
     pub fn properties(&self) -> Vec<u8> {
         match self {
-            CommandParsers::Bool => vec![],
+            CommandParsers::Bool | CommandParsers::BlockPos | CommandParsers::BlockState => vec![],
             CommandParsers::Float { min, max } => {
                 let flags = 0x1 * min.is_some() as u8 | 0x2 * max.is_some() as u8;
                 let mut bytes = vec![flags];
@@ -60,6 +77,10 @@ impl CommandParsers {
                 }
                 bytes
             }
+            CommandParsers::String(string_type) => {
+                // The string type is a VarInt, but all of them fit in a single byte
+                vec![*string_type as u8]
+            }
         }
     }
 }
@@ -92,6 +113,42 @@ impl CommandNode {
         commands.push(Self::argument("id", true, CommandParsers::Integer { min: Some(0), max: None }, None, None));
         commands.get_mut(0).unwrap().children.push(1);
         commands.get_mut(1).unwrap().children.push(2);
+
+        commands.push(Self::literal("datapack", false, None, None));
+        commands.push(Self::literal("list", true, None, None));
+        commands.push(Self::literal("enable", false, None, None));
+        commands.push(Self::argument("name", true, CommandParsers::String(StringParserType::GreedyPhrase), None, None));
+        commands.push(Self::literal("disable", false, None, None));
+        commands.push(Self::argument("name", true, CommandParsers::String(StringParserType::GreedyPhrase), None, None));
+        commands.get_mut(0).unwrap().children.push(3);
+        commands.get_mut(3).unwrap().children.append(&mut vec![4, 5, 7]);
+        commands.get_mut(5).unwrap().children.push(6);
+        commands.get_mut(7).unwrap().children.push(8);
+
+        commands.push(Self::literal("reload", true, None, None));
+        commands.get_mut(0).unwrap().children.push(9);
+
+        commands.push(Self::literal("setblock", false, None, None));
+        commands.push(Self::argument("pos", false, CommandParsers::BlockPos, None, None));
+        commands.push(Self::argument("block", true, CommandParsers::BlockState, None, None));
+        commands.get_mut(0).unwrap().children.push(10);
+        commands.get_mut(10).unwrap().children.push(11);
+        commands.get_mut(11).unwrap().children.push(12);
+
+        commands.push(Self::literal("dimension", false, None, None));
+        commands.push(Self::argument("dimension", true, CommandParsers::String(StringParserType::GreedyPhrase), None, None));
+        commands.get_mut(0).unwrap().children.push(13);
+        commands.get_mut(13).unwrap().children.push(14);
+
+        commands.push(Self::literal("save-all", true, None, None));
+        commands.get_mut(0).unwrap().children.push(15);
+
+        commands.push(Self::literal("gamemode", false, None, None));
+        commands.get_mut(0).unwrap().children.push(16);
+        for (i, game_mode) in GAME_MODES.iter().enumerate() {
+            commands.push(Self::literal(*game_mode, true, None, None));
+            commands.get_mut(16).unwrap().children.push(17 + i as i32);
+        }
         commands
     }
 
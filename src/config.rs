@@ -0,0 +1,64 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use crate::error::ServerError;
+
+/// Settings from `server.properties`, in vanilla's `key=value` format. Keys that are missing keep their defaults.
+#[derive(Debug, Clone, Default)]
+pub struct ServerConfig {
+    /// Shown above the player list, `\n` starts a new line
+    pub tab_list_header: String,
+    /// Shown below the player list
+    pub tab_list_footer: String,
+}
+
+impl ServerConfig {
+    /// A missing file gives the defaults
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ServerError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self::from_properties(&Self::parse_properties(&text)))
+    }
+
+    pub fn from_properties(properties: &BTreeMap<String, String>) -> Self {
+        let string = |key: &str| properties.get(key).cloned().unwrap_or_default();
+        Self {
+            tab_list_header: string("tab-list-header"),
+            tab_list_footer: string("tab-list-footer"),
+        }
+    }
+
+    /// Reads `key=value` lines, skipping comments starting with `#` or `!`. Values can use `\n`, `\t` and `\\`.
+    pub fn parse_properties(text: &str) -> BTreeMap<String, String> {
+        text.lines()
+            .map(|line| line.trim_start())
+            .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+            .filter_map(|line| {
+                let (key, value) = line.split_once(['=', ':'])?;
+                Some((key.trim().to_string(), unescape(value.trim_start())))
+            })
+            .collect()
+    }
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(other) => unescaped.push(other),
+            None => {}
+        }
+    }
+    unescaped
+}
@@ -0,0 +1,293 @@
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use walkdir::WalkDir;
+use crate::error::ServerError;
+use crate::nbt_util::{read_gzip_file, NbtTagExt};
+use crate::packet::KnownPack;
+
+/// Data pack format of 1.21
+pub const DATA_PACK_FORMAT: i32 = 48;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum SupportedFormats {
+    Single(i32),
+    Range([i32; 2]),
+    Object { min_inclusive: i32, max_inclusive: i32 },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct PackMetadataSection {
+    pack_format: i32,
+    #[serde(default)]
+    description: serde_json::Value,
+    supported_formats: Option<SupportedFormats>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct PackMetadata {
+    pack: PackMetadataSection,
+}
+
+#[derive(Debug, Clone)]
+pub enum DataPackSource {
+    Directory(PathBuf),
+    Zip(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+pub struct DataPack {
+    pub id: String,
+    pub description: String,
+    source: DataPackSource,
+    min_format: i32,
+    max_format: i32,
+    /// Set for packs that ship with the client, so their registry data doesn't have to be sent
+    known_pack: Option<KnownPack>,
+}
+
+impl DataPack {
+    /// The generated vanilla data, which has no pack.mcmeta
+    pub fn vanilla<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            id: "vanilla".to_string(),
+            description: "The default data for Minecraft".to_string(),
+            source: DataPackSource::Directory(path.as_ref().to_path_buf()),
+            min_format: DATA_PACK_FORMAT,
+            max_format: DATA_PACK_FORMAT,
+            known_pack: Some(KnownPack {
+                namespace: "minecraft".to_string(),
+                id: "core".to_string(),
+                version: "1.21".to_string(),
+            }),
+        }
+    }
+
+    pub fn load(id: String, source: DataPackSource, known_pack: Option<KnownPack>) -> Result<Self, ServerError> {
+        let mcmeta = match &source {
+            DataPackSource::Directory(path) => fs::read_to_string(path.join("pack.mcmeta"))?,
+            DataPackSource::Zip(path) => {
+                let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+                let mut mcmeta = String::new();
+                archive.by_name("pack.mcmeta")?.read_to_string(&mut mcmeta)?;
+                mcmeta
+            }
+        };
+        let metadata: PackMetadata = serde_json::from_str(&mcmeta)?;
+        let (min_format, max_format) = match metadata.pack.supported_formats {
+            Some(SupportedFormats::Single(format)) => (format, format),
+            Some(SupportedFormats::Range([min, max])) => (min, max),
+            Some(SupportedFormats::Object { min_inclusive, max_inclusive }) => (min_inclusive, max_inclusive),
+            None => (metadata.pack.pack_format, metadata.pack.pack_format),
+        };
+        let description = match metadata.pack.description {
+            serde_json::Value::String(s) => s,
+            serde_json::Value::Null => "".to_string(),
+            other => other.to_string(),
+        };
+
+        Ok(Self {
+            id,
+            description,
+            source,
+            min_format,
+            max_format,
+            known_pack,
+        })
+    }
+
+    pub fn is_compatible(&self) -> bool {
+        (self.min_format..=self.max_format).contains(&DATA_PACK_FORMAT)
+    }
+
+    pub fn known_pack(&self) -> Option<&KnownPack> {
+        self.known_pack.as_ref()
+    }
+
+    /// Reads all json files under the pack's `data` directory that pass the filter.
+    /// Paths are relative to `data`, e.g. `minecraft/dimension_type/overworld.json`.
+    pub fn data_files<F: Fn(&Path) -> bool>(&self, filter: F) -> Result<Vec<(PathBuf, Vec<u8>)>, ServerError> {
+        let mut files = vec![];
+        match &self.source {
+            DataPackSource::Directory(path) => {
+                let data_dir = path.join("data");
+                for entry in WalkDir::new(&data_dir).sort_by_file_name().into_iter().filter_map(|e| e.ok()).filter(|e| e.path().extension().eq(&Some(&*OsString::from("json")))) {
+                    let relative_path = entry.path().strip_prefix(&data_dir)?;
+                    if filter(relative_path) {
+                        files.push((relative_path.to_path_buf(), fs::read(entry.path())?));
+                    }
+                }
+            }
+            DataPackSource::Zip(path) => {
+                let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+                for i in 0..archive.len() {
+                    let mut file = archive.by_index(i)?;
+                    if file.is_dir() || !file.name().ends_with(".json") {
+                        continue;
+                    }
+                    let Some(relative_path) = file.name().strip_prefix("data/").map(PathBuf::from) else {
+                        continue;
+                    };
+                    if filter(&relative_path) {
+                        let mut data = vec![];
+                        file.read_to_end(&mut data)?;
+                        files.push((relative_path, data));
+                    }
+                }
+                files.sort_by(|(p1, _), (p2, _)| p1.cmp(p2));
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Keeps track of every data pack that can be loaded and which of them are enabled
+pub struct DataPackManager {
+    vanilla_path: PathBuf,
+    world_path: PathBuf,
+    available: Vec<DataPack>,
+    /// Enabled pack ids from lowest to highest priority
+    enabled: Vec<String>,
+    disabled: Vec<String>,
+}
+
+impl DataPackManager {
+    /// Finds the packs in `<vanilla>/data/minecraft/datapacks` and `<world>/datapacks` and enables them according to the
+    /// world's level.dat. Packs in the world folder that aren't disabled get enabled automatically, like in vanilla.
+    /// Packs that can't be read are skipped.
+    pub fn discover<P: AsRef<Path>, W: AsRef<Path>>(vanilla_path: P, world_path: W) -> Self {
+        let (enabled, disabled) = Self::read_selection(world_path.as_ref().join("level.dat"));
+        let mut manager = Self {
+            vanilla_path: vanilla_path.as_ref().to_path_buf(),
+            world_path: world_path.as_ref().to_path_buf(),
+            available: vec![],
+            enabled,
+            disabled,
+        };
+        manager.rescan();
+        manager
+    }
+
+    fn read_selection(level_dat: PathBuf) -> (Vec<String>, Vec<String>) {
+        let level = match read_gzip_file(&level_dat) {
+            Ok(level) => level,
+            Err(err) => {
+                warn!("Could not read data pack selection from {}: {}", level_dat.display(), err);
+                return (vec!["vanilla".to_string()], vec![]);
+            }
+        };
+        let data_packs = level.child("Data").and_then(|d| d.child("DataPacks"));
+        let read_list = |name: &str| data_packs
+            .and_then(|d| d.child(name))
+            .and_then(|l| l.as_list())
+            .map(|l| l.iter().filter_map(|p| p.as_str().map(|s| s.to_string())).collect::<Vec<_>>())
+            .unwrap_or_default();
+        (read_list("Enabled"), read_list("Disabled"))
+    }
+
+    /// Looks for new or removed packs on disk
+    pub fn rescan(&mut self) {
+        let mut available = vec![DataPack::vanilla(&self.vanilla_path)];
+
+        // Feature packs like bundle and trade_rebalance that are built into the game
+        let builtin_dir = self.vanilla_path.join("data/minecraft/datapacks");
+        for entry in fs::read_dir(&builtin_dir).into_iter().flatten().filter_map(|e| e.ok()) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let known_pack = KnownPack { namespace: "minecraft".to_string(), id: name.clone(), version: "1.21".to_string() };
+            match DataPack::load(name.clone(), DataPackSource::Directory(entry.path()), Some(known_pack)) {
+                Ok(pack) => available.push(pack),
+                Err(err) => warn!("Skipping built in data pack {}: {}", name, err),
+            }
+        }
+
+        let mut new_packs = vec![];
+        let datapacks_dir = self.world_path.join("datapacks");
+        for entry in fs::read_dir(&datapacks_dir).into_iter().flatten().filter_map(|e| e.ok()) {
+            let path = entry.path();
+            let id = "file/".to_string() + &*entry.file_name().to_string_lossy();
+            let source = if path.is_dir() {
+                DataPackSource::Directory(path)
+            } else if path.extension().eq(&Some(&*OsString::from("zip"))) {
+                DataPackSource::Zip(path)
+            } else {
+                continue;
+            };
+            match DataPack::load(id.clone(), source, None) {
+                Ok(pack) => {
+                    if !pack.is_compatible() {
+                        warn!("Data pack {} was made for a different version of Minecraft (pack format {}-{}, expected {})", id, pack.min_format, pack.max_format, DATA_PACK_FORMAT);
+                    }
+                    if !self.enabled.contains(&id) && !self.disabled.contains(&id) && pack.is_compatible() {
+                        new_packs.push(id.clone());
+                    }
+                    available.push(pack);
+                }
+                Err(err) => warn!("Skipping data pack {}: {}", id, err),
+            }
+        }
+        available.sort_by(|p1, p2| p1.id.cmp(&p2.id));
+        for id in new_packs {
+            info!("Found new data pack {}, enabling it", id);
+            self.enabled.push(id);
+        }
+
+        self.available = available;
+        if !self.enabled.iter().any(|id| id == "vanilla") {
+            self.enabled.insert(0, "vanilla".to_string());
+        }
+        for id in &self.enabled {
+            if !self.available.iter().any(|p| &p.id == id) {
+                warn!("Enabled data pack {} is missing", id);
+            }
+        }
+        debug!("Enabled data packs: {:?}", self.enabled);
+    }
+
+    pub fn available_ref(&self) -> &Vec<DataPack> {
+        &self.available
+    }
+
+    pub fn enabled_ids_ref(&self) -> &Vec<String> {
+        &self.enabled
+    }
+
+    pub fn disabled_ids_ref(&self) -> &Vec<String> {
+        &self.disabled
+    }
+
+    /// Enabled packs that exist on disk, from lowest to highest priority
+    pub fn enabled_packs(&self) -> Vec<&DataPack> {
+        self.enabled.iter().filter_map(|id| self.available.iter().find(|p| &p.id == id)).collect()
+    }
+
+    /// Enables a pack on top of the others. Returns a message explaining why if it can't be enabled.
+    pub fn enable(&mut self, id: &str) -> Result<(), String> {
+        let pack = self.available.iter().find(|p| p.id == id).ok_or(format!("Unknown data pack '{id}'"))?;
+        if self.enabled.iter().any(|e| e == id) {
+            return Err(format!("Pack '{id}' is already enabled!"));
+        }
+        if !pack.is_compatible() {
+            warn!("Enabling data pack {} which is not compatible with this version", id);
+        }
+        self.disabled.retain(|d| d != id);
+        self.enabled.push(id.to_string());
+        Ok(())
+    }
+
+    pub fn disable(&mut self, id: &str) -> Result<(), String> {
+        if id == "vanilla" {
+            return Err("The vanilla pack can't be disabled".to_string());
+        }
+        if !self.enabled.iter().any(|e| e == id) {
+            return Err(format!("Pack '{id}' is not enabled!"));
+        }
+        self.enabled.retain(|e| e != id);
+        self.disabled.push(id.to_string());
+        Ok(())
+    }
+}
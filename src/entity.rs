@@ -0,0 +1,381 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicI32, Ordering};
+use inbt::NbtTag;
+use mc_datatypes::BlockPos;
+use rand::random;
+use uuid::{Builder, Uuid};
+use crate::item::ItemRegistry;
+use crate::nbt_util::NbtTagExt;
+use crate::packet::{PlayPacketClientBound, Slot};
+use crate::packet_builder::PacketBuilder;
+
+/// Entity ids are shared by players and all other entities and never reused while the server runs,
+/// like vanilla's `Entity.ENTITY_COUNTER`
+static NEXT_ENTITY_ID: AtomicI32 = AtomicI32::new(1);
+
+pub fn next_entity_id() -> i32 {
+    NEXT_ENTITY_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Metadata indices shared by all entities
+const INDEX_FLAGS: u8 = 0;
+const INDEX_CUSTOM_NAME: u8 = 2;
+const INDEX_CUSTOM_NAME_VISIBLE: u8 = 3;
+const INDEX_SILENT: u8 = 4;
+const INDEX_NO_GRAVITY: u8 = 5;
+/// Living entities
+const INDEX_HEALTH: u8 = 9;
+/// Mobs and armor stands have their own flags after the living entity ones
+const INDEX_LIVING_FLAGS: u8 = 15;
+const INDEX_BABY: u8 = 16;
+/// Piglins have whether they are immune to zombification before it
+const INDEX_PIGLIN_BABY: u8 = 17;
+/// Armor stand poses: head, body, left arm, right arm, left leg and right leg
+const INDEX_ARMOR_STAND_POSE: u8 = 16;
+/// Item frames
+const INDEX_FRAME_ITEM: u8 = 8;
+const INDEX_FRAME_ROTATION: u8 = 9;
+
+const FLAG_INVISIBLE: u8 = 0x20;
+const FLAG_GLOWING: u8 = 0x40;
+const MOB_FLAG_NO_AI: u8 = 0x01;
+const MOB_FLAG_LEFT_HANDED: u8 = 0x02;
+const ARMOR_STAND_POSES: [&str; 6] = ["Head", "Body", "LeftArm", "RightArm", "LeftLeg", "RightLeg"];
+/// Equipment slots of Set Equipment for the saved `HandItems` and `ArmorItems`, which start with the feet
+const HAND_SLOTS: [u8; 2] = [0, 1];
+const ARMOR_SLOTS: [u8; 4] = [2, 3, 4, 5];
+
+/// A value in an entity's metadata, with the serializer the client uses to read it.
+/// See https://wiki.vg/Entity_metadata#Entity_Metadata_Format for the type ids.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataValue {
+    Byte(u8),
+    VarInt(i32),
+    Float(f32),
+    String(String),
+    TextComponent(String),
+    OptionalTextComponent(Option<String>),
+    Slot(Slot),
+    Boolean(bool),
+    /// Rotation around x, y and z in degrees, like armor stand limbs
+    Rotations(f32, f32, f32),
+    Position(i32, i32, i32),
+    OptionalPosition(Option<(i32, i32, i32)>),
+    /// Down, up, north, south, west, east
+    Direction(i32),
+    OptionalUuid(Option<Uuid>),
+    BlockState(i32),
+    /// Air counts as no block
+    OptionalBlockState(i32),
+    Nbt(NbtTag),
+    /// Type, profession and level
+    VillagerData(i32, i32, i32),
+    OptionalVarInt(Option<i32>),
+    Pose(i32),
+    Vector3(f32, f32, f32),
+    Quaternion(f32, f32, f32, f32),
+}
+
+impl MetadataValue {
+    pub fn type_id(&self) -> i32 {
+        match self {
+            MetadataValue::Byte(_) => 0,
+            MetadataValue::VarInt(_) => 1,
+            MetadataValue::Float(_) => 3,
+            MetadataValue::String(_) => 4,
+            MetadataValue::TextComponent(_) => 5,
+            MetadataValue::OptionalTextComponent(_) => 6,
+            MetadataValue::Slot(_) => 7,
+            MetadataValue::Boolean(_) => 8,
+            MetadataValue::Rotations(..) => 9,
+            MetadataValue::Position(..) => 10,
+            MetadataValue::OptionalPosition(_) => 11,
+            MetadataValue::Direction(_) => 12,
+            MetadataValue::OptionalUuid(_) => 13,
+            MetadataValue::BlockState(_) => 14,
+            MetadataValue::OptionalBlockState(_) => 15,
+            MetadataValue::Nbt(_) => 16,
+            MetadataValue::VillagerData(..) => 19,
+            MetadataValue::OptionalVarInt(_) => 20,
+            MetadataValue::Pose(_) => 21,
+            MetadataValue::Vector3(..) => 29,
+            MetadataValue::Quaternion(..) => 30,
+        }
+    }
+
+    /// Writes the type id and the value
+    pub fn write(&self, packet: PacketBuilder) -> PacketBuilder {
+        let packet = packet.add_varint(self.type_id());
+        let text = |text: &String| NbtTag::String("".to_string(), text.clone());
+        match self {
+            MetadataValue::Byte(value) => packet.add_byte(*value),
+            MetadataValue::VarInt(value) | MetadataValue::Direction(value) | MetadataValue::BlockState(value)
+            | MetadataValue::OptionalBlockState(value) | MetadataValue::Pose(value) => packet.add_varint(*value),
+            MetadataValue::Float(value) => packet.add_float(*value),
+            MetadataValue::String(value) => packet.add_string(value.clone()),
+            MetadataValue::TextComponent(value) => packet.add_nbt(&text(value)),
+            MetadataValue::OptionalTextComponent(value) => match value {
+                Some(value) => packet.add_bool(true).add_nbt(&text(value)),
+                None => packet.add_bool(false),
+            },
+            MetadataValue::Slot(slot) => packet.add_slot(slot),
+            MetadataValue::Boolean(value) => packet.add_bool(*value),
+            MetadataValue::Rotations(x, y, z) | MetadataValue::Vector3(x, y, z) => packet.add_float(*x).add_float(*y).add_float(*z),
+            MetadataValue::Position(x, y, z) => packet.add_long(BlockPos::new(*x, *y, *z).packed()),
+            MetadataValue::OptionalPosition(value) => match value {
+                Some((x, y, z)) => packet.add_bool(true).add_long(BlockPos::new(*x, *y, *z).packed()),
+                None => packet.add_bool(false),
+            },
+            MetadataValue::OptionalUuid(value) => match value {
+                Some(uuid) => packet.add_bool(true).add_uuid(*uuid),
+                None => packet.add_bool(false),
+            },
+            MetadataValue::Nbt(tag) => packet.add_nbt(tag),
+            MetadataValue::VillagerData(villager_type, profession, level) => packet.add_varint(*villager_type).add_varint(*profession).add_varint(*level),
+            // 0 is empty, everything else is one more than the value
+            MetadataValue::OptionalVarInt(value) => packet.add_varint(value.map(|v| v + 1).unwrap_or(0)),
+            MetadataValue::Quaternion(x, y, z, w) => packet.add_float(*x).add_float(*y).add_float(*z).add_float(*w),
+        }
+    }
+}
+
+/// The metadata of an entity by index, remembering which entries changed since they were last sent
+#[derive(Debug, Clone, Default)]
+pub struct EntityMetadata {
+    values: BTreeMap<u8, MetadataValue>,
+    changed: BTreeSet<u8>,
+}
+
+impl EntityMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, index: u8) -> Option<&MetadataValue> {
+        self.values.get(&index)
+    }
+
+    /// Only marks the entry as changed if the value is different
+    pub fn set(&mut self, index: u8, value: MetadataValue) {
+        if self.values.get(&index) != Some(&value) {
+            self.values.insert(index, value);
+            self.changed.insert(index);
+        }
+    }
+
+    /// Every entry, for spawning the entity. `None` if there are none, since the client keeps defaults for the rest.
+    pub fn all_packet(&self, eid: i32) -> Option<Vec<u8>> {
+        (!self.values.is_empty()).then(|| PlayPacketClientBound::set_entity_metadata(eid, &self.values.iter().map(|(i, v)| (*i, v)).collect::<Vec<_>>()))
+    }
+
+    /// The entries that changed since the last call, if any did
+    pub fn changed_packet(&mut self, eid: i32) -> Option<Vec<u8>> {
+        if self.changed.is_empty() {
+            return None;
+        }
+        let entries = self.changed.iter().filter_map(|i| Some((*i, self.values.get(i)?))).collect::<Vec<_>>();
+        let packet = PlayPacketClientBound::set_entity_metadata(eid, &entries);
+        self.changed.clear();
+        Some(packet)
+    }
+}
+
+/// A non-player entity in a dimension
+#[derive(Debug, Clone)]
+pub struct Entity {
+    pub id: i32,
+    pub uuid: Uuid,
+    /// Name in the entity_type registry, like `minecraft:armor_stand`
+    pub type_name: String,
+    /// Id in the entity_type registry
+    pub type_id: i32,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    /// In blocks per tick
+    pub velocity: (f64, f64, f64),
+    pub yaw: f32,
+    pub pitch: f32,
+    pub head_yaw: f32,
+    pub on_ground: bool,
+    /// The Data field of Spawn Entity, which some types use, like the facing of item frames
+    pub spawn_data: i32,
+    pub metadata: EntityMetadata,
+    /// Items held and worn, by Set Equipment slot
+    pub equipment: Vec<(u8, Slot)>,
+    /// What the entity was loaded from, so the fields the server doesn't know about are saved back unchanged
+    nbt: Option<NbtTag>,
+}
+
+impl Entity {
+    /// `None` if the type isn't in the entity_type registry
+    pub fn new(type_name: &str, entity_types: &BTreeMap<String, i32>, x: f64, y: f64, z: f64) -> Option<Self> {
+        let type_id = *entity_types.get(type_name)?;
+        Some(Self {
+            id: next_entity_id(),
+            uuid: Builder::from_random_bytes(random()).into_uuid(),
+            type_name: type_name.to_string(),
+            type_id,
+            x,
+            y,
+            z,
+            velocity: (0.0, 0.0, 0.0),
+            yaw: 0.0,
+            pitch: 0.0,
+            head_yaw: 0.0,
+            on_ground: false,
+            spawn_data: 0,
+            metadata: EntityMetadata::new(),
+            equipment: vec![],
+            nbt: None,
+        })
+    }
+
+    /// An entity saved in an entity chunk. `None` if it has no known type or position.
+    /// Entities don't move yet, so the saved motion stays in the NBT instead of being sent to clients.
+    pub fn from_nbt(nbt: &NbtTag, entity_types: &BTreeMap<String, i32>, items: &ItemRegistry) -> Option<Self> {
+        let type_name = nbt.child("id")?.as_str()?;
+        let pos = nbt.child("Pos")?.as_list()?.iter().filter_map(|v| v.as_f64()).collect::<Vec<_>>();
+        let [x, y, z] = pos[..] else {
+            return None;
+        };
+        let mut entity = Self::new(type_name, entity_types, x, y, z)?;
+        if let Some([a, b, c, d]) = nbt.child("UUID").and_then(|u| u.as_int_array()).map(|u| &u[..]) {
+            entity.uuid = Uuid::from_u64_pair((*a as u32 as u64) << 32 | *b as u32 as u64, (*c as u32 as u64) << 32 | *d as u32 as u64);
+        }
+        let rotation = nbt.child("Rotation").and_then(|r| r.as_list()).map(|r| r.iter().filter_map(|v| v.as_f32()).collect::<Vec<_>>()).unwrap_or_default();
+        entity.yaw = rotation.first().copied().unwrap_or(0.0);
+        entity.pitch = rotation.get(1).copied().unwrap_or(0.0);
+        entity.head_yaw = entity.yaw;
+        entity.on_ground = nbt.child("OnGround").and_then(|g| g.as_i8()).unwrap_or(0) != 0;
+        entity.read_metadata(nbt, items);
+        entity.nbt = Some(nbt.clone());
+        Some(entity)
+    }
+
+    /// Fills the metadata and equipment from the saved fields vanilla sends to clients
+    fn read_metadata(&mut self, nbt: &NbtTag, items: &ItemRegistry) {
+        let flag = |name: &str| nbt.child(name).and_then(|t| t.as_i8()).unwrap_or(0) != 0;
+        let flags = FLAG_INVISIBLE * flag("Invisible") as u8 | FLAG_GLOWING * flag("Glowing") as u8;
+        if flags != 0 {
+            self.metadata.set(INDEX_FLAGS, MetadataValue::Byte(flags));
+        }
+        if let Some(name) = nbt.child("CustomName").and_then(|n| n.as_str()) {
+            self.metadata.set(INDEX_CUSTOM_NAME, MetadataValue::OptionalTextComponent(Some(plain_text(name))));
+        }
+        for (index, name) in [(INDEX_CUSTOM_NAME_VISIBLE, "CustomNameVisible"), (INDEX_SILENT, "Silent"), (INDEX_NO_GRAVITY, "NoGravity")] {
+            if flag(name) {
+                self.metadata.set(index, MetadataValue::Boolean(true));
+            }
+        }
+        if let Some(health) = nbt.child("Health").and_then(|h| h.as_f32()) {
+            self.metadata.set(INDEX_HEALTH, MetadataValue::Float(health));
+        }
+        for (list, slots) in [("HandItems", &HAND_SLOTS[..]), ("ArmorItems", &ARMOR_SLOTS[..])] {
+            let saved = nbt.child(list).and_then(|l| l.as_list()).map(|l| &l[..]).unwrap_or_default();
+            for (slot, item) in slots.iter().zip(saved) {
+                let item = Slot::from_nbt(item, items);
+                if !item.is_empty() {
+                    self.equipment.push((*slot, item));
+                }
+            }
+        }
+
+        match self.type_name.as_str() {
+            "minecraft:item_frame" | "minecraft:glow_item_frame" => {
+                self.spawn_data = nbt.child("Facing").and_then(|f| f.as_i32()).unwrap_or(0);
+                if let Some(item) = nbt.child("Item").map(|item| Slot::from_nbt(item, items)).filter(|item| !item.is_empty()) {
+                    self.metadata.set(INDEX_FRAME_ITEM, MetadataValue::Slot(item));
+                }
+                if let Some(rotation) = nbt.child("ItemRotation").and_then(|r| r.as_i32()).filter(|r| *r != 0) {
+                    self.metadata.set(INDEX_FRAME_ROTATION, MetadataValue::VarInt(rotation));
+                }
+            }
+            "minecraft:armor_stand" => {
+                let flags = 0x01 * flag("Small") as u8 | 0x04 * flag("ShowArms") as u8 | 0x08 * flag("NoBasePlate") as u8 | 0x10 * flag("Marker") as u8;
+                if flags != 0 {
+                    self.metadata.set(INDEX_LIVING_FLAGS, MetadataValue::Byte(flags));
+                }
+                // Parts that aren't saved keep the client's default pose
+                let pose = nbt.child("Pose");
+                for (i, part) in ARMOR_STAND_POSES.iter().enumerate() {
+                    let angles = pose.and_then(|p| p.child(part)).and_then(|a| a.as_list()).map(|a| a.iter().filter_map(|v| v.as_f32()).collect::<Vec<_>>());
+                    if let Some([x, y, z]) = angles.as_deref() {
+                        self.metadata.set(INDEX_ARMOR_STAND_POSE + i as u8, MetadataValue::Rotations(*x, *y, *z));
+                    }
+                }
+            }
+            // Only mobs save whether they need to persist
+            _ if nbt.child("PersistenceRequired").is_some() => {
+                let flags = MOB_FLAG_NO_AI * flag("NoAI") as u8 | MOB_FLAG_LEFT_HANDED * flag("LeftHanded") as u8;
+                if flags != 0 {
+                    self.metadata.set(INDEX_LIVING_FLAGS, MetadataValue::Byte(flags));
+                }
+                // Animals and villagers are babies while their age is negative, zombies have a flag
+                let baby = nbt.child("Age").and_then(|a| a.as_i32()).is_some_and(|age| age < 0) || flag("IsBaby");
+                if baby {
+                    let index = if self.type_name == "minecraft:piglin" { INDEX_PIGLIN_BABY } else { INDEX_BABY };
+                    self.metadata.set(index, MetadataValue::Boolean(true));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The entity as saved in an entity chunk, with its current position and rotation
+    pub fn to_nbt(&self) -> NbtTag {
+        let mut nbt = self.nbt.clone().unwrap_or_else(|| NbtTag::Compound("".to_string(), vec![]));
+        let (most, least) = self.uuid.as_u64_pair();
+        for tag in [
+            NbtTag::String("id".to_string(), self.type_name.clone()),
+            NbtTag::IntArray("UUID".to_string(), vec![(most >> 32) as i32, most as i32, (least >> 32) as i32, least as i32]),
+            NbtTag::List("Pos".to_string(), vec![NbtTag::Double("".to_string(), self.x), NbtTag::Double("".to_string(), self.y), NbtTag::Double("".to_string(), self.z)]),
+            NbtTag::List("Rotation".to_string(), vec![NbtTag::Float("".to_string(), self.yaw), NbtTag::Float("".to_string(), self.pitch)]),
+            NbtTag::Byte("OnGround".to_string(), self.on_ground as i8),
+        ] {
+            nbt.set_child(tag);
+        }
+        nbt
+    }
+
+    pub fn chunk_pos(&self) -> (i32, i32) {
+        ((self.x.floor() as i32).div_euclid(16), (self.z.floor() as i32).div_euclid(16))
+    }
+
+    /// Spawn Entity, followed by the metadata and equipment if the entity has any
+    pub fn spawn_packets(&self) -> Vec<Vec<u8>> {
+        let mut packets = vec![PlayPacketClientBound::spawn_entity(self.id, self.uuid, self.type_id, self.x, self.y, self.z, self.yaw, self.pitch, self.head_yaw, self.spawn_data, self.velocity)];
+        packets.extend(self.metadata.all_packet(self.id));
+        if !self.equipment.is_empty() {
+            packets.push(PlayPacketClientBound::set_equipment(self.id, &self.equipment.iter().map(|(slot, item)| (*slot, item)).collect::<Vec<_>>()));
+        }
+        packets
+    }
+}
+
+/// The text of a JSON text component like saved custom names, without its formatting
+fn plain_text(json: &str) -> String {
+    fn collect(value: &serde_json::Value, text: &mut String) {
+        match value {
+            serde_json::Value::String(string) => text.push_str(string),
+            serde_json::Value::Array(parts) => parts.iter().for_each(|part| collect(part, text)),
+            serde_json::Value::Object(component) => {
+                if let Some(serde_json::Value::String(string)) = component.get("text") {
+                    text.push_str(string);
+                }
+                if let Some(extra) = component.get("extra") {
+                    collect(extra, text);
+                }
+            }
+            _ => {}
+        }
+    }
+    match serde_json::from_str(json) {
+        Ok(value) => {
+            let mut text = String::new();
+            collect(&value, &mut text);
+            text
+        }
+        Err(_) => json.to_string(),
+    }
+}
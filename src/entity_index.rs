@@ -0,0 +1,105 @@
+use std::collections::{BTreeSet, HashMap};
+use crate::collision::Aabb;
+use crate::entity::Entity;
+
+/// The non-player entities of one dimension, indexed by the chunk they are in for range queries
+#[derive(Debug, Default)]
+pub struct EntityIndex {
+    entities: HashMap<i32, Entity>,
+    by_chunk: HashMap<(i32, i32), BTreeSet<i32>>,
+}
+
+impl EntityIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Replaces an entity with the same id
+    pub fn insert(&mut self, entity: Entity) {
+        self.remove(entity.id);
+        self.by_chunk.entry(entity.chunk_pos()).or_default().insert(entity.id);
+        self.entities.insert(entity.id, entity);
+    }
+
+    pub fn remove(&mut self, id: i32) -> Option<Entity> {
+        let entity = self.entities.remove(&id)?;
+        self.unindex(id, entity.chunk_pos());
+        Some(entity)
+    }
+
+    fn unindex(&mut self, id: i32, chunk: (i32, i32)) {
+        if let Some(ids) = self.by_chunk.get_mut(&chunk) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.by_chunk.remove(&chunk);
+            }
+        }
+    }
+
+    pub fn get(&self, id: i32) -> Option<&Entity> {
+        self.entities.get(&id)
+    }
+
+    /// Position changes have to go through `move_to` to keep the index right
+    pub fn get_mut(&mut self, id: i32) -> Option<&mut Entity> {
+        self.entities.get_mut(&id)
+    }
+
+    pub fn move_to(&mut self, id: i32, x: f64, y: f64, z: f64) {
+        let Some(entity) = self.entities.get_mut(&id) else {
+            return;
+        };
+        let old_chunk = entity.chunk_pos();
+        (entity.x, entity.y, entity.z) = (x, y, z);
+        let new_chunk = entity.chunk_pos();
+        if old_chunk != new_chunk {
+            self.unindex(id, old_chunk);
+            self.by_chunk.entry(new_chunk).or_default().insert(id);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entity> {
+        self.entities.values()
+    }
+
+    pub fn in_chunk(&self, chunk_x: i32, chunk_z: i32) -> impl Iterator<Item = &Entity> {
+        self.by_chunk.get(&(chunk_x, chunk_z)).into_iter().flatten().filter_map(|id| self.entities.get(id))
+    }
+
+    /// Entities in the square of chunks around a chunk, like the chunks a player has loaded
+    pub fn in_chunk_range(&self, chunk_x: i32, chunk_z: i32, distance: i32) -> impl Iterator<Item = &Entity> {
+        (chunk_x - distance..=chunk_x + distance)
+            .flat_map(move |x| (chunk_z - distance..=chunk_z + distance).map(move |z| (x, z)))
+            .flat_map(move |(x, z)| self.in_chunk(x, z))
+    }
+
+    /// Entities whose position is inside the box
+    pub fn in_box(&self, aabb: &Aabb) -> impl Iterator<Item = &Entity> + '_ {
+        let (min_x, min_z) = ((aabb.min_x.floor() as i32).div_euclid(16), (aabb.min_z.floor() as i32).div_euclid(16));
+        let (max_x, max_z) = ((aabb.max_x.floor() as i32).div_euclid(16), (aabb.max_z.floor() as i32).div_euclid(16));
+        let aabb = *aabb;
+        (min_x..=max_x)
+            .flat_map(move |x| (min_z..=max_z).map(move |z| (x, z)))
+            .flat_map(move |(x, z)| self.in_chunk(x, z))
+            .filter(move |entity| {
+                (aabb.min_x..=aabb.max_x).contains(&entity.x) && (aabb.min_y..=aabb.max_y).contains(&entity.y) && (aabb.min_z..=aabb.max_z).contains(&entity.z)
+            })
+    }
+
+    /// Entities within `radius` blocks of a position
+    pub fn within(&self, x: f64, y: f64, z: f64, radius: f64) -> impl Iterator<Item = &Entity> + '_ {
+        let aabb = Aabb::new(x - radius, y - radius, z - radius, x + radius, y + radius, z + radius);
+        self.in_box(&aabb).filter(move |entity| {
+            let (dx, dy, dz) = (entity.x - x, entity.y - y, entity.z - z);
+            dx * dx + dy * dy + dz * dz <= radius * radius
+        })
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use inbt::NbtTag;
+use log::{trace, warn};
+use crate::entity::Entity;
+use crate::entity_index::EntityIndex;
+use crate::error::ServerError;
+use crate::item::ItemRegistry;
+use crate::nbt_util::NbtTagExt;
+use crate::player_data::DATA_VERSION;
+use crate::region::RegionStorage;
+
+/// An entity chunk that is in memory
+#[derive(Debug, Default)]
+struct LoadedEntityChunk {
+    /// Whether the region file has the chunk, so chunks without entities are only written to clear it
+    saved: bool,
+    /// Entities of types the server doesn't know, written back as they were
+    unknown: Vec<NbtTag>,
+    /// The entities as they are in the region file, so chunks nothing changed in aren't written again
+    on_disk: Vec<NbtTag>,
+}
+
+/// A chunk read by the loader thread
+type ReadChunk = ((i32, i32), Result<Option<NbtTag>, ServerError>);
+
+/// The entities of a dimension, loaded from its `entities` region files a chunk at a time while players are near
+#[derive(Debug)]
+pub struct EntityStorage {
+    regions: RegionStorage,
+    entities: EntityIndex,
+    loaded: HashMap<(i32, i32), LoadedEntityChunk>,
+    /// Chunks requested from the loader thread that haven't arrived yet
+    loading: HashSet<(i32, i32)>,
+    load_requests: Sender<(i32, i32)>,
+    read_chunks: Receiver<ReadChunk>,
+    entity_types: Arc<BTreeMap<String, i32>>,
+    items: Arc<ItemRegistry>,
+}
+
+impl EntityStorage {
+    /// Starts a thread that reads the requested chunks, which stops when the storage is dropped
+    pub fn new(regions: RegionStorage, entity_types: Arc<BTreeMap<String, i32>>, items: Arc<ItemRegistry>) -> Self {
+        let (load_requests, requested) = mpsc::channel::<(i32, i32)>();
+        let (read_sender, read_chunks) = mpsc::channel();
+        let loader_regions = regions.clone();
+        thread::spawn(move || {
+            for (x, z) in requested {
+                if read_sender.send(((x, z), loader_regions.read_chunk(x, z))).is_err() {
+                    return;
+                }
+            }
+        });
+        Self {
+            regions,
+            entities: EntityIndex::new(),
+            loaded: HashMap::new(),
+            loading: HashSet::new(),
+            load_requests,
+            read_chunks,
+            entity_types,
+            items,
+        }
+    }
+
+    pub fn entities(&self) -> &EntityIndex {
+        &self.entities
+    }
+
+    pub fn entities_mut(&mut self) -> &mut EntityIndex {
+        &mut self.entities
+    }
+
+    pub fn is_loaded(&self, chunk_x: i32, chunk_z: i32) -> bool {
+        self.loaded.contains_key(&(chunk_x, chunk_z))
+    }
+
+    pub fn loaded_chunks(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        self.loaded.keys().copied()
+    }
+
+    /// Has the loader thread read a chunk, unless it is loaded or on its way already. Its entities are put into
+    /// the index by `receive_chunks` once it has been read, so the main thread doesn't wait on the disk.
+    pub fn request_chunk(&mut self, chunk_x: i32, chunk_z: i32) {
+        if self.is_loaded(chunk_x, chunk_z) || !self.loading.insert((chunk_x, chunk_z)) {
+            return;
+        }
+        if self.load_requests.send((chunk_x, chunk_z)).is_err() {
+            self.loading.remove(&(chunk_x, chunk_z));
+            warn!("Entity loader thread stopped, can't load chunk {} {}", chunk_x, chunk_z);
+        }
+    }
+
+    /// Puts the entities of the chunks the loader thread has read since the last call into the index
+    pub fn receive_chunks(&mut self) {
+        while let Ok(((chunk_x, chunk_z), nbt)) = self.read_chunks.try_recv() {
+            self.loading.remove(&(chunk_x, chunk_z));
+            self.add_chunk(chunk_x, chunk_z, nbt);
+        }
+    }
+
+    /// Reads a chunk right away and puts its entities into the index, unless the chunk is loaded already
+    pub fn load_chunk(&mut self, chunk_x: i32, chunk_z: i32) {
+        let nbt = self.regions.read_chunk(chunk_x, chunk_z);
+        self.add_chunk(chunk_x, chunk_z, nbt);
+    }
+
+    /// A chunk that can't be read is loaded without entities
+    fn add_chunk(&mut self, chunk_x: i32, chunk_z: i32, nbt: Result<Option<NbtTag>, ServerError>) {
+        if self.is_loaded(chunk_x, chunk_z) {
+            return;
+        }
+        let mut chunk = LoadedEntityChunk::default();
+        match nbt {
+            Ok(Some(nbt)) => {
+                chunk.saved = true;
+                for entity_nbt in nbt.child("Entities").and_then(|e| e.as_list()).map(|e| &e[..]).unwrap_or_default() {
+                    match Entity::from_nbt(entity_nbt, &self.entity_types, &self.items) {
+                        Some(entity) => self.entities.insert(entity),
+                        None => chunk.unknown.push(entity_nbt.clone()),
+                    }
+                    chunk.on_disk.push(entity_nbt.clone());
+                }
+                trace!("Loaded {} entities in chunk {} {}", self.entities.in_chunk(chunk_x, chunk_z).count(), chunk_x, chunk_z);
+            }
+            Ok(None) => {}
+            Err(err) => warn!("Could not read entities of chunk {} {}: {}", chunk_x, chunk_z, err),
+        }
+        self.loaded.insert((chunk_x, chunk_z), chunk);
+    }
+
+    /// Writes the entities of a loaded chunk to its region file, if they changed since it was read or last written
+    pub fn save_chunk(&mut self, chunk_x: i32, chunk_z: i32) -> Result<(), ServerError> {
+        let Some(chunk) = self.loaded.get_mut(&(chunk_x, chunk_z)) else {
+            return Ok(());
+        };
+        let mut entities = self.entities.in_chunk(chunk_x, chunk_z).map(|entity| entity.to_nbt()).collect::<Vec<_>>();
+        entities.extend(chunk.unknown.iter().cloned());
+        if entities.is_empty() && !chunk.saved {
+            return Ok(());
+        }
+        // The index doesn't keep the order of the region file
+        if chunk.saved && entities.len() == chunk.on_disk.len() && entities.iter().all(|entity| chunk.on_disk.contains(entity)) {
+            return Ok(());
+        }
+        let nbt = NbtTag::Compound("".to_string(), vec![
+            NbtTag::Int("DataVersion".to_string(), DATA_VERSION),
+            NbtTag::IntArray("Position".to_string(), vec![chunk_x, chunk_z]),
+            NbtTag::List("Entities".to_string(), entities.clone()),
+        ]);
+        self.regions.write_chunk(chunk_x, chunk_z, &nbt)?;
+        chunk.saved = true;
+        chunk.on_disk = entities;
+        Ok(())
+    }
+
+    /// Saves a chunk and takes its entities out of the index. They are kept if saving fails.
+    pub fn unload_chunk(&mut self, chunk_x: i32, chunk_z: i32) -> Result<(), ServerError> {
+        self.save_chunk(chunk_x, chunk_z)?;
+        let ids = self.entities.in_chunk(chunk_x, chunk_z).map(|entity| entity.id).collect::<Vec<_>>();
+        for id in ids {
+            self.entities.remove(id);
+        }
+        self.loaded.remove(&(chunk_x, chunk_z));
+        Ok(())
+    }
+
+    /// Saves every loaded chunk, returning how many could not be saved
+    pub fn save(&mut self) -> usize {
+        let chunks = self.loaded_chunks().collect::<Vec<_>>();
+        chunks.into_iter()
+            .filter(|(x, z)| match self.save_chunk(*x, *z) {
+                Ok(()) => false,
+                Err(err) => {
+                    warn!("Could not save entities of chunk {} {}: {}", x, z, err);
+                    true
+                }
+            })
+            .count()
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use uuid::Uuid;
+use crate::entity::{EntityMetadata, MetadataValue};
+use crate::entity_index::EntityIndex;
+use crate::packet::PlayPacketClientBound;
+
+/// Entity flag bits in metadata index 0
+const FLAG_SNEAKING: u8 = 0x02;
+const FLAG_SPRINTING: u8 = 0x08;
+const FLAG_FALL_FLYING: u8 = 0x80;
+
+const POSE_STANDING: i32 = 0;
+const POSE_FALL_FLYING: i32 = 1;
+const POSE_SNEAKING: i32 = 5;
+
+/// Metadata indices of players
+const INDEX_FLAGS: u8 = 0;
+const INDEX_POSE: u8 = 6;
+const INDEX_SKIN_PARTS: u8 = 17;
+
+/// A player as the other players see it
+#[derive(Debug, Clone)]
+pub struct TrackedPlayer {
+    pub eid: i32,
+    pub uuid: Uuid,
+    pub name: String,
+    pub game_mode: u8,
+    pub dimension: String,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub on_ground: bool,
+    pub sneaking: bool,
+    pub sprinting: bool,
+    pub fall_flying: bool,
+    pub skin_parts: u8,
+    /// Chunks around the player it can see other players in
+    pub view_distance: i32,
+    /// Players this player is spawned for
+    viewers: HashSet<i32>,
+    /// Other entities spawned for this player
+    visible_entities: HashSet<i32>,
+    /// Position in 1/4096 blocks and rotation in 1/256 turns, as viewers last got them
+    sent_position: (i64, i64, i64),
+    sent_rotation: (u8, u8),
+    metadata: EntityMetadata,
+}
+
+impl TrackedPlayer {
+    pub fn new(eid: i32, uuid: Uuid, name: String, dimension: String, x: f64, y: f64, z: f64) -> Self {
+        Self {
+            eid,
+            uuid,
+            name,
+            game_mode: 0,
+            dimension,
+            x,
+            y,
+            z,
+            yaw: 0.0,
+            pitch: 0.0,
+            on_ground: false,
+            sneaking: false,
+            sprinting: false,
+            fall_flying: false,
+            skin_parts: 0x7F,
+            view_distance: 12,
+            viewers: HashSet::new(),
+            visible_entities: HashSet::new(),
+            sent_position: (encode_position(x), encode_position(y), encode_position(z)),
+            sent_rotation: (0, 0),
+            metadata: EntityMetadata::new(),
+        }
+    }
+
+    pub fn chunk_pos(&self) -> (i32, i32) {
+        ((self.x.floor() as i32).div_euclid(16), (self.z.floor() as i32).div_euclid(16))
+    }
+
+    /// Writes the entity flags, pose and skin parts into the metadata
+    fn update_metadata(&mut self) {
+        let flags = FLAG_SNEAKING * self.sneaking as u8 | FLAG_SPRINTING * self.sprinting as u8 | FLAG_FALL_FLYING * self.fall_flying as u8;
+        let pose = if self.fall_flying {
+            POSE_FALL_FLYING
+        } else if self.sneaking {
+            POSE_SNEAKING
+        } else {
+            POSE_STANDING
+        };
+        self.metadata.set(INDEX_FLAGS, MetadataValue::Byte(flags));
+        self.metadata.set(INDEX_POSE, MetadataValue::Pose(pose));
+        self.metadata.set(INDEX_SKIN_PARTS, MetadataValue::Byte(self.skin_parts));
+    }
+
+    /// Whether `viewer` has this player in its view
+    fn visible_to(&self, viewer: &TrackedPlayer) -> bool {
+        let ((x, z), (viewer_x, viewer_z)) = (self.chunk_pos(), viewer.chunk_pos());
+        viewer.eid != self.eid && viewer.dimension == self.dimension
+            && x.abs_diff(viewer_x).max(z.abs_diff(viewer_z)) <= viewer.view_distance as u32
+    }
+
+    fn spawn_packets(&self, player_entity_type: i32) -> Vec<Vec<u8>> {
+        let mut packets = vec![
+            PlayPacketClientBound::spawn_entity(self.eid, self.uuid, player_entity_type, self.x, self.y, self.z, self.yaw, self.pitch, self.yaw, 0, (0.0, 0.0, 0.0)),
+            PlayPacketClientBound::set_head_rotation(self.eid, self.yaw),
+        ];
+        packets.extend(self.metadata.all_packet(self.eid));
+        packets
+    }
+
+    /// Packets that bring the viewers up to date with the player, since the last call
+    fn update_packets(&mut self) -> Vec<Vec<u8>> {
+        let mut packets = vec![];
+        let position = (encode_position(self.x), encode_position(self.y), encode_position(self.z));
+        let rotation = (encode_angle(self.yaw), encode_angle(self.pitch));
+        let delta = (position.0 - self.sent_position.0, position.1 - self.sent_position.1, position.2 - self.sent_position.2);
+        let moved = position != self.sent_position;
+        let rotated = rotation != self.sent_rotation;
+        match (i16::try_from(delta.0), i16::try_from(delta.1), i16::try_from(delta.2)) {
+            _ if !moved && !rotated => {}
+            _ if !moved => packets.push(PlayPacketClientBound::update_entity_rotation(self.eid, self.yaw, self.pitch, self.on_ground)),
+            (Ok(dx), Ok(dy), Ok(dz)) if rotated => packets.push(PlayPacketClientBound::update_entity_position_and_rotation(self.eid, dx, dy, dz, self.yaw, self.pitch, self.on_ground)),
+            (Ok(dx), Ok(dy), Ok(dz)) => packets.push(PlayPacketClientBound::update_entity_position(self.eid, dx, dy, dz, self.on_ground)),
+            _ => packets.push(PlayPacketClientBound::teleport_entity(self.eid, self.x, self.y, self.z, self.yaw, self.pitch, self.on_ground)),
+        }
+        if rotated {
+            packets.push(PlayPacketClientBound::set_head_rotation(self.eid, self.yaw));
+        }
+        self.update_metadata();
+        packets.extend(self.metadata.changed_packet(self.eid));
+        self.sent_position = position;
+        self.sent_rotation = rotation;
+        packets
+    }
+}
+
+/// The client keeps entity positions in 1/4096 blocks and moves them by deltas in those units
+fn encode_position(value: f64) -> i64 {
+    (value * 4096.0).floor() as i64
+}
+
+fn encode_angle(degrees: f32) -> u8 {
+    (degrees.rem_euclid(360.0) * 256.0 / 360.0) as u8
+}
+
+/// Keeps track of which players see which, like vanilla's `ChunkMap.TrackedEntity`. Players are spawned for
+/// each other when they come into view and updated every tick while they stay in it.
+#[derive(Debug)]
+pub struct EntityTracker {
+    players: BTreeMap<i32, TrackedPlayer>,
+    /// Id of `minecraft:player` in the entity_type registry
+    player_entity_type: i32,
+}
+
+impl EntityTracker {
+    pub fn new(player_entity_type: i32) -> Self {
+        Self {
+            players: BTreeMap::new(),
+            player_entity_type,
+        }
+    }
+
+    pub fn player(&self, eid: i32) -> Option<&TrackedPlayer> {
+        self.players.get(&eid)
+    }
+
+    pub fn player_mut(&mut self, eid: i32) -> Option<&mut TrackedPlayer> {
+        self.players.get_mut(&eid)
+    }
+
+    /// Starts tracking a player that joined, it is spawned for the players around it in the next tick. It has to
+    /// be on the tab list first.
+    pub fn add_player(&mut self, player: TrackedPlayer) {
+        self.players.insert(player.eid, player);
+    }
+
+    /// Despawns a player that left. `send` gets the eid of the player a packet is for.
+    pub fn remove_player<F: FnMut(i32, Arc<Vec<u8>>)>(&mut self, eid: i32, mut send: F) {
+        let Some(player) = self.players.remove(&eid) else {
+            return;
+        };
+        let remove = Arc::new(PlayPacketClientBound::remove_entities(&[eid]));
+        for viewer in &player.viewers {
+            send(*viewer, remove.clone());
+        }
+        for other in self.players.values_mut() {
+            other.viewers.remove(&eid);
+        }
+    }
+
+    /// The client forgets all entities when it respawns, so the player is no longer a viewer of anything
+    pub fn change_dimension(&mut self, eid: i32, dimension: &str) {
+        for other in self.players.values_mut() {
+            other.viewers.remove(&eid);
+        }
+        if let Some(player) = self.players.get_mut(&eid) {
+            player.dimension = dimension.to_string();
+            player.visible_entities.clear();
+        }
+    }
+
+    /// Sends what changed about each player to the players seeing it, and spawns or despawns players that
+    /// came into or went out of view
+    pub fn tick<F: FnMut(i32, Arc<Vec<u8>>)>(&mut self, mut send: F) {
+        for player in self.players.values_mut() {
+            let packets = player.update_packets();
+            if player.viewers.is_empty() {
+                continue;
+            }
+            for packet in packets.into_iter().map(Arc::new) {
+                for viewer in &player.viewers {
+                    send(*viewer, packet.clone());
+                }
+            }
+        }
+
+        let eids = self.players.keys().copied().collect::<Vec<_>>();
+        for eid in &eids {
+            let mut changes = vec![];
+            let player = &self.players[eid];
+            for viewer in self.players.values() {
+                let visible = player.visible_to(viewer);
+                if visible != player.viewers.contains(&viewer.eid) {
+                    changes.push((viewer.eid, visible));
+                }
+            }
+            if changes.is_empty() {
+                continue;
+            }
+            let spawn = player.spawn_packets(self.player_entity_type).into_iter().map(Arc::new).collect::<Vec<_>>();
+            let player = self.players.get_mut(eid).unwrap();
+            for (viewer, visible) in changes {
+                if visible {
+                    player.viewers.insert(viewer);
+                    for packet in &spawn {
+                        send(viewer, packet.clone());
+                    }
+                } else {
+                    player.viewers.remove(&viewer);
+                    send(viewer, Arc::new(PlayPacketClientBound::remove_entities(&[*eid])));
+                }
+            }
+        }
+    }
+
+    /// Spawns the entities of a dimension for the players in it that have them in view, and despawns the ones
+    /// that left the view or the index
+    pub fn tick_entities<F: FnMut(i32, Arc<Vec<u8>>)>(&mut self, dimension: &str, entities: &EntityIndex, mut send: F) {
+        for player in self.players.values_mut().filter(|player| player.dimension == dimension) {
+            let (x, z) = player.chunk_pos();
+            let in_view = entities.in_chunk_range(x, z, player.view_distance).map(|entity| entity.id).collect::<HashSet<_>>();
+            let gone = player.visible_entities.difference(&in_view).copied().collect::<Vec<_>>();
+            if !gone.is_empty() {
+                send(player.eid, Arc::new(PlayPacketClientBound::remove_entities(&gone)));
+            }
+            for entity in in_view.difference(&player.visible_entities).filter_map(|id| entities.get(*id)) {
+                for packet in entity.spawn_packets() {
+                    send(player.eid, Arc::new(packet));
+                }
+            }
+            player.visible_entities = in_view;
+        }
+    }
+}
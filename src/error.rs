@@ -15,6 +15,10 @@ pub enum ServerError {
     #[error("{0}")]
     IOError(#[from] io::Error),
     #[error("{0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("{0}")]
+    ZipError(#[from] zip::result::ZipError),
+    #[error("{0}")]
     StripPrefixError(#[from] StripPrefixError),
     #[error("Parsed VarInt too big")]
     VarIntTooBig,
@@ -26,6 +30,24 @@ pub enum ServerError {
     InvalidServerState(i32),
     #[error("Server state not yet implemented: {0:?}")]
     ServerStateNotImplemented(ConnectionStatusType),
+    #[error("Unsupported chunk compression type {0}")]
+    UnsupportedChunkCompression(u8),
+    #[error("Chunk {0} {1} is too large for a region file")]
+    ChunkTooLarge(i32, i32),
+    #[error("Chunk {0} {1} is no longer in its region file")]
+    ChunkMissing(i32, i32),
+    #[error("Unsupported item component type {0}")]
+    UnsupportedItemComponent(i32),
+    #[error("Item stacks nested too deep")]
+    ItemNestedTooDeep,
+    #[error("Inline registry value in item component type {0}")]
+    InlineRegistryValue(i32),
+    #[error("Hidden mob effects nested too deep")]
+    EffectNestedTooDeep,
+    #[error("Invalid NBT in packet: {0}")]
+    InvalidNbt(&'static str),
     #[error("Reached end of packet data")]
     EndOfPacket,
+    #[error("Data pack {pack} failed to load: {source}")]
+    DataPackError { pack: String, source: Box<ServerError> },
 }
\ No newline at end of file
@@ -0,0 +1,204 @@
+use inbt::NbtTag;
+use crate::item::ItemRegistry;
+use crate::nbt_util::NbtTagExt;
+use crate::packet::{PlayPacketClientBound, Slot};
+
+/// Window id of the player's own inventory, which is always open
+pub const WINDOW_ID: u8 = 0;
+/// Slots of the inventory window: the crafting result, the 2x2 crafting grid, armor from the head down, the main
+/// inventory, the hotbar and the off hand
+pub const CRAFTING_RESULT: usize = 0;
+pub const ARMOR_START: usize = 5;
+pub const MAIN_START: usize = 9;
+pub const HOTBAR_START: usize = 36;
+pub const OFF_HAND: usize = 45;
+pub const SLOT_COUNT: usize = 46;
+
+/// Slot numbers in saved inventories
+const SAVED_ARMOR_FEET: i8 = 100;
+const SAVED_OFF_HAND: i8 = -106;
+
+/// The player inventory as the server has it, numbered like the inventory window
+#[derive(Debug, Clone)]
+pub struct PlayerInventory {
+    slots: Vec<Slot>,
+    /// The stack held by the mouse cursor while the inventory is open
+    cursor: Slot,
+    /// Hotbar slot in the main hand, 0 to 8
+    selected_slot: u8,
+    /// Counts up with every change the server sends, the client sends the last one it got with each click
+    state_id: i32,
+}
+
+impl Default for PlayerInventory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PlayerInventory {
+    pub fn new() -> Self {
+        Self {
+            slots: vec![Slot::empty(); SLOT_COUNT],
+            cursor: Slot::empty(),
+            selected_slot: 0,
+            state_id: 0,
+        }
+    }
+
+    /// A saved `Inventory` list and `SelectedItemSlot`. Items the server doesn't know are left out.
+    pub fn from_nbt(items: &[NbtTag], selected_slot: i32, registry: &ItemRegistry) -> Self {
+        let mut inventory = Self::new();
+        for item in items {
+            let slot = item.child("Slot").and_then(|s| s.as_i8()).and_then(window_slot);
+            if let Some(slot) = slot {
+                inventory.slots[slot] = Slot::from_nbt(item, registry);
+            }
+        }
+        inventory.set_selected_slot(selected_slot.clamp(0, 8) as u8);
+        inventory
+    }
+
+    /// The `Inventory` list to save. Like vanilla, the crafting grid and the cursor aren't saved.
+    pub fn to_nbt(&self, registry: &ItemRegistry) -> Vec<NbtTag> {
+        self.slots.iter().enumerate()
+            .filter_map(|(slot, item)| {
+                let mut nbt = item.to_nbt(registry)?;
+                nbt.set_child(NbtTag::Byte("Slot".to_string(), saved_slot(slot)?));
+                Some(nbt)
+            })
+            .collect()
+    }
+
+    pub fn get(&self, slot: usize) -> Option<&Slot> {
+        self.slots.get(slot)
+    }
+
+    /// Returns false if there is no such slot
+    pub fn set(&mut self, slot: usize, item: Slot) -> bool {
+        match self.slots.get_mut(slot) {
+            Some(existing) => {
+                *existing = item;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn cursor(&self) -> &Slot {
+        &self.cursor
+    }
+
+    pub fn set_cursor(&mut self, item: Slot) {
+        self.cursor = item;
+    }
+
+    /// Applies the slots and cursor a client predicted for a click. Unless `may_create` is set, like in creative
+    /// mode, the click has to move items around without creating or destroying any. Stacks can't be larger than
+    /// their max stack size either way. Returns false, without changing anything, if the click isn't possible.
+    pub fn apply_click(&mut self, changed_slots: Vec<(i16, Slot)>, carried_item: Slot, registry: &ItemRegistry, may_create: bool) -> bool {
+        let too_large = |item: &Slot| !item.is_empty() && item.count > item.max_stack_size(registry);
+        if too_large(&carried_item) {
+            return false;
+        }
+        // Count change of every kind of item, which has to be zero everywhere
+        let mut changes = vec![];
+        add_change(&mut changes, &self.cursor, -self.cursor.count);
+        add_change(&mut changes, &carried_item, carried_item.count);
+        for (i, (slot, item)) in changed_slots.iter().enumerate() {
+            let Some(old) = usize::try_from(*slot).ok().and_then(|slot| self.slots.get(slot)) else {
+                return false;
+            };
+            if too_large(item) || changed_slots[..i].iter().any(|(other, _)| other == slot) {
+                return false;
+            }
+            add_change(&mut changes, old, -old.count);
+            add_change(&mut changes, item, item.count);
+        }
+        if !may_create && changes.iter().any(|(_, change)| *change != 0) {
+            return false;
+        }
+
+        for (slot, item) in changed_slots {
+            self.slots[slot as usize] = item;
+        }
+        self.cursor = carried_item;
+        true
+    }
+
+    pub fn selected_slot(&self) -> u8 {
+        self.selected_slot
+    }
+
+    /// Returns false if the slot isn't on the hotbar
+    pub fn set_selected_slot(&mut self, slot: u8) -> bool {
+        if slot > 8 {
+            return false;
+        }
+        self.selected_slot = slot;
+        true
+    }
+
+    /// Window slot of the item in the main hand
+    pub fn held_slot(&self) -> usize {
+        HOTBAR_START + self.selected_slot as usize
+    }
+
+    pub fn held_item(&self) -> &Slot {
+        &self.slots[self.held_slot()]
+    }
+
+    pub fn state_id(&self) -> i32 {
+        self.state_id
+    }
+
+    /// Vanilla keeps the state id in 15 bits
+    fn next_state_id(&mut self) -> i32 {
+        self.state_id = (self.state_id + 1) & 0x7FFF;
+        self.state_id
+    }
+
+    /// Set Container Content with every slot and the cursor
+    pub fn content_packet(&mut self) -> Vec<u8> {
+        let state_id = self.next_state_id();
+        PlayPacketClientBound::set_container_content(WINDOW_ID, state_id, &self.slots, &self.cursor)
+    }
+
+    /// Set Container Slot for one slot. `None` if there is no such slot.
+    pub fn slot_packet(&mut self, slot: usize) -> Option<Vec<u8>> {
+        self.slots.get(slot)?;
+        let state_id = self.next_state_id();
+        Some(PlayPacketClientBound::set_container_slot(WINDOW_ID as i8, state_id, slot as i16, &self.slots[slot]))
+    }
+}
+
+fn add_change<'a>(changes: &mut Vec<(&'a Slot, i32)>, item: &'a Slot, count: i32) {
+    if item.is_empty() {
+        return;
+    }
+    match changes.iter_mut().find(|(kind, _)| kind.is_same_item(item)) {
+        Some((_, change)) => *change += count,
+        None => changes.push((item, count)),
+    }
+}
+
+/// Window slot of a slot number in a saved inventory, which counts the hotbar first and armor from the feet up
+fn window_slot(saved: i8) -> Option<usize> {
+    match saved {
+        0..=8 => Some(HOTBAR_START + saved as usize),
+        9..=35 => Some(saved as usize),
+        SAVED_ARMOR_FEET..=103 => Some(ARMOR_START + (103 - saved) as usize),
+        SAVED_OFF_HAND => Some(OFF_HAND),
+        _ => None,
+    }
+}
+
+fn saved_slot(window_slot: usize) -> Option<i8> {
+    match window_slot {
+        ARMOR_START..=8 => Some(SAVED_ARMOR_FEET + 3 - (window_slot - ARMOR_START) as i8),
+        MAIN_START..=35 => Some(window_slot as i8),
+        HOTBAR_START..=44 => Some((window_slot - HOTBAR_START) as i8),
+        OFF_HAND => Some(SAVED_OFF_HAND),
+        _ => None,
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Max stack size of items that aren't in the items report
+pub const DEFAULT_MAX_STACK_SIZE: i32 = 64;
+
+/// Names and protocol ids of one registry, both ways
+#[derive(Debug, Clone, Default)]
+struct IdMap {
+    ids: BTreeMap<String, i32>,
+    names: HashMap<i32, String>,
+}
+
+impl IdMap {
+    fn new(ids: &BTreeMap<String, i32>) -> Self {
+        Self {
+            ids: ids.clone(),
+            names: ids.iter().map(|(name, id)| (*id, name.clone())).collect(),
+        }
+    }
+}
+
+/// The registries item stacks refer to, for converting between the ids in packets and the names in saved items
+#[derive(Debug, Clone, Default)]
+pub struct ItemRegistry {
+    items: IdMap,
+    enchantments: IdMap,
+    effects: IdMap,
+    /// By item id, from the `max_stack_size` default component
+    max_stack_sizes: HashMap<i32, i32>,
+    /// Other registries components refer to, like `potion` or `trim_material`, by registry name
+    registries: HashMap<String, IdMap>,
+}
+
+impl ItemRegistry {
+    pub fn new(items: &BTreeMap<String, i32>) -> Self {
+        Self {
+            items: IdMap::new(items),
+            ..Self::default()
+        }
+    }
+
+    /// Enchantments are data driven, their ids are the order they were sent to the client in
+    pub fn with_enchantments(mut self, enchantments: &BTreeMap<String, i32>) -> Self {
+        self.enchantments = IdMap::new(enchantments);
+        self
+    }
+
+    /// The `mob_effect` registry, for suspicious stew, potions and food
+    pub fn with_effects(mut self, effects: &BTreeMap<String, i32>) -> Self {
+        self.effects = IdMap::new(effects);
+        self
+    }
+
+    /// Adds a registry that components refer to, like `attribute` or `block`
+    pub fn with_registry(mut self, registry: &str, ids: &BTreeMap<String, i32>) -> Self {
+        self.registries.insert(registry.to_string(), IdMap::new(ids));
+        self
+    }
+
+    /// Default max stack sizes by item name
+    pub fn with_max_stack_sizes(mut self, max_stack_sizes: &BTreeMap<String, i32>) -> Self {
+        self.max_stack_sizes = max_stack_sizes.iter()
+            .filter_map(|(name, size)| Some((self.id(name)?, *size)))
+            .collect();
+        self
+    }
+
+    pub fn id(&self, name: &str) -> Option<i32> {
+        self.items.ids.get(name).copied()
+    }
+
+    pub fn name(&self, id: i32) -> Option<&str> {
+        self.items.names.get(&id).map(|name| name.as_str())
+    }
+
+    pub fn max_stack_size(&self, id: i32) -> i32 {
+        self.max_stack_sizes.get(&id).copied().unwrap_or(DEFAULT_MAX_STACK_SIZE)
+    }
+
+    pub fn enchantment_id(&self, name: &str) -> Option<i32> {
+        self.enchantments.ids.get(name).copied()
+    }
+
+    pub fn enchantment_name(&self, id: i32) -> Option<&str> {
+        self.enchantments.names.get(&id).map(|name| name.as_str())
+    }
+
+    pub fn effect_id(&self, name: &str) -> Option<i32> {
+        self.effects.ids.get(name).copied()
+    }
+
+    pub fn effect_name(&self, id: i32) -> Option<&str> {
+        self.effects.names.get(&id).map(|name| name.as_str())
+    }
+
+    pub fn registry_id(&self, registry: &str, name: &str) -> Option<i32> {
+        self.registries.get(registry)?.ids.get(name).copied()
+    }
+
+    pub fn registry_name(&self, registry: &str, id: i32) -> Option<&str> {
+        self.registries.get(registry)?.names.get(&id).map(|name| name.as_str())
+    }
+}
@@ -0,0 +1,1347 @@
+use std::slice::Iter;
+use inbt::NbtTag;
+use mc_datatypes::BlockPos;
+use serde_json::Value;
+use uuid::Uuid;
+use crate::error::ServerError;
+use crate::item::ItemRegistry;
+use crate::nbt_util::{json_to_nbt, nbt_to_json, nbt_to_snbt, NbtTagExt};
+use crate::packet::{next_bool, next_f32, next_f64, next_i32, next_nbt, next_nested_slot, next_string, next_u128, next_u64, next_varint, Slot};
+use crate::packet_builder::PacketBuilder;
+
+/// Data component types, the index is the protocol id
+pub const COMPONENT_TYPES: [&str; 57] = [
+    "minecraft:custom_data",
+    "minecraft:max_stack_size",
+    "minecraft:max_damage",
+    "minecraft:damage",
+    "minecraft:unbreakable",
+    "minecraft:custom_name",
+    "minecraft:item_name",
+    "minecraft:lore",
+    "minecraft:rarity",
+    "minecraft:enchantments",
+    "minecraft:can_place_on",
+    "minecraft:can_break",
+    "minecraft:attribute_modifiers",
+    "minecraft:custom_model_data",
+    "minecraft:hide_additional_tooltip",
+    "minecraft:hide_tooltip",
+    "minecraft:repair_cost",
+    "minecraft:creative_slot_lock",
+    "minecraft:enchantment_glint_override",
+    "minecraft:intangible_projectile",
+    "minecraft:food",
+    "minecraft:fire_resistant",
+    "minecraft:tool",
+    "minecraft:stored_enchantments",
+    "minecraft:dyed_color",
+    "minecraft:map_color",
+    "minecraft:map_id",
+    "minecraft:map_decorations",
+    "minecraft:map_post_processing",
+    "minecraft:charged_projectiles",
+    "minecraft:bundle_contents",
+    "minecraft:potion_contents",
+    "minecraft:suspicious_stew_effects",
+    "minecraft:writable_book_content",
+    "minecraft:written_book_content",
+    "minecraft:trim",
+    "minecraft:debug_stick_state",
+    "minecraft:entity_data",
+    "minecraft:bucket_entity_data",
+    "minecraft:block_entity_data",
+    "minecraft:instrument",
+    "minecraft:ominous_bottle_amplifier",
+    "minecraft:jukebox_playable",
+    "minecraft:recipes",
+    "minecraft:lodestone_tracker",
+    "minecraft:firework_explosion",
+    "minecraft:fireworks",
+    "minecraft:profile",
+    "minecraft:note_block_sound",
+    "minecraft:banner_patterns",
+    "minecraft:base_color",
+    "minecraft:pot_decorations",
+    "minecraft:container",
+    "minecraft:block_state",
+    "minecraft:bees",
+    "minecraft:lock",
+    "minecraft:container_loot",
+];
+
+/// Components that are only saved, the client neither gets nor sends them
+const SAVED_ONLY: [&str; 3] = ["minecraft:recipes", "minecraft:lock", "minecraft:container_loot"];
+
+const RARITIES: [&str; 4] = ["common", "uncommon", "rare", "epic"];
+
+pub const DYE_COLORS: [&str; 16] = [
+    "white", "orange", "magenta", "light_blue", "yellow", "lime", "pink", "gray",
+    "light_gray", "cyan", "purple", "blue", "brown", "green", "red", "black",
+];
+
+const ATTRIBUTE_OPERATIONS: [&str; 3] = ["add_value", "add_multiplied_base", "add_multiplied_total"];
+
+const EQUIPMENT_SLOT_GROUPS: [&str; 10] = ["any", "mainhand", "offhand", "hand", "feet", "legs", "chest", "head", "armor", "body"];
+
+const FIREWORK_SHAPES: [&str; 5] = ["small_ball", "large_ball", "star", "creeper", "burst"];
+
+/// Vanilla's `container` component has at most this many slots
+const MAX_CONTAINER_SLOTS: i32 = 256;
+
+/// How many mob effects a client may hide behind each other
+const MAX_HIDDEN_EFFECTS: usize = 16;
+
+pub fn component_type_id(name: &str) -> Option<i32> {
+    COMPONENT_TYPES.iter().position(|n| *n == name).map(|id| id as i32)
+}
+
+/// Whether components of a type are sent to clients
+pub fn is_synced(type_id: i32) -> bool {
+    usize::try_from(type_id).ok()
+        .and_then(|id| COMPONENT_TYPES.get(id))
+        .is_some_and(|name| !SAVED_ONLY.contains(name))
+}
+
+/// Blocks in a component, either a tag or block ids
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockSet {
+    Tag(String),
+    Ids(Vec<i32>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyMatcher {
+    Exact(String),
+    Range { min: Option<String>, max: Option<String> },
+}
+
+/// A block that adventure mode players can place on or break, all parts being optional
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockPredicate {
+    pub blocks: Option<BlockSet>,
+    pub properties: Option<Vec<(String, PropertyMatcher)>>,
+    pub nbt: Option<NbtTag>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeModifier {
+    /// Id in the `attribute` registry
+    pub attribute: i32,
+    pub id: String,
+    pub amount: f64,
+    /// Index into `ATTRIBUTE_OPERATIONS`
+    pub operation: i32,
+    /// Index into `EQUIPMENT_SLOT_GROUPS`
+    pub slot: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Effect {
+    /// Id in the `mob_effect` registry
+    pub id: i32,
+    pub details: EffectDetails,
+}
+
+/// A mob effect without its type. It can hide a longer lasting weaker effect of the same type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectDetails {
+    pub amplifier: i32,
+    pub duration: i32,
+    pub ambient: bool,
+    pub show_particles: bool,
+    pub show_icon: bool,
+    pub hidden_effect: Option<Box<EffectDetails>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolRule {
+    pub blocks: BlockSet,
+    pub speed: Option<f32>,
+    pub correct_for_drops: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FireworkExplosion {
+    /// Index into `FIREWORK_SHAPES`
+    pub shape: i32,
+    pub colors: Vec<i32>,
+    pub fade_colors: Vec<i32>,
+    pub has_trail: bool,
+    pub has_twinkle: bool,
+}
+
+/// Songs are saved by name, and the client may send either form
+#[derive(Debug, Clone, PartialEq)]
+pub enum JukeboxSong {
+    Id(i32),
+    Name(String),
+}
+
+/// A data component of an item stack, which replaced the item's NBT tag in 1.20.5. Text is kept as the NBT the
+/// network uses, registry entries by their protocol id.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ItemComponent {
+    CustomData(NbtTag),
+    MaxStackSize(i32),
+    MaxDamage(i32),
+    Damage(i32),
+    Unbreakable { show_in_tooltip: bool },
+    CustomName(NbtTag),
+    ItemName(NbtTag),
+    Lore(Vec<NbtTag>),
+    /// Index into `RARITIES`
+    Rarity(i32),
+    /// Enchantment ids and levels
+    Enchantments { levels: Vec<(i32, i32)>, show_in_tooltip: bool },
+    CanPlaceOn { predicates: Vec<BlockPredicate>, show_in_tooltip: bool },
+    CanBreak { predicates: Vec<BlockPredicate>, show_in_tooltip: bool },
+    AttributeModifiers { modifiers: Vec<AttributeModifier>, show_in_tooltip: bool },
+    CustomModelData(i32),
+    HideAdditionalTooltip,
+    HideTooltip,
+    RepairCost(i32),
+    CreativeSlotLock,
+    EnchantmentGlintOverride(bool),
+    IntangibleProjectile,
+    /// Effects come with the chance of getting them
+    Food { nutrition: i32, saturation: f32, can_always_eat: bool, eat_seconds: f32, using_converts_to: Option<Slot>, effects: Vec<(Effect, f32)> },
+    FireResistant,
+    Tool { rules: Vec<ToolRule>, default_mining_speed: f32, damage_per_block: i32 },
+    StoredEnchantments { levels: Vec<(i32, i32)>, show_in_tooltip: bool },
+    DyedColor { rgb: i32, show_in_tooltip: bool },
+    MapColor(i32),
+    MapId(i32),
+    MapDecorations(NbtTag),
+    MapPostProcessing(i32),
+    ChargedProjectiles(Vec<Slot>),
+    BundleContents(Vec<Slot>),
+    /// Id in the `potion` registry
+    PotionContents { potion: Option<i32>, custom_color: Option<i32>, custom_effects: Vec<Effect> },
+    /// Effect ids and durations in ticks
+    SuspiciousStewEffects(Vec<(i32, i32)>),
+    /// Pages, each with the version shown to players with chat filtering
+    WritableBookContent(Vec<(String, Option<String>)>),
+    WrittenBookContent { title: (String, Option<String>), author: String, generation: i32, pages: Vec<(NbtTag, Option<NbtTag>)>, resolved: bool },
+    /// Ids in the `trim_material` and `trim_pattern` registries
+    Trim { material: i32, pattern: i32, show_in_tooltip: bool },
+    DebugStickState(NbtTag),
+    EntityData(NbtTag),
+    BucketEntityData(NbtTag),
+    BlockEntityData(NbtTag),
+    /// Id in the `instrument` registry
+    Instrument(i32),
+    OminousBottleAmplifier(i32),
+    JukeboxPlayable { song: JukeboxSong, show_in_tooltip: bool },
+    /// Dimension and position of the lodestone the compass points at
+    LodestoneTracker { target: Option<(String, i32, i32, i32)>, tracked: bool },
+    FireworkExplosion(FireworkExplosion),
+    Fireworks { flight_duration: i32, explosions: Vec<FireworkExplosion> },
+    /// Player name, uuid and properties like the skin, with their signatures
+    Profile { name: Option<String>, id: Option<u128>, properties: Vec<(String, String, Option<String>)> },
+    NoteBlockSound(String),
+    /// Ids in the `banner_pattern` registry and indices into `DYE_COLORS`, from the bottom layer up
+    BannerPatterns(Vec<(i32, i32)>),
+    /// Index into `DYE_COLORS`
+    BaseColor(i32),
+    /// Item ids of the back, left, right and front
+    PotDecorations(Vec<i32>),
+    /// Slots up to the last one with an item
+    Container(Vec<Slot>),
+    BlockState(Vec<(String, String)>),
+    /// Entity data of each bee, the ticks it spent in the hive and the least it stays there
+    Bees(Vec<(NbtTag, i32, i32)>),
+    /// A saved component the client doesn't get, named by its type: one that is only saved, that the server
+    /// doesn't know or that refers to registry entries the client wasn't sent
+    Saved(NbtTag),
+}
+
+impl ItemComponent {
+    /// Reads the data of a component in a Slot, `depth` being how many items the stack is inside of
+    pub(crate) fn read(type_id: i32, iterator: &mut Iter<u8>, depth: usize) -> Result<Self, ServerError> {
+        Ok(match type_id {
+            0 => Self::CustomData(next_nbt(iterator)?),
+            1 => Self::MaxStackSize(next_varint(iterator)?),
+            2 => Self::MaxDamage(next_varint(iterator)?),
+            3 => Self::Damage(next_varint(iterator)?),
+            4 => Self::Unbreakable { show_in_tooltip: next_bool(iterator)? },
+            5 => Self::CustomName(next_nbt(iterator)?),
+            6 => Self::ItemName(next_nbt(iterator)?),
+            7 => Self::Lore(next_list(iterator, next_nbt)?),
+            8 => Self::Rarity(next_varint(iterator)?),
+            9 => {
+                let levels = next_list(iterator, |i| Ok((next_varint(i)?, next_varint(i)?)))?;
+                Self::Enchantments { levels, show_in_tooltip: next_bool(iterator)? }
+            }
+            10 => Self::CanPlaceOn { predicates: next_list(iterator, next_block_predicate)?, show_in_tooltip: next_bool(iterator)? },
+            11 => Self::CanBreak { predicates: next_list(iterator, next_block_predicate)?, show_in_tooltip: next_bool(iterator)? },
+            12 => Self::AttributeModifiers {
+                modifiers: next_list(iterator, |i| Ok(AttributeModifier {
+                    attribute: next_varint(i)?,
+                    id: next_string(i)?,
+                    amount: next_f64(i)?,
+                    operation: next_varint(i)?,
+                    slot: next_varint(i)?,
+                }))?,
+                show_in_tooltip: next_bool(iterator)?,
+            },
+            13 => Self::CustomModelData(next_varint(iterator)?),
+            14 => Self::HideAdditionalTooltip,
+            15 => Self::HideTooltip,
+            16 => Self::RepairCost(next_varint(iterator)?),
+            17 => Self::CreativeSlotLock,
+            18 => Self::EnchantmentGlintOverride(next_bool(iterator)?),
+            19 => {
+                // Always an empty compound
+                next_nbt(iterator)?;
+                Self::IntangibleProjectile
+            }
+            20 => Self::Food {
+                nutrition: next_varint(iterator)?,
+                saturation: next_f32(iterator)?,
+                can_always_eat: next_bool(iterator)?,
+                eat_seconds: next_f32(iterator)?,
+                using_converts_to: next_optional(iterator, |i| next_nested_slot(i, depth + 1))?,
+                effects: next_list(iterator, |i| Ok((next_effect(i)?, next_f32(i)?)))?,
+            },
+            21 => Self::FireResistant,
+            22 => Self::Tool {
+                rules: next_list(iterator, |i| Ok(ToolRule {
+                    blocks: next_block_set(i)?,
+                    speed: next_optional(i, next_f32)?,
+                    correct_for_drops: next_optional(i, next_bool)?,
+                }))?,
+                default_mining_speed: next_f32(iterator)?,
+                damage_per_block: next_varint(iterator)?,
+            },
+            23 => {
+                let levels = next_list(iterator, |i| Ok((next_varint(i)?, next_varint(i)?)))?;
+                Self::StoredEnchantments { levels, show_in_tooltip: next_bool(iterator)? }
+            }
+            24 => Self::DyedColor { rgb: next_i32(iterator)?, show_in_tooltip: next_bool(iterator)? },
+            25 => Self::MapColor(next_i32(iterator)?),
+            26 => Self::MapId(next_varint(iterator)?),
+            27 => Self::MapDecorations(next_nbt(iterator)?),
+            28 => Self::MapPostProcessing(next_varint(iterator)?),
+            29 => Self::ChargedProjectiles(next_list(iterator, |i| next_nested_slot(i, depth + 1))?),
+            30 => Self::BundleContents(next_list(iterator, |i| next_nested_slot(i, depth + 1))?),
+            31 => Self::PotionContents {
+                potion: next_optional(iterator, next_varint)?,
+                custom_color: next_optional(iterator, next_i32)?,
+                custom_effects: next_list(iterator, next_effect)?,
+            },
+            32 => Self::SuspiciousStewEffects(next_list(iterator, |i| Ok((next_varint(i)?, next_varint(i)?)))?),
+            33 => Self::WritableBookContent(next_list(iterator, |i| next_filterable(i, next_string))?),
+            34 => Self::WrittenBookContent {
+                title: next_filterable(iterator, next_string)?,
+                author: next_string(iterator)?,
+                generation: next_varint(iterator)?,
+                pages: next_list(iterator, |i| next_filterable(i, next_nbt))?,
+                resolved: next_bool(iterator)?,
+            },
+            35 => Self::Trim { material: next_holder(iterator, type_id)?, pattern: next_holder(iterator, type_id)?, show_in_tooltip: next_bool(iterator)? },
+            36 => Self::DebugStickState(next_nbt(iterator)?),
+            37 => Self::EntityData(next_nbt(iterator)?),
+            38 => Self::BucketEntityData(next_nbt(iterator)?),
+            39 => Self::BlockEntityData(next_nbt(iterator)?),
+            40 => Self::Instrument(next_holder(iterator, type_id)?),
+            41 => Self::OminousBottleAmplifier(next_varint(iterator)?),
+            // A song or the name of one
+            42 => Self::JukeboxPlayable {
+                song: if next_bool(iterator)? { JukeboxSong::Id(next_holder(iterator, type_id)?) } else { JukeboxSong::Name(next_string(iterator)?) },
+                show_in_tooltip: next_bool(iterator)?,
+            },
+            44 => Self::LodestoneTracker {
+                target: next_optional(iterator, |i| {
+                    let dimension = next_string(i)?;
+                    let (x, y, z) = unpack_position(next_u64(i)?);
+                    Ok((dimension, x, y, z))
+                })?,
+                tracked: next_bool(iterator)?,
+            },
+            45 => Self::FireworkExplosion(next_firework_explosion(iterator)?),
+            46 => Self::Fireworks { flight_duration: next_varint(iterator)?, explosions: next_list(iterator, next_firework_explosion)? },
+            47 => Self::Profile {
+                name: next_optional(iterator, next_string)?,
+                id: next_optional(iterator, next_u128)?,
+                properties: next_list(iterator, |i| Ok((next_string(i)?, next_string(i)?, next_optional(i, next_string)?)))?,
+            },
+            48 => Self::NoteBlockSound(next_string(iterator)?),
+            49 => Self::BannerPatterns(next_list(iterator, |i| Ok((next_holder(i, type_id)?, next_varint(i)?)))?),
+            50 => Self::BaseColor(next_varint(iterator)?),
+            51 => Self::PotDecorations(next_list(iterator, next_varint)?),
+            52 => Self::Container(next_list(iterator, |i| next_nested_slot(i, depth + 1))?),
+            53 => Self::BlockState(next_list(iterator, |i| Ok((next_string(i)?, next_string(i)?)))?),
+            54 => Self::Bees(next_list(iterator, |i| Ok((next_nbt(i)?, next_varint(i)?, next_varint(i)?)))?),
+            _ => return Err(ServerError::UnsupportedItemComponent(type_id)),
+        })
+    }
+
+    /// Writes the data of the component, without its type id
+    pub(crate) fn write(&self, builder: PacketBuilder) -> PacketBuilder {
+        match self {
+            Self::CustomData(nbt) | Self::CustomName(nbt) | Self::ItemName(nbt) | Self::MapDecorations(nbt)
+            | Self::DebugStickState(nbt) | Self::EntityData(nbt) | Self::BucketEntityData(nbt) | Self::BlockEntityData(nbt) => builder.add_nbt(nbt),
+            Self::MaxStackSize(value) | Self::MaxDamage(value) | Self::Damage(value) | Self::Rarity(value) | Self::CustomModelData(value)
+            | Self::RepairCost(value) | Self::MapId(value) | Self::MapPostProcessing(value) | Self::OminousBottleAmplifier(value)
+            | Self::BaseColor(value) => builder.add_varint(*value),
+            Self::Unbreakable { show_in_tooltip } | Self::EnchantmentGlintOverride(show_in_tooltip) => builder.add_bool(*show_in_tooltip),
+            Self::Lore(lines) => lines.iter().fold(builder.add_varint(lines.len() as i32), |b, line| b.add_nbt(line)),
+            Self::Enchantments { levels, show_in_tooltip } | Self::StoredEnchantments { levels, show_in_tooltip } => {
+                levels.iter()
+                    .fold(builder.add_varint(levels.len() as i32), |b, (id, level)| b.add_varint(*id).add_varint(*level))
+                    .add_bool(*show_in_tooltip)
+            }
+            Self::CanPlaceOn { predicates, show_in_tooltip } | Self::CanBreak { predicates, show_in_tooltip } => {
+                add_list(builder, predicates, add_block_predicate).add_bool(*show_in_tooltip)
+            }
+            Self::AttributeModifiers { modifiers, show_in_tooltip } => {
+                add_list(builder, modifiers, |b, modifier| {
+                    b.add_varint(modifier.attribute)
+                        .add_string(&modifier.id)
+                        .add_double(modifier.amount)
+                        .add_varint(modifier.operation)
+                        .add_varint(modifier.slot)
+                }).add_bool(*show_in_tooltip)
+            }
+            Self::HideAdditionalTooltip | Self::HideTooltip | Self::CreativeSlotLock | Self::FireResistant | Self::Saved(_) => builder,
+            Self::IntangibleProjectile => builder.add_nbt(&NbtTag::Compound(String::new(), vec![])),
+            Self::Food { nutrition, saturation, can_always_eat, eat_seconds, using_converts_to, effects } => {
+                let builder = builder.add_varint(*nutrition).add_float(*saturation).add_bool(*can_always_eat).add_float(*eat_seconds);
+                let builder = add_optional(builder, using_converts_to, |b, item| b.add_slot(item));
+                add_list(builder, effects, |b, (effect, probability)| add_effect(b, effect).add_float(*probability))
+            }
+            Self::Tool { rules, default_mining_speed, damage_per_block } => {
+                add_list(builder, rules, |b, rule| {
+                    let b = add_optional(add_block_set(b, &rule.blocks), &rule.speed, |b, speed| b.add_float(*speed));
+                    add_optional(b, &rule.correct_for_drops, |b, correct| b.add_bool(*correct))
+                }).add_float(*default_mining_speed).add_varint(*damage_per_block)
+            }
+            Self::DyedColor { rgb, show_in_tooltip } => builder.add_int(*rgb).add_bool(*show_in_tooltip),
+            Self::MapColor(color) => builder.add_int(*color),
+            Self::ChargedProjectiles(items) | Self::BundleContents(items) | Self::Container(items) => {
+                items.iter().fold(builder.add_varint(items.len() as i32), |b, item| b.add_slot(item))
+            }
+            Self::PotionContents { potion, custom_color, custom_effects } => {
+                let builder = add_optional(builder, potion, |b, potion| b.add_varint(*potion));
+                let builder = add_optional(builder, custom_color, |b, color| b.add_int(*color));
+                add_list(builder, custom_effects, add_effect)
+            }
+            Self::SuspiciousStewEffects(effects) => {
+                effects.iter().fold(builder.add_varint(effects.len() as i32), |b, (id, duration)| b.add_varint(*id).add_varint(*duration))
+            }
+            Self::WritableBookContent(pages) => {
+                pages.iter().fold(builder.add_varint(pages.len() as i32), |b, (raw, filtered)| {
+                    add_optional(b.add_string(raw), filtered, |b, filtered| b.add_string(filtered))
+                })
+            }
+            Self::WrittenBookContent { title, author, generation, pages, resolved } => {
+                let builder = add_optional(builder.add_string(&title.0), &title.1, |b, filtered| b.add_string(filtered))
+                    .add_string(author)
+                    .add_varint(*generation);
+                pages.iter()
+                    .fold(builder.add_varint(pages.len() as i32), |b, (raw, filtered)| {
+                        add_optional(b.add_nbt(raw), filtered, |b, filtered| b.add_nbt(filtered))
+                    })
+                    .add_bool(*resolved)
+            }
+            Self::Trim { material, pattern, show_in_tooltip } => add_holder(add_holder(builder, *material), *pattern).add_bool(*show_in_tooltip),
+            Self::Instrument(instrument) => add_holder(builder, *instrument),
+            Self::JukeboxPlayable { song, show_in_tooltip } => {
+                let builder = match song {
+                    JukeboxSong::Id(id) => add_holder(builder.add_bool(true), *id),
+                    JukeboxSong::Name(name) => builder.add_bool(false).add_string(name),
+                };
+                builder.add_bool(*show_in_tooltip)
+            }
+            Self::LodestoneTracker { target, tracked } => {
+                add_optional(builder, target, |b, (dimension, x, y, z)| b.add_string(dimension).add_long(BlockPos::new(*x, *y, *z).packed()))
+                    .add_bool(*tracked)
+            }
+            Self::FireworkExplosion(explosion) => add_firework_explosion(builder, explosion),
+            Self::Fireworks { flight_duration, explosions } => add_list(builder.add_varint(*flight_duration), explosions, add_firework_explosion),
+            Self::Profile { name, id, properties } => {
+                let builder = add_optional(builder, name, |b, name| b.add_string(name));
+                let builder = add_optional(builder, id, |b, id| b.add_uuid(Uuid::from_u128(*id)));
+                add_list(builder, properties, |b, (name, value, signature)| {
+                    add_optional(b.add_string(name).add_string(value), signature, |b, signature| b.add_string(signature))
+                })
+            }
+            Self::NoteBlockSound(sound) => builder.add_string(sound),
+            Self::BannerPatterns(layers) => add_list(builder, layers, |b, (pattern, color)| add_holder(b, *pattern).add_varint(*color)),
+            Self::PotDecorations(items) => items.iter().fold(builder.add_varint(items.len() as i32), |b, item| b.add_varint(*item)),
+            Self::BlockState(properties) => {
+                properties.iter().fold(builder.add_varint(properties.len() as i32), |b, (name, value)| b.add_string(name).add_string(value))
+            }
+            Self::Bees(bees) => {
+                add_list(builder, bees, |b, (entity_data, ticks, min_ticks)| b.add_nbt(entity_data).add_varint(*ticks).add_varint(*min_ticks))
+            }
+        }
+    }
+
+    /// The protocol id of the component type, `None` if it isn't sent to clients
+    pub fn network_type_id(&self) -> Option<i32> {
+        match self {
+            Self::Saved(_) => None,
+            _ => component_type_id(self.type_name()),
+        }
+    }
+
+    pub fn type_name(&self) -> &str {
+        let id = match self {
+            Self::CustomData(_) => 0,
+            Self::MaxStackSize(_) => 1,
+            Self::MaxDamage(_) => 2,
+            Self::Damage(_) => 3,
+            Self::Unbreakable { .. } => 4,
+            Self::CustomName(_) => 5,
+            Self::ItemName(_) => 6,
+            Self::Lore(_) => 7,
+            Self::Rarity(_) => 8,
+            Self::Enchantments { .. } => 9,
+            Self::CanPlaceOn { .. } => 10,
+            Self::CanBreak { .. } => 11,
+            Self::AttributeModifiers { .. } => 12,
+            Self::CustomModelData(_) => 13,
+            Self::HideAdditionalTooltip => 14,
+            Self::HideTooltip => 15,
+            Self::RepairCost(_) => 16,
+            Self::CreativeSlotLock => 17,
+            Self::EnchantmentGlintOverride(_) => 18,
+            Self::IntangibleProjectile => 19,
+            Self::Food { .. } => 20,
+            Self::FireResistant => 21,
+            Self::Tool { .. } => 22,
+            Self::StoredEnchantments { .. } => 23,
+            Self::DyedColor { .. } => 24,
+            Self::MapColor(_) => 25,
+            Self::MapId(_) => 26,
+            Self::MapDecorations(_) => 27,
+            Self::MapPostProcessing(_) => 28,
+            Self::ChargedProjectiles(_) => 29,
+            Self::BundleContents(_) => 30,
+            Self::PotionContents { .. } => 31,
+            Self::SuspiciousStewEffects(_) => 32,
+            Self::WritableBookContent(_) => 33,
+            Self::WrittenBookContent { .. } => 34,
+            Self::Trim { .. } => 35,
+            Self::DebugStickState(_) => 36,
+            Self::EntityData(_) => 37,
+            Self::BucketEntityData(_) => 38,
+            Self::BlockEntityData(_) => 39,
+            Self::Instrument(_) => 40,
+            Self::OminousBottleAmplifier(_) => 41,
+            Self::JukeboxPlayable { .. } => 42,
+            Self::LodestoneTracker { .. } => 44,
+            Self::FireworkExplosion(_) => 45,
+            Self::Fireworks { .. } => 46,
+            Self::Profile { .. } => 47,
+            Self::NoteBlockSound(_) => 48,
+            Self::BannerPatterns(_) => 49,
+            Self::BaseColor(_) => 50,
+            Self::PotDecorations(_) => 51,
+            Self::Container(_) => 52,
+            Self::BlockState(_) => 53,
+            Self::Bees(_) => 54,
+            Self::Saved(nbt) => return nbt.name(),
+        };
+        COMPONENT_TYPES.get(id as usize).copied().unwrap_or_default()
+    }
+
+    /// A component from the `components` compound of a saved item stack, named by its type
+    pub fn from_nbt(nbt: &NbtTag, registry: &ItemRegistry) -> Self {
+        Self::convert_nbt(nbt, registry).unwrap_or_else(|| Self::Saved(nbt.clone()))
+    }
+
+    fn convert_nbt(nbt: &NbtTag, registry: &ItemRegistry) -> Option<Self> {
+        let name: &str = nbt.name();
+        Some(match name {
+            "minecraft:custom_data" => Self::CustomData(compound(nbt)?),
+            "minecraft:max_stack_size" => Self::MaxStackSize(nbt.as_i32()?),
+            "minecraft:max_damage" => Self::MaxDamage(nbt.as_i32()?),
+            "minecraft:damage" => Self::Damage(nbt.as_i32()?),
+            "minecraft:unbreakable" => Self::Unbreakable { show_in_tooltip: show_in_tooltip(nbt) },
+            "minecraft:custom_name" => Self::CustomName(text_from_json(nbt)?),
+            "minecraft:item_name" => Self::ItemName(text_from_json(nbt)?),
+            "minecraft:lore" => Self::Lore(nbt.as_list()?.iter().map(text_from_json).collect::<Option<_>>()?),
+            "minecraft:rarity" => Self::Rarity(RARITIES.iter().position(|r| Some(*r) == nbt.as_str())? as i32),
+            "minecraft:enchantments" => {
+                let (levels, show_in_tooltip) = enchantments_from_nbt(nbt, registry)?;
+                Self::Enchantments { levels, show_in_tooltip }
+            }
+            "minecraft:can_place_on" => {
+                let (predicates, show_in_tooltip) = block_predicates_from_nbt(nbt, registry)?;
+                Self::CanPlaceOn { predicates, show_in_tooltip }
+            }
+            "minecraft:can_break" => {
+                let (predicates, show_in_tooltip) = block_predicates_from_nbt(nbt, registry)?;
+                Self::CanBreak { predicates, show_in_tooltip }
+            }
+            // Either `{modifiers: [...], show_in_tooltip: 0b}` or just the modifiers
+            "minecraft:attribute_modifiers" => Self::AttributeModifiers {
+                modifiers: nbt.child("modifiers").unwrap_or(nbt).as_list()?.iter()
+                    .map(|modifier| Some(AttributeModifier {
+                        attribute: registry.registry_id("attribute", modifier.child("type")?.as_str()?)?,
+                        id: modifier.child("id")?.as_str()?.to_string(),
+                        amount: modifier.child("amount")?.as_f64()?,
+                        operation: index_of(&ATTRIBUTE_OPERATIONS, modifier.child("operation")?)?,
+                        slot: optional_child(modifier, "slot", |slot| index_of(&EQUIPMENT_SLOT_GROUPS, slot))?.unwrap_or(0),
+                    }))
+                    .collect::<Option<_>>()?,
+                show_in_tooltip: show_in_tooltip(nbt),
+            },
+            "minecraft:custom_model_data" => Self::CustomModelData(nbt.as_i32()?),
+            "minecraft:hide_additional_tooltip" => Self::HideAdditionalTooltip,
+            "minecraft:hide_tooltip" => Self::HideTooltip,
+            "minecraft:repair_cost" => Self::RepairCost(nbt.as_i32()?),
+            "minecraft:enchantment_glint_override" => Self::EnchantmentGlintOverride(nbt.as_i8()? != 0),
+            "minecraft:intangible_projectile" => Self::IntangibleProjectile,
+            "minecraft:food" => Self::Food {
+                nutrition: nbt.child("nutrition")?.as_i32()?,
+                saturation: nbt.child("saturation")?.as_f32()?,
+                can_always_eat: bool_child(nbt, "can_always_eat", false),
+                eat_seconds: optional_child(nbt, "eat_seconds", |s| s.as_f32())?.unwrap_or(1.6),
+                using_converts_to: optional_child(nbt, "using_converts_to", |item| item_from_nbt(item, registry))?,
+                effects: optional_child(nbt, "effects", |effects| effects.as_list()?.iter()
+                    .map(|effect| Some((
+                        effect_from_nbt(effect.child("effect")?, registry)?,
+                        optional_child(effect, "probability", |p| p.as_f32())?.unwrap_or(1.0),
+                    )))
+                    .collect())?
+                    .unwrap_or_default(),
+            },
+            "minecraft:fire_resistant" => Self::FireResistant,
+            "minecraft:tool" => Self::Tool {
+                rules: nbt.child("rules")?.as_list()?.iter()
+                    .map(|rule| Some(ToolRule {
+                        blocks: block_set_from_nbt(rule.child("blocks")?, registry)?,
+                        speed: optional_child(rule, "speed", |s| s.as_f32())?,
+                        correct_for_drops: optional_child(rule, "correct_for_drops", |c| c.as_i8().map(|c| c != 0))?,
+                    }))
+                    .collect::<Option<_>>()?,
+                default_mining_speed: optional_child(nbt, "default_mining_speed", |s| s.as_f32())?.unwrap_or(1.0),
+                damage_per_block: optional_child(nbt, "damage_per_block", |d| d.as_i32())?.unwrap_or(1),
+            },
+            "minecraft:stored_enchantments" => {
+                let (levels, show_in_tooltip) = enchantments_from_nbt(nbt, registry)?;
+                Self::StoredEnchantments { levels, show_in_tooltip }
+            }
+            // Either just the color or with show_in_tooltip
+            "minecraft:dyed_color" => match nbt.as_i32() {
+                Some(rgb) => Self::DyedColor { rgb, show_in_tooltip: true },
+                None => Self::DyedColor { rgb: nbt.child("rgb")?.as_i32()?, show_in_tooltip: show_in_tooltip(nbt) },
+            },
+            "minecraft:map_color" => Self::MapColor(nbt.as_i32()?),
+            "minecraft:map_id" => Self::MapId(nbt.as_i32()?),
+            "minecraft:map_decorations" => Self::MapDecorations(compound(nbt)?),
+            "minecraft:charged_projectiles" => Self::ChargedProjectiles(items_from_nbt(nbt, registry)?),
+            "minecraft:bundle_contents" => Self::BundleContents(items_from_nbt(nbt, registry)?),
+            // Either just the potion or `{potion, custom_color, custom_effects}`
+            "minecraft:potion_contents" => match nbt {
+                NbtTag::String(_, potion) => Self::PotionContents { potion: Some(registry.registry_id("potion", potion)?), custom_color: None, custom_effects: vec![] },
+                NbtTag::Compound(..) => Self::PotionContents {
+                    potion: optional_child(nbt, "potion", |p| registry.registry_id("potion", p.as_str()?))?,
+                    custom_color: optional_child(nbt, "custom_color", |c| c.as_i32())?,
+                    custom_effects: optional_child(nbt, "custom_effects", |effects| effects.as_list()?.iter()
+                        .map(|effect| effect_from_nbt(effect, registry))
+                        .collect())?
+                        .unwrap_or_default(),
+                },
+                _ => return None,
+            },
+            "minecraft:suspicious_stew_effects" => Self::SuspiciousStewEffects(nbt.as_list()?.iter()
+                .map(|effect| {
+                    let id = registry.effect_id(effect.child("id")?.as_str()?)?;
+                    Some((id, effect.child("duration").and_then(|d| d.as_i32()).unwrap_or(160)))
+                })
+                .collect::<Option<_>>()?),
+            "minecraft:writable_book_content" => Self::WritableBookContent(match nbt.child("pages") {
+                Some(pages) => pages.as_list()?.iter()
+                    .map(|page| filterable_from_nbt(page, |p| p.as_str().map(|s| s.to_string())))
+                    .collect::<Option<_>>()?,
+                None => vec![],
+            }),
+            "minecraft:written_book_content" => Self::WrittenBookContent {
+                title: filterable_from_nbt(nbt.child("title")?, |t| t.as_str().map(|s| s.to_string()))?,
+                author: nbt.child("author")?.as_str()?.to_string(),
+                generation: nbt.child("generation").and_then(|g| g.as_i32()).unwrap_or(0),
+                pages: match nbt.child("pages") {
+                    Some(pages) => pages.as_list()?.iter().map(|page| filterable_from_nbt(page, text_from_json)).collect::<Option<_>>()?,
+                    None => vec![],
+                },
+                resolved: nbt.child("resolved").and_then(|r| r.as_i8()).is_some_and(|r| r != 0),
+            },
+            // Materials and patterns that are written out instead of named stay saved
+            "minecraft:trim" => Self::Trim {
+                material: registry.registry_id("trim_material", nbt.child("material")?.as_str()?)?,
+                pattern: registry.registry_id("trim_pattern", nbt.child("pattern")?.as_str()?)?,
+                show_in_tooltip: show_in_tooltip(nbt),
+            },
+            "minecraft:debug_stick_state" => Self::DebugStickState(compound(nbt)?),
+            "minecraft:entity_data" => Self::EntityData(compound(nbt)?),
+            "minecraft:bucket_entity_data" => Self::BucketEntityData(compound(nbt)?),
+            "minecraft:block_entity_data" => Self::BlockEntityData(compound(nbt)?),
+            "minecraft:instrument" => Self::Instrument(registry.registry_id("instrument", nbt.as_str()?)?),
+            "minecraft:ominous_bottle_amplifier" => Self::OminousBottleAmplifier(nbt.as_i32()?),
+            "minecraft:jukebox_playable" => Self::JukeboxPlayable {
+                song: JukeboxSong::Name(nbt.child("song")?.as_str()?.to_string()),
+                show_in_tooltip: show_in_tooltip(nbt),
+            },
+            "minecraft:lodestone_tracker" => Self::LodestoneTracker {
+                target: optional_child(nbt, "target", |target| {
+                    let &[x, y, z] = &target.child("pos")?.as_int_array()?[..] else {
+                        return None;
+                    };
+                    Some((target.child("dimension")?.as_str()?.to_string(), x, y, z))
+                })?,
+                tracked: bool_child(nbt, "tracked", true),
+            },
+            "minecraft:firework_explosion" => Self::FireworkExplosion(firework_explosion_from_nbt(nbt)?),
+            "minecraft:fireworks" => Self::Fireworks {
+                flight_duration: optional_child(nbt, "flight_duration", unsigned_byte)?.unwrap_or(0),
+                explosions: optional_child(nbt, "explosions", |explosions| explosions.as_list()?.iter().map(firework_explosion_from_nbt).collect())?
+                    .unwrap_or_default(),
+            },
+            // Either just the name or `{name, id, properties}`
+            "minecraft:profile" => match nbt {
+                NbtTag::String(_, name) => Self::Profile { name: Some(name.clone()), id: None, properties: vec![] },
+                NbtTag::Compound(..) => Self::Profile {
+                    name: optional_child(nbt, "name", |n| n.as_str().map(|n| n.to_string()))?,
+                    id: optional_child(nbt, "id", uuid_from_nbt)?,
+                    properties: optional_child(nbt, "properties", properties_from_nbt)?.unwrap_or_default(),
+                },
+                _ => return None,
+            },
+            "minecraft:note_block_sound" => Self::NoteBlockSound(nbt.as_str()?.to_string()),
+            "minecraft:banner_patterns" => Self::BannerPatterns(nbt.as_list()?.iter()
+                .map(|layer| Some((
+                    registry.registry_id("banner_pattern", layer.child("pattern")?.as_str()?)?,
+                    index_of(&DYE_COLORS, layer.child("color")?)?,
+                )))
+                .collect::<Option<_>>()?),
+            "minecraft:base_color" => Self::BaseColor(DYE_COLORS.iter().position(|c| Some(*c) == nbt.as_str())? as i32),
+            "minecraft:pot_decorations" => Self::PotDecorations(nbt.as_list()?.iter()
+                .map(|item| registry.id(item.as_str()?))
+                .collect::<Option<_>>()?),
+            "minecraft:container" => {
+                let mut slots = vec![];
+                for entry in nbt.as_list()? {
+                    let slot = entry.child("slot")?.as_i32().filter(|s| (0..MAX_CONTAINER_SLOTS).contains(s))? as usize;
+                    if slots.len() <= slot {
+                        slots.resize(slot + 1, Slot::empty());
+                    }
+                    slots[slot] = item_from_nbt(entry.child("item")?, registry)?;
+                }
+                Self::Container(slots)
+            }
+            "minecraft:block_state" => Self::BlockState(nbt.as_list()?.iter()
+                .map(|property| Some((property.name().to_string(), property.as_str()?.to_string())))
+                .collect::<Option<_>>()?),
+            "minecraft:bees" => Self::Bees(nbt.as_list()?.iter()
+                .map(|bee| Some((
+                    optional_child(bee, "entity_data", compound)?.unwrap_or_else(|| NbtTag::Compound(String::new(), vec![])),
+                    bee.child("ticks_in_hive")?.as_i32()?,
+                    bee.child("min_ticks_in_hive")?.as_i32()?,
+                )))
+                .collect::<Option<_>>()?),
+            _ => return None,
+        })
+    }
+
+    /// The component as saved in the `components` compound of an item stack. `None` for components vanilla
+    /// doesn't save and ones referring to registry entries the server doesn't know.
+    pub fn to_nbt(&self, registry: &ItemRegistry) -> Option<NbtTag> {
+        let name = self.type_name().to_string();
+        Some(match self {
+            Self::CustomData(nbt) | Self::MapDecorations(nbt) | Self::DebugStickState(nbt) | Self::EntityData(nbt)
+            | Self::BucketEntityData(nbt) | Self::BlockEntityData(nbt) => renamed(nbt, &name),
+            Self::MaxStackSize(value) | Self::MaxDamage(value) | Self::Damage(value) | Self::CustomModelData(value)
+            | Self::RepairCost(value) | Self::MapColor(value) | Self::MapId(value) | Self::OminousBottleAmplifier(value) => NbtTag::Int(name, *value),
+            Self::Unbreakable { show_in_tooltip } => NbtTag::Compound(name, vec![
+                NbtTag::Byte("show_in_tooltip".to_string(), *show_in_tooltip as i8),
+            ]),
+            Self::CustomName(text) | Self::ItemName(text) => text_to_json(name, text),
+            Self::Lore(lines) => NbtTag::List(name, lines.iter().map(|line| text_to_json(String::new(), line)).collect()),
+            Self::Rarity(rarity) => NbtTag::String(name, RARITIES.get(*rarity as usize)?.to_string()),
+            Self::Enchantments { levels, show_in_tooltip } | Self::StoredEnchantments { levels, show_in_tooltip } => {
+                let levels = levels.iter()
+                    .filter_map(|(id, level)| Some(NbtTag::Int(registry.enchantment_name(*id)?.to_string(), *level)))
+                    .collect();
+                NbtTag::Compound(name, vec![
+                    NbtTag::Compound("levels".to_string(), levels),
+                    NbtTag::Byte("show_in_tooltip".to_string(), *show_in_tooltip as i8),
+                ])
+            }
+            Self::CanPlaceOn { predicates, show_in_tooltip } | Self::CanBreak { predicates, show_in_tooltip } => NbtTag::Compound(name, vec![
+                NbtTag::List("predicates".to_string(), predicates.iter().map(|p| block_predicate_to_nbt(p, registry)).collect::<Option<_>>()?),
+                NbtTag::Byte("show_in_tooltip".to_string(), *show_in_tooltip as i8),
+            ]),
+            Self::AttributeModifiers { modifiers, show_in_tooltip } => NbtTag::Compound(name, vec![
+                NbtTag::List("modifiers".to_string(), modifiers.iter()
+                    .map(|modifier| Some(NbtTag::Compound(String::new(), vec![
+                        NbtTag::String("type".to_string(), registry.registry_name("attribute", modifier.attribute)?.to_string()),
+                        NbtTag::String("id".to_string(), modifier.id.clone()),
+                        NbtTag::Double("amount".to_string(), modifier.amount),
+                        NbtTag::String("operation".to_string(), ATTRIBUTE_OPERATIONS.get(modifier.operation as usize)?.to_string()),
+                        NbtTag::String("slot".to_string(), EQUIPMENT_SLOT_GROUPS.get(modifier.slot as usize)?.to_string()),
+                    ])))
+                    .collect::<Option<_>>()?),
+                NbtTag::Byte("show_in_tooltip".to_string(), *show_in_tooltip as i8),
+            ]),
+            Self::HideAdditionalTooltip | Self::HideTooltip | Self::IntangibleProjectile | Self::FireResistant => NbtTag::Compound(name, vec![]),
+            Self::CreativeSlotLock | Self::MapPostProcessing(_) => return None,
+            Self::EnchantmentGlintOverride(glint) => NbtTag::Byte(name, *glint as i8),
+            Self::DyedColor { rgb, show_in_tooltip } => NbtTag::Compound(name, vec![
+                NbtTag::Int("rgb".to_string(), *rgb),
+                NbtTag::Byte("show_in_tooltip".to_string(), *show_in_tooltip as i8),
+            ]),
+            Self::Food { nutrition, saturation, can_always_eat, eat_seconds, using_converts_to, effects } => {
+                let mut children = vec![
+                    NbtTag::Int("nutrition".to_string(), *nutrition),
+                    NbtTag::Float("saturation".to_string(), *saturation),
+                    NbtTag::Byte("can_always_eat".to_string(), *can_always_eat as i8),
+                    NbtTag::Float("eat_seconds".to_string(), *eat_seconds),
+                    NbtTag::List("effects".to_string(), effects.iter()
+                        .map(|(effect, probability)| Some(NbtTag::Compound(String::new(), vec![
+                            effect_to_nbt("effect".to_string(), effect, registry)?,
+                            NbtTag::Float("probability".to_string(), *probability),
+                        ])))
+                        .collect::<Option<_>>()?),
+                ];
+                children.extend(using_converts_to.as_ref().and_then(|item| item.to_nbt(registry)).map(|item| renamed(&item, "using_converts_to")));
+                NbtTag::Compound(name, children)
+            }
+            Self::Tool { rules, default_mining_speed, damage_per_block } => NbtTag::Compound(name, vec![
+                NbtTag::List("rules".to_string(), rules.iter()
+                    .map(|rule| {
+                        let mut children = vec![block_set_to_nbt("blocks".to_string(), &rule.blocks, registry)?];
+                        children.extend(rule.speed.map(|speed| NbtTag::Float("speed".to_string(), speed)));
+                        children.extend(rule.correct_for_drops.map(|correct| NbtTag::Byte("correct_for_drops".to_string(), correct as i8)));
+                        Some(NbtTag::Compound(String::new(), children))
+                    })
+                    .collect::<Option<_>>()?),
+                NbtTag::Float("default_mining_speed".to_string(), *default_mining_speed),
+                NbtTag::Int("damage_per_block".to_string(), *damage_per_block),
+            ]),
+            Self::ChargedProjectiles(items) | Self::BundleContents(items) => {
+                NbtTag::List(name, items.iter().filter_map(|item| item.to_nbt(registry)).collect())
+            }
+            Self::PotionContents { potion, custom_color, custom_effects } => {
+                let mut children = vec![];
+                if let Some(potion) = potion {
+                    children.push(NbtTag::String("potion".to_string(), registry.registry_name("potion", *potion)?.to_string()));
+                }
+                children.extend(custom_color.map(|color| NbtTag::Int("custom_color".to_string(), color)));
+                if !custom_effects.is_empty() {
+                    children.push(NbtTag::List("custom_effects".to_string(), custom_effects.iter()
+                        .map(|effect| effect_to_nbt(String::new(), effect, registry))
+                        .collect::<Option<_>>()?));
+                }
+                NbtTag::Compound(name, children)
+            }
+            Self::SuspiciousStewEffects(effects) => NbtTag::List(name, effects.iter()
+                .filter_map(|(id, duration)| Some(NbtTag::Compound(String::new(), vec![
+                    NbtTag::String("id".to_string(), registry.effect_name(*id)?.to_string()),
+                    NbtTag::Int("duration".to_string(), *duration),
+                ])))
+                .collect()),
+            Self::WritableBookContent(pages) => NbtTag::Compound(name, vec![
+                NbtTag::List("pages".to_string(), pages.iter()
+                    .map(|(raw, filtered)| filterable_to_nbt(
+                        NbtTag::String("raw".to_string(), raw.clone()),
+                        filtered.as_ref().map(|filtered| NbtTag::String("filtered".to_string(), filtered.clone())),
+                    ))
+                    .collect()),
+            ]),
+            Self::WrittenBookContent { title, author, generation, pages, resolved } => NbtTag::Compound(name, vec![
+                renamed(&filterable_to_nbt(
+                    NbtTag::String("raw".to_string(), title.0.clone()),
+                    title.1.as_ref().map(|filtered| NbtTag::String("filtered".to_string(), filtered.clone())),
+                ), "title"),
+                NbtTag::String("author".to_string(), author.clone()),
+                NbtTag::Int("generation".to_string(), *generation),
+                NbtTag::List("pages".to_string(), pages.iter()
+                    .map(|(raw, filtered)| filterable_to_nbt(
+                        text_to_json("raw".to_string(), raw),
+                        filtered.as_ref().map(|filtered| text_to_json("filtered".to_string(), filtered)),
+                    ))
+                    .collect()),
+                NbtTag::Byte("resolved".to_string(), *resolved as i8),
+            ]),
+            Self::Trim { material, pattern, show_in_tooltip } => NbtTag::Compound(name, vec![
+                NbtTag::String("material".to_string(), registry.registry_name("trim_material", *material)?.to_string()),
+                NbtTag::String("pattern".to_string(), registry.registry_name("trim_pattern", *pattern)?.to_string()),
+                NbtTag::Byte("show_in_tooltip".to_string(), *show_in_tooltip as i8),
+            ]),
+            Self::Instrument(instrument) => NbtTag::String(name, registry.registry_name("instrument", *instrument)?.to_string()),
+            Self::JukeboxPlayable { song, show_in_tooltip } => NbtTag::Compound(name, vec![
+                NbtTag::String("song".to_string(), match song {
+                    JukeboxSong::Id(id) => registry.registry_name("jukebox_song", *id)?.to_string(),
+                    JukeboxSong::Name(song) => song.clone(),
+                }),
+                NbtTag::Byte("show_in_tooltip".to_string(), *show_in_tooltip as i8),
+            ]),
+            Self::LodestoneTracker { target, tracked } => {
+                let mut children = vec![NbtTag::Byte("tracked".to_string(), *tracked as i8)];
+                if let Some((dimension, x, y, z)) = target {
+                    children.push(NbtTag::Compound("target".to_string(), vec![
+                        NbtTag::String("dimension".to_string(), dimension.clone()),
+                        NbtTag::IntArray("pos".to_string(), vec![*x, *y, *z]),
+                    ]));
+                }
+                NbtTag::Compound(name, children)
+            }
+            Self::FireworkExplosion(explosion) => firework_explosion_to_nbt(name, explosion)?,
+            Self::Fireworks { flight_duration, explosions } => NbtTag::Compound(name, vec![
+                NbtTag::Byte("flight_duration".to_string(), *flight_duration as u8 as i8),
+                NbtTag::List("explosions".to_string(), explosions.iter()
+                    .map(|explosion| firework_explosion_to_nbt(String::new(), explosion))
+                    .collect::<Option<_>>()?),
+            ]),
+            Self::Profile { name: player_name, id, properties } => {
+                let mut children = vec![];
+                children.extend(player_name.as_ref().map(|player_name| NbtTag::String("name".to_string(), player_name.clone())));
+                children.extend(id.map(|id| NbtTag::IntArray("id".to_string(), (0..4).rev().map(|i| (id >> (i * 32)) as u32 as i32).collect())));
+                children.push(NbtTag::List("properties".to_string(), properties.iter()
+                    .map(|(property, value, signature)| {
+                        let mut children = vec![
+                            NbtTag::String("name".to_string(), property.clone()),
+                            NbtTag::String("value".to_string(), value.clone()),
+                        ];
+                        children.extend(signature.as_ref().map(|signature| NbtTag::String("signature".to_string(), signature.clone())));
+                        NbtTag::Compound(String::new(), children)
+                    })
+                    .collect()));
+                NbtTag::Compound(name, children)
+            }
+            Self::NoteBlockSound(sound) => NbtTag::String(name, sound.clone()),
+            Self::BannerPatterns(layers) => NbtTag::List(name, layers.iter()
+                .map(|(pattern, color)| Some(NbtTag::Compound(String::new(), vec![
+                    NbtTag::String("pattern".to_string(), registry.registry_name("banner_pattern", *pattern)?.to_string()),
+                    NbtTag::String("color".to_string(), DYE_COLORS.get(*color as usize)?.to_string()),
+                ])))
+                .collect::<Option<_>>()?),
+            Self::BaseColor(color) => NbtTag::String(name, DYE_COLORS.get(*color as usize)?.to_string()),
+            Self::PotDecorations(items) => NbtTag::List(name, items.iter()
+                .filter_map(|item| Some(NbtTag::String(String::new(), registry.name(*item)?.to_string())))
+                .collect()),
+            Self::Container(items) => NbtTag::List(name, items.iter().enumerate()
+                .filter_map(|(slot, item)| Some(NbtTag::Compound(String::new(), vec![
+                    NbtTag::Int("slot".to_string(), slot as i32),
+                    renamed(&item.to_nbt(registry)?, "item"),
+                ])))
+                .collect()),
+            Self::BlockState(properties) => NbtTag::Compound(name, properties.iter()
+                .map(|(property, value)| NbtTag::String(property.clone(), value.clone()))
+                .collect()),
+            Self::Bees(bees) => NbtTag::List(name, bees.iter()
+                .map(|(entity_data, ticks, min_ticks)| NbtTag::Compound(String::new(), vec![
+                    renamed(entity_data, "entity_data"),
+                    NbtTag::Int("ticks_in_hive".to_string(), *ticks),
+                    NbtTag::Int("min_ticks_in_hive".to_string(), *min_ticks),
+                ]))
+                .collect()),
+            Self::Saved(nbt) => nbt.clone(),
+        })
+    }
+}
+
+fn next_list<T>(iterator: &mut Iter<u8>, mut read: impl FnMut(&mut Iter<u8>) -> Result<T, ServerError>) -> Result<Vec<T>, ServerError> {
+    let length = next_varint(iterator)?;
+    (0..length).map(|_| read(iterator)).collect()
+}
+
+fn next_optional<T>(iterator: &mut Iter<u8>, mut read: impl FnMut(&mut Iter<u8>) -> Result<T, ServerError>) -> Result<Option<T>, ServerError> {
+    if next_bool(iterator)? {
+        Ok(Some(read(iterator)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// A value and the version shown to players with chat filtering, if it is different
+fn next_filterable<T>(iterator: &mut Iter<u8>, mut read: impl FnMut(&mut Iter<u8>) -> Result<T, ServerError>) -> Result<(T, Option<T>), ServerError> {
+    Ok((read(iterator)?, next_optional(iterator, &mut read)?))
+}
+
+fn add_optional<T>(builder: PacketBuilder, value: &Option<T>, write: impl FnOnce(PacketBuilder, &T) -> PacketBuilder) -> PacketBuilder {
+    match value {
+        Some(value) => write(builder.add_bool(true), value),
+        None => builder.add_bool(false),
+    }
+}
+
+fn add_list<T>(builder: PacketBuilder, values: &[T], write: impl Fn(PacketBuilder, &T) -> PacketBuilder) -> PacketBuilder {
+    values.iter().fold(builder.add_varint(values.len() as i32), write)
+}
+
+/// A registry id plus one, or 0 followed by an inline value. Those are only sent by servers with their own
+/// registry entries, and this one never writes them.
+fn next_holder(iterator: &mut Iter<u8>, type_id: i32) -> Result<i32, ServerError> {
+    match next_varint(iterator)? {
+        0 => Err(ServerError::InlineRegistryValue(type_id)),
+        id => Ok(id - 1),
+    }
+}
+
+fn add_holder(builder: PacketBuilder, id: i32) -> PacketBuilder {
+    builder.add_varint(id + 1)
+}
+
+/// A tag name, or the number of ids plus one followed by the ids
+fn next_block_set(iterator: &mut Iter<u8>) -> Result<BlockSet, ServerError> {
+    Ok(match next_varint(iterator)? {
+        0 => BlockSet::Tag(next_string(iterator)?),
+        length => BlockSet::Ids((1..length).map(|_| next_varint(iterator)).collect::<Result<_, _>>()?),
+    })
+}
+
+fn add_block_set(builder: PacketBuilder, blocks: &BlockSet) -> PacketBuilder {
+    match blocks {
+        BlockSet::Tag(tag) => builder.add_varint(0).add_string(tag),
+        BlockSet::Ids(ids) => ids.iter().fold(builder.add_varint(ids.len() as i32 + 1), |b, id| b.add_varint(*id)),
+    }
+}
+
+fn next_block_predicate(iterator: &mut Iter<u8>) -> Result<BlockPredicate, ServerError> {
+    Ok(BlockPredicate {
+        blocks: next_optional(iterator, next_block_set)?,
+        properties: next_optional(iterator, |i| next_list(i, |i| {
+            let name = next_string(i)?;
+            // An exact value or a range with an optional minimum and maximum
+            let matcher = if next_bool(i)? {
+                PropertyMatcher::Exact(next_string(i)?)
+            } else {
+                PropertyMatcher::Range { min: next_optional(i, next_string)?, max: next_optional(i, next_string)? }
+            };
+            Ok((name, matcher))
+        }))?,
+        nbt: next_optional(iterator, next_nbt)?,
+    })
+}
+
+fn add_block_predicate(builder: PacketBuilder, predicate: &BlockPredicate) -> PacketBuilder {
+    let builder = add_optional(builder, &predicate.blocks, add_block_set);
+    let builder = add_optional(builder, &predicate.properties, |b, properties| add_list(b, properties, |b, (name, matcher)| {
+        match matcher {
+            PropertyMatcher::Exact(value) => b.add_string(name).add_bool(true).add_string(value),
+            PropertyMatcher::Range { min, max } => {
+                let b = add_optional(b.add_string(name).add_bool(false), min, |b, min| b.add_string(min));
+                add_optional(b, max, |b, max| b.add_string(max))
+            }
+        }
+    }));
+    add_optional(builder, &predicate.nbt, |b, nbt| b.add_nbt(nbt))
+}
+
+fn next_effect(iterator: &mut Iter<u8>) -> Result<Effect, ServerError> {
+    Ok(Effect { id: next_varint(iterator)?, details: next_effect_details(iterator, 0)? })
+}
+
+fn next_effect_details(iterator: &mut Iter<u8>, depth: usize) -> Result<EffectDetails, ServerError> {
+    if depth > MAX_HIDDEN_EFFECTS {
+        return Err(ServerError::EffectNestedTooDeep);
+    }
+    Ok(EffectDetails {
+        amplifier: next_varint(iterator)?,
+        duration: next_varint(iterator)?,
+        ambient: next_bool(iterator)?,
+        show_particles: next_bool(iterator)?,
+        show_icon: next_bool(iterator)?,
+        hidden_effect: next_optional(iterator, |i| next_effect_details(i, depth + 1))?.map(Box::new),
+    })
+}
+
+fn add_effect(builder: PacketBuilder, effect: &Effect) -> PacketBuilder {
+    add_effect_details(builder.add_varint(effect.id), &effect.details)
+}
+
+fn add_effect_details(builder: PacketBuilder, details: &EffectDetails) -> PacketBuilder {
+    let builder = builder.add_varint(details.amplifier)
+        .add_varint(details.duration)
+        .add_bool(details.ambient)
+        .add_bool(details.show_particles)
+        .add_bool(details.show_icon);
+    add_optional(builder, &details.hidden_effect, |b, hidden| add_effect_details(b, hidden))
+}
+
+fn next_firework_explosion(iterator: &mut Iter<u8>) -> Result<FireworkExplosion, ServerError> {
+    Ok(FireworkExplosion {
+        shape: next_varint(iterator)?,
+        colors: next_list(iterator, next_i32)?,
+        fade_colors: next_list(iterator, next_i32)?,
+        has_trail: next_bool(iterator)?,
+        has_twinkle: next_bool(iterator)?,
+    })
+}
+
+fn add_firework_explosion(builder: PacketBuilder, explosion: &FireworkExplosion) -> PacketBuilder {
+    let builder = add_list(builder.add_varint(explosion.shape), &explosion.colors, |b, color| b.add_int(*color));
+    add_list(builder, &explosion.fade_colors, |b, color| b.add_int(*color))
+        .add_bool(explosion.has_trail)
+        .add_bool(explosion.has_twinkle)
+}
+
+/// Block positions are sent with x, z and y packed into 26, 26 and 12 bits
+fn unpack_position(packed: u64) -> (i32, i32, i32) {
+    let packed = packed as i64;
+    ((packed >> 38) as i32, (packed << 52 >> 52) as i32, (packed << 26 >> 38) as i32)
+}
+
+fn renamed(nbt: &NbtTag, name: &str) -> NbtTag {
+    let mut nbt = nbt.clone();
+    match &mut nbt {
+        NbtTag::End => {}
+        NbtTag::Byte(n, _) | NbtTag::Short(n, _) | NbtTag::Int(n, _) | NbtTag::Long(n, _) | NbtTag::Float(n, _) | NbtTag::Double(n, _)
+        | NbtTag::ByteArray(n, _) | NbtTag::String(n, _) | NbtTag::List(n, _) | NbtTag::Compound(n, _) | NbtTag::IntArray(n, _)
+        | NbtTag::LongArray(n, _) => *n = name.to_string(),
+    }
+    nbt
+}
+
+/// A compound without its name, like the network has it
+fn compound(nbt: &NbtTag) -> Option<NbtTag> {
+    matches!(nbt, NbtTag::Compound(..)).then(|| renamed(nbt, ""))
+}
+
+fn show_in_tooltip(nbt: &NbtTag) -> bool {
+    bool_child(nbt, "show_in_tooltip", true)
+}
+
+fn bool_child(nbt: &NbtTag, name: &str, default: bool) -> bool {
+    nbt.child(name).and_then(|b| b.as_i8()).map(|b| b != 0).unwrap_or(default)
+}
+
+/// `Some(None)` if the child isn't there and `None` if it is but can't be read
+fn optional_child<T>(nbt: &NbtTag, name: &str, read: impl FnOnce(&NbtTag) -> Option<T>) -> Option<Option<T>> {
+    match nbt.child(name) {
+        Some(child) => read(child).map(Some),
+        None => Some(None),
+    }
+}
+
+/// Index of a saved name in a list like `DYE_COLORS`
+fn index_of(names: &[&str], nbt: &NbtTag) -> Option<i32> {
+    let name = nbt.as_str()?;
+    names.iter().position(|n| *n == name).map(|index| index as i32)
+}
+
+fn unsigned_byte(nbt: &NbtTag) -> Option<i32> {
+    match nbt {
+        NbtTag::Byte(_, value) => Some(*value as u8 as i32),
+        _ => nbt.as_i32(),
+    }
+}
+
+/// Saved text components are JSON strings. Strings that aren't JSON are taken as plain text.
+fn text_from_json(nbt: &NbtTag) -> Option<NbtTag> {
+    let text = nbt.as_str()?;
+    Some(match serde_json::from_str::<Value>(text) {
+        Ok(json) => json_to_nbt(String::new(), &json),
+        Err(_) => NbtTag::String(String::new(), text.to_string()),
+    })
+}
+
+fn text_to_json(name: String, text: &NbtTag) -> NbtTag {
+    NbtTag::String(name, nbt_to_json(text).to_string())
+}
+
+/// Enchantments are saved as `{levels: {"minecraft:sharpness": 5}, show_in_tooltip: 1b}`, or just the levels
+fn enchantments_from_nbt(nbt: &NbtTag, registry: &ItemRegistry) -> Option<(Vec<(i32, i32)>, bool)> {
+    let levels = nbt.child("levels").unwrap_or(nbt).as_list()?.iter()
+        .filter(|level| level.name() != "show_in_tooltip")
+        .map(|level| Some((registry.enchantment_id(level.name())?, level.as_i32()?)))
+        .collect::<Option<_>>()?;
+    Some((levels, show_in_tooltip(nbt)))
+}
+
+/// Items in components are kept as they were saved if the server doesn't know one of them
+fn item_from_nbt(nbt: &NbtTag, registry: &ItemRegistry) -> Option<Slot> {
+    let item = Slot::from_nbt(nbt, registry);
+    (!item.is_empty()).then_some(item)
+}
+
+fn items_from_nbt(nbt: &NbtTag, registry: &ItemRegistry) -> Option<Vec<Slot>> {
+    nbt.as_list()?.iter().map(|item| item_from_nbt(item, registry)).collect()
+}
+
+/// Saved filterable values are either just the value or `{raw, filtered}`
+fn filterable_from_nbt<T>(nbt: &NbtTag, read: impl Fn(&NbtTag) -> Option<T>) -> Option<(T, Option<T>)> {
+    match nbt.child("raw") {
+        Some(raw) => Some((read(raw)?, match nbt.child("filtered") {
+            Some(filtered) => Some(read(filtered)?),
+            None => None,
+        })),
+        None => Some((read(nbt)?, None)),
+    }
+}
+
+fn filterable_to_nbt(raw: NbtTag, filtered: Option<NbtTag>) -> NbtTag {
+    let mut children = vec![raw];
+    children.extend(filtered);
+    NbtTag::Compound(String::new(), children)
+}
+
+/// Adventure mode predicates are saved as `{predicates: [...], show_in_tooltip: 0b}` or as a single predicate
+fn block_predicates_from_nbt(nbt: &NbtTag, registry: &ItemRegistry) -> Option<(Vec<BlockPredicate>, bool)> {
+    match nbt.child("predicates") {
+        Some(predicates) => {
+            let predicates = predicates.as_list()?.iter().map(|p| block_predicate_from_nbt(p, registry)).collect::<Option<_>>()?;
+            Some((predicates, show_in_tooltip(nbt)))
+        }
+        None => Some((vec![block_predicate_from_nbt(nbt, registry)?], true)),
+    }
+}
+
+/// The NBT a block has to match is saved as SNBT, which the server can't read, so those predicates stay saved
+fn block_predicate_from_nbt(nbt: &NbtTag, registry: &ItemRegistry) -> Option<BlockPredicate> {
+    if !matches!(nbt, NbtTag::Compound(..)) || nbt.child("nbt").is_some() {
+        return None;
+    }
+    Some(BlockPredicate {
+        blocks: optional_child(nbt, "blocks", |blocks| block_set_from_nbt(blocks, registry))?,
+        properties: optional_child(nbt, "state", |state| state.as_list()?.iter()
+            .map(|property| Some((property.name().to_string(), match property {
+                NbtTag::String(_, value) => PropertyMatcher::Exact(value.clone()),
+                NbtTag::Compound(..) => PropertyMatcher::Range {
+                    min: optional_child(property, "min", |min| min.as_str().map(|min| min.to_string()))?,
+                    max: optional_child(property, "max", |max| max.as_str().map(|max| max.to_string()))?,
+                },
+                _ => return None,
+            })))
+            .collect())?,
+        nbt: None,
+    })
+}
+
+fn block_predicate_to_nbt(predicate: &BlockPredicate, registry: &ItemRegistry) -> Option<NbtTag> {
+    let mut children = vec![];
+    if let Some(blocks) = &predicate.blocks {
+        children.push(block_set_to_nbt("blocks".to_string(), blocks, registry)?);
+    }
+    if let Some(properties) = &predicate.properties {
+        children.push(NbtTag::Compound("state".to_string(), properties.iter()
+            .map(|(property, matcher)| match matcher {
+                PropertyMatcher::Exact(value) => NbtTag::String(property.clone(), value.clone()),
+                PropertyMatcher::Range { min, max } => NbtTag::Compound(property.clone(), [("min", min), ("max", max)].into_iter()
+                    .filter_map(|(key, value)| Some(NbtTag::String(key.to_string(), value.clone()?)))
+                    .collect()),
+            })
+            .collect()));
+    }
+    if let Some(nbt) = &predicate.nbt {
+        children.push(NbtTag::String("nbt".to_string(), nbt_to_snbt(nbt)));
+    }
+    Some(NbtTag::Compound(String::new(), children))
+}
+
+/// Blocks are saved as one block, a `#` tag or a list of blocks
+fn block_set_from_nbt(nbt: &NbtTag, registry: &ItemRegistry) -> Option<BlockSet> {
+    match nbt {
+        NbtTag::String(_, name) => match name.strip_prefix('#') {
+            Some(tag) => Some(BlockSet::Tag(tag.to_string())),
+            None => Some(BlockSet::Ids(vec![registry.registry_id("block", name)?])),
+        },
+        NbtTag::List(_, blocks) => Some(BlockSet::Ids(blocks.iter()
+            .map(|block| registry.registry_id("block", block.as_str()?))
+            .collect::<Option<_>>()?)),
+        _ => None,
+    }
+}
+
+fn block_set_to_nbt(name: String, blocks: &BlockSet, registry: &ItemRegistry) -> Option<NbtTag> {
+    let block_name = |id: i32| registry.registry_name("block", id).map(|block| block.to_string());
+    Some(match blocks {
+        BlockSet::Tag(tag) => NbtTag::String(name, format!("#{tag}")),
+        BlockSet::Ids(ids) if ids.len() == 1 => NbtTag::String(name, block_name(ids[0])?),
+        BlockSet::Ids(ids) => NbtTag::List(name, ids.iter()
+            .map(|id| Some(NbtTag::String(String::new(), block_name(*id)?)))
+            .collect::<Option<_>>()?),
+    })
+}
+
+/// `{id: "minecraft:speed", amplifier: 1b, duration: 200, ...}`
+fn effect_from_nbt(nbt: &NbtTag, registry: &ItemRegistry) -> Option<Effect> {
+    Some(Effect { id: registry.effect_id(nbt.child("id")?.as_str()?)?, details: effect_details_from_nbt(nbt)? })
+}
+
+fn effect_details_from_nbt(nbt: &NbtTag) -> Option<EffectDetails> {
+    let show_particles = bool_child(nbt, "show_particles", true);
+    Some(EffectDetails {
+        amplifier: optional_child(nbt, "amplifier", unsigned_byte)?.unwrap_or(0),
+        duration: optional_child(nbt, "duration", |d| d.as_i32())?.unwrap_or(0),
+        ambient: bool_child(nbt, "ambient", false),
+        show_particles,
+        show_icon: bool_child(nbt, "show_icon", show_particles),
+        hidden_effect: optional_child(nbt, "hidden_effect", effect_details_from_nbt)?.map(Box::new),
+    })
+}
+
+fn effect_to_nbt(name: String, effect: &Effect, registry: &ItemRegistry) -> Option<NbtTag> {
+    let mut children = vec![NbtTag::String("id".to_string(), registry.effect_name(effect.id)?.to_string())];
+    children.extend(effect_details_to_nbt(&effect.details));
+    Some(NbtTag::Compound(name, children))
+}
+
+fn effect_details_to_nbt(details: &EffectDetails) -> Vec<NbtTag> {
+    let mut children = vec![
+        NbtTag::Byte("amplifier".to_string(), details.amplifier as u8 as i8),
+        NbtTag::Int("duration".to_string(), details.duration),
+        NbtTag::Byte("ambient".to_string(), details.ambient as i8),
+        NbtTag::Byte("show_particles".to_string(), details.show_particles as i8),
+        NbtTag::Byte("show_icon".to_string(), details.show_icon as i8),
+    ];
+    if let Some(hidden) = &details.hidden_effect {
+        children.push(NbtTag::Compound("hidden_effect".to_string(), effect_details_to_nbt(hidden)));
+    }
+    children
+}
+
+fn firework_explosion_from_nbt(nbt: &NbtTag) -> Option<FireworkExplosion> {
+    Some(FireworkExplosion {
+        shape: index_of(&FIREWORK_SHAPES, nbt.child("shape")?)?,
+        colors: optional_child(nbt, "colors", |c| c.as_int_array().cloned())?.unwrap_or_default(),
+        fade_colors: optional_child(nbt, "fade_colors", |c| c.as_int_array().cloned())?.unwrap_or_default(),
+        has_trail: bool_child(nbt, "has_trail", false),
+        has_twinkle: bool_child(nbt, "has_twinkle", false),
+    })
+}
+
+fn firework_explosion_to_nbt(name: String, explosion: &FireworkExplosion) -> Option<NbtTag> {
+    Some(NbtTag::Compound(name, vec![
+        NbtTag::String("shape".to_string(), FIREWORK_SHAPES.get(explosion.shape as usize)?.to_string()),
+        NbtTag::IntArray("colors".to_string(), explosion.colors.clone()),
+        NbtTag::IntArray("fade_colors".to_string(), explosion.fade_colors.clone()),
+        NbtTag::Byte("has_trail".to_string(), explosion.has_trail as i8),
+        NbtTag::Byte("has_twinkle".to_string(), explosion.has_twinkle as i8),
+    ]))
+}
+
+/// UUIDs are saved as four ints, most significant first
+fn uuid_from_nbt(nbt: &NbtTag) -> Option<u128> {
+    let ints = nbt.as_int_array().filter(|ints| ints.len() == 4)?;
+    Some(ints.iter().fold(0, |uuid, int| uuid << 32 | *int as u32 as u128))
+}
+
+/// Profile properties are saved as `[{name, value, signature}]`, or as lists of values by name without signatures
+fn properties_from_nbt(nbt: &NbtTag) -> Option<Vec<(String, String, Option<String>)>> {
+    match nbt {
+        NbtTag::List(_, properties) => properties.iter()
+            .map(|property| Some((
+                property.child("name")?.as_str()?.to_string(),
+                property.child("value")?.as_str()?.to_string(),
+                optional_child(property, "signature", |s| s.as_str().map(|s| s.to_string()))?,
+            )))
+            .collect(),
+        NbtTag::Compound(_, properties) => {
+            let mut all = vec![];
+            for property in properties {
+                for value in property.as_list()? {
+                    all.push((property.name().to_string(), value.as_str()?.to_string(), None));
+                }
+            }
+            Some(all)
+        }
+        _ => None,
+    }
+}
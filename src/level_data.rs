@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use inbt::NbtTag;
+use sha2::{Digest, Sha256};
+use crate::error::ServerError;
+use crate::nbt_util::{read_gzip_file, write_gzip_file, NbtTagExt};
+
+/// The world-wide state from `level.dat`. The whole file is kept, so everything the server doesn't understand
+/// is written back unchanged.
+#[derive(Debug, Clone)]
+pub struct LevelData {
+    root: NbtTag,
+    pub spawn_x: i32,
+    pub spawn_y: i32,
+    pub spawn_z: i32,
+    pub spawn_angle: f32,
+    /// Time of day in ticks, which doesn't move while `doDaylightCycle` is off
+    pub day_time: i64,
+    /// Ticks the world has been running
+    pub time: i64,
+    pub raining: bool,
+    pub thundering: bool,
+    pub difficulty: u8,
+    pub difficulty_locked: bool,
+    /// Game mode new players start in
+    pub game_type: u8,
+    pub hardcore: bool,
+    pub seed: i64,
+    pub game_rules: BTreeMap<String, String>,
+    /// Data pack ids, kept in sync with the `DataPackManager` when saving
+    pub enabled_data_packs: Vec<String>,
+    pub disabled_data_packs: Vec<String>,
+}
+
+impl LevelData {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ServerError> {
+        Ok(Self::from_nbt(read_gzip_file(path)?))
+    }
+
+    /// Reads the `Data` compound of a level.dat, with vanilla's defaults for anything missing
+    pub fn from_nbt(root: NbtTag) -> Self {
+        let empty = NbtTag::Compound("Data".to_string(), vec![]);
+        let data = root.child("Data").unwrap_or(&empty);
+        let int = |name: &str, default: i32| data.child(name).and_then(|t| t.as_i32()).unwrap_or(default);
+        let long = |name: &str, default: i64| data.child(name).and_then(|t| t.as_i64()).unwrap_or(default);
+        let strings = |tag: Option<&NbtTag>| tag
+            .and_then(|l| l.as_list())
+            .map(|l| l.iter().filter_map(|s| s.as_str().map(|s| s.to_string())).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let data_packs = data.child("DataPacks");
+
+        Self {
+            spawn_x: int("SpawnX", 0),
+            spawn_y: int("SpawnY", 64),
+            spawn_z: int("SpawnZ", 0),
+            spawn_angle: data.child("SpawnAngle").and_then(|t| t.as_f32()).unwrap_or(0.0),
+            day_time: long("DayTime", 0),
+            time: long("Time", 0),
+            raining: int("raining", 0) != 0,
+            thundering: int("thundering", 0) != 0,
+            difficulty: int("Difficulty", 2) as u8,
+            difficulty_locked: int("DifficultyLocked", 0) != 0,
+            game_type: int("GameType", 0) as u8,
+            hardcore: int("hardcore", 0) != 0,
+            seed: data.child("WorldGenSettings").and_then(|w| w.child("seed")).and_then(|s| s.as_i64()).unwrap_or(0),
+            game_rules: data.child("GameRules").and_then(|g| g.as_list()).into_iter().flatten()
+                .filter_map(|rule| Some((rule.name().to_string(), rule.as_str()?.to_string())))
+                .collect(),
+            enabled_data_packs: strings(data_packs.and_then(|d| d.child("Enabled"))),
+            disabled_data_packs: strings(data_packs.and_then(|d| d.child("Disabled"))),
+            root,
+        }
+    }
+
+    /// The level.dat with the current values written into it
+    pub fn to_nbt(&self) -> NbtTag {
+        let mut data = self.root.child("Data").cloned().unwrap_or(NbtTag::Compound("Data".to_string(), vec![]));
+
+        let last_played = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or(0);
+        let strings = |name: &str, values: &[String]| NbtTag::List(name.to_string(), values.iter().map(|v| NbtTag::String("".to_string(), v.clone())).collect());
+        for tag in [
+            NbtTag::Int("SpawnX".to_string(), self.spawn_x),
+            NbtTag::Int("SpawnY".to_string(), self.spawn_y),
+            NbtTag::Int("SpawnZ".to_string(), self.spawn_z),
+            NbtTag::Float("SpawnAngle".to_string(), self.spawn_angle),
+            NbtTag::Long("DayTime".to_string(), self.day_time),
+            NbtTag::Long("Time".to_string(), self.time),
+            NbtTag::Byte("raining".to_string(), self.raining as i8),
+            NbtTag::Byte("thundering".to_string(), self.thundering as i8),
+            NbtTag::Byte("Difficulty".to_string(), self.difficulty as i8),
+            NbtTag::Byte("DifficultyLocked".to_string(), self.difficulty_locked as i8),
+            NbtTag::Int("GameType".to_string(), self.game_type as i32),
+            NbtTag::Byte("hardcore".to_string(), self.hardcore as i8),
+            NbtTag::Long("LastPlayed".to_string(), last_played),
+            NbtTag::Compound("GameRules".to_string(), self.game_rules.iter().map(|(name, value)| NbtTag::String(name.clone(), value.clone())).collect()),
+            NbtTag::Compound("DataPacks".to_string(), vec![
+                strings("Enabled", &self.enabled_data_packs),
+                strings("Disabled", &self.disabled_data_packs),
+            ]),
+        ] {
+            data.set_child(tag);
+        }
+        let mut root = self.root.clone();
+        root.set_child(data);
+        root
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ServerError> {
+        write_gzip_file(path, &self.to_nbt())
+    }
+
+    /// Advances the clocks by one tick
+    pub fn tick(&mut self) {
+        self.time += 1;
+        if self.game_rule_bool("doDaylightCycle", true) {
+            self.day_time += 1;
+        }
+    }
+
+    pub fn game_rule(&self, name: &str) -> Option<&str> {
+        self.game_rules.get(name).map(|v| &**v)
+    }
+
+    pub fn game_rule_bool(&self, name: &str, default: bool) -> bool {
+        self.game_rule(name).map(|value| value == "true").unwrap_or(default)
+    }
+
+    /// What the client gets instead of the seed, which it uses for biome noise. First 8 bytes of the
+    /// SHA-256 of the seed, like vanilla's `BiomeManager.obfuscateSeed`.
+    pub fn hashed_seed(&self) -> i64 {
+        let hash = Sha256::digest(self.seed.to_le_bytes());
+        i64::from_le_bytes(hash[..8].try_into().unwrap())
+    }
+}
@@ -0,0 +1,31 @@
+pub mod packet;
+pub mod packet_builder;
+pub mod error;
+pub mod server_util;
+pub mod server;
+pub mod server_connection;
+pub mod resource_manager;
+pub mod command;
+pub mod block_registry;
+pub mod tags;
+pub mod datapack;
+pub mod nbt_util;
+pub mod chunk_service;
+pub mod region;
+pub mod light;
+pub mod block_storage;
+pub mod light_engine;
+pub mod block_entity;
+pub mod level_data;
+pub mod player_data;
+pub mod collision;
+pub mod movement;
+pub mod entity_tracker;
+pub mod config;
+pub mod tab_list;
+pub mod entity;
+pub mod entity_index;
+pub mod entity_storage;
+pub mod item;
+pub mod item_component;
+pub mod inventory;
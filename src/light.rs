@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, BTreeSet};
+use inbt::NbtTag;
+use crate::block_storage::section_nbt_mut;
+use crate::nbt_util::NbtTagExt;
+
+/// Size of the light array of one section, 4 bits for each of the 16x16x16 blocks
+pub const LIGHT_ARRAY_SIZE: usize = 2048;
+
+pub type LightArray = Box<[u8; LIGHT_ARRAY_SIZE]>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightKind {
+    Sky,
+    Block,
+}
+
+/// Sky and block light of a chunk. There is one light section more than there are block sections
+/// above and below the world, since light spreads into those.
+#[derive(Debug, Clone)]
+pub struct ChunkLight {
+    /// Y of the lowest light section, one below the lowest block section
+    min_section: i32,
+    /// `None` for sections where no light is stored, which the client then leaves alone
+    sky: Vec<Option<LightArray>>,
+    block: Vec<Option<LightArray>>,
+}
+
+impl ChunkLight {
+    /// Light for a chunk without any stored light. `min_section` and `section_count` describe the block sections.
+    pub fn new(min_section: i32, section_count: usize) -> Self {
+        Self {
+            min_section: min_section - 1,
+            sky: vec![None; section_count + 2],
+            block: vec![None; section_count + 2],
+        }
+    }
+
+    /// Reads the `SkyLight` and `BlockLight` arrays from the `sections` of a chunk's NBT
+    pub fn from_nbt(chunk: &NbtTag, min_section: i32, section_count: usize) -> Self {
+        let mut light = Self::new(min_section, section_count);
+        for section in chunk.child("sections").and_then(|s| s.as_list()).into_iter().flatten() {
+            let Some(index) = section.child("Y").and_then(|y| y.as_i32()).and_then(|y| light.index_of(y)) else {
+                continue;
+            };
+            light.sky[index] = section.child("SkyLight").and_then(Self::read_array);
+            light.block[index] = section.child("BlockLight").and_then(Self::read_array);
+        }
+        light
+    }
+
+    /// Puts the light arrays into the `sections` of a chunk's NBT. Sections without stored light are left alone.
+    pub fn write_sections(&self, sections: &mut Vec<NbtTag>) {
+        for index in 0..self.sky.len() {
+            let section_y = self.min_section + index as i32;
+            for (name, array) in [("SkyLight", &self.sky[index]), ("BlockLight", &self.block[index])] {
+                if let Some(array) = array {
+                    section_nbt_mut(sections, section_y).set_child(NbtTag::ByteArray(name.to_string(), array.iter().map(|b| *b as i8).collect()));
+                }
+            }
+        }
+    }
+
+    fn read_array(tag: &NbtTag) -> Option<LightArray> {
+        let bytes = tag.as_byte_array()?;
+        if bytes.len() != LIGHT_ARRAY_SIZE {
+            return None;
+        }
+        let mut array = Box::new([0u8; LIGHT_ARRAY_SIZE]);
+        for (i, byte) in bytes.iter().enumerate() {
+            array[i] = *byte as u8;
+        }
+        Some(array)
+    }
+
+    /// Index into the light sections of the section at `section_y`
+    fn index_of(&self, section_y: i32) -> Option<usize> {
+        let index = section_y - self.min_section;
+        (0..self.sky.len() as i32).contains(&index).then_some(index as usize)
+    }
+
+    /// Light level at a block. `x` and `z` are inside the chunk, `y` is absolute. `None` if `y` is outside the light sections.
+    pub fn get(&self, kind: LightKind, x: i32, y: i32, z: i32) -> Option<u8> {
+        let index = self.index_of(y.div_euclid(16))?;
+        let Some(array) = &self.sections(kind)[index] else {
+            return Some(0);
+        };
+        let (byte, shift) = Self::nibble_of(x, y, z);
+        Some((array[byte] >> shift) & 0xF)
+    }
+
+    /// Sets the light level at a block, adding a section if there wasn't one. Returns false if `y` is outside the light sections.
+    pub fn set(&mut self, kind: LightKind, x: i32, y: i32, z: i32, level: u8) -> bool {
+        let Some(index) = self.index_of(y.div_euclid(16)) else {
+            return false;
+        };
+        let (byte, shift) = Self::nibble_of(x, y, z);
+        let sections = match kind {
+            LightKind::Sky => &mut self.sky,
+            LightKind::Block => &mut self.block,
+        };
+        let array = sections[index].get_or_insert_with(|| Box::new([0u8; LIGHT_ARRAY_SIZE]));
+        array[byte] = (array[byte] & !(0xF << shift)) | ((level & 0xF) << shift);
+        true
+    }
+
+    /// Replaces all light with zeros, for relighting the chunk from scratch
+    pub fn clear(&mut self) {
+        for section in self.sky.iter_mut().chain(self.block.iter_mut()) {
+            *section = Some(Box::new([0u8; LIGHT_ARRAY_SIZE]));
+        }
+    }
+
+    /// Copy with only the given sections, used for Update Light packets that leave the other sections alone
+    pub fn only_sections(&self, section_ys: &BTreeSet<i32>) -> Self {
+        let mut light = Self {
+            min_section: self.min_section,
+            sky: vec![None; self.sky.len()],
+            block: vec![None; self.block.len()],
+        };
+        for index in section_ys.iter().filter_map(|y| self.index_of(*y)) {
+            // Missing sections are sent as empty, since the client has to forget the old light there
+            light.sky[index] = Some(self.sky[index].clone().unwrap_or_else(|| Box::new([0u8; LIGHT_ARRAY_SIZE])));
+            light.block[index] = Some(self.block[index].clone().unwrap_or_else(|| Box::new([0u8; LIGHT_ARRAY_SIZE])));
+        }
+        light
+    }
+
+    fn nibble_of(x: i32, y: i32, z: i32) -> (usize, u32) {
+        let index = (y.rem_euclid(16) * 256 + z * 16 + x) as usize;
+        (index / 2, (index % 2) as u32 * 4)
+    }
+
+    fn sections(&self, kind: LightKind) -> &Vec<Option<LightArray>> {
+        match kind {
+            LightKind::Sky => &self.sky,
+            LightKind::Block => &self.block,
+        }
+    }
+
+    /// Lowest and highest light section
+    pub fn section_range(&self) -> (i32, i32) {
+        (self.min_section, self.min_section + self.sky.len() as i32 - 1)
+    }
+
+    pub fn min_section(&self) -> i32 {
+        self.min_section
+    }
+
+    pub fn sky_sections(&self) -> &Vec<Option<LightArray>> {
+        &self.sky
+    }
+
+    pub fn block_sections(&self) -> &Vec<Option<LightArray>> {
+        &self.block
+    }
+}
+
+/// Block types whose states fill the whole block and stop all light
+pub(crate) const OPAQUE_BLOCK_TYPES: &[&str] = &[
+    "minecraft:block", "minecraft:rotated_pillar", "minecraft:grass", "minecraft:snowy_dirt", "minecraft:mycelium",
+    "minecraft:nylium", "minecraft:colored_falling", "minecraft:concrete_powder", "minecraft:dropper", "minecraft:dispenser",
+    "minecraft:furnace", "minecraft:blast_furnace", "minecraft:smoker", "minecraft:note", "minecraft:jukebox",
+    "minecraft:redstone_ore", "minecraft:redstone_lamp", "minecraft:infested", "minecraft:infested_rotated_pillar",
+    "minecraft:weathering_copper_full", "minecraft:crafting_table", "minecraft:cartography_table", "minecraft:fletching_table",
+    "minecraft:smithing_table", "minecraft:loom", "minecraft:barrel", "minecraft:beehive", "minecraft:command",
+    "minecraft:structure", "minecraft:jigsaw", "minecraft:target", "minecraft:magma", "minecraft:sponge", "minecraft:wet_sponge",
+    "minecraft:huge_mushroom", "minecraft:pumpkin", "minecraft:carved_pumpkin", "minecraft:glazed_terracotta",
+    "minecraft:observer", "minecraft:crying_obsidian", "minecraft:respawn_anchor", "minecraft:lodestone", "minecraft:sculk",
+    "minecraft:sculk_catalyst", "minecraft:tnt", "minecraft:chiseled_book_shelf", "minecraft:budding_amethyst",
+    "minecraft:amethyst", "minecraft:powered", "minecraft:hay", "minecraft:crafter", "minecraft:mud", "minecraft:soul_sand",
+];
+
+/// How much light a block state takes away when light passes through it, between 0 and 15.
+/// Vanilla works this out from the block shapes, which the data reports don't have, so this goes by block type.
+pub fn opacity_of(block_type: &str, name: &str, properties: &BTreeMap<String, String>) -> u8 {
+    if properties.get("waterlogged").is_some_and(|w| w == "true") {
+        return 1;
+    }
+    match name {
+        "minecraft:tinted_glass" => return 15,
+        "minecraft:water" | "minecraft:lava" | "minecraft:bubble_column" | "minecraft:kelp" | "minecraft:kelp_plant"
+        | "minecraft:seagrass" | "minecraft:tall_seagrass" | "minecraft:ice" | "minecraft:frosted_ice"
+        | "minecraft:slime_block" | "minecraft:honey_block" => return 1,
+        _ => {}
+    }
+    if block_type.ends_with("leaves") {
+        1
+    } else if block_type == "minecraft:slab" && properties.get("type").is_some_and(|t| t == "double") {
+        15
+    } else if OPAQUE_BLOCK_TYPES.contains(&block_type) {
+        15
+    } else {
+        0
+    }
+}
+
+/// Block light a block state emits, between 0 and 15
+pub fn luminance_of(name: &str, properties: &BTreeMap<String, String>) -> u8 {
+    let property = |key: &str| properties.get(key).map(|v| &**v);
+    let lit = property("lit") == Some("true");
+    let name = name.strip_prefix("minecraft:").unwrap_or(name);
+    match name {
+        "beacon" | "conduit" | "end_gateway" | "end_portal" | "fire" | "glowstone" | "jack_o_lantern" | "lantern" | "lava"
+        | "sea_lantern" | "shroomlight" | "ochre_froglight" | "verdant_froglight" | "pearlescent_froglight" => 15,
+        "campfire" | "redstone_lamp" | "copper_bulb" | "waxed_copper_bulb" if lit => 15,
+        "cave_vines" | "cave_vines_plant" if property("berries") == Some("true") => 14,
+        "end_rod" | "torch" | "wall_torch" => 14,
+        "furnace" | "blast_furnace" | "smoker" if lit => 13,
+        "exposed_copper_bulb" | "waxed_exposed_copper_bulb" if lit => 12,
+        "nether_portal" => 11,
+        "crying_obsidian" | "soul_fire" | "soul_torch" | "soul_wall_torch" | "soul_lantern" => 10,
+        "soul_campfire" if lit => 10,
+        "redstone_ore" | "deepslate_redstone_ore" if lit => 9,
+        "weathered_copper_bulb" | "waxed_weathered_copper_bulb" if lit => 8,
+        "enchanting_table" | "ender_chest" | "glow_lichen" => 7,
+        "redstone_torch" | "redstone_wall_torch" if lit => 7,
+        "sculk_catalyst" => 6,
+        "amethyst_cluster" => 5,
+        "large_amethyst_bud" => 4,
+        "oxidized_copper_bulb" | "waxed_oxidized_copper_bulb" if lit => 4,
+        "magma_block" => 3,
+        "medium_amethyst_bud" => 2,
+        "brewing_stand" | "brown_mushroom" | "dragon_egg" | "end_portal_frame" | "sculk_sensor" | "calibrated_sculk_sensor"
+        | "small_amethyst_bud" => 1,
+        "light" => property("level").and_then(|l| l.parse().ok()).unwrap_or(0),
+        "respawn_anchor" => property("charges").and_then(|c| c.parse::<u8>().ok()).map(|c| c * 15 / 4).unwrap_or(0),
+        "sea_pickle" if property("waterlogged") == Some("true") => property("pickles").and_then(|p| p.parse::<u8>().ok()).map(|p| 3 + 3 * p).unwrap_or(0),
+        "trial_spawner" => if property("trial_spawner_state").is_some_and(|s| s == "inactive" || s == "cooldown") { 4 } else { 8 },
+        "vault" => if property("vault_state") == Some("inactive") { 6 } else { 12 },
+        _ if name.ends_with("candle_cake") && lit => 3,
+        _ if name.ends_with("candle") && lit => property("candles").and_then(|c| c.parse::<u8>().ok()).map(|c| 3 * c).unwrap_or(0),
+        _ => 0,
+    }
+}
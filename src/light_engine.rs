@@ -0,0 +1,198 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::Arc;
+use crate::block_registry::{BlockRegistry, StateInfo};
+use crate::block_storage::ChunkBlocks;
+use crate::light::{ChunkLight, LightKind};
+
+const DIRECTIONS: [(i32, i32, i32); 6] = [(0, -1, 0), (0, 1, 0), (-1, 0, 0), (1, 0, 0), (0, 0, -1), (0, 0, 1)];
+const DOWN: (i32, i32, i32) = (0, -1, 0);
+
+/// Spreads sky and block light through a group of loaded chunks, like vanilla's light engine.
+/// Light only spreads into chunks that were added, so relighting at a chunk border needs the neighbours too.
+pub struct LightEngine<'a> {
+    block_registry: &'a BlockRegistry,
+    chunks: HashMap<(i32, i32), (Arc<ChunkBlocks>, ChunkLight)>,
+    /// (chunk x, section y, chunk z) of every light section that changed
+    changed_sections: BTreeSet<(i32, i32, i32)>,
+}
+
+impl<'a> LightEngine<'a> {
+    pub fn new(block_registry: &'a BlockRegistry) -> Self {
+        Self {
+            block_registry,
+            chunks: HashMap::new(),
+            changed_sections: BTreeSet::new(),
+        }
+    }
+
+    pub fn add_chunk(&mut self, chunk_x: i32, chunk_z: i32, blocks: Arc<ChunkBlocks>, light: ChunkLight) {
+        self.chunks.insert((chunk_x, chunk_z), (blocks, light));
+    }
+
+    pub fn light(&self, chunk_x: i32, chunk_z: i32) -> Option<&ChunkLight> {
+        self.chunks.get(&(chunk_x, chunk_z)).map(|(_, light)| light)
+    }
+
+    pub fn remove_chunk(&mut self, chunk_x: i32, chunk_z: i32) -> Option<ChunkLight> {
+        self.chunks.remove(&(chunk_x, chunk_z)).map(|(_, light)| light)
+    }
+
+    /// Gives back the light of the chunks that changed, with the sections that changed in each
+    pub fn into_changed(mut self) -> Vec<(i32, i32, ChunkLight, BTreeSet<i32>)> {
+        let mut sections_by_chunk: BTreeMap<(i32, i32), BTreeSet<i32>> = BTreeMap::new();
+        for (chunk_x, section_y, chunk_z) in &self.changed_sections {
+            sections_by_chunk.entry((*chunk_x, *chunk_z)).or_default().insert(*section_y);
+        }
+        sections_by_chunk.into_iter()
+            .filter_map(|((x, z), sections)| self.chunks.remove(&(x, z)).map(|(_, light)| (x, z, light, sections)))
+            .collect()
+    }
+
+    /// Computes the light of a chunk from scratch, taking in light from the neighbouring chunks that were added
+    pub fn light_chunk(&mut self, chunk_x: i32, chunk_z: i32) {
+        let Some((_, light)) = self.chunks.get_mut(&(chunk_x, chunk_z)) else {
+            return;
+        };
+        light.clear();
+        let (min_section, max_section) = light.section_range();
+        let (min_y, max_y) = (min_section * 16, max_section * 16 + 15);
+        for section_y in min_section..=max_section {
+            self.changed_sections.insert((chunk_x, section_y, chunk_z));
+        }
+
+        let (base_x, base_z) = (chunk_x * 16, chunk_z * 16);
+        let mut sky_queue = VecDeque::new();
+        let mut block_queue = VecDeque::new();
+        for x in base_x..base_x + 16 {
+            for z in base_z..base_z + 16 {
+                // Sky light comes in from above the world and goes down from there
+                self.set_light(LightKind::Sky, x, max_y, z, 15);
+                sky_queue.push_back((x, max_y, z));
+
+                for y in min_y..=max_y {
+                    let luminance = self.state_info(x, y, z).map(|info| info.luminance).unwrap_or(0);
+                    if luminance > 0 {
+                        self.set_light(LightKind::Block, x, y, z, luminance);
+                        block_queue.push_back((x, y, z));
+                    }
+                }
+            }
+        }
+
+        // Light from the neighbouring chunks spreads in from their border blocks
+        for i in 0..16 {
+            for (x, z) in [(base_x - 1, base_z + i), (base_x + 16, base_z + i), (base_x + i, base_z - 1), (base_x + i, base_z + 16)] {
+                for y in min_y..=max_y {
+                    sky_queue.push_back((x, y, z));
+                    block_queue.push_back((x, y, z));
+                }
+            }
+        }
+
+        self.propagate(LightKind::Sky, sky_queue);
+        self.propagate(LightKind::Block, block_queue);
+    }
+
+    /// Updates the light around a block after it was changed in its chunk's blocks
+    pub fn block_changed(&mut self, x: i32, y: i32, z: i32) {
+        for kind in [LightKind::Sky, LightKind::Block] {
+            self.relight(kind, x, y, z);
+        }
+    }
+
+    fn relight(&mut self, kind: LightKind, x: i32, y: i32, z: i32) {
+        let Some(old_level) = self.get_light(kind, x, y, z) else {
+            return;
+        };
+
+        // Take away all light that could have come through this block
+        let mut removal_queue = VecDeque::from([(x, y, z, old_level)]);
+        let mut add_queue = VecDeque::new();
+        self.set_light(kind, x, y, z, 0);
+        while let Some((x, y, z, level)) = removal_queue.pop_front() {
+            for direction in DIRECTIONS {
+                let (nx, ny, nz) = (x + direction.0, y + direction.1, z + direction.2);
+                let Some(neighbour_level) = self.get_light(kind, nx, ny, nz) else {
+                    continue;
+                };
+                if neighbour_level == 0 {
+                    continue;
+                }
+                let from_here = neighbour_level < level || (kind == LightKind::Sky && direction == DOWN && level == 15 && neighbour_level == 15);
+                if from_here {
+                    self.set_light(kind, nx, ny, nz, 0);
+                    removal_queue.push_back((nx, ny, nz, neighbour_level));
+                    // Light sources keep their own light and spread it again
+                    let luminance = if kind == LightKind::Block { self.state_info(nx, ny, nz).map(|info| info.luminance).unwrap_or(0) } else { 0 };
+                    if luminance > 0 {
+                        self.set_light(kind, nx, ny, nz, luminance);
+                        add_queue.push_back((nx, ny, nz));
+                    }
+                } else {
+                    // Lit from somewhere else, so it can fill the removed area back in
+                    add_queue.push_back((nx, ny, nz));
+                }
+            }
+        }
+
+        // Then spread it again from the sources that are left
+        if kind == LightKind::Block {
+            let luminance = self.state_info(x, y, z).map(|info| info.luminance).unwrap_or(0);
+            if luminance > 0 {
+                self.set_light(kind, x, y, z, luminance);
+                add_queue.push_back((x, y, z));
+            }
+        }
+        for direction in DIRECTIONS {
+            add_queue.push_back((x + direction.0, y + direction.1, z + direction.2));
+        }
+        self.propagate(kind, add_queue);
+    }
+
+    fn propagate(&mut self, kind: LightKind, mut queue: VecDeque<(i32, i32, i32)>) {
+        while let Some((x, y, z)) = queue.pop_front() {
+            let Some(level) = self.get_light(kind, x, y, z) else {
+                continue;
+            };
+            if level <= 1 {
+                continue;
+            }
+            for direction in DIRECTIONS {
+                let (nx, ny, nz) = (x + direction.0, y + direction.1, z + direction.2);
+                let (Some(info), Some(neighbour_level)) = (self.state_info(nx, ny, nz), self.get_light(kind, nx, ny, nz)) else {
+                    continue;
+                };
+                if info.opacity >= 15 {
+                    continue;
+                }
+                // Sky light goes straight down through transparent blocks without getting darker
+                let decrease = if kind == LightKind::Sky && direction == DOWN && level == 15 && info.opacity == 0 { 0 } else { info.opacity.max(1) };
+                let new_level = level.saturating_sub(decrease);
+                if new_level > neighbour_level {
+                    self.set_light(kind, nx, ny, nz, new_level);
+                    queue.push_back((nx, ny, nz));
+                }
+            }
+        }
+    }
+
+    fn state_info(&self, x: i32, y: i32, z: i32) -> Option<StateInfo> {
+        let (blocks, _) = self.chunks.get(&(x.div_euclid(16), z.div_euclid(16)))?;
+        Some(self.block_registry.state_info(blocks.get(x.rem_euclid(16), y, z.rem_euclid(16))))
+    }
+
+    fn get_light(&self, kind: LightKind, x: i32, y: i32, z: i32) -> Option<u8> {
+        let (_, light) = self.chunks.get(&(x.div_euclid(16), z.div_euclid(16)))?;
+        light.get(kind, x.rem_euclid(16), y, z.rem_euclid(16))
+    }
+
+    fn set_light(&mut self, kind: LightKind, x: i32, y: i32, z: i32, level: u8) {
+        let (chunk_x, chunk_z) = (x.div_euclid(16), z.div_euclid(16));
+        if let Some((_, light)) = self.chunks.get_mut(&(chunk_x, chunk_z)) {
+            let (local_x, local_z) = (x.rem_euclid(16), z.rem_euclid(16));
+            if light.get(kind, local_x, y, local_z).is_some_and(|old| old != level) && light.set(kind, local_x, y, local_z, level) {
+                self.changed_sections.insert((chunk_x, y.div_euclid(16), chunk_z));
+            }
+        }
+    }
+}
@@ -1,15 +1,5 @@
-mod packet;
-mod packet_builder;
-mod error;
-mod server_util;
-mod server;
-mod server_connection;
-mod resource_manager;
-mod command;
-mod block_registry;
-
 use std::env;
-use crate::server::MCServer;
+use mc_server::server::MCServer;
 
 
 fn main() {
@@ -0,0 +1,67 @@
+use crate::collision::{self, Aabb};
+
+/// Players can't go further out than the world border can be
+pub const MAX_COORDINATE: f64 = 3.0e7;
+/// Squared distance a player may move per move packet, like vanilla
+pub const MAX_MOVE_DISTANCE_SQUARED: f64 = 100.0;
+/// Gliding with elytra is faster
+pub const MAX_FALL_FLYING_MOVE_DISTANCE_SQUARED: f64 = 300.0;
+/// Move packets per tick the allowed distance can add up over, when the client sends several at once after lagging
+pub const MAX_MOVES_PER_TICK: u32 = 5;
+pub const PLAYER_WIDTH: f64 = 0.6;
+/// Height of the smallest pose, swimming or crawling. Poses aren't tracked, so moves are checked with this height.
+pub const PLAYER_MIN_HEIGHT: f64 = 0.6;
+
+/// What a player was doing when a move came in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MoveFrom {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub fall_flying: bool,
+    /// Spectators go through blocks and as fast as they like
+    pub spectator: bool,
+    /// The client hasn't confirmed the last teleport yet
+    pub awaiting_teleport: bool,
+    /// Move packets this tick, counting this one
+    pub moves_this_tick: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveCheck {
+    Accept,
+    /// Not a number or outside the world, the client is disconnected
+    Invalid,
+    /// Sent before the client got the last teleport
+    Ignore,
+    /// The player is teleported back
+    TooFast,
+    /// The player is teleported back
+    IntoBlock,
+}
+
+/// Checks a move to `x`, `y`, `z`. `collision_at` gives the collision box of the block at a position, blocks the
+/// client doesn't have count as empty.
+pub fn check_move(from: &MoveFrom, x: f64, y: f64, z: f64, collision_at: impl Fn(i32, i32, i32) -> Option<Aabb>) -> MoveCheck {
+    if !x.is_finite() || !y.is_finite() || !z.is_finite() || x.abs() > MAX_COORDINATE || z.abs() > MAX_COORDINATE {
+        return MoveCheck::Invalid;
+    }
+    if from.awaiting_teleport {
+        return MoveCheck::Ignore;
+    }
+    if from.spectator {
+        return MoveCheck::Accept;
+    }
+
+    let (dx, dy, dz) = (x - from.x, y - from.y, z - from.z);
+    let max_distance_squared = if from.fall_flying { MAX_FALL_FLYING_MOVE_DISTANCE_SQUARED } else { MAX_MOVE_DISTANCE_SQUARED };
+    if dx * dx + dy * dy + dz * dz > max_distance_squared * from.moves_this_tick.clamp(1, MAX_MOVES_PER_TICK) as f64 {
+        return MoveCheck::TooFast;
+    }
+    let before = Aabb::of_entity(from.x, from.y, from.z, PLAYER_WIDTH, PLAYER_MIN_HEIGHT).deflate(1.0e-5);
+    let after = Aabb::of_entity(x, y, z, PLAYER_WIDTH, PLAYER_MIN_HEIGHT).deflate(1.0e-5);
+    if collision::moves_into_block(&before, &after, collision_at) {
+        return MoveCheck::IntoBlock;
+    }
+    MoveCheck::Accept
+}
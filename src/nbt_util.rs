@@ -0,0 +1,232 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use inbt::NbtTag;
+use serde_json::{Number, Value};
+use crate::error::ServerError;
+use crate::packet_builder::{nbt_type_id, write_nbt_payload, write_nbt_string};
+
+/// Convenience accessors for reading values out of parsed NBT
+pub trait NbtTagExt {
+    /// Returns the child of a compound tag with the given name
+    fn child(&self, name: &str) -> Option<&NbtTag>;
+    fn as_i8(&self) -> Option<i8>;
+    fn as_i32(&self) -> Option<i32>;
+    fn as_i64(&self) -> Option<i64>;
+    fn as_f32(&self) -> Option<f32>;
+    fn as_f64(&self) -> Option<f64>;
+    fn as_str(&self) -> Option<&str>;
+    fn as_byte_array(&self) -> Option<&Vec<i8>>;
+    fn as_int_array(&self) -> Option<&Vec<i32>>;
+    /// Returns the elements of a list tag or the children of a compound tag
+    fn as_list(&self) -> Option<&Vec<NbtTag>>;
+    /// Adds a child to a compound tag, replacing the one with the same name
+    fn set_child(&mut self, child: NbtTag);
+}
+
+impl NbtTagExt for NbtTag {
+    fn child(&self, name: &str) -> Option<&NbtTag> {
+        match self {
+            NbtTag::Compound(_, children) => children.iter().find(|c| c.name() == name),
+            _ => None,
+        }
+    }
+
+    fn as_i8(&self) -> Option<i8> {
+        match self {
+            NbtTag::Byte(_, v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    fn as_i32(&self) -> Option<i32> {
+        match self {
+            NbtTag::Byte(_, v) => Some(*v as i32),
+            NbtTag::Short(_, v) => Some(*v as i32),
+            NbtTag::Int(_, v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    fn as_i64(&self) -> Option<i64> {
+        match self {
+            NbtTag::Long(_, v) => Some(*v),
+            _ => self.as_i32().map(|v| v as i64),
+        }
+    }
+
+    fn as_f32(&self) -> Option<f32> {
+        match self {
+            NbtTag::Float(_, v) => Some(*v),
+            NbtTag::Double(_, v) => Some(*v as f32),
+            _ => None,
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            NbtTag::Float(_, v) => Some(*v as f64),
+            NbtTag::Double(_, v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            NbtTag::String(_, v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn as_byte_array(&self) -> Option<&Vec<i8>> {
+        match self {
+            NbtTag::ByteArray(_, v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn as_int_array(&self) -> Option<&Vec<i32>> {
+        match self {
+            NbtTag::IntArray(_, v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn as_list(&self) -> Option<&Vec<NbtTag>> {
+        match self {
+            NbtTag::List(_, v) => Some(v),
+            NbtTag::Compound(_, v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn set_child(&mut self, child: NbtTag) {
+        if let NbtTag::Compound(_, children) = self {
+            match children.iter_mut().find(|c| c.name() == child.name()) {
+                Some(existing) => *existing = child,
+                None => children.push(child),
+            }
+        }
+    }
+}
+
+/// Converts JSON, like registry files and text components, to the NBT the client's codecs expect. Numbers that
+/// fit in an int become Int tags, other integers Long and everything else Double, since the client reads any
+/// numeric tag.
+pub fn json_to_nbt(name: String, json: &Value) -> NbtTag {
+    match json {
+        Value::Null => NbtTag::End,
+        Value::Bool(b) => NbtTag::Byte(name, *b as i8),
+        Value::Number(n) => {
+            if let Some(int) = n.as_i64() {
+                if let Ok(int) = i32::try_from(int) {
+                    NbtTag::Int(name, int)
+                } else {
+                    NbtTag::Long(name, int)
+                }
+            } else {
+                NbtTag::Double(name, n.as_f64().unwrap_or(0.0))
+            }
+        }
+        Value::String(s) => NbtTag::String(name, s.clone()),
+        Value::Array(values) => {
+            let mut list = values.iter().map(|v| json_to_nbt("".to_string(), v)).collect::<Vec<_>>();
+            // NBT lists must be homogeneous, wrap mixed lists the same way vanilla does
+            if list.windows(2).any(|w| std::mem::discriminant(&w[0]) != std::mem::discriminant(&w[1])) {
+                list = list.into_iter().map(|t| NbtTag::Compound("".to_string(), vec![t])).collect();
+            }
+            NbtTag::List(name, list)
+        }
+        Value::Object(map) => {
+            let children = map.iter()
+                .map(|(key, value)| json_to_nbt(key.clone(), value))
+                .filter(|tag| !matches!(tag, NbtTag::End))
+                .collect();
+            NbtTag::Compound(name, children)
+        }
+    }
+}
+
+/// The other way around, for saving text components as JSON. Bytes become numbers, which vanilla also reads as
+/// booleans, and the compounds wrapping the elements of mixed lists are unwrapped.
+pub fn nbt_to_json(tag: &NbtTag) -> Value {
+    let float = |value: f64| Number::from_f64(value).map(Value::Number).unwrap_or(Value::Null);
+    match tag {
+        NbtTag::End => Value::Null,
+        NbtTag::Byte(_, v) => Value::from(*v),
+        NbtTag::Short(_, v) => Value::from(*v),
+        NbtTag::Int(_, v) => Value::from(*v),
+        NbtTag::Long(_, v) => Value::from(*v),
+        NbtTag::Float(_, v) => float(*v as f64),
+        NbtTag::Double(_, v) => float(*v),
+        NbtTag::ByteArray(_, v) => Value::from(v.clone()),
+        NbtTag::String(_, v) => Value::from(v.clone()),
+        NbtTag::List(_, values) => Value::Array(values.iter()
+            .map(|value| match value {
+                NbtTag::Compound(_, children) if children.len() == 1 && children[0].name().is_empty() => nbt_to_json(&children[0]),
+                value => nbt_to_json(value),
+            })
+            .collect()),
+        NbtTag::Compound(_, children) => Value::Object(children.iter().map(|child| (child.name().to_string(), nbt_to_json(child))).collect()),
+        NbtTag::IntArray(_, v) => Value::from(v.clone()),
+        NbtTag::LongArray(_, v) => Value::from(v.clone()),
+    }
+}
+
+/// Writes NBT as SNBT, the text form commands use and vanilla saves some NBT inside of NBT as
+pub fn nbt_to_snbt(tag: &NbtTag) -> String {
+    let join = |values: Vec<String>| values.join(",");
+    match tag {
+        NbtTag::End => String::new(),
+        NbtTag::Byte(_, v) => format!("{v}b"),
+        NbtTag::Short(_, v) => format!("{v}s"),
+        NbtTag::Int(_, v) => v.to_string(),
+        NbtTag::Long(_, v) => format!("{v}L"),
+        NbtTag::Float(_, v) => format!("{v}f"),
+        NbtTag::Double(_, v) => format!("{v}d"),
+        NbtTag::ByteArray(_, v) => format!("[B;{}]", join(v.iter().map(|b| format!("{b}b")).collect())),
+        NbtTag::String(_, v) => snbt_string(v),
+        NbtTag::List(_, values) => format!("[{}]", join(values.iter().map(nbt_to_snbt).collect())),
+        NbtTag::Compound(_, children) => format!("{{{}}}", join(children.iter()
+            .map(|child| {
+                let name = child.name();
+                let plain = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || "_-.+".contains(c));
+                let key = if plain { name.to_string() } else { snbt_string(name) };
+                format!("{key}:{}", nbt_to_snbt(child))
+            })
+            .collect())),
+        NbtTag::IntArray(_, v) => format!("[I;{}]", join(v.iter().map(|i| i.to_string()).collect())),
+        NbtTag::LongArray(_, v) => format!("[L;{}]", join(v.iter().map(|l| format!("{l}L")).collect())),
+    }
+}
+
+fn snbt_string(string: &str) -> String {
+    format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Reads a gzip compressed NBT file like level.dat
+pub fn read_gzip_file<P: AsRef<Path>>(path: P) -> Result<NbtTag, ServerError> {
+    Ok(inbt::nbt_parser::parse_gzip(fs::read(path)?)?)
+}
+
+/// Writes a gzip compressed NBT file with a named root tag. The file is written next to `path` first and then
+/// moved over it, keeping the previous version as `<name>_old` like vanilla does.
+pub fn write_gzip_file<P: AsRef<Path>>(path: P, tag: &NbtTag) -> Result<(), ServerError> {
+    let path = path.as_ref();
+    let mut data = vec![nbt_type_id(tag)];
+    write_nbt_string(tag.name(), &mut data);
+    write_nbt_payload(tag, &mut data);
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(&data)?;
+
+    let file_name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let new_path = path.with_file_name(format!("{file_name}_new"));
+    fs::write(&new_path, encoder.finish()?)?;
+    if path.exists() {
+        fs::rename(path, path.with_file_name(format!("{file_name}_old")))?;
+    }
+    fs::rename(new_path, path)?;
+    Ok(())
+}
@@ -1,10 +1,11 @@
+use inbt::NbtTag;
 use log::error;
 use crate::error::ServerError;
 use crate::packet::*;
 use crate::packet_builder::PacketBuilder;
 use crate::server_util::{RegistryEntry, TagEntry};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct KnownPack {
     pub namespace: String,
     pub id: String,
@@ -45,43 +46,75 @@ impl MCPacketType for ConfigurationPacketResponse {
 }
 
 impl ConfigurationPacketResponse {
-    pub fn registry_data(registry_id: String, entries: Vec<RegistryEntry>) -> Vec<u8> {
+    pub fn disconnect<S: Into<String>>(reason: S) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::Disconnect)
+            .add_nbt(&NbtTag::String("".to_string(), reason.into()))
+            .build().unwrap()
+    }
+
+    pub fn known_packs(known_packs: &[KnownPack]) -> Vec<u8> {
+        let mut packet = PacketBuilder::new()
+            .set_id(Self::ClientBoundKnownPacks)
+            .add_varint(known_packs.len() as i32);
+
+        for pack in known_packs {
+            packet = packet.add_string(pack.namespace.clone())
+                .add_string(pack.id.clone())
+                .add_string(pack.version.clone());
+        }
+
+        packet.build().unwrap()
+    }
+
+    /// Entry data is omitted for entries that come from one of the `shared_packs`
+    pub fn registry_data(registry_id: &str, entries: &[RegistryEntry], shared_packs: &[KnownPack]) -> Vec<u8> {
         let mut packet = PacketBuilder::new()
             .set_id(Self::RegistryData)
             .add_string(registry_id)
             .add_varint(entries.len() as i32);
 
         for entry in entries {
-            packet = packet.add_string(entry.id)
-                //.add_bool(entry.data.is_some());
-                .add_bool(false);
-            if entry.data.is_some() {
-                //packet = packet.add_nbt(entry.data.unwrap();
+            packet = packet.add_string(entry.id.clone());
+            match &entry.data {
+                Some(data) if !entry.client_has_entry(shared_packs) => {
+                    packet = packet.add_bool(true)
+                        .add_nbt(data);
+                }
+                _ => {
+                    packet = packet.add_bool(false);
+                }
             }
         }
 
         packet.build().unwrap()
     }
 
-    pub fn update_tags(tags: Vec<TagEntry>) -> Vec<u8> {
-        let mut packet = PacketBuilder::new()
-            .set_id(Self::UpdateTags)
-            .add_varint(tags.len() as i32);
-
-        for tag in tags {
-            packet = packet.add_string(tag.id)
-                .add_varint(tag.data.len() as i32);
-            for tag_array in tag.data {
-                packet = packet.add_string(tag_array.tag_name)
-                    .add_varint(tag_array.entries.len() as i32);
-                for entry in tag_array.entries {
-                    packet = packet.add_varint(entry);
-                }
+    pub fn update_tags(tags: &[TagEntry]) -> Vec<u8> {
+        let packet = PacketBuilder::new()
+            .set_id(Self::UpdateTags);
+
+        write_tags(packet, tags).build().unwrap()
+    }
+}
+
+/// Writes the body of Update Tags, which is the same in the configuration and play state
+pub(super) fn write_tags(mut packet: PacketBuilder, tags: &[TagEntry]) -> PacketBuilder {
+    packet = packet.add_varint(tags.len() as i32);
+
+    for tag in tags {
+        packet = packet.add_string(tag.id.clone())
+            .add_varint(tag.data.len() as i32);
+        for tag_array in &tag.data {
+            packet = packet.add_string(tag_array.tag_name.clone())
+                .add_varint(tag_array.entries.len() as i32);
+            for entry in &tag_array.entries {
+                packet = packet.add_varint(*entry);
             }
         }
-
-        packet.build().unwrap()
     }
+
+    packet
 }
 
 impl ConfigurationPacketType {
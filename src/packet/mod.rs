@@ -5,13 +5,15 @@ mod configure;
 mod play;
 
 use std::slice::Iter;
+use inbt::NbtTag;
 use crate::error::ServerError;
 
 pub use status::StatusPacketType;
 pub use handshake::HandshakePacketType;
 pub use login::{LoginPacketType, LoginPacketResponse};
-pub use configure::{ConfigurationPacketType, ConfigurationPacketResponse};
-pub use play::{PlayPacketServerBound, PlayPacketClientBound};
+pub use configure::{ConfigurationPacketType, ConfigurationPacketResponse, KnownPack};
+pub use play::{PlayPacketServerBound, PlayPacketClientBound, Slot};
+pub(crate) use play::next_nested_slot;
 
 pub trait MCPacketType {
     fn id(self) -> i32;
@@ -22,7 +24,7 @@ pub trait MCPacketType {
 //      packet_id: VarInt
 //      data: ByteArray
 
-fn next_varint(data: &mut Iter<u8>) -> Result<i32, ServerError> {
+pub(crate) fn next_varint(data: &mut Iter<u8>) -> Result<i32, ServerError> {
     let mut value = 0;
     let mut shift = 0;
     loop {
@@ -39,43 +41,47 @@ fn next_varint(data: &mut Iter<u8>) -> Result<i32, ServerError> {
     Ok(value)
 }
 
-fn next_u8(data: &mut Iter<u8>) -> Result<u8, ServerError> {
+pub(crate) fn next_u8(data: &mut Iter<u8>) -> Result<u8, ServerError> {
     Ok(*data.next().ok_or(ServerError::EndOfPacket)?)
 }
 
-fn next_u16(data: &mut Iter<u8>) -> Result<u16, ServerError> {
+pub(crate) fn next_u16(data: &mut Iter<u8>) -> Result<u16, ServerError> {
     Ok(u16::from_be_bytes([*data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?]))
 }
 
-fn next_u64(data: &mut Iter<u8>) -> Result<u64, ServerError> {
+pub(crate) fn next_i32(data: &mut Iter<u8>) -> Result<i32, ServerError> {
+    Ok(i32::from_be_bytes([*data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?]))
+}
+
+pub(crate) fn next_u64(data: &mut Iter<u8>) -> Result<u64, ServerError> {
     Ok(u64::from_be_bytes([
         *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?,
         *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?,
     ]))
 }
 
-fn next_f32(data: &mut Iter<u8>) -> Result<f32, ServerError> {
+pub(crate) fn next_f32(data: &mut Iter<u8>) -> Result<f32, ServerError> {
     Ok(f32::from_be_bytes([*data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?]))
 }
 
-fn next_f64(data: &mut Iter<u8>) -> Result<f64, ServerError> {
+pub(crate) fn next_f64(data: &mut Iter<u8>) -> Result<f64, ServerError> {
     Ok(f64::from_be_bytes([
         *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?,
         *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?
     ]))
 }
 
-fn next_string(data: &mut Iter<u8>) -> Result<String, ServerError> {
+pub(crate) fn next_string(data: &mut Iter<u8>) -> Result<String, ServerError> {
     let length = next_varint(data)?;
     let utf8 = data.take(length as usize).map(|n| *n).collect::<Vec<u8>>();
     Ok(String::from_utf8(utf8)?)
 }
 
-fn next_bool(data: &mut Iter<u8>) -> Result<bool, ServerError> {
+pub(crate) fn next_bool(data: &mut Iter<u8>) -> Result<bool, ServerError> {
     Ok(*data.next().ok_or(ServerError::EndOfPacket)? != 0)
 }
 
-fn next_u128(data: &mut Iter<u8>) -> Result<u128, ServerError> {
+pub(crate) fn next_u128(data: &mut Iter<u8>) -> Result<u128, ServerError> {
     Ok(u128::from_be_bytes(
         [
             *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?,
@@ -84,4 +90,81 @@ fn next_u128(data: &mut Iter<u8>) -> Result<u128, ServerError> {
             *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?, *data.next().ok_or(ServerError::EndOfPacket)?,
         ]
     ))
-}
\ No newline at end of file
+}
+
+/// Vanilla refuses NBT nested deeper than this
+const MAX_NBT_DEPTH: usize = 512;
+
+/// A nameless root tag, as used by the network protocol since 1.20.2. A lone TAG_End means no value.
+pub(crate) fn next_nbt(data: &mut Iter<u8>) -> Result<NbtTag, ServerError> {
+    let type_id = next_u8(data)?;
+    next_nbt_payload(type_id, String::new(), data, 0)
+}
+
+fn next_nbt_string(data: &mut Iter<u8>) -> Result<String, ServerError> {
+    let length = next_u16(data)? as usize;
+    let bytes = data.take(length).copied().collect::<Vec<u8>>();
+    if bytes.len() != length {
+        return Err(ServerError::EndOfPacket);
+    }
+    // Java writes modified UTF-8, which only differs for NUL and characters outside the BMP
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Lengths of arrays and lists, which can't be larger than what is left of the packet
+fn next_nbt_length(data: &mut Iter<u8>, element_size: usize) -> Result<usize, ServerError> {
+    let length = next_i32(data)?.max(0) as usize;
+    if length.saturating_mul(element_size) > data.len() {
+        return Err(ServerError::EndOfPacket);
+    }
+    Ok(length)
+}
+
+fn next_nbt_payload(type_id: u8, name: String, data: &mut Iter<u8>, depth: usize) -> Result<NbtTag, ServerError> {
+    if depth > MAX_NBT_DEPTH {
+        return Err(ServerError::InvalidNbt("nested too deep"));
+    }
+    Ok(match type_id {
+        0 => NbtTag::End,
+        1 => NbtTag::Byte(name, next_u8(data)? as i8),
+        2 => NbtTag::Short(name, next_u16(data)? as i16),
+        3 => NbtTag::Int(name, next_i32(data)?),
+        4 => NbtTag::Long(name, next_u64(data)? as i64),
+        5 => NbtTag::Float(name, next_f32(data)?),
+        6 => NbtTag::Double(name, next_f64(data)?),
+        7 => {
+            let length = next_nbt_length(data, 1)?;
+            NbtTag::ByteArray(name, data.take(length).map(|b| *b as i8).collect())
+        }
+        8 => NbtTag::String(name, next_nbt_string(data)?),
+        9 => {
+            let element_type = next_u8(data)?;
+            let length = next_nbt_length(data, 1)?;
+            let elements = (0..length)
+                .map(|_| next_nbt_payload(element_type, String::new(), data, depth + 1))
+                .collect::<Result<Vec<_>, _>>()?;
+            NbtTag::List(name, elements)
+        }
+        10 => {
+            let mut children = vec![];
+            loop {
+                let child_type = next_u8(data)?;
+                if child_type == 0 {
+                    break;
+                }
+                let child_name = next_nbt_string(data)?;
+                children.push(next_nbt_payload(child_type, child_name, data, depth + 1)?);
+            }
+            NbtTag::Compound(name, children)
+        }
+        11 => {
+            let length = next_nbt_length(data, 4)?;
+            NbtTag::IntArray(name, (0..length).map(|_| next_i32(data)).collect::<Result<Vec<_>, _>>()?)
+        }
+        12 => {
+            let length = next_nbt_length(data, 8)?;
+            NbtTag::LongArray(name, (0..length).map(|_| next_u64(data).map(|v| v as i64)).collect::<Result<Vec<_>, _>>()?)
+        }
+        _ => return Err(ServerError::InvalidNbt("unknown tag type")),
+    })
+}
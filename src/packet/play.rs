@@ -1,20 +1,145 @@
+use std::slice::Iter;
+use inbt::NbtTag;
 use log::debug;
 use mc_datatypes::{BlockPos, VarInt};
 use mc_world_parser::{Block, Position};
-use mc_world_parser::chunk::Chunk;
-use mc_world_parser::section::BlockIDGetter;
 use uuid::Uuid;
+use crate::block_entity::BlockEntity;
+use crate::block_registry::BlockRegistry;
+use crate::block_storage::ChunkBlocks;
 use crate::command::CommandNode;
+use crate::entity::MetadataValue;
 use crate::error::ServerError;
+use crate::item::ItemRegistry;
+use crate::item_component::{self, ItemComponent};
+use crate::level_data::LevelData;
+use crate::light::{ChunkLight, LightArray};
+use crate::nbt_util::NbtTagExt;
 use crate::packet::*;
+use crate::packet::configure::write_tags;
 use crate::packet_builder::PacketBuilder;
+use crate::server_util::{DimensionInfo, TagEntry};
+use crate::tab_list::{self, PlayerListEntry};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Slot {
     pub(crate) count: i32,
     pub(crate) item_id: Option<i32>,
-    pub(crate) components_to_add: Option<Vec<(i32, Vec<u8>)>>,
-    pub(crate) number_to_remove: Option<Vec<i32>>,
+    /// Components that differ from the item's defaults
+    pub(crate) components_to_add: Vec<ItemComponent>,
+    /// Types of default components the stack doesn't have
+    pub(crate) components_to_remove: Vec<i32>,
+}
+
+impl Slot {
+    pub fn empty() -> Self {
+        Self { count: 0, item_id: None, components_to_add: vec![], components_to_remove: vec![] }
+    }
+
+    pub fn new(item_id: i32, count: i32) -> Self {
+        Self { count, item_id: Some(item_id), components_to_add: vec![], components_to_remove: vec![] }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count <= 0 || self.item_id.is_none()
+    }
+
+    pub fn count(&self) -> i32 {
+        self.count
+    }
+
+    pub fn item_id(&self) -> Option<i32> {
+        self.item_id
+    }
+
+    pub fn with_count(mut self, count: i32) -> Self {
+        self.count = count;
+        self
+    }
+
+    /// The stack's own `max_stack_size` component, or the item's default
+    pub fn max_stack_size(&self, items: &ItemRegistry) -> i32 {
+        self.components_to_add.iter()
+            .find_map(|c| match c {
+                ItemComponent::MaxStackSize(size) => Some(*size),
+                _ => None,
+            })
+            .or_else(|| self.item_id.map(|id| items.max_stack_size(id)))
+            .unwrap_or(0)
+    }
+
+    /// Whether both stacks are the same item with the same components, so they could be stacked
+    pub fn is_same_item(&self, other: &Slot) -> bool {
+        self.item_id == other.item_id
+            && self.components_to_add == other.components_to_add
+            && self.components_to_remove == other.components_to_remove
+    }
+
+    pub fn components(&self) -> &[ItemComponent] {
+        &self.components_to_add
+    }
+
+    pub fn removed_components(&self) -> &[i32] {
+        &self.components_to_remove
+    }
+
+    /// Adds a component, replacing the one of the same type
+    pub fn with_component(mut self, component: ItemComponent) -> Self {
+        let type_name = component.type_name().to_string();
+        self.components_to_add.retain(|c| c.type_name() != type_name);
+        if let Some(type_id) = item_component::component_type_id(&type_name) {
+            self.components_to_remove.retain(|id| *id != type_id);
+        }
+        self.components_to_add.push(component);
+        self
+    }
+
+    /// Removes a default component of the item
+    pub fn without_component(mut self, type_id: i32) -> Self {
+        self.components_to_add.retain(|c| item_component::component_type_id(c.type_name()) != Some(type_id));
+        if !self.components_to_remove.contains(&type_id) {
+            self.components_to_remove.push(type_id);
+        }
+        self
+    }
+
+    /// An item stack as saved in chunks and player files, `{id: "minecraft:bow", count: 1, components: {...}}`.
+    /// Empty if the item isn't in the item registry.
+    pub fn from_nbt(nbt: &NbtTag, items: &ItemRegistry) -> Self {
+        let count = nbt.child("count").and_then(|c| c.as_i32()).unwrap_or(1);
+        let mut slot = match nbt.child("id").and_then(|id| id.as_str()).and_then(|id| items.id(id)) {
+            Some(item_id) if count > 0 => Self::new(item_id, count),
+            _ => return Self::empty(),
+        };
+        for component in nbt.child("components").and_then(|c| c.as_list()).map(|c| &c[..]).unwrap_or_default() {
+            // Removed default components are saved as `"!minecraft:food": {}`
+            match component.name().strip_prefix('!') {
+                Some(name) => match item_component::component_type_id(name) {
+                    Some(type_id) => slot.components_to_remove.push(type_id),
+                    None => debug!("Dropping removal of unknown item component {}", name),
+                },
+                None => slot.components_to_add.push(ItemComponent::from_nbt(component, items)),
+            }
+        }
+        slot
+    }
+
+    /// The stack in the saved format, `None` if it is empty
+    pub fn to_nbt(&self, items: &ItemRegistry) -> Option<NbtTag> {
+        let name = items.name(self.item_id?).filter(|_| self.count > 0)?;
+        let mut nbt = NbtTag::Compound("".to_string(), vec![
+            NbtTag::String("id".to_string(), name.to_string()),
+            NbtTag::Int("count".to_string(), self.count),
+        ]);
+        let mut components = self.components_to_add.iter().filter_map(|c| c.to_nbt(items)).collect::<Vec<_>>();
+        components.extend(self.components_to_remove.iter()
+            .filter_map(|id| item_component::COMPONENT_TYPES.get(*id as usize))
+            .map(|name| NbtTag::Compound(format!("!{name}"), vec![])));
+        if !components.is_empty() {
+            nbt.set_child(NbtTag::Compound("components".to_string(), components));
+        }
+        Some(nbt)
+    }
 }
 
 #[derive(Debug)]
@@ -22,9 +147,13 @@ pub enum PlayPacketServerBound {
     ConfirmTeleportation { id: i32 },
     ChatCommand { command: String },
     ChatMessage { message: String, timestamp: i64, salt: i64, signature: Option<Vec<u8>>, message_count: i32, acknowledged: Vec<u8> },
+    ChunkBatchReceived { chunks_per_tick: f32 },
+    /// The slots the click changed and the stack on the cursor afterwards, as the client predicted them
+    ClickContainer { window_id: u8, state_id: i32, slot: i16, button: i8, mode: i32, changed_slots: Vec<(i16, Slot)>, carried_item: Slot },
     CloseContainer(u8),
     ClientInformation { locale: String, view_distance: i8, chat_mode: i32, chat_has_colors: bool, /** This is a bit mask */ displayed_skin_parts: u8, main_hand: i32, enable_text_filtering: bool, allow_server_listings: bool,  },
     DebugSampleSubscription { sample_type: i32 },
+    KeepAlive(u64),
     SetPlayerPosition { x: f64, y: f64, z: f64, on_ground: bool },
     SetPlayerPositionAndRotation { x: f64, y: f64, z: f64, yaw: f32, pitch: f32, on_ground: bool },
     SetPlayerRotation{ yaw: f32, pitch: f32, on_ground: bool },
@@ -34,7 +163,8 @@ pub enum PlayPacketServerBound {
     PlayerAction { status: i32, packed_location: u64, face: u8, sequence: i32 },
     PlayerCommand { eid: i32, id: i32, jump_boost: i32 },
     SetHeldItem(u16),
-    SetCreativeModeSlot { slot: u16, clicked_item: Slot },
+    /// Slot -1 drops the item
+    SetCreativeModeSlot { slot: i16, clicked_item: Slot },
     SwingArm{ off_hand: bool },
     UseItemOn { off_hand: bool, packed_location: u64, face: i32, cursor_x: f32, cursor_y: f32, cursor_z: f32, inside_block: bool, sequence: i32 },
     UseItem { off_hand: bool, sequence: i32, yaw: f32, pitch: f32 },
@@ -42,23 +172,50 @@ pub enum PlayPacketServerBound {
 
 #[repr(i32)]
 pub enum PlayPacketClientBound {
+    SpawnEntity = 0x01,
     AcknowledgeBlockChange = 0x05,
+    BlockEntityData = 0x07,
     BlockUpdate = 0x09,
     ChangeDifficulty = 0x0B,
+    ChunkBatchFinished = 0x0C,
+    ChunkBatchStart = 0x0D,
     Commands = 0x11,
+    SetContainerContent = 0x13,
+    SetContainerSlot = 0x15,
+    Disconnect = 0x1D,
     DisguisedChatMessage = 0x1E,
     EntityEvent = 0x1F,
+    GameEvent = 0x22,
+    UnloadChunk = 0x21,
+    KeepAlive = 0x26,
+    UpdateLight = 0x2A,
+    UpdateEntityPosition = 0x2E,
+    UpdateEntityPositionAndRotation = 0x2F,
+    UpdateEntityRotation = 0x30,
     ChunkDataAndUpdateLight = 0x27,
     PingResponse = 0x36,
     PlayerAbilities = 0x38,
     PlayerChatMessage = 0x39,
+    PlayerInfoRemove = 0x3D,
+    PlayerInfoUpdate = 0x3E,
+    RemoveEntities = 0x42,
     Login = 0x2B,
+    Respawn = 0x47,
+    SetHeadRotation = 0x48,
     SyncPlayerPosition = 0x40,
     SetHeldItem = 0x53,
     SetCenterChunk = 0x54,
+    SetDefaultSpawnPosition = 0x56,
+    SetEntityMetadata = 0x58,
+    SetEquipment = 0x5B,
+    TeleportEntity = 0x70,
     SetTickingState = 0x71,
+    SystemChatMessage = 0x6C,
+    SetTabListHeaderAndFooter = 0x6D,
     StepTick = 0x72,
+    UpdateTime = 0x64,
     EntityEffect = 0x76,
+    UpdateTags = 0x78,
 }
 
 impl PlayPacketClientBound {
@@ -71,11 +228,11 @@ impl PlayPacketClientBound {
             .build().unwrap()
     }
 
-    pub fn login(eid: i32, hardcore: bool, dimension_names: Vec<String>, max_players: i32, view_dist: i32, ) -> Vec<u8> {
+    pub fn login(eid: i32, dimension_names: Vec<String>, max_players: i32, view_dist: i32, dimension: &DimensionInfo, game_mode: u8, level: &LevelData) -> Vec<u8> {
         let mut packet = PacketBuilder::new()
             .set_id(Self::Login)
             .add_int(eid)
-            .add_bool(hardcore)
+            .add_bool(level.hardcore)
             .add_varint(dimension_names.len() as i32);
 
         for dim in dimension_names {
@@ -86,13 +243,13 @@ impl PlayPacketClientBound {
             .add_varint(max_players)
             .add_varint(view_dist)
             .add_varint(view_dist) // Simulation dist
-            .add_bool(false) // Reduced debug view
-            .add_bool(false) // Enable respawn screen
-            .add_bool(false) // Do limited crafting
-            .add_varint(0) // Dimension Type ID
-            .add_string("minecraft:overworld") // Dimension identifier
-            .add_long(-6574177734957711742i64 as u64) // Hashed seed (used for biome noise)
-            .add_byte(1) // Gamemode creative
+            .add_bool(level.game_rule_bool("reducedDebugInfo", false)) // Reduced debug view
+            .add_bool(!level.game_rule_bool("doImmediateRespawn", false)) // Enable respawn screen
+            .add_bool(level.game_rule_bool("doLimitedCrafting", false)) // Do limited crafting
+            .add_varint(dimension.dimension_type) // Dimension Type ID
+            .add_string(dimension.name.clone()) // Dimension identifier
+            .add_long(level.hashed_seed() as u64) // Hashed seed (used for biome noise)
+            .add_byte(game_mode) // Gamemode
             .add_byte(0xFF) // Previous gamemode (-1/0xFF is undefined)
             .add_bool(false) // Debug world
             .add_bool(false) // Flat world
@@ -104,11 +261,56 @@ impl PlayPacketClientBound {
         return packet.build().unwrap();
     }
 
-    pub fn change_difficulty(difficulty: u8) -> Vec<u8> {
+    /// Moves the player to another dimension. The client drops all chunks and waits for the new ones.
+    pub fn respawn(dimension: &DimensionInfo, game_mode: u8, level: &LevelData) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::Respawn)
+            .add_varint(dimension.dimension_type)
+            .add_string(dimension.name.clone())
+            .add_long(level.hashed_seed() as u64)
+            .add_byte(game_mode) // Gamemode
+            .add_byte(0xFF) // Previous gamemode
+            .add_bool(false) // Debug world
+            .add_bool(false) // Flat world
+            .add_bool(false) // Has death location
+            .add_varint(0) // Portal cooldown
+            .add_byte(0x03) // Keep attributes and metadata, like vanilla does when changing dimensions
+            .build().unwrap()
+    }
+
+    pub fn change_difficulty(difficulty: u8, locked: bool) -> Vec<u8> {
         PacketBuilder::new()
             .set_id(Self::ChangeDifficulty)
             .add_byte(difficulty)
-            .add_bool(false)
+            .add_bool(locked)
+            .build().unwrap()
+    }
+
+    /// Where compasses point to
+    pub fn set_default_spawn_position(pos: BlockPos, angle: f32) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::SetDefaultSpawnPosition)
+            .add_long(pos.packed())
+            .add_float(angle)
+            .build().unwrap()
+    }
+
+    /// A negative time of day stops the client's sun from moving, which is how `doDaylightCycle false` is sent
+    pub fn update_time(world_age: i64, time_of_day: i64, daylight_cycle: bool) -> Vec<u8> {
+        let time_of_day = if daylight_cycle { time_of_day } else { -time_of_day.max(1) };
+        PacketBuilder::new()
+            .set_id(Self::UpdateTime)
+            .add_long(world_age as u64)
+            .add_long(time_of_day as u64)
+            .build().unwrap()
+    }
+
+    /// Game events like 1 (begin raining), 2 (end raining), 7 (rain level) and 8 (thunder level)
+    pub fn game_event(event: u8, value: f32) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::GameEvent)
+            .add_byte(event)
+            .add_float(value)
             .build().unwrap()
     }
 
@@ -146,10 +348,20 @@ impl PlayPacketClientBound {
             .build().unwrap()
     }
 
-    pub fn player_abilities() -> Vec<u8> {
+    /// The abilities that come with a game mode: invulnerable (0x01), flying (0x02), allow flying (0x04) and
+    /// instant break (0x08)
+    pub fn player_abilities(game_mode: u8) -> Vec<u8> {
+        let flags = match game_mode {
+            // Creative
+            1 => 0x01 | 0x04 | 0x08,
+            // Spectator
+            3 => 0x01 | 0x02 | 0x04,
+            // Survival and adventure
+            _ => 0,
+        };
         PacketBuilder::new()
             .set_id(Self::PlayerAbilities)
-            .add_byte(0x0D)
+            .add_byte(flags)
             .add_float(0.05)
             .add_float(0.1)
             .build().unwrap()
@@ -204,6 +416,21 @@ impl PlayPacketClientBound {
         packet.build().unwrap()
     }
 
+    pub fn system_chat_message<S: Into<String>>(msg: S) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::SystemChatMessage)
+            .add_nbt(&NbtTag::String("".to_string(), msg.into()))
+            .add_bool(false) // Overlay (action bar)
+            .build().unwrap()
+    }
+
+    pub fn update_tags(tags: &[TagEntry]) -> Vec<u8> {
+        let packet = PacketBuilder::new()
+            .set_id(Self::UpdateTags);
+
+        write_tags(packet, tags).build().unwrap()
+    }
+
     /// See https://wiki.vg/Entity_statuses for event codes
     pub fn entity_event(eid: i32, event: u8) -> Vec<u8> {
         PacketBuilder::new()
@@ -213,6 +440,218 @@ impl PlayPacketClientBound {
             .build().unwrap()
     }
 
+    /// Writes the fields of every action in `actions` for each entry, see the `tab_list` module for the actions
+    pub fn player_info_update(actions: u8, entries: &[&PlayerListEntry]) -> Vec<u8> {
+        let mut packet = PacketBuilder::new()
+            .set_id(Self::PlayerInfoUpdate)
+            .add_byte(actions)
+            .add_varint(entries.len() as i32);
+        for entry in entries {
+            packet = packet.add_uuid(entry.uuid);
+            if actions & tab_list::ADD_PLAYER != 0 {
+                packet = packet.add_string(entry.name.clone())
+                    .add_varint(entry.properties.len() as i32);
+                for property in &entry.properties {
+                    packet = packet.add_string(property.name.clone())
+                        .add_string(property.value.clone())
+                        .add_bool(property.signature.is_some());
+                    if let Some(signature) = &property.signature {
+                        packet = packet.add_string(signature.clone());
+                    }
+                }
+            }
+            if actions & tab_list::INITIALIZE_CHAT != 0 {
+                // No chat session, chat isn't signed
+                packet = packet.add_bool(false);
+            }
+            if actions & tab_list::UPDATE_GAME_MODE != 0 {
+                packet = packet.add_varint(entry.game_mode as i32);
+            }
+            if actions & tab_list::UPDATE_LISTED != 0 {
+                packet = packet.add_bool(entry.listed);
+            }
+            if actions & tab_list::UPDATE_LATENCY != 0 {
+                packet = packet.add_varint(entry.latency);
+            }
+            if actions & tab_list::UPDATE_DISPLAY_NAME != 0 {
+                packet = packet.add_bool(entry.display_name.is_some());
+                if let Some(display_name) = &entry.display_name {
+                    packet = packet.add_nbt(&NbtTag::String("".to_string(), display_name.clone()));
+                }
+            }
+        }
+        packet.build().unwrap()
+    }
+
+    pub fn set_tab_list_header_and_footer(header: &str, footer: &str) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::SetTabListHeaderAndFooter)
+            .add_nbt(&NbtTag::String("".to_string(), header.to_string()))
+            .add_nbt(&NbtTag::String("".to_string(), footer.to_string()))
+            .build().unwrap()
+    }
+
+    pub fn keep_alive(id: u64) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::KeepAlive)
+            .add_long(id)
+            .build().unwrap()
+    }
+
+    pub fn disconnect<S: Into<String>>(reason: S) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::Disconnect)
+            .add_nbt(&NbtTag::String("".to_string(), reason.into()))
+            .build().unwrap()
+    }
+
+    pub fn player_info_remove(uuids: &[Uuid]) -> Vec<u8> {
+        let mut packet = PacketBuilder::new()
+            .set_id(Self::PlayerInfoRemove)
+            .add_varint(uuids.len() as i32);
+        for uuid in uuids {
+            packet = packet.add_uuid(*uuid);
+        }
+        packet.build().unwrap()
+    }
+
+    /// Velocity is in blocks per tick
+    pub fn spawn_entity(eid: i32, uuid: Uuid, entity_type: i32, x: f64, y: f64, z: f64, yaw: f32, pitch: f32, head_yaw: f32, data: i32, velocity: (f64, f64, f64)) -> Vec<u8> {
+        // The client reads velocity in 1/8000 blocks per tick and caps it at 3.9
+        let encode_velocity = |v: f64| (v.clamp(-3.9, 3.9) * 8000.0) as i16;
+        PacketBuilder::new()
+            .set_id(Self::SpawnEntity)
+            .add_varint(eid)
+            .add_uuid(uuid)
+            .add_varint(entity_type)
+            .add_double(x)
+            .add_double(y)
+            .add_double(z)
+            .add_angle(pitch)
+            .add_angle(yaw)
+            .add_angle(head_yaw)
+            .add_varint(data)
+            .add_short(encode_velocity(velocity.0))
+            .add_short(encode_velocity(velocity.1))
+            .add_short(encode_velocity(velocity.2))
+            .build().unwrap()
+    }
+
+    pub fn remove_entities(eids: &[i32]) -> Vec<u8> {
+        let mut packet = PacketBuilder::new()
+            .set_id(Self::RemoveEntities)
+            .add_varint(eids.len() as i32);
+        for eid in eids {
+            packet = packet.add_varint(*eid);
+        }
+        packet.build().unwrap()
+    }
+
+    /// Moves an entity by less than 8 blocks. The deltas are in 1/4096 of a block.
+    pub fn update_entity_position(eid: i32, dx: i16, dy: i16, dz: i16, on_ground: bool) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::UpdateEntityPosition)
+            .add_varint(eid)
+            .add_short(dx)
+            .add_short(dy)
+            .add_short(dz)
+            .add_bool(on_ground)
+            .build().unwrap()
+    }
+
+    pub fn update_entity_position_and_rotation(eid: i32, dx: i16, dy: i16, dz: i16, yaw: f32, pitch: f32, on_ground: bool) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::UpdateEntityPositionAndRotation)
+            .add_varint(eid)
+            .add_short(dx)
+            .add_short(dy)
+            .add_short(dz)
+            .add_angle(yaw)
+            .add_angle(pitch)
+            .add_bool(on_ground)
+            .build().unwrap()
+    }
+
+    /// For moves too far for Update Entity Position
+    pub fn teleport_entity(eid: i32, x: f64, y: f64, z: f64, yaw: f32, pitch: f32, on_ground: bool) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::TeleportEntity)
+            .add_varint(eid)
+            .add_double(x)
+            .add_double(y)
+            .add_double(z)
+            .add_angle(yaw)
+            .add_angle(pitch)
+            .add_bool(on_ground)
+            .build().unwrap()
+    }
+
+    /// Entries are the metadata index and value, see https://wiki.vg/Entity_metadata for the indices of each type
+    pub fn set_entity_metadata(eid: i32, entries: &[(u8, &MetadataValue)]) -> Vec<u8> {
+        let mut packet = PacketBuilder::new()
+            .set_id(Self::SetEntityMetadata)
+            .add_varint(eid);
+        for (index, value) in entries {
+            packet = value.write(packet.add_byte(*index));
+        }
+        packet.add_byte(0xFF).build().unwrap()
+    }
+
+    pub fn set_container_content(window_id: u8, state_id: i32, slots: &[Slot], carried_item: &Slot) -> Vec<u8> {
+        let mut packet = PacketBuilder::new()
+            .set_id(Self::SetContainerContent)
+            .add_byte(window_id)
+            .add_varint(state_id)
+            .add_varint(slots.len() as i32);
+        for slot in slots {
+            packet = packet.add_slot(slot);
+        }
+        packet.add_slot(carried_item).build().unwrap()
+    }
+
+    pub fn set_container_slot(window_id: i8, state_id: i32, slot: i16, item: &Slot) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::SetContainerSlot)
+            .add_byte(window_id as u8)
+            .add_varint(state_id)
+            .add_short(slot)
+            .add_slot(item)
+            .build().unwrap()
+    }
+
+    /// Items an entity holds and wears: slot 0 main hand, 1 off hand, then boots, leggings, chestplate and helmet.
+    /// Needs at least one entry.
+    pub fn set_equipment(eid: i32, equipment: &[(u8, &Slot)]) -> Vec<u8> {
+        let mut packet = PacketBuilder::new()
+            .set_id(Self::SetEquipment)
+            .add_varint(eid);
+        for (i, (slot, item)) in equipment.iter().enumerate() {
+            // The top bit says another entry follows
+            let more = if i + 1 < equipment.len() { 0x80 } else { 0 };
+            packet = packet.add_byte(slot | more).add_slot(item);
+        }
+        packet.build().unwrap()
+    }
+
+    pub fn update_entity_rotation(eid: i32, yaw: f32, pitch: f32, on_ground: bool) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::UpdateEntityRotation)
+            .add_varint(eid)
+            .add_angle(yaw)
+            .add_angle(pitch)
+            .add_bool(on_ground)
+            .build().unwrap()
+    }
+
+    /// Players turn their body separately, but the head always faces where the player looks
+    pub fn set_head_rotation(eid: i32, head_yaw: f32) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::SetHeadRotation)
+            .add_varint(eid)
+            .add_angle(head_yaw)
+            .build().unwrap()
+    }
+
     pub fn entity_effect(eid: i32, effect: i32, amplifier: i32, duration: i32, flags: u8) -> Vec<u8> {
         PacketBuilder::new()
             .set_id(Self::EntityEffect)
@@ -231,40 +670,115 @@ impl PlayPacketClientBound {
             .build().unwrap()
     }
 
-    pub fn set_center_chunk(player_position: BlockPos) -> Vec<u8> {
+    pub fn set_center_chunk(chunk_x: i32, chunk_z: i32) -> Vec<u8> {
         PacketBuilder::new()
             .set_id(Self::SetCenterChunk)
-            .add_varint(player_position.x()/16)
-            .add_varint(player_position.z()/16)
+            .add_varint(chunk_x)
+            .add_varint(chunk_z)
             .build().unwrap()
     }
 
-    pub fn chunk_data(chunk: Chunk, id_getter: Box<dyn BlockIDGetter>) -> Vec<u8> {
-        let data = chunk.network_data(id_getter);
+    pub fn unload_chunk(chunk_x: i32, chunk_z: i32) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::UnloadChunk)
+            .add_int(chunk_z) // Z comes first
+            .add_int(chunk_x)
+            .build().unwrap()
+    }
 
-        let packet = PacketBuilder::new()
+    pub fn chunk_batch_start() -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::ChunkBatchStart)
+            .build().unwrap()
+    }
+
+    pub fn chunk_batch_finished(batch_size: i32) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::ChunkBatchFinished)
+            .add_varint(batch_size)
+            .build().unwrap()
+    }
+
+    pub fn chunk_data(chunk_x: i32, chunk_z: i32, blocks: &ChunkBlocks, block_entities: &[BlockEntity], light: &ChunkLight, block_registry: &BlockRegistry) -> Vec<u8> {
+        let data = blocks.network_data(block_registry);
+
+        let mut packet = PacketBuilder::new()
             .set_id(Self::ChunkDataAndUpdateLight)
-            .add_int(chunk.chunk_pos().x)
-            .add_int(chunk.chunk_pos().z)
-            .add_byte(0x0a) // Compound NBT Tag ID
-            .add_byte(0x00) // NBT END Tag, since root tags have no name when transferred over the network.
+            .add_int(chunk_x)
+            .add_int(chunk_z)
+            .add_nbt(&blocks.heightmaps_nbt())
             .add_varint(data.len() as i32)
             .add_bytes(data)
-            .add_varint(0) // block entities
+            .add_varint(block_entities.len() as i32);
+        for block_entity in block_entities {
+            packet = packet
+                .add_byte((((block_entity.x & 15) << 4) | (block_entity.z & 15)) as u8)
+                .add_short(block_entity.y as i16)
+                .add_varint(block_entity.type_id)
+                .add_nbt(&block_entity.data);
+        }
 
-            .add_varint(0) // Bit sets
-            .add_varint(0) // Bit sets
-            .add_varint(0) // Bit sets
-            .add_varint(0) // Bit sets
+        write_light_data(packet, light).build().unwrap()
+    }
 
-            .add_varint(0) // Skylight array size
-            .add_varint(0) // Blocklight array size
-            ;
+    pub fn block_entity_data(block_entity: &BlockEntity) -> Vec<u8> {
+        PacketBuilder::new()
+            .set_id(Self::BlockEntityData)
+            .add_long(BlockPos::new(block_entity.x, block_entity.y, block_entity.z).packed())
+            .add_varint(block_entity.type_id)
+            .add_nbt(&block_entity.data)
+            .build().unwrap()
+    }
 
-        packet.build().unwrap()
+    pub fn update_light(chunk_x: i32, chunk_z: i32, light: &ChunkLight) -> Vec<u8> {
+        let packet = PacketBuilder::new()
+            .set_id(Self::UpdateLight)
+            .add_varint(chunk_x)
+            .add_varint(chunk_z);
+
+        write_light_data(packet, light).build().unwrap()
     }
 }
 
+/// Writes the light part shared by Chunk Data and Update Light. Sections filled with zeros only go in the empty
+/// masks, and sections without stored light are in neither mask so the client keeps what it has.
+fn write_light_data(mut packet: PacketBuilder, light: &ChunkLight) -> PacketBuilder {
+    let masks = |sections: &Vec<Option<LightArray>>| {
+        let mut mask = vec![0u64; sections.len().div_ceil(64)];
+        let mut empty_mask = mask.clone();
+        for (i, section) in sections.iter().enumerate() {
+            match section {
+                Some(array) if array.iter().all(|b| *b == 0) => empty_mask[i / 64] |= 1 << (i % 64),
+                Some(_) => mask[i / 64] |= 1 << (i % 64),
+                None => {}
+            }
+        }
+        (mask, empty_mask)
+    };
+    let (sky_mask, empty_sky_mask) = masks(light.sky_sections());
+    let (block_mask, empty_block_mask) = masks(light.block_sections());
+
+    for bit_set in [&sky_mask, &block_mask, &empty_sky_mask, &empty_block_mask] {
+        // Trailing zeros are left out like Java's BitSet does
+        let length = bit_set.iter().rposition(|l| *l != 0).map(|i| i + 1).unwrap_or(0);
+        packet = packet.add_varint(length as i32);
+        for long in &bit_set[..length] {
+            packet = packet.add_long(*long);
+        }
+    }
+
+    for sections in [light.sky_sections(), light.block_sections()] {
+        let arrays = sections.iter().flatten().filter(|array| array.iter().any(|b| *b != 0)).collect::<Vec<_>>();
+        packet = packet.add_varint(arrays.len() as i32);
+        for array in arrays {
+            packet = packet
+                .add_varint(array.len() as i32)
+                .add_bytes(array.to_vec());
+        }
+    }
+    packet
+}
+
 impl MCPacketType for PlayPacketClientBound {
     fn id(self) -> i32 {
         self as i32
@@ -286,6 +800,9 @@ impl PlayPacketServerBound {
             0x04 => {
                 Ok(Self::ChatCommand { command: next_string(&mut iterator)? })
             }
+            0x08 => {
+                Ok(Self::ChunkBatchReceived { chunks_per_tick: next_f32(&mut iterator)? })
+            }
             0x0A => {
                 Ok(Self::ClientInformation {
                     locale: next_string(&mut iterator)?,
@@ -312,6 +829,18 @@ impl PlayPacketServerBound {
                     acknowledged: iterator.take(3).map(|n| *n).collect(),
                 })
             }
+            0x0E => {
+                let window_id = next_u8(&mut iterator)?;
+                let state_id = next_varint(&mut iterator)?;
+                let slot = next_u16(&mut iterator)? as i16;
+                let button = next_u8(&mut iterator)? as i8;
+                let mode = next_varint(&mut iterator)?;
+                let changed_count = next_varint(&mut iterator)?;
+                let changed_slots = (0..changed_count)
+                    .map(|_| Ok((next_u16(&mut iterator)? as i16, next_slot(&mut iterator)?)))
+                    .collect::<Result<Vec<_>, ServerError>>()?;
+                Ok(Self::ClickContainer { window_id, state_id, slot, button, mode, changed_slots, carried_item: next_slot(&mut iterator)? })
+            }
             0x0F => {
                 Ok(Self::CloseContainer(next_u8(&mut iterator)?))
             }
@@ -320,6 +849,9 @@ impl PlayPacketServerBound {
                     sample_type: next_varint(&mut iterator)?,
                 })
             }
+            0x18 => {
+                Ok(Self::KeepAlive(next_u64(&mut iterator)?))
+            }
             0x1A => {
                 Ok(Self::SetPlayerPosition {
                     x: next_f64(&mut iterator)?,
@@ -340,9 +872,9 @@ impl PlayPacketServerBound {
             }
             0x1C => {
                 Ok(Self::SetPlayerRotation {
-                    yaw: 0.0,
-                    pitch: 0.0,
-                    on_ground: false,
+                    yaw: next_f32(&mut iterator)?,
+                    pitch: next_f32(&mut iterator)?,
+                    on_ground: next_bool(&mut iterator)?,
                 })
             }
             0x1D => {
@@ -373,24 +905,10 @@ impl PlayPacketServerBound {
                 Ok(Self::SetHeldItem(next_u16(&mut iterator)?))
             }
             0x32 => {
-                let slot = next_u16(&mut iterator)?;
-                let count = next_varint(&mut iterator)?;
-                if count > 0 {
-                    Ok(Self::SetCreativeModeSlot {
-                        slot,
-                        clicked_item: Slot {
-                            count,
-                            item_id: Some(next_varint(&mut iterator)?),
-                            components_to_add: None, // TODO
-                            number_to_remove: None, // TODO
-                        },
-                    })
-                } else {
-                    Ok(Self::SetCreativeModeSlot {
-                        slot,
-                        clicked_item: Slot { count, item_id: None, components_to_add: None, number_to_remove: None, },
-                    })
-                }
+                Ok(Self::SetCreativeModeSlot {
+                    slot: next_u16(&mut iterator)? as i16,
+                    clicked_item: next_slot(&mut iterator)?,
+                })
             }
             0x36 => {
                 Ok(Self::SwingArm { off_hand: next_bool(&mut iterator)? })
@@ -418,4 +936,39 @@ impl PlayPacketServerBound {
             _ => unimplemented!("Invalid play packet id: {:02X}", id)
         }
     }
-}
\ No newline at end of file
+}
+
+/// Items can be nested in bundles, crossbows and containers, this limits how deep so a packet can't exhaust the stack
+const MAX_ITEM_DEPTH: usize = 16;
+
+/// An item stack: the count, and for non-empty stacks the item id and the component changes
+pub(crate) fn next_slot(iterator: &mut Iter<u8>) -> Result<Slot, ServerError> {
+    next_nested_slot(iterator, 0)
+}
+
+/// A Slot inside the components of `depth` other items
+pub(crate) fn next_nested_slot(iterator: &mut Iter<u8>, depth: usize) -> Result<Slot, ServerError> {
+    if depth > MAX_ITEM_DEPTH {
+        return Err(ServerError::ItemNestedTooDeep);
+    }
+    let count = next_varint(iterator)?;
+    if count <= 0 {
+        return Ok(Slot::empty());
+    }
+    let item_id = next_varint(iterator)?;
+    let added_count = next_varint(iterator)?;
+    let removed_count = next_varint(iterator)?;
+    let components_to_add = (0..added_count)
+        .map(|_| {
+            let type_id = next_varint(iterator)?;
+            ItemComponent::read(type_id, iterator, depth)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let components_to_remove = (0..removed_count).map(|_| next_varint(iterator)).collect::<Result<Vec<_>, _>>()?;
+    Ok(Slot {
+        count,
+        item_id: Some(item_id),
+        components_to_add,
+        components_to_remove,
+    })
+}
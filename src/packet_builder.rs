@@ -1,6 +1,8 @@
+use inbt::NbtTag;
 use uuid::Uuid;
 use mc_datatypes::{MCString, VarInt};
-use crate::packet::MCPacketType;
+use crate::item_component;
+use crate::packet::{MCPacketType, Slot};
 
 pub struct PacketBuilder {
     packet_id: Option<i32>,
@@ -71,11 +73,45 @@ impl PacketBuilder {
         self
     }
 
+    /// Writes a rotation in degrees as steps of 1/256 of a full turn
+    pub fn add_angle(mut self, degrees: f32) -> Self {
+        self.proto_packet.push((degrees.rem_euclid(360.0) * 256.0 / 360.0) as u8);
+        self
+    }
+
     pub fn add_bool(mut self, value: bool) -> Self {
         self.proto_packet.push(value as u8);
         self
     }
 
+    /// An item stack: the count, and for non-empty stacks the item id and the component changes
+    pub fn add_slot(mut self, slot: &Slot) -> Self {
+        if slot.is_empty() {
+            return self.add_varint(0);
+        }
+        // Components that are only saved are left out
+        let added = slot.components_to_add.iter().filter_map(|c| Some((c.network_type_id()?, c))).collect::<Vec<_>>();
+        let removed = slot.components_to_remove.iter().filter(|id| item_component::is_synced(**id)).collect::<Vec<_>>();
+        self = self.add_varint(slot.count)
+            .add_varint(slot.item_id.unwrap_or_default())
+            .add_varint(added.len() as i32)
+            .add_varint(removed.len() as i32);
+        for (type_id, component) in added {
+            self = component.write(self.add_varint(type_id));
+        }
+        for type_id in removed {
+            self = self.add_varint(*type_id);
+        }
+        self
+    }
+
+    /// Writes a nameless root tag, as used by the network protocol since 1.20.2
+    pub fn add_nbt(mut self, tag: &NbtTag) -> Self {
+        self.proto_packet.push(nbt_type_id(tag));
+        write_nbt_payload(tag, &mut self.proto_packet);
+        self
+    }
+
     pub fn build(mut self) -> Option<Vec<u8>> {
         let mut packet = vec![];
         packet.append(&mut VarInt::new(self.packet_id?).bytes);
@@ -87,4 +123,71 @@ impl PacketBuilder {
         packet.reverse();
         Some(packet)
     }
-}
\ No newline at end of file
+}
+
+pub(crate) fn nbt_type_id(tag: &NbtTag) -> u8 {
+    match tag {
+        NbtTag::End => 0,
+        NbtTag::Byte(..) => 1,
+        NbtTag::Short(..) => 2,
+        NbtTag::Int(..) => 3,
+        NbtTag::Long(..) => 4,
+        NbtTag::Float(..) => 5,
+        NbtTag::Double(..) => 6,
+        NbtTag::ByteArray(..) => 7,
+        NbtTag::String(..) => 8,
+        NbtTag::List(..) => 9,
+        NbtTag::Compound(..) => 10,
+        NbtTag::IntArray(..) => 11,
+        NbtTag::LongArray(..) => 12,
+    }
+}
+
+pub(crate) fn write_nbt_string(string: &str, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(string.len() as u16).to_be_bytes());
+    out.extend_from_slice(string.as_bytes());
+}
+
+pub(crate) fn write_nbt_payload(tag: &NbtTag, out: &mut Vec<u8>) {
+    match tag {
+        NbtTag::End => {}
+        NbtTag::Byte(_, value) => out.push(*value as u8),
+        NbtTag::Short(_, value) => out.extend_from_slice(&value.to_be_bytes()),
+        NbtTag::Int(_, value) => out.extend_from_slice(&value.to_be_bytes()),
+        NbtTag::Long(_, value) => out.extend_from_slice(&value.to_be_bytes()),
+        NbtTag::Float(_, value) => out.extend_from_slice(&value.to_be_bytes()),
+        NbtTag::Double(_, value) => out.extend_from_slice(&value.to_be_bytes()),
+        NbtTag::ByteArray(_, values) => {
+            out.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            out.extend(values.iter().map(|v| *v as u8));
+        }
+        NbtTag::String(_, value) => write_nbt_string(value, out),
+        NbtTag::List(_, values) => {
+            out.push(values.first().map(nbt_type_id).unwrap_or(0));
+            out.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            for value in values {
+                write_nbt_payload(value, out);
+            }
+        }
+        NbtTag::Compound(_, children) => {
+            for child in children {
+                out.push(nbt_type_id(child));
+                write_nbt_string(child.name(), out);
+                write_nbt_payload(child, out);
+            }
+            out.push(0); // TAG_End
+        }
+        NbtTag::IntArray(_, values) => {
+            out.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            for value in values {
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        NbtTag::LongArray(_, values) => {
+            out.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            for value in values {
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use inbt::NbtTag;
+use uuid::Uuid;
+use crate::error::ServerError;
+use crate::level_data::LevelData;
+use crate::nbt_util::{read_gzip_file, write_gzip_file, NbtTagExt};
+
+/// Data version of 1.21, written into new player files
+pub(crate) const DATA_VERSION: i32 = 3953;
+
+/// A player's saved state from `playerdata/<uuid>.dat`. The whole file is kept, so things the server doesn't
+/// handle yet like the inventory and abilities are written back unchanged.
+#[derive(Debug, Clone)]
+pub struct PlayerData {
+    root: NbtTag,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub on_ground: bool,
+    pub dimension: String,
+    pub game_mode: u8,
+    /// -1 if the player never switched game modes
+    pub previous_game_mode: i8,
+    pub health: f32,
+    pub food_level: i32,
+    pub selected_slot: i32,
+    /// The saved item stacks, which the connection turns into its inventory
+    pub inventory: Vec<NbtTag>,
+}
+
+impl PlayerData {
+    /// A player joining for the first time, at the world spawn in the world's game mode
+    pub fn new_player(uuid: Uuid, level: &LevelData) -> Self {
+        let (most, least) = uuid.as_u64_pair();
+        let uuid_ints = vec![(most >> 32) as i32, most as i32, (least >> 32) as i32, least as i32];
+        let mut data = Self::from_nbt(NbtTag::Compound("".to_string(), vec![
+            NbtTag::Int("DataVersion".to_string(), DATA_VERSION),
+            NbtTag::IntArray("UUID".to_string(), uuid_ints),
+            NbtTag::List("Inventory".to_string(), vec![]),
+        ]));
+        data.x = level.spawn_x as f64 + 0.5;
+        data.y = level.spawn_y as f64;
+        data.z = level.spawn_z as f64 + 0.5;
+        data.yaw = level.spawn_angle;
+        data.game_mode = level.game_type;
+        data
+    }
+
+    /// Reads a vanilla player file, with vanilla's defaults for anything missing
+    pub fn from_nbt(root: NbtTag) -> Self {
+        let doubles = root.child("Pos").and_then(|p| p.as_list()).map(|p| p.iter().filter_map(|v| v.as_f64()).collect::<Vec<_>>()).unwrap_or_default();
+        let floats = root.child("Rotation").and_then(|r| r.as_list()).map(|r| r.iter().filter_map(|v| v.as_f32()).collect::<Vec<_>>()).unwrap_or_default();
+        let int = |name: &str, default: i32| root.child(name).and_then(|t| t.as_i32()).unwrap_or(default);
+        Self {
+            x: doubles.first().copied().unwrap_or(0.0),
+            y: doubles.get(1).copied().unwrap_or(0.0),
+            z: doubles.get(2).copied().unwrap_or(0.0),
+            yaw: floats.first().copied().unwrap_or(0.0),
+            pitch: floats.get(1).copied().unwrap_or(0.0),
+            on_ground: int("OnGround", 0) != 0,
+            dimension: root.child("Dimension").and_then(|d| d.as_str()).unwrap_or("minecraft:overworld").to_string(),
+            game_mode: int("playerGameType", 0) as u8,
+            previous_game_mode: int("previousPlayerGameType", -1) as i8,
+            health: root.child("Health").and_then(|h| h.as_f32()).unwrap_or(20.0),
+            food_level: int("foodLevel", 20),
+            selected_slot: int("SelectedItemSlot", 0),
+            inventory: root.child("Inventory").and_then(|i| i.as_list()).cloned().unwrap_or_default(),
+            root,
+        }
+    }
+
+    /// The player file with the current values written into it
+    pub fn to_nbt(&self) -> NbtTag {
+        let mut root = self.root.clone();
+        for tag in [
+            NbtTag::List("Pos".to_string(), vec![NbtTag::Double("".to_string(), self.x), NbtTag::Double("".to_string(), self.y), NbtTag::Double("".to_string(), self.z)]),
+            NbtTag::List("Rotation".to_string(), vec![NbtTag::Float("".to_string(), self.yaw), NbtTag::Float("".to_string(), self.pitch)]),
+            NbtTag::Byte("OnGround".to_string(), self.on_ground as i8),
+            NbtTag::String("Dimension".to_string(), self.dimension.clone()),
+            NbtTag::Int("playerGameType".to_string(), self.game_mode as i32),
+            NbtTag::Int("previousPlayerGameType".to_string(), self.previous_game_mode as i32),
+            NbtTag::Float("Health".to_string(), self.health),
+            NbtTag::Int("foodLevel".to_string(), self.food_level),
+            NbtTag::Int("SelectedItemSlot".to_string(), self.selected_slot),
+            NbtTag::List("Inventory".to_string(), self.inventory.clone()),
+        ] {
+            root.set_child(tag);
+        }
+        root
+    }
+}
+
+/// The `playerdata` folder of a world, with one file per player named after its UUID
+#[derive(Debug, Clone)]
+pub struct PlayerDataStorage {
+    dir: PathBuf,
+}
+
+impl PlayerDataStorage {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn path_of(&self, uuid: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.dat", uuid.hyphenated()))
+    }
+
+    /// Returns `None` for players that never joined before
+    pub fn load(&self, uuid: Uuid) -> Result<Option<PlayerData>, ServerError> {
+        let path = self.path_of(uuid);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(PlayerData::from_nbt(read_gzip_file(path)?)))
+    }
+
+    pub fn save(&self, uuid: Uuid, data: &PlayerData) -> Result<(), ServerError> {
+        match fs::create_dir(&self.dir) {
+            Err(err) if err.kind() != ErrorKind::AlreadyExists => return Err(err.into()),
+            _ => {}
+        }
+        write_gzip_file(self.path_of(uuid), &data.to_nbt())
+    }
+}
@@ -0,0 +1,143 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use flate2::Compression;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::ZlibEncoder;
+use inbt::NbtTag;
+use crate::error::ServerError;
+use crate::packet_builder::{nbt_type_id, write_nbt_payload, write_nbt_string};
+
+const SECTOR_SIZE: u64 = 4096;
+/// The location table and the timestamp table
+const HEADER_SIZE: u64 = 2 * SECTOR_SIZE;
+/// The sector count of a location is a single byte
+const MAX_CHUNK_SECTORS: u64 = 255;
+
+/// Reads and writes the raw NBT of chunks in a folder of `.mca` region files, for the parts of a chunk
+/// `mc_world_parser` doesn't give us like light, block entities and entities
+#[derive(Debug, Clone)]
+pub struct RegionStorage {
+    dir: PathBuf,
+}
+
+impl RegionStorage {
+    /// `dir` is the folder containing the region files, like `world/region` or `world/entities`
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn region_path(&self, chunk_x: i32, chunk_z: i32) -> PathBuf {
+        self.dir.join(format!("r.{}.{}.mca", chunk_x.div_euclid(32), chunk_z.div_euclid(32)))
+    }
+
+    /// Position of the chunk in the location and timestamp tables
+    fn header_index(chunk_x: i32, chunk_z: i32) -> u64 {
+        (chunk_x.rem_euclid(32) + chunk_z.rem_euclid(32) * 32) as u64
+    }
+
+    /// Returns `None` if the chunk was never saved
+    pub fn read_chunk(&self, chunk_x: i32, chunk_z: i32) -> Result<Option<NbtTag>, ServerError> {
+        let mut file = match File::open(self.region_path(chunk_x, chunk_z)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        // Vanilla creates empty region files for regions it never saved a chunk in
+        if file.metadata()?.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        // The header starts with 1024 locations: 3 bytes sector offset and 1 byte sector count
+        let mut location = [0u8; 4];
+        file.seek(SeekFrom::Start(Self::header_index(chunk_x, chunk_z) * 4))?;
+        file.read_exact(&mut location)?;
+        let sector = u32::from_be_bytes(location) >> 8;
+        if sector == 0 {
+            return Ok(None);
+        }
+
+        let mut header = [0u8; 5];
+        file.seek(SeekFrom::Start(sector as u64 * SECTOR_SIZE))?;
+        file.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let mut compressed = file.take(length.saturating_sub(1));
+
+        let mut data = vec![];
+        match header[4] {
+            1 => GzDecoder::new(compressed).read_to_end(&mut data)?,
+            2 => ZlibDecoder::new(compressed).read_to_end(&mut data)?,
+            3 => compressed.read_to_end(&mut data)?,
+            compression => return Err(ServerError::UnsupportedChunkCompression(compression)),
+        };
+        Ok(Some(inbt::nbt_parser::parse_binary(data)?))
+    }
+
+    /// Saves a chunk zlib compressed, with the root tag's name. The chunk keeps its sectors if it still fits in
+    /// them, and otherwise goes into the first gap left by other chunks that is large enough, or at the end of the file.
+    pub fn write_chunk(&self, chunk_x: i32, chunk_z: i32, tag: &NbtTag) -> Result<(), ServerError> {
+        let mut data = vec![nbt_type_id(tag)];
+        write_nbt_string(tag.name(), &mut data);
+        write_nbt_payload(tag, &mut data);
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&data)?;
+        let compressed = encoder.finish()?;
+
+        // Length including the compression type, the compression type and the data, padded to whole sectors
+        let mut sectors_data = ((compressed.len() + 1) as u32).to_be_bytes().to_vec();
+        sectors_data.push(2);
+        sectors_data.extend(compressed);
+        let sector_count = (sectors_data.len() as u64).div_ceil(SECTOR_SIZE);
+        if sector_count > MAX_CHUNK_SECTORS {
+            return Err(ServerError::ChunkTooLarge(chunk_x, chunk_z));
+        }
+        sectors_data.resize((sector_count * SECTOR_SIZE) as usize, 0);
+
+        fs::create_dir_all(&self.dir)?;
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(self.region_path(chunk_x, chunk_z))?;
+        let length = file.metadata()?.len();
+        if length < HEADER_SIZE {
+            file.set_len(HEADER_SIZE)?;
+        }
+
+        let index = Self::header_index(chunk_x, chunk_z);
+        let mut locations = [0u8; SECTOR_SIZE as usize];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut locations)?;
+        // Start and end of the sectors of every saved chunk
+        let mut used = locations.chunks_exact(4)
+            .map(|location| {
+                let start = (u32::from_be_bytes([location[0], location[1], location[2], location[3]]) >> 8) as u64;
+                (start, start + location[3] as u64)
+            })
+            .collect::<Vec<_>>();
+        let (old_sector, old_end) = used[index as usize];
+        let sector = if old_sector != 0 && old_end - old_sector >= sector_count {
+            old_sector
+        } else {
+            // The old sectors only become free once the location points somewhere else
+            used.retain(|(start, _)| *start != 0);
+            used.sort();
+            let mut sector = HEADER_SIZE / SECTOR_SIZE;
+            for (start, end) in used {
+                if start >= sector + sector_count {
+                    break;
+                }
+                sector = sector.max(end);
+            }
+            sector
+        };
+
+        file.seek(SeekFrom::Start(sector * SECTOR_SIZE))?;
+        file.write_all(&sectors_data)?;
+        file.seek(SeekFrom::Start(index * 4))?;
+        file.write_all(&((sector as u32) << 8 | sector_count as u32).to_be_bytes())?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs() as u32).unwrap_or(0);
+        file.seek(SeekFrom::Start(SECTOR_SIZE + index * 4))?;
+        file.write_all(&timestamp.to_be_bytes())?;
+        Ok(())
+    }
+}
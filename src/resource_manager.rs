@@ -1,103 +1,189 @@
 use std::collections::BTreeMap;
-use std::ffi::OsString;
-use std::io;
 use std::path::Path;
 use std::str::FromStr;
-use log::{debug, error, trace};
+use std::sync::Arc;
+use log::{debug, trace};
 use serde_json::Value;
-use walkdir::WalkDir;
 use crate::block_registry::BlockRegistry;
+use crate::datapack::DataPack;
 use crate::error::ServerError;
-use crate::server_util::{RegistryEntry, TagEntry, TagEntryData};
+use crate::nbt_util::json_to_nbt;
+use crate::packet::KnownPack;
+use crate::server_util::{Registries, RegistryEntry, TagEntry};
+use crate::tags::{RegistryIdLookup, TagLoader};
+
+/// Registries the client needs the contents of, either from a shared known pack or sent as NBT
+const NETWORK_REGISTRIES: [&str; 11] = [
+    "dimension_type",
+    "worldgen/biome",
+    "chat_type",
+    "damage_type",
+    "painting_variant",
+    "wolf_variant",
+    "trim_pattern",
+    "trim_material",
+    "banner_pattern",
+    "enchantment",
+    "jukebox_song",
+];
 
 pub struct ResourceManager {
-    registries: BTreeMap<String, Vec<RegistryEntry>>,
-    block_registry: BlockRegistry,
-    tags: Vec<TagEntry>,
+    known_packs: Vec<KnownPack>,
+    registries: Arc<Registries>,
+    /// Protocol ids of the registries built into the game, from the registries report
+    builtin_registries: BTreeMap<String, BTreeMap<String, i32>>,
+    block_registry: Arc<BlockRegistry>,
+    /// Default max stack size of each item, from the items report
+    max_stack_sizes: BTreeMap<String, i32>,
+    tags: Arc<Vec<TagEntry>>,
 }
 
 impl ResourceManager {
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, ServerError> {
-        let mut registries = BTreeMap::new();
+    /// Loads the registries and tags from the data packs, where later packs override earlier ones.
+    /// Errors in a pack's files come back as `DataPackError` with the pack's id.
+    pub fn new<P: AsRef<Path>>(path: P, packs: &[&DataPack]) -> Result<Self, ServerError> {
+        let mut registries: Registries = BTreeMap::new();
+        let mut known_packs = vec![];
+        let mut tag_loader = TagLoader::new();
 
         // TODO: Check which directories should be included instead
-        let excluded_dirs = vec!["minecraft/tags", "minecraft/datapacks", "minecraft/loot_table", "minecraft/recipe", "minecraft/advancement"];
-
-        let registries_dir = WalkDir::new(path.as_ref().join("generated/data"));
-        'outer: for entry in registries_dir.into_iter().filter_map(|e| e.ok()).filter(|e| e.path().extension().eq(&Some(&*OsString::from("json")))) {
-            let full_entry_name = entry.path().strip_prefix(path.as_ref().join("generated/data"))?;
-            for dir in &excluded_dirs {
-                if full_entry_name.starts_with(dir) {
-                    continue 'outer;
-                }
+        let excluded_dirs = vec!["minecraft/datapacks", "minecraft/loot_table", "minecraft/recipe", "minecraft/advancement"];
+
+        for pack in packs {
+            debug!("Loading data pack {}", pack.id);
+            if let Some(known_pack) = pack.known_pack() {
+                known_packs.push(known_pack.clone());
             }
-            debug!("Registry entry path: {}", full_entry_name.display());
-            let mut name_iterator = full_entry_name.iter();
-            let namespace = name_iterator.next().unwrap().to_str().unwrap();
-            let registry_name_parts = name_iterator.clone().take(name_iterator.clone().collect::<Vec<_>>().len()-1).map(|s| s.to_str().unwrap()).collect::<Vec<_>>();
-            let mut registry_name = "".to_string();
-            for (i, part) in registry_name_parts.iter().enumerate() {
-                registry_name += part;
-                if i != registry_name_parts.len() - 1 {
-                    registry_name += "/";
+            let in_pack = |err: ServerError| ServerError::DataPackError { pack: pack.id.clone(), source: Box::new(err) };
+            let files = pack.data_files(|full_entry_name| !excluded_dirs.iter().any(|dir| full_entry_name.starts_with(dir))).map_err(in_pack)?;
+            tag_loader.load_pack_files(&files).map_err(in_pack)?;
+
+            for (full_entry_name, file_data) in &files {
+                // Tags are loaded separately by the TagLoader
+                if full_entry_name.iter().nth(1).is_some_and(|dir| dir == "tags") {
+                    continue;
                 }
-            }
-            let identifier = name_iterator.as_path().file_stem().unwrap().to_str().unwrap();
+                trace!("Registry entry path: {}", full_entry_name.display());
+                let mut name_iterator = full_entry_name.iter();
+                let namespace = name_iterator.next().unwrap().to_str().unwrap();
+                let registry_name_parts = name_iterator.clone().take(name_iterator.clone().collect::<Vec<_>>().len()-1).map(|s| s.to_str().unwrap()).collect::<Vec<_>>();
+                let mut registry_name = "".to_string();
+                for (i, part) in registry_name_parts.iter().enumerate() {
+                    registry_name += part;
+                    if i != registry_name_parts.len() - 1 {
+                        registry_name += "/";
+                    }
+                }
+                let identifier = name_iterator.as_path().file_stem().unwrap().to_str().unwrap();
 
-            trace!("[{}] {}:{}", registry_name, namespace, identifier);
-            if !registries.contains_key(&registry_name) {
-                registries.insert(registry_name.clone(), vec![]);
+                trace!("[{}] {}:{}", registry_name, namespace, identifier);
+                let data = if Self::is_network_registry(&registry_name) {
+                    let json = serde_json::from_slice::<Value>(file_data).map_err(|err| in_pack(err.into()))?;
+                    Some(json_to_nbt("".to_string(), &json))
+                } else {
+                    None
+                };
+                let entry = RegistryEntry {
+                    id: namespace.to_string() + ":" + identifier,
+                    data,
+                    known_pack: pack.known_pack().cloned(),
+                };
+                let registry = registries.entry(registry_name).or_default();
+                // Entries from later packs replace existing ones but keep their id
+                match registry.iter_mut().find(|e| e.id == entry.id) {
+                    Some(existing) => *existing = entry,
+                    None => registry.push(entry),
+                }
             }
-            let registry = registries.get_mut(&registry_name).unwrap();
-            registry.push(RegistryEntry {
-                id: namespace.to_string() + ":" + identifier,
-                data: None,
-            })
         }
 
-        let file_data = std::fs::read_to_string("resources/tags/minecraft/tags.json").unwrap();
-        let json = Value::from_str(&*file_data).unwrap();
-        let tags = Self::json_to_tags(json);
+        let mut resource_manager = Self {
+            known_packs,
+            registries: Arc::new(registries),
+            builtin_registries: Self::load_builtin_registries(path.as_ref().join("generated/reports/registries.json"))?,
+            block_registry: Arc::new(BlockRegistry::load(path.as_ref().join("generated/reports/blocks.json"))?),
+            max_stack_sizes: Self::load_max_stack_sizes(path.as_ref().join("generated/reports/items.json"))?,
+            tags: Arc::new(vec![]),
+        };
+        resource_manager.tags = Arc::new(tag_loader.resolve(&resource_manager));
 
-        Ok(Self {
-            registries,
-            block_registry: BlockRegistry::load(path.as_ref().join("generated/reports/blocks.json"))?,
-            tags,
-        })
+        Ok(resource_manager)
     }
 
-    pub fn registries_ref(&self) -> &BTreeMap<String, Vec<RegistryEntry>> {
-        &self.registries
+    fn load_builtin_registries<P: AsRef<Path>>(path: P) -> Result<BTreeMap<String, BTreeMap<String, i32>>, ServerError> {
+        let json = Value::from_str(&*std::fs::read_to_string(path)?)?;
+        let mut builtin_registries = BTreeMap::new();
+        for (registry_id, registry) in json.as_object().unwrap() {
+            let entries = registry.get("entries").unwrap().as_object().unwrap().iter()
+                .map(|(id, entry)| (id.clone(), entry.get("protocol_id").unwrap().as_i64().unwrap() as i32))
+                .collect();
+            let registry_name = registry_id.strip_prefix("minecraft:").unwrap_or(registry_id);
+            builtin_registries.insert(registry_name.to_string(), entries);
+        }
+        Ok(builtin_registries)
     }
 
-    pub fn block_registry_ref(&self) -> &BlockRegistry {
-        &self.block_registry
+    /// Reads the `max_stack_size` component of every item in the items report
+    fn load_max_stack_sizes<P: AsRef<Path>>(path: P) -> Result<BTreeMap<String, i32>, ServerError> {
+        let json = Value::from_str(&*std::fs::read_to_string(path)?)?;
+        Ok(json.as_object().unwrap().iter()
+            .filter_map(|(id, item)| {
+                let size = item.get("components")?.get("minecraft:max_stack_size")?.as_i64()?;
+                Some((id.clone(), size as i32))
+            })
+            .collect())
     }
 
-    pub fn tags_ref(&self) -> &Vec<TagEntry> {
-        &self.tags
+    pub fn known_packs_ref(&self) -> &Vec<KnownPack> {
+        &self.known_packs
     }
 
-    fn json_to_tags(json: Value) -> Vec<TagEntry> {
-        // TODO: Fix these variable names
-        let mut entries = vec![];
-        let map = json.get("tags").unwrap().as_object().unwrap();
-        for (id, val) in map {
-            let tag_entry_data_arr = val.as_array().cloned().unwrap();
-            let mut data = vec![];
-            for entry_data in tag_entry_data_arr {
-                let entry_entries = entry_data.get("entries").unwrap().as_array().unwrap().iter().map(|v| v.as_i64().unwrap() as i32).collect::<Vec<i32>>();
-                let tag_name = entry_data.get("tag_name").unwrap();
-                data.push(TagEntryData {
-                    entries: entry_entries,
-                    tag_name: tag_name.get("namespace").unwrap().as_str().unwrap().to_string() + ":" + tag_name.get("name").unwrap().as_str().unwrap(),
-                });
-            }
-            entries.push(TagEntry {
-                id: id.clone(),
-                data
-            })
+    pub fn is_network_registry(registry_id: &str) -> bool {
+        NETWORK_REGISTRIES.contains(&registry_id)
+    }
+
+    /// The registries are immutable once loaded, so every connection shares the same copy
+    pub fn registries(&self) -> Arc<Registries> {
+        self.registries.clone()
+    }
+
+    pub fn block_registry(&self) -> Arc<BlockRegistry> {
+        self.block_registry.clone()
+    }
+
+    /// Protocol ids of a registry built into the game, like `block_entity_type`. Empty if there is no such registry.
+    pub fn builtin_registry(&self, registry: &str) -> Arc<BTreeMap<String, i32>> {
+        Arc::new(self.builtin_registries.get(registry).cloned().unwrap_or_default())
+    }
+
+    /// Ids of a synced registry in the order it is sent to clients. Empty if there is no such registry.
+    pub fn registry_ids(&self, registry: &str) -> BTreeMap<String, i32> {
+        self.registries.get(registry)
+            .map(|entries| entries.iter().enumerate().map(|(id, entry)| (entry.id.clone(), id as i32)).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn max_stack_sizes(&self) -> &BTreeMap<String, i32> {
+        &self.max_stack_sizes
+    }
+
+    pub fn tags(&self) -> Arc<Vec<TagEntry>> {
+        self.tags.clone()
+    }
+}
+
+impl RegistryIdLookup for ResourceManager {
+    fn has_registry(&self, registry: &str) -> bool {
+        Self::is_network_registry(registry) || self.builtin_registries.contains_key(registry)
+    }
+
+    fn id_of(&self, registry: &str, identifier: &str) -> Option<i32> {
+        if registry == "block" {
+            return self.block_registry.block_id(identifier);
+        }
+        if let Some(entries) = self.builtin_registries.get(registry) {
+            return entries.get(identifier).copied();
         }
-        entries
+        self.registries.get(registry)?.iter().position(|entry| entry.id == identifier).map(|id| id as i32)
     }
-}
\ No newline at end of file
+}
@@ -1,21 +1,132 @@
+use std::collections::HashMap;
 use std::io::ErrorKind;
 use std::net::TcpListener;
-use std::sync::mpsc::TryRecvError;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
 use std::thread;
+use std::time::{Duration, Instant};
+use inbt::NbtTag;
 use log::*;
-use mc_world_parser::World;
+use mc_datatypes::BlockPos;
+use uuid::Uuid;
+use crate::chunk_service::{ChunkKey, ChunkService, Dimension};
+use crate::config::ServerConfig;
+use crate::datapack::DataPackManager;
+use crate::entity_storage::EntityStorage;
+use crate::error::ServerError;
+use crate::entity_tracker::EntityTracker;
+use crate::item::ItemRegistry;
+use crate::level_data::LevelData;
+use crate::player_data::{PlayerData, PlayerDataStorage};
 use crate::resource_manager::ResourceManager;
 use crate::server_connection::MCServerConnection;
-use crate::server_util::{DescriptionInfo, PlayerInfo, PlayerSample, ServerConnectionThreadBound, ServerInfo, ServerMainThreadBound, VersionInfo};
+use crate::tab_list::{PlayerListEntry, TabList};
+use crate::server_util::{DescriptionInfo, DimensionInfo, PlayerInfo, PlayerSample, ServerConnectionThreadBound, ServerInfo, ServerMainThreadBound, VersionInfo};
+use crate::tags::RegistryIdLookup;
+
+/// The vanilla dimensions: name, the folder they are saved in, min y and height
+const DIMENSIONS: [(&str, &str, i32, i32); 3] = [
+    ("minecraft:overworld", "world", -64, 384),
+    ("minecraft:the_nether", "world/DIM-1", 0, 256),
+    ("minecraft:the_end", "world/DIM1", 0, 256),
+];
+
+const CONFIG: &str = "server.properties";
+const LEVEL_DAT: &str = "world/level.dat";
+const PLAYER_DATA_DIR: &str = "world/playerdata";
+const TICK_DURATION: Duration = Duration::from_millis(50);
+/// Ticks between saves, every 5 minutes like vanilla
+const AUTOSAVE_INTERVAL: u64 = 6000;
+/// Ticks between tab list latency updates
+const LATENCY_UPDATE_INTERVAL: u64 = 600;
+/// Ticks between unloading the entity chunks no player is near
+const ENTITY_UNLOAD_INTERVAL: u64 = 20;
+/// Connections don't load chunks further away than this, whatever the client asks for
+const MAX_VIEW_DISTANCE: i32 = 12;
+
+/// Where players arrive in the End, on the obsidian platform
+const END_SPAWN: (f64, f64, f64) = (100.5, 49.0, 0.5);
+
+/// Channels to and from a connection thread
+type Channel = (Sender<ServerConnectionThreadBound>, Receiver<ServerMainThreadBound>);
+
+#[derive(Debug, Clone, Copy)]
+enum Portal {
+    Nether,
+    End,
+}
+
+/// What the main thread knows about a connected player
+struct ConnectedPlayer {
+    /// Set once the player is in the game and tracked
+    eid: Option<i32>,
+    dimension: String,
+    /// Block the player is in, to notice when it steps into a portal
+    pos: (i32, i32, i32),
+}
 
 pub struct MCServer {
     server_info: ServerInfo,
+    datapacks: DataPackManager,
     resource_manager: ResourceManager,
-    world: World
+    chunk_service: ChunkService,
+    level_data: LevelData,
+    /// False if level.dat couldn't be read, so saving it would replace the world's settings with the defaults
+    level_data_saveable: bool,
+    player_data: PlayerDataStorage,
+    entity_tracker: EntityTracker,
+    /// The entities of each dimension
+    entities: HashMap<String, EntityStorage>,
+    items: Arc<ItemRegistry>,
+    tab_list: TabList,
+    last_tick: Instant,
+    ticks: u64,
 }
 
 impl MCServer {
     pub fn new() -> Self {
+        let config = ServerConfig::load(CONFIG).unwrap_or_else(|err| {
+            warn!("Could not read {}, starting with defaults: {}", CONFIG, err);
+            ServerConfig::default()
+        });
+        let mut datapacks = DataPackManager::discover("resources/generated", "world");
+        // A level.dat that exists but can't be read is never overwritten with the defaults
+        let (level_data, level_data_saveable) = match LevelData::load(LEVEL_DAT) {
+            Ok(level_data) => (level_data, true),
+            Err(err) => {
+                warn!("Could not read {}, starting with defaults: {}", LEVEL_DAT, err);
+                let missing = matches!(&err, ServerError::IOError(io) if io.kind() == ErrorKind::NotFound);
+                (LevelData::from_nbt(NbtTag::Compound("".to_string(), vec![])), missing)
+            }
+        };
+        // Only fails without the vanilla data, which the server can't run without
+        let (resource_manager, _) = Self::load_resources(&mut datapacks).expect("Could not load the vanilla data");
+        let dimensions = DIMENSIONS.iter().filter_map(|(name, path, min_y, height)| {
+            match Dimension::load(path, *min_y, *height) {
+                Ok(dimension) => Some((name.to_string(), dimension)),
+                Err(err) => {
+                    warn!("Could not load {} from {}: {}", name, path, err);
+                    None
+                }
+            }
+        }).collect::<HashMap<_, _>>();
+        let entity_types = resource_manager.builtin_registry("entity_type");
+        let enchantments = resource_manager.registry_ids("enchantment");
+        let biomes = Arc::new(resource_manager.registry_ids("worldgen/biome"));
+        let mut items = ItemRegistry::new(&resource_manager.builtin_registry("item"))
+            .with_enchantments(&enchantments)
+            .with_effects(&resource_manager.builtin_registry("mob_effect"))
+            .with_max_stack_sizes(resource_manager.max_stack_sizes());
+        for registry in ["attribute", "potion", "instrument", "block"] {
+            items = items.with_registry(registry, &resource_manager.builtin_registry(registry));
+        }
+        for registry in ["trim_material", "trim_pattern", "banner_pattern", "jukebox_song"] {
+            items = items.with_registry(registry, &resource_manager.registry_ids(registry));
+        }
+        let items = Arc::new(items);
+        let entities = dimensions.iter()
+            .map(|(name, dimension)| (name.clone(), EntityStorage::new(dimension.entities.clone(), entity_types.clone(), items.clone())))
+            .collect();
         Self {
             server_info: ServerInfo {
                 description: DescriptionInfo { text: "RustMC 1.21-dev".to_string() },
@@ -27,8 +138,18 @@ impl MCServer {
                 version: VersionInfo { name: "RustMC 1.21".to_string(), protocol: 767 },
                 favicon: "data:image/png;base64,<data>".to_string(),
             },
-            resource_manager: ResourceManager::new("resources").unwrap(),
-            world: World::load("world").unwrap(),
+            chunk_service: ChunkService::new(dimensions, resource_manager.block_registry(), resource_manager.builtin_registry("block_entity_type"), biomes),
+            resource_manager,
+            datapacks,
+            level_data,
+            level_data_saveable,
+            player_data: PlayerDataStorage::new(PLAYER_DATA_DIR),
+            entity_tracker: EntityTracker::new(entity_types.get("minecraft:player").copied().unwrap_or(0)),
+            entities,
+            items,
+            tab_list: TabList::new(config.tab_list_header, config.tab_list_footer),
+            last_tick: Instant::now(),
+            ticks: 0,
         }
     }
 
@@ -39,6 +160,8 @@ impl MCServer {
 
         let mut threads = vec![];
         let mut channels = vec![];
+        // Same order as `channels`
+        let mut players = vec![];
         for stream in listener.incoming() {
             match stream {
                 Ok(connection) => {
@@ -46,11 +169,16 @@ impl MCServer {
                     let ch_to_thread = std::sync::mpsc::channel();
                     let ch_from_thread = std::sync::mpsc::channel();
                     let server_info = self.server_info.clone();
-                    let block_reg = self.resource_manager.block_registry_ref().clone();
+                    let block_reg = self.resource_manager.block_registry();
+                    let items = self.items.clone();
+                    let dimension = self.dimension_info("minecraft:overworld");
+                    let level_data = self.level_data.clone();
+                    let player_data = self.player_data.clone();
                     threads.push(thread::spawn(|| {
-                        MCServerConnection::new(connection, ch_from_thread.0, ch_to_thread.1, server_info, block_reg).run()
+                        MCServerConnection::new(connection, ch_from_thread.0, ch_to_thread.1, server_info, block_reg, items, dimension, level_data, player_data).run()
                     }));
                     channels.push((ch_to_thread.0, ch_from_thread.1));
+                    players.push(ConnectedPlayer { eid: None, dimension: "minecraft:overworld".to_string(), pos: (0, 0, 0) });
                 }
                 Err(err) => {
                     if err.kind() != ErrorKind::WouldBlock {
@@ -63,11 +191,19 @@ impl MCServer {
                 if threads[i].is_finished() {
                     let thread = threads.remove(i);
                     let _channel = channels.remove(i);
+                    if let Some(eid) = players.remove(i).eid {
+                        self.entity_tracker.remove_player(eid, |eid, packet| send_to_player(&channels, &players, eid, packet));
+                        self.tab_list.remove(eid, |eid, packet| send_to_player(&channels, &players, eid, packet));
+                    }
                     if let Err(err) = thread.join() {
                         error!("Thread panicked: {}", err.downcast::<std::io::Error>().map(|e| e.to_string()).unwrap_or("Unknown reason".to_string()));
                     }
                 }
             }
+            while self.last_tick.elapsed() >= TICK_DURATION {
+                self.last_tick += TICK_DURATION;
+                self.tick(&channels, &players);
+            }
             // Handle all channel messages both ways
             // TODO: Add a message queue
             for i in 0..channels.len() {
@@ -75,26 +211,123 @@ impl MCServer {
                 match rec.try_recv() {
                     Ok(request) => {
                         match request {
+                            ServerMainThreadBound::RequestKnownPacks => {
+                                let _ = send.send(ServerConnectionThreadBound::KnownPacks(self.resource_manager.known_packs_ref().clone()));
+                            }
                             ServerMainThreadBound::RequestRegistryInfo => {
-                                for (id, entries) in self.resource_manager.registries_ref() {
-                                    let _ = send.send(ServerConnectionThreadBound::RegistryInfo {
-                                        registry_id: id.clone(),
-                                        entries: entries.clone(),
-                                    });
-                                }
-                                let _ = send.send(ServerConnectionThreadBound::RegistryInfoFinished);
+                                let _ = send.send(ServerConnectionThreadBound::RegistryInfo(self.resource_manager.registries()));
                             }
                             ServerMainThreadBound::RequestTagInfo => {
-                                let _ = send.send(ServerConnectionThreadBound::TagInfo(self.resource_manager.tags_ref().clone()));
+                                let _ = send.send(ServerConnectionThreadBound::TagInfo(self.resource_manager.tags()));
                             }
-                            ServerMainThreadBound::RequestChunk(pos) => {
-                                let _ = send.send(ServerConnectionThreadBound::ChunkData(self.world.get_chunk(pos)));
+                            ServerMainThreadBound::RequestChunk { pos, distance } => {
+                                if let Some(storage) = self.entities.get_mut(&players[i].dimension) {
+                                    storage.request_chunk(pos.x, pos.z);
+                                }
+                                self.chunk_service.request(ChunkKey::new(players[i].dimension.clone(), pos), distance, send.clone());
                             }
                             ServerMainThreadBound::ChatMessage { player_name, message, timestamp, salt } => {
                                 for (channel_send, _) in &channels {
                                     let _= channel_send.send(ServerConnectionThreadBound::ChatMessage {player_name: player_name.clone(), message: message.clone(), timestamp, salt});
                                 }
                             }
+                            ServerMainThreadBound::BlockChanged { pos, block_state } => {
+                                let dimension = players[i].dimension.clone();
+                                // The player that changed the block already has it
+                                for (j, (channel_send, _)) in channels.iter().enumerate() {
+                                    if j != i && players[j].dimension == dimension {
+                                        let _ = channel_send.send(ServerConnectionThreadBound::BlockChanged { pos: BlockPos::new(pos.x(), pos.y(), pos.z()), block_state });
+                                    }
+                                }
+                                // Relit on the chunk workers, which send the light to everyone in the dimension
+                                let recipients = channels.iter().zip(&players)
+                                    .filter(|(_, player)| player.dimension == dimension)
+                                    .map(|((channel_send, _), _)| channel_send.clone())
+                                    .collect();
+                                self.chunk_service.set_block(&dimension, pos.x(), pos.y(), pos.z(), block_state, recipients);
+                            }
+                            ServerMainThreadBound::PlayerLogin { uuid } => {
+                                let data = self.load_player_data(uuid);
+                                players[i].dimension = data.dimension.clone();
+                                players[i].pos = (data.x.floor() as i32, data.y.floor() as i32, data.z.floor() as i32);
+                                let _ = send.send(ServerConnectionThreadBound::PlayerData { dimension: self.dimension_info(&data.dimension), data });
+                            }
+                            ServerMainThreadBound::PlayerJoined(mut tracked) => {
+                                players[i].eid = Some(tracked.eid);
+                                tracked.dimension = players[i].dimension.clone();
+                                let entry = PlayerListEntry::new(tracked.uuid, tracked.name.clone(), tracked.game_mode);
+                                self.tab_list.add(tracked.eid, entry, |eid, packet| send_to_player(&channels, &players, eid, packet));
+                                self.entity_tracker.add_player(tracked);
+                            }
+                            ServerMainThreadBound::PlayerMoved { x, y, z, on_ground } => {
+                                if let Some(tracked) = players[i].eid.and_then(|eid| self.entity_tracker.player_mut(eid)) {
+                                    (tracked.x, tracked.y, tracked.z, tracked.on_ground) = (x, y, z, on_ground);
+                                }
+                                let player = &mut players[i];
+                                let pos = (x.floor() as i32, y.floor() as i32, z.floor() as i32);
+                                if pos == player.pos {
+                                    continue;
+                                }
+                                let was_in_portal = self.portal_at(&player.dimension, player.pos).is_some();
+                                player.pos = pos;
+                                // Only stepping into a portal counts, so players don't bounce back and forth
+                                if let Some(portal) = self.portal_at(&player.dimension, player.pos).filter(|_| !was_in_portal) {
+                                    if let Some((dimension, x, y, z)) = self.portal_destination(&player.dimension, portal, player.pos) {
+                                        if let Err(message) = self.change_dimension(send, player, dimension, x, y, z) {
+                                            let _ = send.send(ServerConnectionThreadBound::SystemMessage(message));
+                                        }
+                                    }
+                                }
+                            }
+                            ServerMainThreadBound::ChangeDimension { dimension } => {
+                                let player = &mut players[i];
+                                let (x, y, z) = player.pos;
+                                let message = match self.change_dimension(send, player, &dimension, x as f64 + 0.5, y as f64, z as f64 + 0.5) {
+                                    Ok(()) => format!("Moved to {dimension}"),
+                                    Err(message) => message,
+                                };
+                                let _ = send.send(ServerConnectionThreadBound::SystemMessage(message));
+                            }
+                            ServerMainThreadBound::PlayerRotated { yaw, pitch, on_ground } => {
+                                if let Some(tracked) = players[i].eid.and_then(|eid| self.entity_tracker.player_mut(eid)) {
+                                    (tracked.yaw, tracked.pitch, tracked.on_ground) = (yaw, pitch, on_ground);
+                                }
+                            }
+                            ServerMainThreadBound::PlayerFlags { sneaking, sprinting, fall_flying } => {
+                                if let Some(tracked) = players[i].eid.and_then(|eid| self.entity_tracker.player_mut(eid)) {
+                                    (tracked.sneaking, tracked.sprinting, tracked.fall_flying) = (sneaking, sprinting, fall_flying);
+                                }
+                            }
+                            ServerMainThreadBound::ClientInformation { view_distance, skin_parts } => {
+                                if let Some(tracked) = players[i].eid.and_then(|eid| self.entity_tracker.player_mut(eid)) {
+                                    (tracked.view_distance, tracked.skin_parts) = (view_distance, skin_parts);
+                                }
+                            }
+                            ServerMainThreadBound::Latency(latency) => {
+                                if let Some(eid) = players[i].eid {
+                                    self.tab_list.set_latency(eid, latency);
+                                }
+                            }
+                            ServerMainThreadBound::GameModeChanged(game_mode) => {
+                                if let Some(eid) = players[i].eid {
+                                    if let Some(tracked) = self.entity_tracker.player_mut(eid) {
+                                        tracked.game_mode = game_mode;
+                                    }
+                                    self.tab_list.set_game_mode(eid, game_mode, |eid, packet| send_to_player(&channels, &players, eid, packet));
+                                }
+                            }
+                            ServerMainThreadBound::RunCommand { player_name, command } => {
+                                info!("Running command for {}: {}", player_name, command);
+                                let (messages, reloaded) = self.run_command(&command, &channels);
+                                for message in messages {
+                                    let _ = send.send(ServerConnectionThreadBound::SystemMessage(message));
+                                }
+                                if reloaded {
+                                    for (channel_send, _) in &channels {
+                                        let _ = channel_send.send(ServerConnectionThreadBound::TagsReloaded(self.resource_manager.tags()));
+                                    }
+                                }
+                            }
                         }
                     }
                     Err(_) => {}
@@ -102,4 +335,240 @@ impl MCServer {
             }
         }
     }
-}
\ No newline at end of file
+
+    /// Advances the world by one tick
+    fn tick(&mut self, channels: &[Channel], players: &[ConnectedPlayer]) {
+        self.ticks += 1;
+        self.level_data.tick();
+        self.entity_tracker.tick(|eid, packet| send_to_player(channels, players, eid, packet));
+        for storage in self.entities.values_mut() {
+            storage.receive_chunks();
+        }
+        for (dimension, storage) in &self.entities {
+            self.entity_tracker.tick_entities(dimension, storage.entities(), |eid, packet| send_to_player(channels, players, eid, packet));
+        }
+        if self.ticks % ENTITY_UNLOAD_INTERVAL == 0 {
+            self.unload_entity_chunks(players);
+        }
+        if self.ticks % LATENCY_UPDATE_INTERVAL == 0 {
+            self.tab_list.send_latency(|eid, packet| send_to_player(channels, players, eid, packet));
+        }
+        if self.ticks % 20 == 0 {
+            for (channel_send, _) in channels {
+                let _ = channel_send.send(ServerConnectionThreadBound::Time { world_age: self.level_data.time, time_of_day: self.level_data.day_time });
+            }
+        }
+        if self.ticks % AUTOSAVE_INTERVAL == 0 {
+            self.save(channels);
+        }
+    }
+
+    /// Saves and unloads the entity chunks that are out of view of every player in their dimension
+    fn unload_entity_chunks(&mut self, players: &[ConnectedPlayer]) {
+        for (dimension, storage) in &mut self.entities {
+            let views = players.iter()
+                .filter(|player| &player.dimension == dimension)
+                .map(|player| {
+                    let view_distance = player.eid.and_then(|eid| self.entity_tracker.player(eid)).map(|tracked| tracked.view_distance).unwrap_or(MAX_VIEW_DISTANCE);
+                    (player.pos.0.div_euclid(16), player.pos.2.div_euclid(16), view_distance as u32)
+                })
+                .collect::<Vec<_>>();
+            let out_of_view = storage.loaded_chunks()
+                .filter(|(x, z)| !views.iter().any(|(view_x, view_z, distance)| view_x.abs_diff(*x).max(view_z.abs_diff(*z)) <= *distance))
+                .collect::<Vec<_>>();
+            for (x, z) in out_of_view {
+                if let Err(err) = storage.unload_chunk(x, z) {
+                    warn!("Could not save entities of chunk {} {} in {}: {}", x, z, dimension, err);
+                }
+            }
+        }
+    }
+
+    /// Writes level.dat, with the data packs that are enabled now, and the loaded entities, and has every
+    /// connection save its player
+    fn save(&mut self, channels: &[Channel]) -> Vec<String> {
+        for (channel_send, _) in channels {
+            let _ = channel_send.send(ServerConnectionThreadBound::SavePlayerData);
+        }
+        let failed_entity_chunks = self.entities.values_mut().map(|storage| storage.save()).sum::<usize>();
+        if failed_entity_chunks > 0 {
+            error!("Failed to save the entities of {} chunks", failed_entity_chunks);
+        }
+        let failed_chunks = self.chunk_service.save();
+        if failed_chunks > 0 {
+            error!("Failed to save {} chunks", failed_chunks);
+        }
+        if !self.level_data_saveable {
+            error!("Not saving {}, it couldn't be read when the server started", LEVEL_DAT);
+            return vec![format!("Not saving {LEVEL_DAT}, it couldn't be read when the server started")];
+        }
+        self.level_data.enabled_data_packs = self.datapacks.enabled_ids_ref().clone();
+        self.level_data.disabled_data_packs = self.datapacks.disabled_ids_ref().clone();
+        match self.level_data.save(LEVEL_DAT) {
+            Ok(()) => {
+                debug!("Saved {}", LEVEL_DAT);
+                vec!["Saved the game".to_string()]
+            }
+            Err(err) => {
+                error!("Failed to save {}: {}", LEVEL_DAT, err);
+                vec![format!("Saving failed: {err}")]
+            }
+        }
+    }
+
+    /// Saved data of a player, or a new player at the world spawn if it never joined or its file can't be used
+    fn load_player_data(&self, uuid: Uuid) -> PlayerData {
+        match self.player_data.load(uuid) {
+            Ok(Some(data)) if self.chunk_service.dimension(&data.dimension).is_some() => data,
+            Ok(Some(data)) => {
+                warn!("Player {} was in {}, which isn't loaded, moving it to the world spawn", uuid, data.dimension);
+                let spawn = PlayerData::new_player(uuid, &self.level_data);
+                PlayerData { x: spawn.x, y: spawn.y, z: spawn.z, dimension: spawn.dimension, ..data }
+            }
+            Ok(None) => PlayerData::new_player(uuid, &self.level_data),
+            Err(err) => {
+                error!("Failed to read the data of player {}: {}", uuid, err);
+                PlayerData::new_player(uuid, &self.level_data)
+            }
+        }
+    }
+
+    fn dimension_info(&self, name: &str) -> DimensionInfo {
+        DimensionInfo {
+            name: name.to_string(),
+            // The vanilla dimensions have a dimension type of the same name
+            dimension_type: self.resource_manager.id_of("dimension_type", name).unwrap_or(0),
+            min_y: self.chunk_service.dimension(name).map(|dimension| dimension.min_y).unwrap_or(0),
+        }
+    }
+
+    /// Sends a player to another dimension. `y` is moved into the dimension's height if it's outside.
+    fn change_dimension(&mut self, send: &Sender<ServerConnectionThreadBound>, player: &mut ConnectedPlayer, dimension: &str, x: f64, y: f64, z: f64) -> Result<(), String> {
+        let Some(loaded) = self.chunk_service.dimension(dimension) else {
+            return Err(format!("Unknown dimension: {dimension}"));
+        };
+        if player.dimension == dimension {
+            return Err(format!("Already in {dimension}"));
+        }
+        let y = y.clamp(loaded.min_y as f64, (loaded.min_y + loaded.height - 1) as f64);
+        player.dimension = dimension.to_string();
+        player.pos = (x.floor() as i32, y.floor() as i32, z.floor() as i32);
+        if let Some(eid) = player.eid {
+            self.entity_tracker.change_dimension(eid, dimension);
+        }
+        let _ = send.send(ServerConnectionThreadBound::ChangeDimension { dimension: self.dimension_info(dimension), x, y, z });
+        Ok(())
+    }
+
+    fn portal_at(&self, dimension: &str, (x, y, z): (i32, i32, i32)) -> Option<Portal> {
+        let block_registry = self.resource_manager.block_registry();
+        let state = self.chunk_service.block_at(dimension, x, y, z)?;
+        match block_registry.state(state)?.0 {
+            "minecraft:nether_portal" => Some(Portal::Nether),
+            "minecraft:end_portal" => Some(Portal::End),
+            _ => None,
+        }
+    }
+
+    /// Where a portal leads to, like vanilla but without looking for or building a portal on the other side
+    fn portal_destination(&self, dimension: &str, portal: Portal, (x, y, z): (i32, i32, i32)) -> Option<(&'static str, f64, f64, f64)> {
+        let level = &self.level_data;
+        match (portal, dimension) {
+            // The Nether is 8 times smaller
+            (Portal::Nether, "minecraft:overworld") => Some(("minecraft:the_nether", x.div_euclid(8) as f64 + 0.5, y as f64, z.div_euclid(8) as f64 + 0.5)),
+            (Portal::Nether, "minecraft:the_nether") => Some(("minecraft:overworld", (x * 8) as f64 + 0.5, y as f64, (z * 8) as f64 + 0.5)),
+            // Back to the world spawn
+            (Portal::End, "minecraft:the_end") => Some(("minecraft:overworld", level.spawn_x as f64 + 0.5, level.spawn_y as f64, level.spawn_z as f64 + 0.5)),
+            (Portal::End, _) => Some(("minecraft:the_end", END_SPAWN.0, END_SPAWN.1, END_SPAWN.2)),
+            _ => None,
+        }
+    }
+
+    /// Runs the commands handled by the main thread. Returns the feedback for the player and whether the
+    /// resources were reloaded, in which case connected clients need the new tags.
+    fn run_command(&mut self, command: &str, channels: &[Channel]) -> (Vec<String>, bool) {
+        let args = command.split(' ').collect::<Vec<_>>();
+        match args.as_slice() {
+            ["save-all"] => (self.save(channels), false),
+            ["reload"] => {
+                let messages = self.reload();
+                (messages, true)
+            }
+            ["datapack", "list"] => {
+                let enabled = self.datapacks.enabled_ids_ref().clone();
+                let available = self.datapacks.available_ref().iter()
+                    .filter(|p| !enabled.contains(&p.id))
+                    .map(|p| p.id.clone())
+                    .collect::<Vec<_>>();
+                (vec![
+                    format!("There are {} data pack(s) enabled: {}", enabled.len(), enabled.join(", ")),
+                    if available.is_empty() {
+                        "There are no more data packs available".to_string()
+                    } else {
+                        format!("There are {} data pack(s) available: {}", available.len(), available.join(", "))
+                    },
+                ], false)
+            }
+            ["datapack", action @ ("enable" | "disable"), ..] => {
+                let id = args[2..].join(" ");
+                let id = id.trim_matches('"');
+                let result = if *action == "enable" {
+                    self.datapacks.enable(id)
+                } else {
+                    self.datapacks.disable(id)
+                };
+                match result {
+                    Ok(()) => {
+                        let mut messages = vec![format!("{}d pack {}", if *action == "enable" { "Enable" } else { "Disable" }, id)];
+                        messages.append(&mut self.reload());
+                        (messages, true)
+                    }
+                    Err(message) => (vec![message], false),
+                }
+            }
+            _ => (vec![format!("Unknown command: {command}")], false),
+        }
+    }
+
+    /// Loads the resources of the enabled data packs. Packs that fail to load are disabled and the resources are
+    /// loaded again without them. Returns the messages about the disabled packs.
+    fn load_resources(datapacks: &mut DataPackManager) -> Result<(ResourceManager, Vec<String>), ServerError> {
+        let mut messages = vec![];
+        loop {
+            let result = ResourceManager::new("resources", &datapacks.enabled_packs());
+            match result {
+                Ok(resource_manager) => return Ok((resource_manager, messages)),
+                // Vanilla can't be disabled, without it there is nothing to fall back to
+                Err(ServerError::DataPackError { pack, source }) if datapacks.disable(&pack).is_ok() => {
+                    error!("Disabled data pack {}, it failed to load: {}", pack, source);
+                    messages.push(format!("Disabled data pack {pack}, it failed to load: {source}"));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Rebuilds the registries and tags from the enabled data packs. Registries only change for new connections,
+    /// since the client can't receive them outside the configuration state.
+    fn reload(&mut self) -> Vec<String> {
+        self.datapacks.rescan();
+        match Self::load_resources(&mut self.datapacks) {
+            Ok((resource_manager, mut messages)) => {
+                self.resource_manager = resource_manager;
+                messages.push("Reloading!".to_string());
+                messages
+            }
+            Err(err) => {
+                error!("Failed to reload data packs: {}", err);
+                vec![format!("Reload failed, keeping old data: {err}")]
+            }
+        }
+    }
+}
+
+/// Sends a packet from the entity tracker to the connection of a player
+fn send_to_player(channels: &[Channel], players: &[ConnectedPlayer], eid: i32, packet: Arc<Vec<u8>>) {
+    if let Some(index) = players.iter().position(|player| player.eid == Some(eid)) {
+        let _ = channels[index].0.send(ServerConnectionThreadBound::PlayPacket(packet));
+    }
+}
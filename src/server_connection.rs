@@ -1,19 +1,37 @@
-use std::cmp::{Ordering, PartialEq};
+use std::cmp::PartialEq;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::{ErrorKind, Read, Write};
 use std::net::{Shutdown, TcpStream};
+use std::sync::Arc;
 use std::sync::mpsc::{Receiver, Sender, TryRecvError};
 use std::thread::sleep;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use log::*;
 use mc_world_parser::Position;
 use rand::random;
+use uuid::Uuid;
 use mc_datatypes::{BlockPos, MCString, VarInt};
 use crate::block_registry::BlockRegistry;
-use crate::command::CommandNode;
+use crate::block_storage::ChunkBlocks;
+use crate::chunk_service::LoadedChunk;
+use crate::command::{CommandNode, GAME_MODES};
+use crate::entity::next_entity_id;
+use crate::entity_tracker::TrackedPlayer;
 use crate::error::ServerError;
-use crate::packet::{ConfigurationPacketResponse, ConfigurationPacketType, HandshakePacketType, LoginPacketResponse, LoginPacketType, PlayPacketClientBound, PlayPacketServerBound, StatusPacketType};
+use crate::inventory::{self, PlayerInventory};
+use crate::item::ItemRegistry;
+use crate::level_data::LevelData;
+use crate::movement::{self, MoveCheck, MoveFrom, MAX_MOVES_PER_TICK};
+use crate::packet::{ConfigurationPacketResponse, ConfigurationPacketType, HandshakePacketType, KnownPack, LoginPacketResponse, LoginPacketType, PlayPacketClientBound, PlayPacketServerBound, Slot, StatusPacketType};
 use crate::packet_builder::PacketBuilder;
-use crate::server_util::{ServerInfo, ServerConnectionThreadBound, ServerMainThreadBound};
+use crate::player_data::{PlayerData, PlayerDataStorage};
+use crate::resource_manager::ResourceManager;
+use crate::server_util::{DimensionInfo, ServerInfo, ServerConnectionThreadBound, ServerMainThreadBound};
+
+const CREATIVE: u8 = 1;
+const SPECTATOR: u8 = 3;
+/// How often the client is asked for a keep alive, and how long it has to answer
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ConnectionStatusType {
@@ -33,6 +51,9 @@ pub struct Player {
     yaw: f32,
     pitch: f32,
     on_ground: bool,
+    sneaking: bool,
+    sprinting: bool,
+    fall_flying: bool,
     confirm_tp_count: u32,
 }
 
@@ -46,6 +67,9 @@ impl Player {
             yaw: 0.0,
             pitch: 0.0,
             on_ground: false,
+            sneaking: false,
+            sprinting: false,
+            fall_flying: false,
             confirm_tp_count: 0,
         }
     }
@@ -64,6 +88,10 @@ impl Player {
         self.on_ground = on_ground;
     }
 
+    pub fn chunk_pos(&self) -> (i32, i32) {
+        ((self.x.floor() as i32).div_euclid(16), (self.z.floor() as i32).div_euclid(16))
+    }
+
     pub fn block_pos(&self) -> BlockPos {
         let x = self.x.floor() as i32;
         let y = self.y.floor() as i32;
@@ -80,16 +108,54 @@ pub struct MCServerConnection {
     receiver: Receiver<ServerConnectionThreadBound>,
     server_info: ServerInfo,
     packet_buffer: Vec<u8>,
-    block_registry: BlockRegistry,
-    client_loaded_chunks: Vec<Position>,
+    block_registry: Arc<BlockRegistry>,
+    items: Arc<ItemRegistry>,
+    dimension: DimensionInfo,
+    /// The world's level.dat from when the player connected, with the time kept up to date
+    level: LevelData,
+    game_mode: u8,
+    player_data_storage: PlayerDataStorage,
+    uuid: Option<Uuid>,
+    /// What was loaded for the player, updated from `player` when saving
+    player_data: Option<PlayerData>,
+    /// Registry data was sent, configuration finishes once the player's data has arrived as well
+    registries_sent: bool,
+    inventory: PlayerInventory,
+    /// Chunks in the client's view square, whether they were sent yet or not
+    client_loaded_chunks: HashSet<(i32, i32)>,
+    client_sent_chunks: HashSet<(i32, i32)>,
+    /// Blocks of the sent chunks as the client has them, to check moves against
+    chunk_blocks: HashMap<(i32, i32), Arc<ChunkBlocks>>,
+    /// Chunks loaded by the chunk service that wait for the next batch
+    pending_chunks: VecDeque<LoadedChunk>,
+    /// Chunks per tick the client asked for in its last Chunk Batch Received
+    desired_chunks_per_tick: f32,
+    /// How many chunks may be sent in the next batch, grows by `desired_chunks_per_tick` every tick
+    batch_quota: f32,
+    unacknowledged_batches: i32,
+    /// Only one batch is in flight until the client first tells us how fast it can go
+    max_unacknowledged_batches: i32,
+    /// Player chunk and view distance the loaded chunks were last updated for
+    chunk_loading_center: Option<(i32, i32, i32)>,
     player: Player,
     last_tick: SystemTime,
     waiting_for_confirm_teleport: Option<i32>,
-    view_distance: i32
+    /// Move packets since the last tick, up to `MAX_MOVES_PER_TICK`
+    moves_this_tick: u32,
+    /// Id of the keep alive the client hasn't answered yet
+    pending_keep_alive: Option<u64>,
+    last_keep_alive: Instant,
+    /// Smoothed keep alive round trip time in milliseconds
+    latency: i32,
+    view_distance: i32,
+    /// Bit mask of the skin layers the player shows, which the other players see
+    displayed_skin_parts: u8,
+    server_known_packs: Vec<KnownPack>,
+    shared_known_packs: Vec<KnownPack>,
 }
 
 impl MCServerConnection {
-    pub fn new(connection: TcpStream, sender: Sender<ServerMainThreadBound>, receiver: Receiver<ServerConnectionThreadBound>, server_info: ServerInfo, block_registry: BlockRegistry) -> Self {
+    pub fn new(connection: TcpStream, sender: Sender<ServerMainThreadBound>, receiver: Receiver<ServerConnectionThreadBound>, server_info: ServerInfo, block_registry: Arc<BlockRegistry>, items: Arc<ItemRegistry>, dimension: DimensionInfo, level: LevelData, player_data_storage: PlayerDataStorage) -> Self {
         connection.set_nonblocking(true).unwrap();
         Self {
             pretty_identifier: connection.peer_addr().map(|a| {a.to_string()}).unwrap_or("UNKNOWN".to_string()),
@@ -100,31 +166,76 @@ impl MCServerConnection {
             server_info,
             packet_buffer: vec![],
             block_registry,
-            client_loaded_chunks: vec![],
-            player: Player::new(random()),
+            items,
+            dimension,
+            game_mode: level.game_type,
+            level,
+            player_data_storage,
+            uuid: None,
+            player_data: None,
+            registries_sent: false,
+            inventory: PlayerInventory::new(),
+            client_loaded_chunks: HashSet::new(),
+            client_sent_chunks: HashSet::new(),
+            chunk_blocks: HashMap::new(),
+            pending_chunks: VecDeque::new(),
+            desired_chunks_per_tick: 9.0,
+            batch_quota: 0.0,
+            unacknowledged_batches: 0,
+            max_unacknowledged_batches: 1,
+            chunk_loading_center: None,
+            player: Player::new(next_entity_id()),
             last_tick: SystemTime::UNIX_EPOCH,
             waiting_for_confirm_teleport: None,
+            moves_this_tick: 0,
+            pending_keep_alive: None,
+            last_keep_alive: Instant::now(),
+            latency: 0,
             view_distance: 12,
+            displayed_skin_parts: 0x7F,
+            server_known_packs: vec![],
+            shared_known_packs: vec![],
         }
     }
 
     fn send_packet(&mut self, packet: Vec<u8>) {
+        self.send_packet_bytes(&packet);
+    }
+
+    /// Sends an already built packet, like the cached chunk packets shared between connections
+    fn send_packet_bytes(&mut self, packet: &[u8]) {
         trace!("Sending: {packet:02X?}");
-        self.connection.write(packet.as_slice()).unwrap();
+        self.connection.write(packet).unwrap();
     }
 
-    fn sync_player_pos(&mut self) {
+    fn disconnect<S: Into<String>>(&mut self, reason: S) {
+        let reason = reason.into();
+        info!("{}: Disconnecting: {}", self.pretty_identifier, reason);
+        match self.state {
+            ConnectionStatusType::Configuration => self.send_packet(ConfigurationPacketResponse::disconnect(reason)),
+            ConnectionStatusType::Play => self.send_packet(PlayPacketClientBound::disconnect(reason)),
+            _ => {}
+        }
+        let _ = self.connection.shutdown(Shutdown::Both);
+    }
+
+    /// Moves the player to an absolute position, facing the way the server last knew
+    fn teleport(&mut self, x: f64, y: f64, z: f64) {
+        self.set_pos(x, y, z);
+        let confirm_id = self.player.confirm_tp_count;
+        self.player.confirm_tp_count += 1;
         let packet = PacketBuilder::new()
             .set_id(PlayPacketClientBound::SyncPlayerPosition)
-            .add_double(0f64)
-            .add_double(0f64)
-            .add_double(0f64)
-            .add_float(0f32)
-            .add_float(0f32)
-            .add_byte(0x1F)
-            .add_varint(0)
+            .add_double(x)
+            .add_double(y)
+            .add_double(z)
+            .add_float(self.player.yaw)
+            .add_float(self.player.pitch)
+            .add_byte(0x00)
+            .add_varint(confirm_id as i32)
             .build()
             .unwrap();
+        self.waiting_for_confirm_teleport = Some(confirm_id as i32);
         self.send_packet(packet);
     }
 
@@ -162,29 +273,85 @@ impl MCServerConnection {
             match self.receiver.try_recv() {
                 Ok(message) => {
                     match message {
-                        ServerConnectionThreadBound::RegistryInfo { registry_id, entries } => {
-                            self.send_packet(ConfigurationPacketResponse::registry_data(registry_id, entries));
+                        ServerConnectionThreadBound::KnownPacks(known_packs) => {
+                            self.send_packet(ConfigurationPacketResponse::known_packs(&known_packs));
+                            self.server_known_packs = known_packs;
+                        }
+                        ServerConnectionThreadBound::RegistryInfo(registries) => {
+                            for (registry_id, entries) in registries.iter() {
+                                let missing_entry = entries.iter().find(|entry| entry.is_missing_for(&self.shared_known_packs));
+                                if let Some(missing_entry) = missing_entry.filter(|_| ResourceManager::is_network_registry(registry_id)) {
+                                    let reason = format!("Missing registry data for {} in {}", missing_entry.id, registry_id);
+                                    self.disconnect(reason);
+                                    continue 'outer;
+                                }
+                                self.send_packet(ConfigurationPacketResponse::registry_data(registry_id, entries, &self.shared_known_packs));
+                            }
+                            self.registries_sent = true;
+                            self.try_finish_configuration();
                         }
                         ServerConnectionThreadBound::TagInfo(tags) => {
-                            self.send_packet(ConfigurationPacketResponse::update_tags(tags));
-                            self.sender.send(ServerMainThreadBound::RequestRegistryInfo).unwrap();
+                            if self.state == ConnectionStatusType::Configuration {
+                                self.send_packet(ConfigurationPacketResponse::update_tags(&tags));
+                                let _ = self.sender.send(ServerMainThreadBound::RequestRegistryInfo);
+                            }
                         }
-                        ServerConnectionThreadBound::RegistryInfoFinished => {
-                            // Next stage ig
-                            let packet = PacketBuilder::new()
-                                .set_id(ConfigurationPacketResponse::FinishConfiguration)
-                                .build()
-                                .unwrap();
-                            self.send_packet(packet);
+                        ServerConnectionThreadBound::TagsReloaded(tags) => {
+                            // Connections that aren't playing yet get the new tags during configuration
+                            if self.state == ConnectionStatusType::Play {
+                                self.send_packet(PlayPacketClientBound::update_tags(&tags));
+                            }
                         }
                         ServerConnectionThreadBound::ChunkData(chunk) => {
-                            if let Some(chunk) = chunk {
-                                self.send_packet(PlayPacketClientBound::chunk_data(chunk, Box::new(self.block_registry.clone())));
+                            // Chunks requested before a dimension change are dropped
+                            if let Some(chunk) = chunk.filter(|chunk| chunk.dimension == self.dimension.name) {
+                                self.pending_chunks.push_back(chunk);
                             }
                         }
                         ServerConnectionThreadBound::ChatMessage { player_name, message, timestamp, salt } => {
                             self.send_packet(PlayPacketClientBound::player_chat_message_fake(player_name, message));
                         }
+                        ServerConnectionThreadBound::SystemMessage(message) => {
+                            if self.state == ConnectionStatusType::Play {
+                                self.send_packet(PlayPacketClientBound::system_chat_message(message));
+                            }
+                        }
+                        ServerConnectionThreadBound::ChunkPacket { chunk_x, chunk_z, packet } => {
+                            if self.client_sent_chunks.contains(&(chunk_x, chunk_z)) {
+                                self.send_packet_bytes(&packet);
+                            }
+                        }
+                        ServerConnectionThreadBound::BlockChanged { pos, block_state } => {
+                            self.set_block(pos, block_state);
+                        }
+                        ServerConnectionThreadBound::PlayPacket(packet) => {
+                            self.send_packet_bytes(&packet);
+                        }
+                        ServerConnectionThreadBound::ChangeDimension { dimension, x, y, z } => {
+                            self.change_dimension(dimension, x, y, z);
+                        }
+                        // The client joined where the player was, don't move them without telling it
+                        ServerConnectionThreadBound::PlayerData { .. } if self.state == ConnectionStatusType::Play => {}
+                        ServerConnectionThreadBound::PlayerData { data, dimension } => {
+                            self.player.set_pos(data.x, data.y, data.z);
+                            self.player.set_yaw_pitch(data.yaw, data.pitch);
+                            self.player.set_on_ground(data.on_ground);
+                            self.game_mode = data.game_mode;
+                            self.inventory = PlayerInventory::from_nbt(&data.inventory, data.selected_slot, &self.items);
+                            self.dimension = dimension;
+                            self.player_data = Some(data);
+                            self.try_finish_configuration();
+                        }
+                        ServerConnectionThreadBound::SavePlayerData => {
+                            self.save_player_data();
+                        }
+                        ServerConnectionThreadBound::Time { world_age, time_of_day } => {
+                            self.level.time = world_age;
+                            self.level.day_time = time_of_day;
+                            if self.state == ConnectionStatusType::Play {
+                                self.send_packet(PlayPacketClientBound::update_time(world_age, time_of_day, self.level.game_rule_bool("doDaylightCycle", true)));
+                            }
+                        }
                     }
                 }
                 Err(_) => {}
@@ -193,60 +360,100 @@ impl MCServerConnection {
             if self.state == ConnectionStatusType::Play {
                 self.handle_chunk_loading();
                 self.handle_ticks();
+                self.handle_keep_alive();
             }
         }
+        self.save_player_data();
     }
 
     fn handle_chunk_loading(&mut self) {
-        // TODO: Handle position ig
-        let mut chunk_to_load = vec![];
-        let player_pos = self.player.block_pos();
-        let player_x = (player_pos.x() - (player_pos.x() < 0) as i32)/16;
-        let player_z = (player_pos.z() - (player_pos.z() < 0) as i32)/16;
-        for x in -self.view_distance..self.view_distance {
-            for z in -self.view_distance..self.view_distance {
-                chunk_to_load.push(Position::new(x + player_x, 0, z + player_z));
+        let (player_x, player_z) = self.player.chunk_pos();
+        let view_distance = self.view_distance;
+        if self.chunk_loading_center == Some((player_x, player_z, view_distance)) {
+            return;
+        }
+        self.chunk_loading_center = Some((player_x, player_z, view_distance));
+        let distance_to = |(x, z): (i32, i32)| player_x.abs_diff(x).max(player_z.abs_diff(z)) as i32;
+
+        // Unload the chunks that left the view square
+        let out_of_view = self.client_loaded_chunks.iter().copied().filter(|chunk| distance_to(*chunk) > view_distance).collect::<Vec<_>>();
+        for chunk in out_of_view {
+            self.client_loaded_chunks.remove(&chunk);
+            // Chunks that haven't been sent yet are dropped when they come back from the main thread
+            self.chunk_blocks.remove(&chunk);
+            if self.client_sent_chunks.remove(&chunk) {
+                self.send_packet(PlayPacketClientBound::unload_chunk(chunk.0, chunk.1));
             }
         }
 
-        // Sort by distance to player
-        chunk_to_load.sort_by(|c1, c2| {
-            let x_diff1 = player_x.abs_diff(c1.x) as i32;
-            let z_diff1 = player_z.abs_diff(c1.z) as i32;
+        let mut chunk_to_load = vec![];
+        for x in -view_distance..=view_distance {
+            for z in -view_distance..=view_distance {
+                let chunk = (x + player_x, z + player_z);
+                if !self.client_loaded_chunks.contains(&chunk) {
+                    chunk_to_load.push(chunk);
+                }
+            }
+        }
+        // Closest first
+        chunk_to_load.sort_by_key(|chunk| distance_to(*chunk));
 
-            let x_diff2 = player_x.abs_diff(c2.x) as i32;
-            let z_diff2 = player_z.abs_diff(c2.z) as i32;
+        for chunk in chunk_to_load {
+            self.client_loaded_chunks.insert(chunk);
+            self.sender.send(ServerMainThreadBound::RequestChunk { pos: Position::new(chunk.0, 0, chunk.1), distance: distance_to(chunk) }).unwrap();
+        }
+    }
 
-            let length1 = x_diff1.max(z_diff1);
-            let length2 = x_diff2.max(z_diff2);
+    /// Sends the chunks that arrived since the last tick, as many as the client said it can handle.
+    /// Works like vanilla, which waits for the client to acknowledge batches before sending more.
+    fn send_chunk_batch(&mut self) {
+        if self.unacknowledged_batches >= self.max_unacknowledged_batches {
+            return;
+        }
+        let max_batch_size = self.desired_chunks_per_tick.max(1.0);
+        self.batch_quota = (self.batch_quota + self.desired_chunks_per_tick).min(max_batch_size);
+        if self.batch_quota < 1.0 {
+            return;
+        }
 
-            if length1 < length2 {
-                Ordering::Less
-            } else if length1 > length2 {
-                Ordering::Greater
-            } else {
-                Ordering::Equal
+        let mut batch = vec![];
+        while batch.len() < self.batch_quota as usize {
+            let Some(chunk) = self.pending_chunks.pop_front() else {
+                break;
+            };
+            // The player might have moved away while the chunk was waiting
+            if self.client_loaded_chunks.contains(&(chunk.x, chunk.z)) {
+                batch.push(chunk);
             }
-        });
+        }
+        if batch.is_empty() {
+            return;
+        }
+        self.unacknowledged_batches += 1;
+        self.batch_quota -= batch.len() as f32;
 
-        for chunk in chunk_to_load {
-            if !self.client_loaded_chunks.contains(&chunk) {
-                self.client_loaded_chunks.push(chunk);
-                self.sender.send(ServerMainThreadBound::RequestChunk(chunk)).unwrap();
-            }
+        self.send_packet(PlayPacketClientBound::chunk_batch_start());
+        for chunk in &batch {
+            self.client_sent_chunks.insert((chunk.x, chunk.z));
+            self.chunk_blocks.insert((chunk.x, chunk.z), chunk.blocks.clone());
+            self.send_packet_bytes(&chunk.packet);
         }
-        // Keep track of chunks the client will unload
-        for i in (0..self.client_loaded_chunks.len()).rev() {
-            let chunkpos = self.client_loaded_chunks.get(i).unwrap();
-            let x_diff = player_x.abs_diff(chunkpos.x) as i32;
-            let z_diff = player_z.abs_diff(chunkpos.z) as i32;
-            let length = x_diff.max(z_diff);
-            // Cube of size view_distance * 2 + 7
-            if length > self.view_distance + 3 {
-                // Safe since we start from the end
-                self.client_loaded_chunks.remove(i);
-            }
+        self.send_packet(PlayPacketClientBound::chunk_batch_finished(batch.len() as i32));
+    }
+
+    /// Sends a keep alive every 15 seconds and disconnects clients that didn't answer the last one in time
+    fn handle_keep_alive(&mut self) {
+        if self.last_keep_alive.elapsed() < KEEP_ALIVE_INTERVAL {
+            return;
+        }
+        if self.pending_keep_alive.is_some() {
+            self.disconnect("Timed out");
+            return;
         }
+        let id = random();
+        self.pending_keep_alive = Some(id);
+        self.last_keep_alive = Instant::now();
+        self.send_packet(PlayPacketClientBound::keep_alive(id));
     }
 
     fn handle_ticks(&mut self) {
@@ -265,6 +472,7 @@ impl MCServerConnection {
                 .add_varint(1)
                 .build().unwrap();
             self.send_packet(packet);
+            self.send_chunk_batch();
         } else {
             let curr_time = SystemTime::now();
             if let Ok(duration) = curr_time.duration_since(self.last_tick) {
@@ -275,6 +483,8 @@ impl MCServerConnection {
                         .build().unwrap();
                     self.send_packet(packet);
                     self.last_tick = curr_time;
+                    self.moves_this_tick = 0;
+                    self.send_chunk_batch();
                 }
             } else {
                 self.last_tick = curr_time;
@@ -282,52 +492,224 @@ impl MCServerConnection {
         }
     }
 
-    fn confirm_teleport(&mut self) {
-        // Send sync player pos
-        let confirm_id = self.player.confirm_tp_count;
-        self.player.confirm_tp_count += 1;
-        //sleep(Duration::from_secs_f64(0.1));
-        let (new_y, mask) = if self.player.y < -80f64 {
-            (-64f64, 0x1D)
-        } else {
-            (0f64, 0x1F)
-        };
-        let packet = PacketBuilder::new()
-            .set_id(PlayPacketClientBound::SyncPlayerPosition)
-            .add_double(0f64)
-            .add_double(new_y)
-            .add_double(0f64)
-            .add_float(0f32)
-            .add_float(0f32)
-            .add_byte(mask)
-            .add_varint(confirm_id as i32)
-            ;
-        self.waiting_for_confirm_teleport = Some(confirm_id as i32);
-        self.send_packet(packet.build().unwrap())
-    }
-
     fn play_mode_initialize_client(&mut self) {
         // Sends all required packets for clients to connect that don't get sent on different signals
-        self.send_packet(PlayPacketClientBound::login(self.player.eid, false, vec!["minecraft:overworld".to_string(), "minecraft:the_end".to_string(), "minecraft:the_nether".to_string()], 20, self.view_distance));
-        self.send_packet(PlayPacketClientBound::change_difficulty(1));
+        self.send_packet(PlayPacketClientBound::login(self.player.eid, vec!["minecraft:overworld".to_string(), "minecraft:the_end".to_string(), "minecraft:the_nether".to_string()], 20, self.view_distance, &self.dimension, self.game_mode, &self.level));
+        self.send_packet(PlayPacketClientBound::change_difficulty(self.level.difficulty, self.level.difficulty_locked));
         self.send_packet(PlayPacketClientBound::commands(CommandNode::commands()));
-        self.send_packet(PlayPacketClientBound::player_abilities());
-        self.send_packet(PlayPacketClientBound::set_held_item(0));
+        self.send_packet(PlayPacketClientBound::player_abilities(self.game_mode));
+        self.send_packet(PlayPacketClientBound::set_held_item(self.inventory.selected_slot()));
         //self.send_packet(PlayPacketClientBound::set_recipes());
         self.send_packet(PlayPacketClientBound::entity_event(self.player.eid, 24));
         self.send_packet(PlayPacketClientBound::entity_effect(self.player.eid, 15, 1, 0x7F, 0x07));
+
+        let level = &self.level;
+        let spawn = BlockPos::new(level.spawn_x, level.spawn_y, level.spawn_z);
+        let (spawn_angle, daylight_cycle) = (level.spawn_angle, level.game_rule_bool("doDaylightCycle", true));
+        self.send_packet(PlayPacketClientBound::set_default_spawn_position(spawn, spawn_angle));
+        self.send_packet(PlayPacketClientBound::update_time(self.level.time, self.level.day_time, daylight_cycle));
+        if self.level.raining {
+            self.send_packet(PlayPacketClientBound::game_event(1, 0.0));
+            self.send_packet(PlayPacketClientBound::game_event(7, 1.0));
+        }
+        if self.level.thundering {
+            self.send_packet(PlayPacketClientBound::game_event(8, 1.0));
+        }
+        self.teleport(self.player.x, self.player.y, self.player.z);
+        self.send_inventory();
+    }
+
+    /// Sends the whole inventory, which the client needs after joining or respawning and when it got out of sync
+    fn send_inventory(&mut self) {
+        let packet = self.inventory.content_packet();
+        self.send_packet(packet);
+    }
+
+    /// Tells the client about a slot the server changed
+    fn send_inventory_slot(&mut self, slot: usize) {
+        if let Some(packet) = self.inventory.slot_packet(slot) {
+            self.send_packet(packet);
+        }
+    }
+
+    /// Lets the client into the game once it has the registries and the player's position and dimension are known
+    fn try_finish_configuration(&mut self) {
+        if self.state != ConnectionStatusType::Configuration || !self.registries_sent || self.player_data.is_none() {
+            return;
+        }
+        self.registries_sent = false;
+        let packet = PacketBuilder::new()
+            .set_id(ConfigurationPacketResponse::FinishConfiguration)
+            .build()
+            .unwrap();
+        self.send_packet(packet);
+    }
+
+    /// Writes the player's file, if it got far enough into logging in to have one
+    fn save_player_data(&mut self) {
+        let (Some(uuid), Some(data)) = (self.uuid, &mut self.player_data) else {
+            return;
+        };
+        data.x = self.player.x;
+        data.y = self.player.y;
+        data.z = self.player.z;
+        data.yaw = self.player.yaw;
+        data.pitch = self.player.pitch;
+        data.on_ground = self.player.on_ground;
+        data.dimension = self.dimension.name.clone();
+        data.game_mode = self.game_mode;
+        data.inventory = self.inventory.to_nbt(&self.items);
+        data.selected_slot = self.inventory.selected_slot() as i32;
+        match self.player_data_storage.save(uuid, data) {
+            Ok(()) => debug!("{}: Saved player data", self.pretty_identifier),
+            Err(err) => error!("{}: Failed to save player data: {}", self.pretty_identifier, err),
+        }
+    }
+
+    /// Stores where the player looks and tells the other players if it changed
+    fn set_rotation(&mut self, yaw: f32, pitch: f32) {
+        if (yaw, pitch) == (self.player.yaw, self.player.pitch) {
+            return;
+        }
+        self.player.set_yaw_pitch(yaw, pitch);
+        let _ = self.sender.send(ServerMainThreadBound::PlayerRotated { yaw, pitch, on_ground: self.player.on_ground });
+    }
+
+    fn set_on_ground(&mut self, on_ground: bool) {
+        self.player.set_on_ground(on_ground);
+        // Landing ends elytra flight
+        if on_ground && self.player.fall_flying {
+            self.player.fall_flying = false;
+            self.send_flags();
+        }
+    }
+
+    /// Switches the client to another game mode and shows it on everyone's tab list
+    fn set_game_mode(&mut self, game_mode: u8) {
+        self.game_mode = game_mode;
+        // Game event 3 changes the game mode
+        self.send_packet(PlayPacketClientBound::game_event(3, game_mode as f32));
+        self.send_packet(PlayPacketClientBound::player_abilities(game_mode));
+        let _ = self.sender.send(ServerMainThreadBound::GameModeChanged(game_mode));
+        self.send_packet(PlayPacketClientBound::system_chat_message(format!("Set own game mode to {}", GAME_MODES[game_mode as usize])));
+    }
+
+    fn send_flags(&self) {
+        let (sneaking, sprinting, fall_flying) = (self.player.sneaking, self.player.sprinting, self.player.fall_flying);
+        let _ = self.sender.send(ServerMainThreadBound::PlayerFlags { sneaking, sprinting, fall_flying });
+    }
+
+    /// What the other players need to know about the player once it's in the game
+    fn tracked_player(&self) -> TrackedPlayer {
+        let player = &self.player;
+        let mut tracked = TrackedPlayer::new(player.eid, self.uuid.unwrap_or_default(), self.pretty_identifier.clone(), self.dimension.name.clone(), player.x, player.y, player.z);
+        tracked.game_mode = self.game_mode;
+        tracked.yaw = player.yaw;
+        tracked.pitch = player.pitch;
+        tracked.on_ground = player.on_ground;
+        tracked.skin_parts = self.displayed_skin_parts;
+        tracked.view_distance = self.view_distance;
+        tracked
     }
 
     fn set_pos(&mut self, x: f64, y: f64, z: f64) {
-        let mut old_blockpos = self.player.block_pos();
+        let old_chunk = self.player.chunk_pos();
+        let moved = (x, y, z) != (self.player.x, self.player.y, self.player.z);
         self.player.set_pos(x, y, z);
-        let mut new_blockpos = self.player.block_pos();
-        old_blockpos.set_y(0);
-        new_blockpos.set_y(0);
-        if old_blockpos.x()/16 != new_blockpos.x()/16 || old_blockpos.z()/16 != new_blockpos.z()/16 {
-            self.send_packet(PlayPacketClientBound::set_center_chunk(new_blockpos))
+        let new_chunk = self.player.chunk_pos();
+        if old_chunk != new_chunk {
+            self.send_packet(PlayPacketClientBound::set_center_chunk(new_chunk.0, new_chunk.1))
+        }
+        if moved {
+            let _ = self.sender.send(ServerMainThreadBound::PlayerMoved { x, y, z, on_ground: self.player.on_ground });
         }
-        //self.handle_chunk_loading();
+    }
+
+    /// Checks a move from the client against the last position the server accepted, and sends the player back there
+    /// if the move isn't possible. Moves made before the client got a teleport are ignored.
+    fn accept_move(&mut self, x: f64, y: f64, z: f64) -> bool {
+        let moves_this_tick = (self.moves_this_tick + 1).min(MAX_MOVES_PER_TICK);
+        let from = MoveFrom {
+            x: self.player.x,
+            y: self.player.y,
+            z: self.player.z,
+            fall_flying: self.player.fall_flying,
+            spectator: self.game_mode == SPECTATOR,
+            awaiting_teleport: self.waiting_for_confirm_teleport.is_some(),
+            moves_this_tick,
+        };
+        let check = movement::check_move(&from, x, y, z, |x, y, z| {
+            let blocks = self.chunk_blocks.get(&(x.div_euclid(16), z.div_euclid(16)))?;
+            self.block_registry.state_info(blocks.get(x.rem_euclid(16), y, z.rem_euclid(16))).collision
+        });
+        if !matches!(check, MoveCheck::Invalid | MoveCheck::Ignore) {
+            self.moves_this_tick = moves_this_tick;
+        }
+        match check {
+            MoveCheck::Accept => return true,
+            MoveCheck::Invalid => self.disconnect("Invalid move player packet received"),
+            MoveCheck::Ignore => {}
+            MoveCheck::TooFast => {
+                warn!("{}: Moved too quickly! {}, {}, {}", self.pretty_identifier, x - from.x, y - from.y, z - from.z);
+                self.teleport(from.x, from.y, from.z);
+            }
+            MoveCheck::IntoBlock => {
+                warn!("{}: Moved wrongly! {}, {}, {}", self.pretty_identifier, x, y, z);
+                self.teleport(from.x, from.y, from.z);
+            }
+        }
+        false
+    }
+
+    /// Changes a block in the player's copy of the chunk and tells the client, if it has the chunk
+    fn set_block(&mut self, pos: BlockPos, block_state: i32) {
+        let chunk = (pos.x().div_euclid(16), pos.z().div_euclid(16));
+        if let Some(blocks) = self.chunk_blocks.get_mut(&chunk) {
+            Arc::make_mut(blocks).set(pos.x().rem_euclid(16), pos.y(), pos.z().rem_euclid(16), block_state, &self.block_registry);
+        }
+        if self.client_sent_chunks.contains(&chunk) {
+            self.send_packet(PlayPacketClientBound::block_update(block_state, pos));
+        }
+    }
+
+    /// Respawns the player in another dimension. The client forgets all chunks, so they are loaded again from scratch.
+    fn change_dimension(&mut self, dimension: DimensionInfo, x: f64, y: f64, z: f64) {
+        info!("{}: Moving to {}", self.pretty_identifier, dimension.name);
+        self.send_packet(PlayPacketClientBound::respawn(&dimension, self.game_mode, &self.level));
+        self.dimension = dimension;
+        self.client_loaded_chunks.clear();
+        self.client_sent_chunks.clear();
+        self.chunk_blocks.clear();
+        self.pending_chunks.clear();
+        self.chunk_loading_center = None;
+
+        self.teleport(x, y, z);
+        let (chunk_x, chunk_z) = self.player.chunk_pos();
+        self.send_packet(PlayPacketClientBound::set_center_chunk(chunk_x, chunk_z));
+        self.send_packet(PlayPacketClientBound::set_held_item(self.inventory.selected_slot()));
+        self.send_inventory();
+    }
+
+    /// Parses `setblock <x> <y> <z> <block>`, where coordinates can be relative to the player with `~`
+    fn parse_setblock(&self, command: &str) -> Option<(BlockPos, i32)> {
+        let args = command.split(' ').collect::<Vec<_>>();
+        if args.len() != 5 {
+            return None;
+        }
+        let player_pos = self.player.block_pos();
+        let parse_coordinate = |arg: &str, relative_to: i32| -> Option<i32> {
+            match arg.strip_prefix('~') {
+                Some("") => Some(relative_to),
+                Some(offset) => offset.parse::<i32>().ok().map(|o| o + relative_to),
+                None => arg.parse::<i32>().ok(),
+            }
+        };
+        let pos = BlockPos::new(
+            parse_coordinate(args[1], player_pos.x())?,
+            parse_coordinate(args[2], player_pos.y())?,
+            parse_coordinate(args[3], player_pos.z())?,
+        );
+        Some((pos, self.block_registry.parse_state(args[4])?))
     }
 
     fn handle_packet(&mut self, packet: Vec<u8>) -> Result<(), ServerError> {
@@ -348,7 +730,10 @@ impl MCServerConnection {
                 self.handle_config_packet(packet).unwrap();
             }
             ConnectionStatusType::Play => {
-                self.handle_play_packet(packet).unwrap();
+                // Malformed packets, like items nested too deep, only end this player's connection
+                if let Err(err) = self.handle_play_packet(packet) {
+                    self.disconnect(format!("Invalid packet: {err}"));
+                }
             }
             _ => {
                 error!("Server state not yet implemented: {:?}", self.state);
@@ -411,6 +796,8 @@ impl MCServerConnection {
             LoginPacketType::LoginStart { name, uuid } => {
                 info!("Login from {}. Name: {} (UUID: {})", self.pretty_identifier, name, uuid.hyphenated());
                 self.pretty_identifier = name.clone();
+                self.uuid = Some(uuid);
+                let _ = self.sender.send(ServerMainThreadBound::PlayerLogin { uuid });
                 let packet = PacketBuilder::new()
                     .set_id(LoginPacketResponse::LoginSuccess)
                     .add_uuid(uuid)
@@ -446,33 +833,24 @@ impl MCServerConnection {
                 }
                 Ok(())
             }
-            ConfigurationPacketType::ClientInformation { view_distance, .. } => {
+            ConfigurationPacketType::ClientInformation { view_distance, displayed_skin_parts, .. } => {
                 self.view_distance = view_distance.min(12) as i32;
-                let packet = PacketBuilder::new()
-                    .set_id(ConfigurationPacketResponse::ClientBoundKnownPacks)
-                    .add_varint(1)
-                    .add_string("minecraft")
-                    .add_string("core")
-                    .add_string("1.21")
-                    .build()
-                    .unwrap();
-                self.send_packet(packet);
+                self.displayed_skin_parts = displayed_skin_parts;
+                self.sender.send(ServerMainThreadBound::RequestKnownPacks).unwrap();
                 Ok(())
             }
             ConfigurationPacketType::FinishConfigurationAck => {
                 self.state = ConnectionStatusType::Play;
                 debug!("Going into Play state");
                 self.play_mode_initialize_client();
+                let _ = self.sender.send(ServerMainThreadBound::PlayerJoined(self.tracked_player()));
                 let _= self.sender.send(ServerMainThreadBound::ChatMessage { player_name: self.pretty_identifier.clone(), message: "Joined the game".to_string(), timestamp: 0, salt: 0 });
                 Ok(())
             }
             ConfigurationPacketType::ServerBoundKnownPacks { known_packs } => {
-                if let Some(pack) = known_packs.first() {
-                    if !(pack.namespace == "minecraft" && pack.id == "core" && pack.version == "1.21") {
-                        panic!("Server and client known pack mismatch");
-                    }
-                } else {
-                    panic!("Client known packs empty");
+                self.shared_known_packs = known_packs.into_iter().filter(|pack| self.server_known_packs.contains(pack)).collect();
+                if self.shared_known_packs.len() < self.server_known_packs.len() {
+                    warn!("{}: Client only shares {} of {} known packs, sending full registry data for the rest", self.pretty_identifier, self.shared_known_packs.len(), self.server_known_packs.len());
                 }
                 self.sender.send(ServerMainThreadBound::RequestTagInfo).unwrap();
                 Ok(())
@@ -491,11 +869,58 @@ impl MCServerConnection {
             }
             PlayPacketServerBound::ChatCommand { command } => {
                 info!("{} ran the command: {}", self.pretty_identifier, command);
-                let block_state = command[6..].parse::<i32>().unwrap();
-                self.send_packet(PlayPacketClientBound::block_update(block_state, self.player.block_pos()));
+                match command.split(' ').next().unwrap_or("") {
+                    "place" => {
+                        let block_state = command[6..].parse::<i32>().unwrap();
+                        self.set_block(self.player.block_pos(), block_state);
+                        let _ = self.sender.send(ServerMainThreadBound::BlockChanged { pos: self.player.block_pos(), block_state });
+                    }
+                    "setblock" => {
+                        match self.parse_setblock(&command) {
+                            Some((pos, block_state)) => {
+                                let feedback = format!("Changed the block at {}, {}, {}", pos.x(), pos.y(), pos.z());
+                                let _ = self.sender.send(ServerMainThreadBound::BlockChanged { pos: BlockPos::new(pos.x(), pos.y(), pos.z()), block_state });
+                                self.set_block(pos, block_state);
+                                self.send_packet(PlayPacketClientBound::system_chat_message(feedback));
+                            }
+                            None => {
+                                self.send_packet(PlayPacketClientBound::system_chat_message(format!("Invalid command: {command}")));
+                            }
+                        }
+                    }
+                    "dimension" => {
+                        let name = command.strip_prefix("dimension ").unwrap_or("").trim();
+                        let dimension = if name.contains(':') { name.to_string() } else { format!("minecraft:{name}") };
+                        let _ = self.sender.send(ServerMainThreadBound::ChangeDimension { dimension });
+                    }
+                    "gamemode" => {
+                        let name = command.strip_prefix("gamemode ").unwrap_or("").trim();
+                        match GAME_MODES.iter().position(|mode| *mode == name) {
+                            Some(game_mode) => self.set_game_mode(game_mode as u8),
+                            None => self.send_packet(PlayPacketClientBound::system_chat_message(format!("Unknown game mode: {name}"))),
+                        }
+                    }
+                    "datapack" | "reload" | "save-all" => {
+                        let _ = self.sender.send(ServerMainThreadBound::RunCommand { player_name: self.pretty_identifier.clone(), command });
+                    }
+                    _ => {
+                        self.send_packet(PlayPacketClientBound::system_chat_message(format!("Unknown command: {command}")));
+                    }
+                }
             }
-            PlayPacketServerBound::ClientInformation { view_distance, .. } => {
+            PlayPacketServerBound::ClientInformation { view_distance, displayed_skin_parts, .. } => {
                 self.view_distance = view_distance.min(12) as i32;
+                self.displayed_skin_parts = displayed_skin_parts;
+                let _ = self.sender.send(ServerMainThreadBound::ClientInformation { view_distance: self.view_distance, skin_parts: displayed_skin_parts });
+            }
+            PlayPacketServerBound::ChunkBatchReceived { chunks_per_tick } => {
+                trace!("Client wants {chunks_per_tick} chunks per tick");
+                self.unacknowledged_batches = (self.unacknowledged_batches - 1).max(0);
+                self.desired_chunks_per_tick = if chunks_per_tick.is_nan() { 0.01 } else { chunks_per_tick.clamp(0.01, 64.0) };
+                if self.unacknowledged_batches == 0 {
+                    self.batch_quota = 1.0;
+                }
+                self.max_unacknowledged_batches = 10;
             }
             PlayPacketServerBound::ChatMessage { message, timestamp, salt, .. } => {
                 info!("[CHAT] <{}>: {}", self.pretty_identifier, message);
@@ -503,29 +928,36 @@ impl MCServerConnection {
             }
             PlayPacketServerBound::CloseContainer( .. ) => {}
             PlayPacketServerBound::DebugSampleSubscription{ .. } => {}
+            PlayPacketServerBound::KeepAlive(id) => {
+                if self.pending_keep_alive == Some(id) {
+                    self.pending_keep_alive = None;
+                    // Smoothed like vanilla does it
+                    let round_trip = self.last_keep_alive.elapsed().as_millis() as i32;
+                    self.latency = (self.latency * 3 + round_trip) / 4;
+                    let _ = self.sender.send(ServerMainThreadBound::Latency(self.latency));
+                }
+            }
             PlayPacketServerBound::SetPlayerPosition { x, y, z, on_ground } => {
-                self.set_pos(x, y, z);
-                self.player.set_on_ground(on_ground);
-                if self.waiting_for_confirm_teleport.is_none() {
-                    self.confirm_teleport();
+                if self.accept_move(x, y, z) {
+                    self.set_on_ground(on_ground);
+                    self.set_pos(x, y, z);
                 }
                 trace!("pos: {x} / {y} / {z}, on_ground: {on_ground}")
             }
             PlayPacketServerBound::SetPlayerPositionAndRotation { x, y, z, yaw, pitch, on_ground } => {
-                self.set_pos(x, y, z);
-                self.player.set_yaw_pitch(yaw, pitch);
-                self.player.set_on_ground(on_ground);
-                if self.waiting_for_confirm_teleport.is_none() {
-                    self.confirm_teleport();
+                if self.accept_move(x, y, z) {
+                    self.set_on_ground(on_ground);
+                    self.set_pos(x, y, z);
+                    self.set_rotation(yaw, pitch);
                 }
                 trace!("pos: {x} / {y} / {z}, yaw: {yaw}, pitch: {pitch}, on_ground: {on_ground}")
             }
             PlayPacketServerBound::SetPlayerRotation { yaw, pitch, on_ground } => {
-                self.player.set_yaw_pitch(yaw, pitch);
-                self.player.set_on_ground(on_ground);
+                self.set_on_ground(on_ground);
+                self.set_rotation(yaw, pitch);
             }
             PlayPacketServerBound::SetPlayerOnGround(on_ground) => {
-                self.player.set_on_ground(on_ground);
+                self.set_on_ground(on_ground);
             }
             PlayPacketServerBound::PingRequest(ping_id) => {
                 let packet = PacketBuilder::new()
@@ -535,11 +967,69 @@ impl MCServerConnection {
                 self.send_packet(packet);
             }
             PlayPacketServerBound::PlayerAbilities { .. } => {}
-            PlayPacketServerBound::PlayerAction { .. } => {}
-            PlayPacketServerBound::PlayerCommand { .. } => {}
-            PlayPacketServerBound::SetHeldItem { .. } => {}
+            PlayPacketServerBound::PlayerAction { status, .. } => {
+                match status {
+                    // Items can't be dropped yet, so the client gets the item back
+                    3 | 4 => self.send_inventory_slot(self.inventory.held_slot()),
+                    // Swap the items in the main and off hand
+                    6 => {
+                        let held_slot = self.inventory.held_slot();
+                        let held = self.inventory.held_item().clone();
+                        let off_hand = self.inventory.get(inventory::OFF_HAND).cloned().unwrap_or_else(Slot::empty);
+                        self.inventory.set(held_slot, off_hand);
+                        self.inventory.set(inventory::OFF_HAND, held);
+                        self.send_inventory_slot(held_slot);
+                        self.send_inventory_slot(inventory::OFF_HAND);
+                    }
+                    _ => {}
+                }
+            }
+            PlayPacketServerBound::PlayerCommand { id, .. } => {
+                let player = &mut self.player;
+                match id {
+                    0 => player.sneaking = true,
+                    1 => player.sneaking = false,
+                    3 => player.sprinting = true,
+                    4 => player.sprinting = false,
+                    // Start flying with elytra
+                    8 => player.fall_flying = true,
+                    _ => return Ok(()),
+                }
+                self.send_flags();
+            }
+            PlayPacketServerBound::SetHeldItem(slot) => {
+                if !self.inventory.set_selected_slot(slot.min(u8::MAX as u16) as u8) {
+                    warn!("{}: Tried to select hotbar slot {}", self.pretty_identifier, slot);
+                }
+            }
             PlayPacketServerBound::SetCreativeModeSlot { slot, clicked_item } => {
-                debug!("Set slot {slot} to {:?}", clicked_item.item_id)
+                // Slot -1 drops the item, which isn't possible yet. The client already shows the item, so nothing is sent back.
+                if self.game_mode == CREATIVE && (1..inventory::SLOT_COUNT as i16).contains(&slot) {
+                    debug!("{}: Set slot {} to {:?}", self.pretty_identifier, slot, clicked_item.item_id);
+                    let max_stack_size = clicked_item.max_stack_size(&self.items);
+                    if clicked_item.count() > max_stack_size {
+                        self.inventory.set(slot as usize, clicked_item.with_count(max_stack_size));
+                        self.send_inventory_slot(slot as usize);
+                    } else {
+                        self.inventory.set(slot as usize, clicked_item);
+                    }
+                }
+            }
+            PlayPacketServerBound::ClickContainer { window_id, state_id, changed_slots, carried_item, .. } => {
+                // There are no other containers yet
+                if window_id != inventory::WINDOW_ID {
+                    return Ok(());
+                }
+                // Clicks aren't simulated yet, so the client's prediction is taken if it only moves items around. A
+                // click that doesn't add up, or one made on an out of date inventory, is followed by the whole
+                // inventory like vanilla does.
+                let applied = self.inventory.apply_click(changed_slots, carried_item, &self.items, self.game_mode == CREATIVE);
+                if !applied {
+                    debug!("{}: Rejected a click in the inventory", self.pretty_identifier);
+                }
+                if !applied || state_id != self.inventory.state_id() {
+                    self.send_inventory();
+                }
             }
             PlayPacketServerBound::SwingArm { .. } => {}
             PlayPacketServerBound::UseItemOn { .. } => {}
@@ -1,8 +1,14 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
 use inbt::NbtTag;
-use mc_world_parser::chunk::Chunk;
+use mc_datatypes::BlockPos;
 use mc_world_parser::Position;
 use serde::Serialize;
-use crate::block_registry::BlockRegistry;
+use uuid::Uuid;
+use crate::chunk_service::LoadedChunk;
+use crate::entity_tracker::TrackedPlayer;
+use crate::packet::KnownPack;
+use crate::player_data::PlayerData;
 
 #[derive(Serialize, Clone)]
 pub struct VersionInfo {
@@ -36,8 +42,26 @@ pub struct ServerInfo {
 pub struct RegistryEntry {
     pub id: String,
     pub data: Option<NbtTag>,
+    /// The pack this entry was loaded from, if the client could know it
+    pub known_pack: Option<KnownPack>,
 }
 
+impl RegistryEntry {
+    /// Whether the client has the entry from a pack it shares with the server, so its data isn't sent
+    pub fn client_has_entry(&self, shared_packs: &[KnownPack]) -> bool {
+        self.known_pack.as_ref().is_some_and(|pack| shared_packs.contains(pack))
+    }
+
+    /// Whether the client neither has the entry nor gets data it could read for it. Entries are compounds, a
+    /// file that is an array or a string is as good as no file.
+    pub fn is_missing_for(&self, shared_packs: &[KnownPack]) -> bool {
+        !self.client_has_entry(shared_packs) && !matches!(self.data, Some(NbtTag::Compound(..)))
+    }
+}
+
+/// Registry name -> entries, in the order their numeric ids are assigned
+pub type Registries = BTreeMap<String, Vec<RegistryEntry>>;
+
 #[derive(Debug, Clone)]
 pub struct TagEntryData {
     pub entries: Vec<i32>,
@@ -50,17 +74,65 @@ pub struct TagEntry {
     pub data: Vec<TagEntryData>,
 }
 
+/// The dimension a player is in, as the client needs it for Login and Respawn
+#[derive(Debug, Clone)]
+pub struct DimensionInfo {
+    pub name: String,
+    /// Id in the dimension_type registry
+    pub dimension_type: i32,
+    pub min_y: i32,
+}
+
 pub enum ServerMainThreadBound {
+    RequestKnownPacks,
     RequestRegistryInfo,
     RequestTagInfo,
-    RequestChunk(Position),
+    /// `distance` is the distance from the player in chunks, closer chunks are loaded first
+    RequestChunk { pos: Position, distance: i32 },
     ChatMessage { player_name: String, message: String, timestamp: i64, salt: i64, },
+    /// Commands that need the main thread, like /datapack and /reload
+    RunCommand { player_name: String, command: String },
+    BlockChanged { pos: BlockPos, block_state: i32 },
+    /// Loads the player's saved data, which comes back as `PlayerData`
+    PlayerLogin { uuid: Uuid },
+    /// The player finished configuration and can be shown to the others
+    PlayerJoined(TrackedPlayer),
+    /// Sent for every move, moving into another block could step into a portal
+    PlayerMoved { x: f64, y: f64, z: f64, on_ground: bool },
+    /// From /dimension, moves the player to the same position in another dimension
+    ChangeDimension { dimension: String },
+    /// The player looked somewhere else, shown to the other players
+    PlayerRotated { yaw: f32, pitch: f32, on_ground: bool },
+    /// From Player Command, changes how the other players see the player
+    PlayerFlags { sneaking: bool, sprinting: bool, fall_flying: bool },
+    ClientInformation { view_distance: i32, skin_parts: u8 },
+    /// Keep alive round trip time in milliseconds, for the tab list
+    Latency(i32),
+    /// From /gamemode, shown on the tab list
+    GameModeChanged(u8),
 }
 
 pub enum ServerConnectionThreadBound {
-    RegistryInfo { registry_id: String, entries: Vec<RegistryEntry> },
-    RegistryInfoFinished,
-    TagInfo(Vec<TagEntry>),
-    ChunkData(Option<Chunk>),
+    KnownPacks(Vec<KnownPack>),
+    RegistryInfo(Arc<Registries>),
+    TagInfo(Arc<Vec<TagEntry>>),
+    /// The tags after a reload, for the players that are already in the game
+    TagsReloaded(Arc<Vec<TagEntry>>),
+    ChunkData(Option<LoadedChunk>),
     ChatMessage { player_name: String, message: String, timestamp: i64, salt: i64, },
-}
\ No newline at end of file
+    SystemMessage(String),
+    /// A packet about a chunk, like Update Light, only sent if the player has the chunk
+    ChunkPacket { chunk_x: i32, chunk_z: i32, packet: Arc<Vec<u8>> },
+    /// Another player changed a block in the player's dimension
+    BlockChanged { pos: BlockPos, block_state: i32 },
+    /// A packet built by the main thread, like entity and tab list updates
+    PlayPacket(Arc<Vec<u8>>),
+    /// Respawns the player in another dimension at the given position
+    ChangeDimension { dimension: DimensionInfo, x: f64, y: f64, z: f64 },
+    /// Where and how the player left off, or where new players start
+    PlayerData { data: PlayerData, dimension: DimensionInfo },
+    /// Autosave, the connection writes its player's data
+    SavePlayerData,
+    /// The world's clocks, sent every second like vanilla does
+    Time { world_age: i64, time_of_day: i64 },
+}
@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use uuid::Uuid;
+use crate::packet::PlayPacketClientBound;
+
+/// Player Info Update actions, a bit each. The fields of an entry are written in this order.
+pub const ADD_PLAYER: u8 = 0x01;
+pub const INITIALIZE_CHAT: u8 = 0x02;
+pub const UPDATE_GAME_MODE: u8 = 0x04;
+pub const UPDATE_LISTED: u8 = 0x08;
+pub const UPDATE_LATENCY: u8 = 0x10;
+pub const UPDATE_DISPLAY_NAME: u8 = 0x20;
+
+/// A property of a game profile, like the `textures` holding the skin. Offline mode players have none.
+#[derive(Debug, Clone)]
+pub struct ProfileProperty {
+    pub name: String,
+    pub value: String,
+    pub signature: Option<String>,
+}
+
+/// A player on the tab list
+#[derive(Debug, Clone)]
+pub struct PlayerListEntry {
+    pub uuid: Uuid,
+    pub name: String,
+    pub properties: Vec<ProfileProperty>,
+    pub game_mode: u8,
+    pub listed: bool,
+    /// Round trip time of keep alives in milliseconds, which the client shows as bars
+    pub latency: i32,
+    /// Shown instead of the name
+    pub display_name: Option<String>,
+}
+
+impl PlayerListEntry {
+    pub fn new(uuid: Uuid, name: String, game_mode: u8) -> Self {
+        Self {
+            uuid,
+            name,
+            properties: vec![],
+            game_mode,
+            listed: true,
+            latency: 0,
+            display_name: None,
+        }
+    }
+}
+
+/// The players every client has in its player list, keyed by entity id. The list has to have a player before
+/// it can be spawned, so players are added to everyone's list when joining, no matter where they are.
+#[derive(Debug)]
+pub struct TabList {
+    entries: BTreeMap<i32, PlayerListEntry>,
+    header: String,
+    footer: String,
+    /// Players whose latency changed since the last `send_latency`
+    latency_changed: Vec<i32>,
+}
+
+impl TabList {
+    pub fn new(header: String, footer: String) -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            header,
+            footer,
+            latency_changed: vec![],
+        }
+    }
+
+    /// Puts a player that joined on everyone's list, and sends it the whole list with the header and footer.
+    /// `send` gets the eid of the player a packet is for.
+    pub fn add<F: FnMut(i32, Arc<Vec<u8>>)>(&mut self, eid: i32, entry: PlayerListEntry, mut send: F) {
+        let actions = ADD_PLAYER | UPDATE_GAME_MODE | UPDATE_LISTED | UPDATE_LATENCY | UPDATE_DISPLAY_NAME;
+        let added = Arc::new(PlayPacketClientBound::player_info_update(actions, &[&entry]));
+        for other in self.entries.keys() {
+            send(*other, added.clone());
+        }
+        self.entries.insert(eid, entry);
+        send(eid, Arc::new(PlayPacketClientBound::player_info_update(actions, &self.entries.values().collect::<Vec<_>>())));
+        if !self.header.is_empty() || !self.footer.is_empty() {
+            send(eid, Arc::new(PlayPacketClientBound::set_tab_list_header_and_footer(&self.header, &self.footer)));
+        }
+    }
+
+    /// Takes a player that left off everyone's list
+    pub fn remove<F: FnMut(i32, Arc<Vec<u8>>)>(&mut self, eid: i32, mut send: F) {
+        let Some(entry) = self.entries.remove(&eid) else {
+            return;
+        };
+        self.latency_changed.retain(|changed| *changed != eid);
+        let removed = Arc::new(PlayPacketClientBound::player_info_remove(&[entry.uuid]));
+        for other in self.entries.keys() {
+            send(*other, removed.clone());
+        }
+    }
+
+    /// Shows everyone the player's new game mode, spectators are listed greyed out at the bottom
+    pub fn set_game_mode<F: FnMut(i32, Arc<Vec<u8>>)>(&mut self, eid: i32, game_mode: u8, mut send: F) {
+        let Some(entry) = self.entries.get_mut(&eid) else {
+            return;
+        };
+        entry.game_mode = game_mode;
+        let packet = Arc::new(PlayPacketClientBound::player_info_update(UPDATE_GAME_MODE, &[entry]));
+        for other in self.entries.keys() {
+            send(*other, packet.clone());
+        }
+    }
+
+    /// Shows everyone the player under a new name, `None` goes back to the player's own name
+    pub fn set_display_name<F: FnMut(i32, Arc<Vec<u8>>)>(&mut self, eid: i32, display_name: Option<String>, mut send: F) {
+        let Some(entry) = self.entries.get_mut(&eid) else {
+            return;
+        };
+        entry.display_name = display_name;
+        let packet = Arc::new(PlayPacketClientBound::player_info_update(UPDATE_DISPLAY_NAME, &[entry]));
+        for other in self.entries.keys() {
+            send(*other, packet.clone());
+        }
+    }
+
+    /// Stores a new latency, which is sent with the next `send_latency`
+    pub fn set_latency(&mut self, eid: i32, latency: i32) {
+        if let Some(entry) = self.entries.get_mut(&eid) {
+            entry.latency = latency;
+            if !self.latency_changed.contains(&eid) {
+                self.latency_changed.push(eid);
+            }
+        }
+    }
+
+    /// Sends the latencies that changed to everyone in one packet. Vanilla does this every 30 seconds.
+    pub fn send_latency<F: FnMut(i32, Arc<Vec<u8>>)>(&mut self, mut send: F) {
+        if self.latency_changed.is_empty() {
+            return;
+        }
+        let changed = self.latency_changed.drain(..).filter_map(|eid| self.entries.get(&eid)).collect::<Vec<_>>();
+        let packet = Arc::new(PlayPacketClientBound::player_info_update(UPDATE_LATENCY, &changed));
+        for eid in self.entries.keys() {
+            send(*eid, packet.clone());
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use log::{error, trace, warn};
+use serde::Deserialize;
+use crate::error::ServerError;
+use crate::server_util::{TagEntry, TagEntryData};
+
+/// Maps identifiers to the numeric ids the client uses for a registry
+pub trait RegistryIdLookup {
+    fn has_registry(&self, registry: &str) -> bool;
+    fn id_of(&self, registry: &str, identifier: &str) -> Option<i32>;
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum TagFileValue {
+    Plain(String),
+    Detailed {
+        id: String,
+        #[serde(default = "required_default")]
+        required: bool,
+    },
+}
+
+fn required_default() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TagFile {
+    #[serde(default)]
+    replace: bool,
+    values: Vec<TagFileValue>,
+}
+
+#[derive(Debug, Clone)]
+struct TagValue {
+    id: String,
+    is_tag: bool,
+    required: bool,
+}
+
+impl From<TagFileValue> for TagValue {
+    fn from(value: TagFileValue) -> Self {
+        let (id, required) = match value {
+            TagFileValue::Plain(id) => (id, true),
+            TagFileValue::Detailed { id, required } => (id, required),
+        };
+        match id.strip_prefix('#') {
+            Some(tag) => Self { id: full_identifier(tag), is_tag: true, required },
+            None => Self { id: full_identifier(&id), is_tag: false, required },
+        }
+    }
+}
+
+fn full_identifier(id: &str) -> String {
+    if id.contains(':') {
+        id.to_string()
+    } else {
+        "minecraft:".to_string() + id
+    }
+}
+
+/// Collects tag definitions from `data/<namespace>/tags/<registry>/**.json` and resolves them into the
+/// numeric lists sent in Update Tags. Packs loaded later override earlier ones.
+pub struct TagLoader {
+    /// Registry name -> tag identifier -> values, before nested tags are resolved
+    definitions: BTreeMap<String, BTreeMap<String, Vec<TagValue>>>,
+}
+
+impl TagLoader {
+    pub fn new() -> Self {
+        Self {
+            definitions: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the tags from a data pack's files (relative to its `data` directory), on top of the packs loaded before it
+    pub fn load_pack_files(&mut self, files: &[(PathBuf, Vec<u8>)]) -> Result<(), ServerError> {
+        for (relative_path, data) in files {
+            let parts = relative_path.iter().map(|p| p.to_str().unwrap()).collect::<Vec<_>>();
+            // namespace, "tags", registry (1 or 2 parts), tag path
+            if parts.len() < 3 || parts[1] != "tags" {
+                continue;
+            }
+            let namespace = parts[0];
+            let registry_len = if parts[2] == "worldgen" { 2 } else { 1 };
+            if parts.len() < 3 + registry_len + 1 {
+                continue;
+            }
+            let registry = parts[2..2 + registry_len].join("/");
+            let mut tag_path = parts[2 + registry_len..].join("/");
+            tag_path.truncate(tag_path.len() - ".json".len());
+            let tag_id = format!("{namespace}:{tag_path}");
+            trace!("[{}] tag #{}", registry, tag_id);
+
+            let file: TagFile = serde_json::from_slice(data)?;
+            let values = self.definitions.entry(registry).or_default().entry(tag_id).or_default();
+            if file.replace {
+                values.clear();
+            }
+            values.extend(file.values.into_iter().map(TagValue::from));
+        }
+        Ok(())
+    }
+
+    pub fn resolve<L: RegistryIdLookup>(&self, lookup: &L) -> Vec<TagEntry> {
+        let mut entries = vec![];
+        for (registry, definitions) in &self.definitions {
+            if !lookup.has_registry(registry) {
+                trace!("Skipping tags for registry {} which the client doesn't know", registry);
+                continue;
+            }
+            let mut resolved = BTreeMap::new();
+            let mut data = vec![];
+            for tag in definitions.keys() {
+                if let Some(ids) = Self::resolve_tag(registry, tag, definitions, lookup, &mut resolved, &mut BTreeSet::new()) {
+                    data.push(TagEntryData {
+                        entries: ids,
+                        tag_name: tag.clone(),
+                    });
+                }
+            }
+            entries.push(TagEntry {
+                id: full_identifier(registry),
+                data,
+            });
+        }
+        entries
+    }
+
+    /// Returns `None` if the tag can't be loaded, which happens for cycles and missing required values
+    fn resolve_tag<L: RegistryIdLookup>(registry: &str, tag: &str, definitions: &BTreeMap<String, Vec<TagValue>>, lookup: &L, resolved: &mut BTreeMap<String, Option<Vec<i32>>>, visiting: &mut BTreeSet<String>) -> Option<Vec<i32>> {
+        if let Some(ids) = resolved.get(tag) {
+            return ids.clone();
+        }
+        if !visiting.insert(tag.to_string()) {
+            error!("[{}] Tag #{} references itself", registry, tag);
+            return None;
+        }
+
+        let mut ids = vec![];
+        let mut failed = false;
+        for value in definitions.get(tag).into_iter().flatten() {
+            let value_ids = if value.is_tag {
+                if definitions.contains_key(&value.id) {
+                    Self::resolve_tag(registry, &value.id, definitions, lookup, resolved, visiting)
+                } else {
+                    None
+                }
+            } else {
+                lookup.id_of(registry, &value.id).map(|id| vec![id])
+            };
+            match value_ids {
+                Some(value_ids) => {
+                    for id in value_ids {
+                        if !ids.contains(&id) {
+                            ids.push(id);
+                        }
+                    }
+                }
+                None if value.required => {
+                    warn!("[{}] Tag #{} is missing required value {}{}", registry, tag, if value.is_tag { "#" } else { "" }, value.id);
+                    failed = true;
+                    break;
+                }
+                None => {}
+            }
+        }
+
+        visiting.remove(tag);
+        let ids = if failed { None } else { Some(ids) };
+        resolved.insert(tag.to_string(), ids.clone());
+        ids
+    }
+}
@@ -0,0 +1,56 @@
+//! Fixtures shared by the integration tests, each of which only uses some of them
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use mc_server::item::ItemRegistry;
+
+pub const STONE: i32 = 1;
+pub const DIAMOND: i32 = 800;
+pub const DIAMOND_SWORD: i32 = 812;
+pub const DIAMOND_HELMET: i32 = 816;
+pub const SHIELD: i32 = 1155;
+pub const SHARPNESS: i32 = 5;
+pub const ATTACK_DAMAGE: i32 = 2;
+pub const SWIFTNESS: i32 = 11;
+pub const GOLD: i32 = 3;
+pub const COAST: i32 = 1;
+
+pub fn varint(value: i32) -> Vec<u8> {
+    let mut value = value as u32;
+    let mut bytes = vec![];
+    loop {
+        if value < 0x80 {
+            bytes.push(value as u8);
+            return bytes;
+        }
+        bytes.push(value as u8 & 0x7F | 0x80);
+        value >>= 7;
+    }
+}
+
+/// Frames a packet the way the client sends it: length, id, then the fields
+pub fn packet(id: i32, fields: &[u8]) -> Vec<u8> {
+    let mut body = varint(id);
+    body.extend_from_slice(fields);
+    let mut packet = varint(body.len() as i32);
+    packet.append(&mut body);
+    packet
+}
+
+/// The registries the tests' item stacks refer to
+pub fn items() -> ItemRegistry {
+    let registry = |entries: &[(&str, i32)]| entries.iter().map(|(name, id)| (name.to_string(), *id)).collect::<BTreeMap<_, _>>();
+    ItemRegistry::new(&registry(&[
+        ("minecraft:stone", STONE),
+        ("minecraft:diamond", DIAMOND),
+        ("minecraft:diamond_sword", DIAMOND_SWORD),
+        ("minecraft:diamond_helmet", DIAMOND_HELMET),
+        ("minecraft:shield", SHIELD),
+    ]))
+        .with_max_stack_sizes(&registry(&[("minecraft:stone", 64), ("minecraft:diamond_helmet", 1)]))
+        .with_enchantments(&registry(&[("minecraft:sharpness", SHARPNESS)]))
+        .with_registry("attribute", &registry(&[("minecraft:generic.attack_damage", ATTACK_DAMAGE)]))
+        .with_registry("potion", &registry(&[("minecraft:swiftness", SWIFTNESS)]))
+        .with_registry("trim_material", &registry(&[("minecraft:gold", GOLD)]))
+        .with_registry("trim_pattern", &registry(&[("minecraft:coast", COAST)]))
+}
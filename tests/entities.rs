@@ -0,0 +1,199 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use inbt::NbtTag;
+use mc_server::collision::Aabb;
+use mc_server::entity::{next_entity_id, Entity, EntityMetadata, MetadataValue};
+use mc_server::entity_index::EntityIndex;
+use mc_server::entity_storage::EntityStorage;
+use mc_server::item::ItemRegistry;
+use mc_server::nbt_util::NbtTagExt;
+use mc_server::region::RegionStorage;
+
+fn entity_types() -> BTreeMap<String, i32> {
+    BTreeMap::from([("minecraft:armor_stand".to_string(), 5), ("minecraft:item_frame".to_string(), 57), ("minecraft:piglin".to_string(), 84)])
+}
+
+#[test]
+fn entity_ids_are_unique() {
+    let types = entity_types();
+    let ids = (0..100).map(|_| Entity::new("minecraft:armor_stand", &types, 0.0, 0.0, 0.0).unwrap().id).collect::<Vec<_>>();
+    let player_id = next_entity_id();
+    assert!(ids.windows(2).all(|ids| ids[0] < ids[1]));
+    assert!(ids.iter().all(|id| *id < player_id));
+    assert!(Entity::new("minecraft:not_an_entity", &types, 0.0, 0.0, 0.0).is_none());
+}
+
+#[test]
+fn metadata_packets() {
+    let mut metadata = EntityMetadata::new();
+    metadata.set(0, MetadataValue::Byte(0x20));
+    metadata.set(8, MetadataValue::OptionalVarInt(Some(4)));
+    metadata.set(15, MetadataValue::Rotations(0.0, 1.0, 0.0));
+    // Length, packet id, entity id, then index, type and value of each entry, ending with 0xFF
+    assert_eq!(metadata.changed_packet(3).unwrap(), vec![
+        23, 0x58, 3,
+        0, 0, 0x20,
+        8, 20, 5,
+        15, 9, 0, 0, 0, 0, 0x3F, 0x80, 0, 0, 0, 0, 0, 0,
+        0xFF,
+    ]);
+    assert!(metadata.changed_packet(3).is_none());
+
+    // Setting the same value again doesn't count as a change
+    metadata.set(0, MetadataValue::Byte(0x20));
+    assert!(metadata.changed_packet(3).is_none());
+    metadata.set(8, MetadataValue::OptionalVarInt(None));
+    assert_eq!(metadata.changed_packet(3).unwrap(), vec![6, 0x58, 3, 8, 20, 0, 0xFF]);
+    assert_eq!(metadata.all_packet(3).unwrap().len(), 24);
+}
+
+#[test]
+fn spatial_index() {
+    let types = entity_types();
+    let mut index = EntityIndex::new();
+    let positions = [(0.5, 64.0, 0.5), (15.5, 64.0, 15.5), (-0.5, 70.0, 0.5), (100.0, 64.0, -100.0)];
+    let ids = positions.iter()
+        .map(|(x, y, z)| {
+            let entity = Entity::new("minecraft:item_frame", &types, *x, *y, *z).unwrap();
+            let id = entity.id;
+            index.insert(entity);
+            id
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(index.len(), 4);
+
+    let ids_of = |entities: Vec<&Entity>| {
+        let mut found = entities.iter().map(|entity| entity.id).collect::<Vec<_>>();
+        found.sort();
+        found
+    };
+    assert_eq!(ids_of(index.in_chunk(0, 0).collect()), vec![ids[0], ids[1]]);
+    assert_eq!(ids_of(index.in_chunk_range(0, 0, 1).collect()), vec![ids[0], ids[1], ids[2]]);
+    assert_eq!(ids_of(index.within(0.0, 64.0, 0.0, 2.0).collect()), vec![ids[0]]);
+    assert_eq!(ids_of(index.in_box(&Aabb::new(-1.0, 60.0, -1.0, 16.0, 80.0, 16.0)).collect()), vec![ids[0], ids[1], ids[2]]);
+
+    // Moving into another chunk updates the index
+    index.move_to(ids[3], 0.0, 64.0, 0.0);
+    assert_eq!(ids_of(index.in_chunk(0, 0).collect()), vec![ids[0], ids[1], ids[3]]);
+    assert_eq!(index.in_chunk(6, -7).count(), 0);
+
+    assert!(index.remove(ids[0]).is_some());
+    assert!(index.remove(ids[0]).is_none());
+    assert_eq!(ids_of(index.in_chunk(0, 0).collect()), vec![ids[1], ids[3]]);
+}
+
+fn saved_item_frame() -> NbtTag {
+    let double = |value| NbtTag::Double("".to_string(), value);
+    NbtTag::Compound("".to_string(), vec![
+        NbtTag::String("id".to_string(), "minecraft:item_frame".to_string()),
+        NbtTag::List("Pos".to_string(), vec![double(3.5), double(64.5), double(-0.03125)]),
+        NbtTag::List("Rotation".to_string(), vec![NbtTag::Float("".to_string(), 180.0), NbtTag::Float("".to_string(), 0.0)]),
+        NbtTag::IntArray("UUID".to_string(), vec![1, 2, 3, 4]),
+        NbtTag::Byte("Facing".to_string(), 2),
+        NbtTag::Byte("ItemRotation".to_string(), 3),
+        NbtTag::Compound("Item".to_string(), vec![
+            NbtTag::String("id".to_string(), "minecraft:diamond".to_string()),
+            NbtTag::Int("count".to_string(), 1),
+        ]),
+        NbtTag::String("CustomName".to_string(), r#"{"text":"Shiny ","extra":["diamond"]}"#.to_string()),
+        NbtTag::Int("TileX".to_string(), 3),
+    ])
+}
+
+#[test]
+fn entities_from_nbt() {
+    let items = common::items();
+    let entity = Entity::from_nbt(&saved_item_frame(), &entity_types(), &items).unwrap();
+    assert_eq!((entity.x, entity.y, entity.z), (3.5, 64.5, -0.03125));
+    assert_eq!(entity.uuid.as_u64_pair(), (1 << 32 | 2, 3 << 32 | 4));
+    assert_eq!((entity.type_id, entity.spawn_data, entity.yaw), (57, 2, 180.0));
+    assert!(matches!(entity.metadata.get(8), Some(MetadataValue::Slot(item)) if !item.is_empty()));
+    assert_eq!(entity.metadata.get(9), Some(&MetadataValue::VarInt(3)));
+    assert_eq!(entity.metadata.get(2), Some(&MetadataValue::OptionalTextComponent(Some("Shiny diamond".to_string()))));
+
+    // Saving keeps what the server doesn't know about
+    let saved = entity.to_nbt();
+    assert_eq!(saved.child("TileX").and_then(|x| x.as_i32()), Some(3));
+    assert_eq!(saved.child("UUID").and_then(|u| u.as_int_array()), Some(&vec![1, 2, 3, 4]));
+    assert!(Entity::from_nbt(&NbtTag::Compound("".to_string(), vec![]), &entity_types(), &items).is_none());
+
+    // Piglins keep their baby flag one index later than other mobs
+    let mut piglin = saved_item_frame();
+    piglin.set_child(NbtTag::String("id".to_string(), "minecraft:piglin".to_string()));
+    piglin.set_child(NbtTag::Byte("PersistenceRequired".to_string(), 0));
+    piglin.set_child(NbtTag::Byte("IsBaby".to_string(), 1));
+    let piglin = Entity::from_nbt(&piglin, &entity_types(), &items).unwrap();
+    assert_eq!(piglin.metadata.get(17), Some(&MetadataValue::Boolean(true)));
+    assert_eq!(piglin.metadata.get(16), None);
+}
+
+#[test]
+fn region_files() {
+    let dir = std::env::temp_dir().join(format!("mc_server_region_test_{}", std::process::id()));
+    let regions = RegionStorage::new(&dir);
+    assert!(regions.read_chunk(0, 0).unwrap().is_none());
+    // Vanilla leaves empty region files behind
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("r.0.0.mca"), b"").unwrap();
+    assert!(regions.read_chunk(0, 0).unwrap().is_none());
+
+    let chunk = |entities| NbtTag::Compound("".to_string(), vec![NbtTag::List("Entities".to_string(), entities)]);
+    regions.write_chunk(0, 0, &chunk(vec![saved_item_frame()])).unwrap();
+    regions.write_chunk(-1, 31, &chunk(vec![])).unwrap();
+    // Noise doesn't compress, so this doesn't fit into the chunk's old sector any more
+    let mut noise = chunk(vec![saved_item_frame(); 100]);
+    noise.set_child(NbtTag::ByteArray("Noise".to_string(), (0..20000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as i8).collect()));
+    regions.write_chunk(0, 0, &noise).unwrap();
+
+    let entities = |x, z| regions.read_chunk(x, z).unwrap().unwrap().child("Entities").and_then(|e| e.as_list()).unwrap().len();
+    assert_eq!(entities(0, 0), 100);
+    assert_eq!(entities(-1, 31), 0);
+    assert!(regions.read_chunk(1, 0).unwrap().is_none());
+
+    // The sector chunk 0 0 moved out of is used again
+    let length = fs::metadata(dir.join("r.0.0.mca")).unwrap().len();
+    regions.write_chunk(1, 0, &chunk(vec![saved_item_frame()])).unwrap();
+    assert_eq!(fs::metadata(dir.join("r.0.0.mca")).unwrap().len(), length);
+    assert_eq!(entities(1, 0), 1);
+    assert_eq!(entities(0, 0), 100);
+    assert_eq!(entities(-1, 31), 0);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn entity_storage() {
+    let dir = std::env::temp_dir().join(format!("mc_server_entity_storage_test_{}", std::process::id()));
+    let regions = RegionStorage::new(&dir);
+    let chunk = |entities| NbtTag::Compound("".to_string(), vec![NbtTag::List("Entities".to_string(), entities)]);
+    regions.write_chunk(0, 0, &chunk(vec![saved_item_frame()])).unwrap();
+    let items = Arc::new(common::items());
+    let mut storage = EntityStorage::new(regions.clone(), Arc::new(entity_types()), items);
+
+    storage.request_chunk(0, 0);
+    let started = Instant::now();
+    while !storage.is_loaded(0, 0) {
+        assert!(started.elapsed() < Duration::from_secs(5), "Chunk wasn't loaded");
+        storage.receive_chunks();
+    }
+    assert_eq!(storage.entities().in_chunk(0, 0).count(), 1);
+
+    // Chunks nothing changed in aren't written back, so this stays
+    regions.write_chunk(0, 0, &chunk(vec![])).unwrap();
+    storage.unload_chunk(0, 0).unwrap();
+    let entities = |x, z| regions.read_chunk(x, z).unwrap().unwrap().child("Entities").and_then(|e| e.as_list()).unwrap().len();
+    assert_eq!(entities(0, 0), 0);
+
+    // Changed ones are
+    storage.load_chunk(1, 0);
+    let mut frame = Entity::from_nbt(&saved_item_frame(), &entity_types(), &ItemRegistry::default()).unwrap();
+    (frame.x, frame.z) = (20.5, 0.5);
+    storage.entities_mut().insert(frame);
+    storage.unload_chunk(1, 0).unwrap();
+    assert_eq!(entities(1, 0), 1);
+    assert!(!storage.is_loaded(1, 0));
+    fs::remove_dir_all(&dir).unwrap();
+}
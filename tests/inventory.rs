@@ -0,0 +1,99 @@
+mod common;
+
+use inbt::NbtTag;
+use mc_server::inventory::{self, PlayerInventory};
+use mc_server::nbt_util::NbtTagExt;
+use mc_server::packet::{PlayPacketServerBound, Slot};
+use common::{items, packet};
+
+fn saved_item(slot: i8, id: &str, count: i32) -> NbtTag {
+    NbtTag::Compound("".to_string(), vec![
+        NbtTag::Byte("Slot".to_string(), slot),
+        NbtTag::String("id".to_string(), id.to_string()),
+        NbtTag::Int("count".to_string(), count),
+    ])
+}
+
+#[test]
+fn saved_inventory() {
+    let saved = vec![
+        saved_item(0, "minecraft:stone", 64),
+        saved_item(9, "minecraft:stone", 3),
+        saved_item(103, "minecraft:diamond_helmet", 1),
+        saved_item(-106, "minecraft:shield", 1),
+        saved_item(1, "minecraft:not_an_item", 1),
+    ];
+    let mut inventory = PlayerInventory::from_nbt(&saved, 4, &items());
+    assert_eq!(inventory.get(inventory::HOTBAR_START).map(|s| (s.item_id(), s.count())), Some((Some(1), 64)));
+    assert_eq!(inventory.get(inventory::MAIN_START).map(|s| s.count()), Some(3));
+    assert_eq!(inventory.get(inventory::ARMOR_START).and_then(|s| s.item_id()), Some(816));
+    assert_eq!(inventory.get(inventory::OFF_HAND).and_then(|s| s.item_id()), Some(1155));
+    assert!(inventory.get(inventory::HOTBAR_START + 1).unwrap().is_empty());
+    assert_eq!(inventory.held_slot(), inventory::HOTBAR_START + 4);
+
+    assert!(inventory.set(inventory::HOTBAR_START + 4, Slot::new(1, 2)));
+    assert!(!inventory.set(inventory::SLOT_COUNT, Slot::new(1, 2)));
+    assert!(!inventory.set_selected_slot(9));
+    let slots = inventory.to_nbt(&items()).iter()
+        .map(|item| (item.child("Slot").and_then(|s| s.as_i8()).unwrap(), item.child("count").and_then(|c| c.as_i32()).unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(slots, vec![(103, 1), (9, 3), (0, 64), (4, 2), (-106, 1)]);
+}
+
+#[test]
+fn state_ids() {
+    let mut inventory = PlayerInventory::new();
+    let content = inventory.content_packet();
+    // Length, packet id, window id, state id, slot count, 46 empty slots and an empty cursor
+    assert_eq!(&content[..5], &[51, 0x13, 0, 1, 46]);
+    assert_eq!(content.len(), 52);
+    assert_eq!(inventory.slot_packet(inventory::OFF_HAND).unwrap(), vec![6, 0x15, 0, 2, 0, 45, 0]);
+    assert!(inventory.slot_packet(inventory::SLOT_COUNT).is_none());
+    assert_eq!(inventory.state_id(), 2);
+}
+
+#[test]
+fn click_container() {
+    // Window 0, state id 5, slot 36, button 0, mode 0, one changed slot: 36 becomes empty, then 64 stone on the cursor
+    let fields = [0, 5, 0, 36, 0, 0, 1, 0, 36, 0, 64, 1, 0, 0];
+    match PlayPacketServerBound::parse(packet(0x0E, &fields)).unwrap() {
+        PlayPacketServerBound::ClickContainer { window_id, state_id, slot, changed_slots, carried_item, .. } => {
+            assert_eq!((window_id, state_id, slot), (0, 5, 36));
+            assert_eq!(changed_slots.len(), 1);
+            assert!(changed_slots[0].0 == 36 && changed_slots[0].1.is_empty());
+            assert_eq!((carried_item.item_id(), carried_item.count()), (Some(1), 64));
+        }
+        other => panic!("Parsed as {other:?}"),
+    }
+}
+
+#[test]
+fn checked_clicks() {
+    let mut inventory = PlayerInventory::new();
+    inventory.set(inventory::HOTBAR_START, Slot::new(1, 64));
+    inventory.set(inventory::ARMOR_START, Slot::new(816, 1));
+
+    // Picking up half the stack
+    assert!(inventory.apply_click(vec![(36, Slot::new(1, 32))], Slot::new(1, 32), &items(), false));
+    assert_eq!(inventory.cursor().count(), 32);
+    // Putting it down twice over
+    assert!(!inventory.apply_click(vec![(37, Slot::new(1, 64))], Slot::empty(), &items(), false));
+    // Dropping it, which isn't possible yet
+    assert!(!inventory.apply_click(vec![], Slot::empty(), &items(), false));
+    // Turning it into another item
+    assert!(!inventory.apply_click(vec![(37, Slot::new(1155, 32))], Slot::empty(), &items(), false));
+    // Slots that don't exist, or twice in one click
+    assert!(!inventory.apply_click(vec![(-999, Slot::new(1, 32))], Slot::empty(), &items(), false));
+    assert!(!inventory.apply_click(vec![(37, Slot::new(1, 32)), (37, Slot::empty())], Slot::empty(), &items(), false));
+    assert_eq!(inventory.get(37).map(|s| s.is_empty()), Some(true));
+    assert_eq!(inventory.cursor().count(), 32);
+
+    // Merging the halves back into a full stack, but not past it
+    assert!(!inventory.apply_click(vec![(36, Slot::new(1, 65))], Slot::new(1, -1), &items(), false));
+    assert!(inventory.apply_click(vec![(36, Slot::new(1, 64))], Slot::empty(), &items(), false));
+    // Helmets don't stack
+    assert!(!inventory.apply_click(vec![(5, Slot::new(816, 2)), (36, Slot::new(1, 63))], Slot::empty(), &items(), true));
+    // Creative mode can clone stacks
+    assert!(inventory.apply_click(vec![], Slot::new(1, 64), &items(), true));
+    assert_eq!(inventory.get(36).map(|s| s.count()), Some(64));
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use inbt::NbtTag;
+use mc_server::item_component::{self, AttributeModifier, FireworkExplosion, ItemComponent};
+use mc_server::nbt_util::NbtTagExt;
+use mc_server::packet::{PlayPacketClientBound, PlayPacketServerBound, Slot};
+use common::{items, packet, varint, ATTACK_DAMAGE, COAST, DIAMOND_SWORD as SWORD, GOLD, SHARPNESS, SWIFTNESS};
+
+const ATTRIBUTE_MODIFIERS: i32 = 12;
+
+/// The Slot in a Set Container Slot packet, sent back as Set Creative Mode Slot
+fn send_back(item: &Slot) -> Slot {
+    let packet = PlayPacketClientBound::set_container_slot(0, 1, 36, item);
+    // Length, packet id, window id, state id and slot number
+    assert!(packet[0] < 0x80);
+    let mut fields = vec![0, 36];
+    fields.extend_from_slice(&packet[6..]);
+    match PlayPacketServerBound::parse(common::packet(0x32, &fields)).unwrap() {
+        PlayPacketServerBound::SetCreativeModeSlot { slot, clicked_item } => {
+            assert_eq!(slot, 36);
+            clicked_item
+        }
+        other => panic!("Parsed as {other:?}"),
+    }
+}
+
+#[test]
+fn network_components() {
+    let lore = NbtTag::Compound("".to_string(), vec![
+        NbtTag::String("text".to_string(), "Sharp".to_string()),
+        NbtTag::String("color".to_string(), "gold".to_string()),
+    ]);
+    let sword = Slot::new(SWORD, 1)
+        .with_component(ItemComponent::CustomName(NbtTag::String("".to_string(), "Sword".to_string())))
+        .with_component(ItemComponent::Lore(vec![lore]))
+        .with_component(ItemComponent::Enchantments { levels: vec![(SHARPNESS, 3)], show_in_tooltip: true })
+        .with_component(ItemComponent::Damage(12))
+        .without_component(ATTRIBUTE_MODIFIERS);
+    assert_eq!(send_back(&sword), sword);
+
+    // Saved only components stay on the server
+    let locked = sword.clone().with_component(ItemComponent::Saved(NbtTag::String("minecraft:lock".to_string(), "key".to_string())));
+    assert_eq!(send_back(&locked), sword);
+}
+
+#[test]
+fn decoded_components() {
+    // A food component: nutrition, saturation, can always eat, eat seconds, no leftover item and no effects
+    let mut food = vec![4];
+    food.extend_from_slice(&0.3f32.to_be_bytes());
+    food.push(0);
+    food.extend_from_slice(&1.6f32.to_be_bytes());
+    food.extend_from_slice(&[0, 0]);
+
+    let mut fields = vec![0, 36, 1];
+    fields.extend(varint(SWORD));
+    fields.extend_from_slice(&[1, 0, 20]);
+    fields.extend_from_slice(&food);
+    let item = match PlayPacketServerBound::parse(packet(0x32, &fields)).unwrap() {
+        PlayPacketServerBound::SetCreativeModeSlot { clicked_item, .. } => clicked_item,
+        other => panic!("Parsed as {other:?}"),
+    };
+    assert_eq!(item.components(), &[ItemComponent::Food {
+        nutrition: 4,
+        saturation: 0.3,
+        can_always_eat: false,
+        eat_seconds: 1.6,
+        using_converts_to: None,
+        effects: vec![],
+    }]);
+    assert_eq!(send_back(&item), item);
+
+    // Unknown component types can't be skipped
+    assert!(PlayPacketServerBound::parse(packet(0x32, &[0, 36, 1, 1, 1, 0, 100])).is_err());
+}
+
+#[test]
+fn saved_components() {
+    let saved = NbtTag::Compound("".to_string(), vec![
+        NbtTag::String("id".to_string(), "minecraft:diamond_sword".to_string()),
+        NbtTag::Int("count".to_string(), 1),
+        NbtTag::Compound("components".to_string(), vec![
+            NbtTag::String("minecraft:custom_name".to_string(), "\"Sword\"".to_string()),
+            NbtTag::List("minecraft:lore".to_string(), vec![NbtTag::String("".to_string(), "{\"text\":\"Sharp\"}".to_string())]),
+            NbtTag::Compound("minecraft:enchantments".to_string(), vec![
+                NbtTag::Compound("levels".to_string(), vec![NbtTag::Int("minecraft:sharpness".to_string(), 5)]),
+            ]),
+            NbtTag::Int("minecraft:damage".to_string(), 3),
+            NbtTag::String("minecraft:base_color".to_string(), "light_blue".to_string()),
+            NbtTag::Compound("minecraft:food".to_string(), vec![NbtTag::Int("nutrition".to_string(), 4)]),
+            NbtTag::Compound("!minecraft:attribute_modifiers".to_string(), vec![]),
+        ]),
+    ]);
+    let sword = Slot::from_nbt(&saved, &items());
+    assert_eq!(sword.components(), &[
+        ItemComponent::CustomName(NbtTag::String("".to_string(), "Sword".to_string())),
+        ItemComponent::Lore(vec![NbtTag::Compound("".to_string(), vec![NbtTag::String("text".to_string(), "Sharp".to_string())])]),
+        ItemComponent::Enchantments { levels: vec![(SHARPNESS, 5)], show_in_tooltip: true },
+        ItemComponent::Damage(3),
+        ItemComponent::BaseColor(3),
+        ItemComponent::Saved(NbtTag::Compound("minecraft:food".to_string(), vec![NbtTag::Int("nutrition".to_string(), 4)])),
+    ]);
+    assert_eq!(sword.removed_components(), &[ATTRIBUTE_MODIFIERS]);
+    assert_eq!(item_component::component_type_id("minecraft:attribute_modifiers"), Some(ATTRIBUTE_MODIFIERS));
+
+    let nbt = sword.to_nbt(&items()).unwrap();
+    let components = nbt.child("components").unwrap();
+    assert_eq!(components.child("minecraft:custom_name").and_then(|n| n.as_str()), Some("\"Sword\""));
+    assert_eq!(components.child("minecraft:food").and_then(|f| f.child("nutrition")).and_then(|n| n.as_i32()), Some(4));
+    assert!(components.child("!minecraft:attribute_modifiers").is_some());
+    assert_eq!(Slot::from_nbt(&nbt, &items()), sword);
+}
+
+#[test]
+fn registry_components() {
+    let saved = NbtTag::Compound("".to_string(), vec![
+        NbtTag::String("id".to_string(), "minecraft:diamond_sword".to_string()),
+        NbtTag::Int("count".to_string(), 1),
+        NbtTag::Compound("components".to_string(), vec![
+            NbtTag::String("minecraft:potion_contents".to_string(), "minecraft:swiftness".to_string()),
+            NbtTag::Compound("minecraft:trim".to_string(), vec![
+                NbtTag::String("material".to_string(), "minecraft:gold".to_string()),
+                NbtTag::String("pattern".to_string(), "minecraft:coast".to_string()),
+            ]),
+            NbtTag::List("minecraft:attribute_modifiers".to_string(), vec![NbtTag::Compound("".to_string(), vec![
+                NbtTag::String("type".to_string(), "minecraft:generic.attack_damage".to_string()),
+                NbtTag::String("id".to_string(), "minecraft:base_attack_damage".to_string()),
+                NbtTag::Double("amount".to_string(), 5.0),
+                NbtTag::String("operation".to_string(), "add_value".to_string()),
+                NbtTag::String("slot".to_string(), "mainhand".to_string()),
+            ])]),
+            NbtTag::Compound("minecraft:fireworks".to_string(), vec![
+                NbtTag::Byte("flight_duration".to_string(), 2),
+                NbtTag::List("explosions".to_string(), vec![NbtTag::Compound("".to_string(), vec![
+                    NbtTag::String("shape".to_string(), "star".to_string()),
+                    NbtTag::IntArray("colors".to_string(), vec![0xff0000]),
+                ])]),
+            ]),
+            NbtTag::String("minecraft:profile".to_string(), "Notch".to_string()),
+            // Not in the fixture's registries
+            NbtTag::String("minecraft:instrument".to_string(), "minecraft:ponder_goat_horn".to_string()),
+        ]),
+    ]);
+    let sword = Slot::from_nbt(&saved, &items());
+    assert_eq!(sword.components(), &[
+        ItemComponent::PotionContents { potion: Some(SWIFTNESS), custom_color: None, custom_effects: vec![] },
+        ItemComponent::Trim { material: GOLD, pattern: COAST, show_in_tooltip: true },
+        ItemComponent::AttributeModifiers {
+            modifiers: vec![AttributeModifier {
+                attribute: ATTACK_DAMAGE,
+                id: "minecraft:base_attack_damage".to_string(),
+                amount: 5.0,
+                operation: 0,
+                slot: 1,
+            }],
+            show_in_tooltip: true,
+        },
+        ItemComponent::Fireworks {
+            flight_duration: 2,
+            explosions: vec![FireworkExplosion { shape: 2, colors: vec![0xff0000], fade_colors: vec![], has_trail: false, has_twinkle: false }],
+        },
+        ItemComponent::Profile { name: Some("Notch".to_string()), id: None, properties: vec![] },
+        ItemComponent::Saved(NbtTag::String("minecraft:instrument".to_string(), "minecraft:ponder_goat_horn".to_string())),
+    ]);
+
+    // Everything but the unknown instrument reaches the client, and all of it is saved again
+    let sent = sword.components()[..5].iter().fold(Slot::new(SWORD, 1), |slot, c| slot.with_component(c.clone()));
+    assert_eq!(send_back(&sword), sent);
+    assert_eq!(Slot::from_nbt(&sword.to_nbt(&items()).unwrap(), &items()), sword);
+}
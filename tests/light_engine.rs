@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use mc_server::block_registry::BlockRegistry;
+use mc_server::block_storage::ChunkBlocks;
+use mc_server::chunk_service::Dimension;
+use mc_server::datapack::DataPack;
+use mc_server::light::{ChunkLight, LightKind};
+use mc_server::light_engine::LightEngine;
+use mc_server::resource_manager::ResourceManager;
+
+type Area = HashMap<(i32, i32), (Arc<ChunkBlocks>, ChunkLight)>;
+
+const AIR: i32 = 0;
+const STONE: i32 = 1;
+const GLOWSTONE: i32 = 2;
+/// Gives off light 14, one less than glowstone
+const TORCH: i32 = 3;
+/// Two block sections, so light goes from y -16 to 47
+const MIN_Y: i32 = -16;
+const MAX_Y: i32 = 47;
+const FLOOR_Y: i32 = 8;
+/// A glowstone under the floor of the center chunk
+const BURIED_GLOWSTONE: (i32, i32, i32) = (8, 4, 8);
+
+fn block_registry() -> BlockRegistry {
+    BlockRegistry::from_json(r#"{
+        "minecraft:air": {"definition": {"type": "minecraft:air", "properties": {}}, "states": [{"default": true, "id": 0}]},
+        "minecraft:stone": {"definition": {"type": "minecraft:block", "properties": {}}, "states": [{"default": true, "id": 1}]},
+        "minecraft:glowstone": {"definition": {"type": "minecraft:block", "properties": {}}, "states": [{"default": true, "id": 2}]},
+        "minecraft:torch": {"definition": {"type": "minecraft:torch", "properties": {}}, "states": [{"default": true, "id": 3}]}
+    }"#).unwrap()
+}
+
+/// Chunk 0 0 and its 8 neighbours, all air except for a stone floor over chunk 0 0 with a glowstone under it
+fn area(block_registry: &BlockRegistry) -> Area {
+    let mut area = Area::new();
+    for chunk_x in -1..=1 {
+        for chunk_z in -1..=1 {
+            let mut blocks = ChunkBlocks::empty(0, 2);
+            if (chunk_x, chunk_z) == (0, 0) {
+                for x in 0..16 {
+                    for z in 0..16 {
+                        blocks.set(x, FLOOR_Y, z, STONE, block_registry).unwrap();
+                    }
+                }
+                let (x, y, z) = BURIED_GLOWSTONE;
+                blocks.set(x, y, z, GLOWSTONE, block_registry).unwrap();
+            }
+            area.insert((chunk_x, chunk_z), (Arc::new(blocks), ChunkLight::new(0, 2)));
+        }
+    }
+    area
+}
+
+/// Lights the neighbours and then chunk 0 0 from scratch
+fn light_area(block_registry: &BlockRegistry, area: &Area) -> Area {
+    let mut engine = LightEngine::new(block_registry);
+    for ((x, z), (blocks, _)) in area {
+        engine.add_chunk(*x, *z, blocks.clone(), ChunkLight::new(0, 2));
+    }
+    for (x, z) in area.keys().filter(|key| **key != (0, 0)) {
+        engine.light_chunk(*x, *z);
+    }
+    engine.light_chunk(0, 0);
+    area.iter().map(|((x, z), (blocks, _))| ((*x, *z), (blocks.clone(), engine.light(*x, *z).unwrap().clone()))).collect()
+}
+
+fn light_at(area: &Area, kind: LightKind, x: i32, y: i32, z: i32) -> u8 {
+    area[&(x.div_euclid(16), z.div_euclid(16))].1.get(kind, x.rem_euclid(16), y, z.rem_euclid(16)).unwrap()
+}
+
+/// Every block of the area, in absolute coordinates
+fn positions() -> impl Iterator<Item = (i32, i32, i32)> {
+    (-16..32).flat_map(|x| (-16..32).flat_map(move |z| (MIN_Y..=MAX_Y).map(move |y| (x, y, z))))
+}
+
+fn in_center(x: i32, z: i32) -> bool {
+    (0..16).contains(&x) && (0..16).contains(&z)
+}
+
+#[test]
+fn lighting_from_scratch() {
+    let block_registry = block_registry();
+    let area = light_area(&block_registry, &area(&block_registry));
+
+    for (x, y, z) in positions() {
+        let sky = light_at(&area, LightKind::Sky, x, y, z);
+        let expected_sky = if !in_center(x, z) || y > FLOOR_Y {
+            15
+        } else if y == FLOOR_Y || (x, y, z) == BURIED_GLOWSTONE {
+            0
+        } else {
+            // Under the floor sky light only comes in from the sides, losing one level per block
+            15 - (x + 1).min(16 - x).min(z + 1).min(16 - z) as u8
+        };
+        assert_eq!(sky, expected_sky, "sky light at {x} {y} {z}");
+
+        // Above the floor block light has to go around it, so only check where it spreads freely
+        if in_center(x, z) && y >= FLOOR_Y {
+            continue;
+        }
+        let (gx, gy, gz) = BURIED_GLOWSTONE;
+        let distance = (x - gx).abs() + (y - gy).abs() + (z - gz).abs();
+        let expected_block = (15 - distance).max(0) as u8;
+        assert_eq!(light_at(&area, LightKind::Block, x, y, z), expected_block, "block light at {x} {y} {z}");
+    }
+    assert_eq!(light_at(&area, LightKind::Block, 8, FLOOR_Y + 1, 8), 0);
+}
+
+/// Sets a block in chunk 0 0 and relights around it, checking that gives the same light as lighting everything again
+fn set_block(block_registry: &BlockRegistry, area: &mut Area, (x, y, z): (i32, i32, i32), state: i32) {
+    Arc::make_mut(&mut area.get_mut(&(0, 0)).unwrap().0).set(x, y, z, state, block_registry).unwrap();
+    let mut engine = LightEngine::new(block_registry);
+    for ((cx, cz), (blocks, light)) in area.iter() {
+        engine.add_chunk(*cx, *cz, blocks.clone(), light.clone());
+    }
+    engine.block_changed(x, y, z);
+    for (cx, cz, light, _) in engine.into_changed() {
+        area.get_mut(&(cx, cz)).unwrap().1 = light;
+    }
+
+    let relit = light_area(block_registry, area);
+    for (x, y, z) in positions() {
+        for kind in [LightKind::Sky, LightKind::Block] {
+            assert_eq!(light_at(area, kind, x, y, z), light_at(&relit, kind, x, y, z), "{kind:?} light at {x} {y} {z} after setting {state}");
+        }
+    }
+}
+
+#[test]
+fn placing_and_removing_blocks_relights() {
+    let block_registry = block_registry();
+    let mut area = light_area(&block_registry, &area(&block_registry));
+    // Above the floor, where everything is air and fully sky lit
+    let (x, y, z) = (8, 20, 8);
+    let set = |area: &mut Area, state: i32| set_block(&block_registry, area, (x, y, z), state);
+
+    set(&mut area, GLOWSTONE);
+    assert_eq!(light_at(&area, LightKind::Block, x, y, z), 15);
+    assert_eq!(light_at(&area, LightKind::Block, x + 3, y, z), 12);
+    // Across the chunk border
+    assert_eq!(light_at(&area, LightKind::Block, x - 9, y, z), 6);
+    // Glowstone stops sky light
+    assert_eq!(light_at(&area, LightKind::Sky, x, y - 1, z), 14);
+
+    set(&mut area, STONE);
+    assert_eq!(light_at(&area, LightKind::Block, x, y, z), 0);
+    assert_eq!(light_at(&area, LightKind::Block, x + 3, y, z), 0);
+    assert_eq!(light_at(&area, LightKind::Block, x - 9, y, z), 0);
+    assert_eq!(light_at(&area, LightKind::Sky, x, y - 1, z), 14);
+
+    set(&mut area, AIR);
+    assert_eq!(light_at(&area, LightKind::Sky, x, y, z), 15);
+    assert_eq!(light_at(&area, LightKind::Sky, x, y - 1, z), 15);
+}
+
+#[test]
+fn removing_light_keeps_nearby_light_sources() {
+    let block_registry = block_registry();
+    let (x, y, z) = (8, 20, 8);
+    let mut area = area(&block_registry);
+    Arc::make_mut(&mut area.get_mut(&(0, 0)).unwrap().0).set(x + 1, y, z, TORCH, &block_registry).unwrap();
+    let mut area = light_area(&block_registry, &area);
+
+    // The torch is darker than the glowstone next to it, so taking the glowstone's light away also clears the torch
+    set_block(&block_registry, &mut area, (x, y, z), GLOWSTONE);
+    set_block(&block_registry, &mut area, (x, y, z), STONE);
+    assert_eq!(light_at(&area, LightKind::Block, x + 1, y, z), 14);
+    assert_eq!(light_at(&area, LightKind::Block, x + 4, y, z), 11);
+}
+
+/// Relights a chunk of the fixture world with its neighbours' stored light and compares it to the light vanilla stored
+#[test]
+fn lighting_matches_vanilla() {
+    let vanilla = DataPack::vanilla("resources/generated");
+    let resource_manager = ResourceManager::new("resources", &[&vanilla]).unwrap();
+    let block_registry = resource_manager.block_registry();
+    let biomes = resource_manager.registry_ids("worldgen/biome");
+    let overworld = Dimension::load("world", -64, 384).unwrap();
+    // A fully generated chunk whose neighbours are fully generated too
+    let (chunk_x, chunk_z) = (0, -34);
+
+    let mut engine = LightEngine::new(&block_registry);
+    let mut stored = None;
+    for x in chunk_x - 1..=chunk_x + 1 {
+        for z in chunk_z - 1..=chunk_z + 1 {
+            let (blocks, light) = overworld.read_chunk(x, z, &block_registry, &biomes).unwrap();
+            let light = light.expect("chunk was saved without light");
+            if (x, z) == (chunk_x, chunk_z) {
+                stored = Some(light.clone());
+            }
+            engine.add_chunk(x, z, Arc::new(blocks), light);
+        }
+    }
+    engine.light_chunk(chunk_x, chunk_z);
+    let relit = engine.light(chunk_x, chunk_z).unwrap();
+    let stored = stored.unwrap();
+
+    let (min_section, _) = stored.section_range();
+    for (kind, sections) in [(LightKind::Sky, stored.sky_sections()), (LightKind::Block, stored.block_sections())] {
+        let mut compared = 0;
+        let mut mismatches = vec![];
+        // Only where vanilla stored light, elsewhere the client works it out itself
+        for (index, _) in sections.iter().enumerate().filter(|(_, section)| section.is_some()) {
+            let section_y = min_section + index as i32;
+            for y in section_y * 16..section_y * 16 + 16 {
+                for x in 0..16 {
+                    for z in 0..16 {
+                        compared += 1;
+                        let (expected, actual) = (stored.get(kind, x, y, z), relit.get(kind, x, y, z));
+                        if expected != actual {
+                            mismatches.push((x, y, z, expected, actual));
+                        }
+                    }
+                }
+            }
+        }
+        // Chunks without light sources can come without any block light
+        assert!(compared > 0 || kind == LightKind::Block, "no stored {kind:?} light");
+        // Vanilla lets light through blocks like stairs and slabs by their shape, which only goes by block type here
+        assert!(mismatches.len() * 100 <= compared, "{} of {compared} {kind:?} light levels differ, first ones at (x, y, z, vanilla, relit): {:?}",
+            mismatches.len(), &mismatches[..mismatches.len().min(10)]);
+    }
+}
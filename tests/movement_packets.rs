@@ -0,0 +1,155 @@
+mod common;
+
+use mc_server::collision::{self, Aabb};
+use mc_server::movement::{self, MoveCheck, MoveFrom, MAX_COORDINATE};
+use mc_server::packet::PlayPacketServerBound;
+use common::packet;
+
+fn fields(doubles: &[f64], floats: &[f32], on_ground: bool) -> Vec<u8> {
+    let mut fields = doubles.iter().flat_map(|d| d.to_be_bytes()).collect::<Vec<_>>();
+    fields.extend(floats.iter().flat_map(|f| f.to_be_bytes()));
+    fields.push(on_ground as u8);
+    fields
+}
+
+#[test]
+fn set_player_position() {
+    let packet = packet(0x1A, &fields(&[1.5, -60.0, 1e7 + 0.25], &[], true));
+    match PlayPacketServerBound::parse(packet).unwrap() {
+        PlayPacketServerBound::SetPlayerPosition { x, y, z, on_ground } => {
+            assert_eq!((x, y, z, on_ground), (1.5, -60.0, 1e7 + 0.25, true));
+        }
+        other => panic!("Parsed as {other:?}"),
+    }
+}
+
+#[test]
+fn set_player_position_and_rotation() {
+    let packet = packet(0x1B, &fields(&[-3.75, 70.0, 12.0], &[-135.5, 42.0], false));
+    match PlayPacketServerBound::parse(packet).unwrap() {
+        PlayPacketServerBound::SetPlayerPositionAndRotation { x, y, z, yaw, pitch, on_ground } => {
+            assert_eq!((x, y, z, yaw, pitch, on_ground), (-3.75, 70.0, 12.0, -135.5, 42.0, false));
+        }
+        other => panic!("Parsed as {other:?}"),
+    }
+}
+
+#[test]
+fn set_player_rotation() {
+    let packet = packet(0x1C, &fields(&[], &[721.25, -90.0], true));
+    match PlayPacketServerBound::parse(packet).unwrap() {
+        PlayPacketServerBound::SetPlayerRotation { yaw, pitch, on_ground } => {
+            assert_eq!((yaw, pitch, on_ground), (721.25, -90.0, true));
+        }
+        other => panic!("Parsed as {other:?}"),
+    }
+}
+
+#[test]
+fn set_player_on_ground() {
+    for on_ground in [false, true] {
+        match PlayPacketServerBound::parse(packet(0x1D, &fields(&[], &[], on_ground))).unwrap() {
+            PlayPacketServerBound::SetPlayerOnGround(parsed) => assert_eq!(parsed, on_ground),
+            other => panic!("Parsed as {other:?}"),
+        }
+    }
+}
+
+#[test]
+fn truncated_movement_packets_fail() {
+    for (id, fields) in [
+        (0x1A, fields(&[1.0, 2.0], &[], true)),
+        (0x1B, fields(&[1.0, 2.0, 3.0], &[4.0], true)),
+        (0x1C, fields(&[], &[4.0], true)),
+        (0x1D, vec![]),
+    ] {
+        assert!(PlayPacketServerBound::parse(packet(id, &fields)).is_err(), "Packet 0x{id:02X} should not parse");
+    }
+}
+
+/// Standing on the ground at 0.5 64 0.5, with a stone block at 1 64 0 next to the player
+fn standing() -> MoveFrom {
+    MoveFrom { x: 0.5, y: 64.0, z: 0.5, fall_flying: false, spectator: false, awaiting_teleport: false, moves_this_tick: 1 }
+}
+
+fn stone(x: i32, y: i32, z: i32) -> Option<Aabb> {
+    ((x, y, z) == (1, 64, 0)).then_some(Aabb::FULL_BLOCK)
+}
+
+fn no_blocks(_: i32, _: i32, _: i32) -> Option<Aabb> {
+    None
+}
+
+#[test]
+fn move_speed_limit() {
+    let from = standing();
+    assert_eq!(movement::check_move(&from, 0.5, 64.0, -9.5, no_blocks), MoveCheck::Accept);
+    assert_eq!(movement::check_move(&from, 0.5, 64.0, -9.6, no_blocks), MoveCheck::TooFast);
+    assert_eq!(movement::check_move(&from, 0.5, 52.0, 0.5, no_blocks), MoveCheck::TooFast);
+
+    // The distance adds up over the moves of a tick, up to a limit
+    let lagging = MoveFrom { moves_this_tick: 2, ..standing() };
+    assert_eq!(movement::check_move(&lagging, 0.5, 64.0, -13.5, no_blocks), MoveCheck::Accept);
+    let lagging = MoveFrom { moves_this_tick: 100, ..standing() };
+    assert_eq!(movement::check_move(&lagging, 0.5, 64.0, -21.5, no_blocks), MoveCheck::Accept);
+    assert_eq!(movement::check_move(&lagging, 0.5, 64.0, -22.5, no_blocks), MoveCheck::TooFast);
+
+    let gliding = MoveFrom { fall_flying: true, ..standing() };
+    assert_eq!(movement::check_move(&gliding, 0.5, 64.0, -16.5, no_blocks), MoveCheck::Accept);
+    assert_eq!(movement::check_move(&gliding, 0.5, 64.0, -17.5, no_blocks), MoveCheck::TooFast);
+
+    let spectating = MoveFrom { spectator: true, ..standing() };
+    assert_eq!(movement::check_move(&spectating, 1000.5, 64.0, 0.5, no_blocks), MoveCheck::Accept);
+}
+
+#[test]
+fn moves_wait_for_teleport_confirmation() {
+    let teleported = MoveFrom { awaiting_teleport: true, ..standing() };
+    assert_eq!(movement::check_move(&teleported, 0.5, 64.0, 1.0, no_blocks), MoveCheck::Ignore);
+    assert_eq!(movement::check_move(&teleported, 500.5, 64.0, 0.5, no_blocks), MoveCheck::Ignore);
+    // Broken positions still disconnect
+    assert_eq!(movement::check_move(&teleported, f64::NAN, 64.0, 0.5, no_blocks), MoveCheck::Invalid);
+}
+
+#[test]
+fn move_bounds() {
+    // Even spectators, who may move anywhere
+    let from = MoveFrom { spectator: true, ..standing() };
+    for (x, y, z) in [
+        (f64::NAN, 64.0, 0.5),
+        (0.5, f64::INFINITY, 0.5),
+        (0.5, 64.0, f64::NEG_INFINITY),
+        (MAX_COORDINATE + 1.0, 64.0, 0.5),
+        (0.5, 64.0, -MAX_COORDINATE - 1.0),
+    ] {
+        assert_eq!(movement::check_move(&from, x, y, z, no_blocks), MoveCheck::Invalid, "Move to {x} {y} {z}");
+    }
+    assert_eq!(movement::check_move(&from, MAX_COORDINATE, 64.0, -MAX_COORDINATE, no_blocks), MoveCheck::Accept);
+}
+
+#[test]
+fn moves_into_blocks() {
+    let from = standing();
+    // The player is 0.6 wide, so this is just against the stone
+    assert_eq!(movement::check_move(&from, 0.7, 64.0, 0.5, stone), MoveCheck::Accept);
+    assert_eq!(movement::check_move(&from, 0.8, 64.0, 0.5, stone), MoveCheck::IntoBlock);
+    // On top of it
+    assert_eq!(movement::check_move(&from, 1.5, 65.0, 0.5, stone), MoveCheck::Accept);
+    assert_eq!(movement::check_move(&MoveFrom { spectator: true, ..from }, 1.5, 64.0, 0.5, stone), MoveCheck::Accept);
+
+    // Players stuck in a block can move inside of it and out again
+    let stuck = MoveFrom { x: 1.5, ..standing() };
+    assert_eq!(movement::check_move(&stuck, 1.6, 64.0, 0.5, stone), MoveCheck::Accept);
+    assert_eq!(movement::check_move(&stuck, 2.5, 64.0, 0.5, stone), MoveCheck::Accept);
+}
+
+#[test]
+fn block_shapes() {
+    let slab = |x: i32, y: i32, z: i32| ((x, y, z) == (0, 64, 0)).then_some(Aabb::new(0.0, 0.0, 0.0, 1.0, 0.5, 1.0));
+    let player_at = |x: f64, y: f64| Aabb::of_entity(x, y, 0.5, 0.6, 1.8);
+    // Stepping onto the slab from the side touches its top without going into it
+    assert!(!collision::moves_into_block(&player_at(-0.5, 64.5), &player_at(0.5, 64.5), slab));
+    assert!(collision::moves_into_block(&player_at(-0.5, 64.0), &player_at(0.5, 64.0), slab));
+    // Falling through the slab
+    assert!(collision::moves_into_block(&player_at(0.5, 65.0), &player_at(0.5, 64.25), slab));
+}
@@ -0,0 +1,58 @@
+use inbt::NbtTag;
+use mc_server::packet::{ConfigurationPacketResponse, KnownPack};
+use mc_server::server_util::RegistryEntry;
+
+fn vanilla() -> KnownPack {
+    KnownPack { namespace: "minecraft".to_string(), id: "core".to_string(), version: "1.21".to_string() }
+}
+
+fn entry(id: &str, data: Option<NbtTag>, known_pack: Option<KnownPack>) -> RegistryEntry {
+    RegistryEntry { id: id.to_string(), data, known_pack }
+}
+
+fn compound() -> Option<NbtTag> {
+    Some(NbtTag::Compound("".to_string(), vec![NbtTag::String("asset_id".to_string(), "minecraft:wolf".to_string())]))
+}
+
+/// A string as written in packets, for strings shorter than 128 bytes
+fn string(value: &str) -> Vec<u8> {
+    let mut bytes = vec![value.len() as u8];
+    bytes.extend_from_slice(value.as_bytes());
+    bytes
+}
+
+#[test]
+fn missing_entries() {
+    let shared = [vanilla()];
+
+    // Known entries don't need data
+    assert!(!entry("minecraft:pale", compound(), Some(vanilla())).is_missing_for(&shared));
+    assert!(!entry("minecraft:pale", None, Some(vanilla())).is_missing_for(&shared));
+    // Without the pack the client needs the data
+    assert!(!entry("minecraft:pale", compound(), Some(vanilla())).is_missing_for(&[]));
+    assert!(entry("minecraft:pale", None, Some(vanilla())).is_missing_for(&[]));
+    assert!(!entry("custom:red", compound(), None).is_missing_for(&shared));
+    assert!(entry("custom:red", None, None).is_missing_for(&shared));
+    // Files that aren't objects can't be read by the client
+    assert!(entry("custom:red", Some(NbtTag::List("".to_string(), vec![])), None).is_missing_for(&shared));
+    assert!(entry("custom:red", Some(NbtTag::String("".to_string(), "red".to_string())), None).is_missing_for(&shared));
+}
+
+#[test]
+fn data_is_only_sent_for_unknown_entries() {
+    let entries = [entry("minecraft:pale", compound(), Some(vanilla())), entry("custom:red", compound(), None)];
+    let packet = ConfigurationPacketResponse::registry_data("minecraft:wolf_variant", &entries, &[vanilla()]);
+
+    let mut known = string("minecraft:pale");
+    known.push(0);
+    let mut sent = string("custom:red");
+    sent.push(1);
+    assert!(packet.windows(known.len()).any(|w| w == known));
+    assert!(packet.windows(sent.len()).any(|w| w == sent));
+
+    // Without the pack both entries come with their data
+    let packet = ConfigurationPacketResponse::registry_data("minecraft:wolf_variant", &entries, &[]);
+    known.pop();
+    known.push(1);
+    assert!(packet.windows(known.len()).any(|w| w == known));
+}